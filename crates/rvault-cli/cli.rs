@@ -1,4 +1,7 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rvault_core::domain::MatchMode;
+use rvault_core::kind::{ApiKey, Card, CardExpiry, EntryKind, Identity, SecureNote, TypedSecret};
+use rvault_core::storage::AuditOperation;
 /// RVault: A modern, secure password manager using encrypted local vaults.
 #[derive(Debug, Parser)]
 #[command(version, about = "Welcome to RVault!", author = "Ata Sesli")]
@@ -9,6 +12,7 @@ pub struct Cli {
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Gets the password in the specified platform via id in the given vault and copies to the clipboard.
+    /// Notes and custom fields are printed; secret field values are only shown with --reveal.
     /// If no vault is given, the pair will be added to the CURRENT_VAULT.
     /// Example Usage: rvault get instagram johndoe
     Get {
@@ -16,21 +20,121 @@ pub enum Commands {
         vault: Option<String>,
         platform: String,
         id: String,
+        #[arg(long)]
+        reveal: bool,
     },
     /// Adds id:password pair to the given vault for the given platform
+    /// Notes and NAME=VALUE custom fields can be attached; --secret-field values are masked when shown.
+    /// With --kind note, card, identity or api-key only an ID is given and the secret comes from
+    /// that kind's flags. Card numbers must pass the Luhn check.
     /// If no vault is given, the pair will be added to the CURRENT_VAULT.
-    /// Example Usage: rvault add instagram johndoe:jd1234
+    /// Example Usage: rvault add bank johndoe:jd1234 --note "Branch: Main St." --field account=12345678
+    /// Example Usage: rvault add visa johndoe --kind card --number 4111111111111111 --expiry 09/27
     Add {
         #[arg(short, long)]
         vault: Option<String>,
         platform: String,
+        #[arg(value_name = "ID_AND_PASSWORD")]
         id_and_password: String,
+        #[arg(long, default_value_t = EntryKind::Login)]
+        kind: EntryKind,
+        #[command(flatten)]
+        typed: Box<TypedSecretArgs>,
+        #[arg(long)]
+        note: Option<String>,
+        #[arg(long = "field", value_name = "NAME=VALUE", value_parser = parse_field)]
+        fields: Vec<(String, String)>,
+        #[arg(long = "secret-field", value_name = "NAME=VALUE", value_parser = parse_field)]
+        secret_fields: Vec<(String, String)>,
+    },
+    /// Lists the entries of the given vault with their folders and tags.
+    /// --tag shows only entries with that tag; --folder only entries in that folder or below it.
+    /// Example Usage: rvault list --tag work --folder Clients/Acme
+    List {
+        #[arg(short, long)]
+        vault: Option<String>,
+        #[arg(long)]
+        tag: Option<String>,
+        #[arg(long, value_name = "PATH")]
+        folder: Option<String>,
+    },
+    /// Searches entries by platform, ID, tags, login URLs and notes, tolerating typos, and lists
+    /// the best matches first. --all-vaults searches every vault.
+    /// Example Usage: rvault search gthub work
+    Search {
+        #[arg(short, long, conflicts_with = "all_vaults")]
+        vault: Option<String>,
+        #[arg(long)]
+        all_vaults: bool,
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Adds or removes tags of the entry and moves it between folders, then prints its tags.
+    /// Folders are /-separated paths; --folder / moves the entry back to the top level.
+    /// Example Usage: rvault tag github johndoe --add work --remove personal --folder Dev/Hosting
+    Tag {
+        #[arg(short, long)]
+        vault: Option<String>,
+        platform: String,
+        id: String,
+        #[arg(long = "add", value_name = "TAG")]
+        add: Vec<String>,
+        #[arg(long = "remove", value_name = "TAG")]
+        remove: Vec<String>,
+        #[arg(long, value_name = "PATH")]
+        folder: Option<String>,
+    },
+    /// Adds or removes login URLs of the entry, then prints them.
+    /// --match sets how added URLs are matched: base-domain (default), host, starts-with, regex or never.
+    /// Example Usage: rvault url github johndoe --add github.com --match host
+    Url {
+        #[arg(short, long)]
+        vault: Option<String>,
+        platform: String,
+        id: String,
+        #[arg(long = "add", value_name = "URL")]
+        add: Vec<String>,
+        #[arg(long = "match", value_name = "MODE", default_value_t = MatchMode::BaseDomain)]
+        mode: MatchMode,
+        #[arg(long = "remove", value_name = "URL")]
+        remove: Vec<String>,
+    },
+    /// Copies the current TOTP code of the entry to the clipboard.
+    /// --set prompts for a base32 secret or otpauth:// URI to attach; --remove detaches it.
+    /// Example Usage: rvault totp github johndoe
+    Totp {
+        #[arg(short, long)]
+        vault: Option<String>,
+        platform: String,
+        id: String,
+        #[arg(long, conflicts_with = "remove")]
+        set: bool,
+        #[arg(long)]
+        remove: bool,
+    },
+    /// Lists the previous passwords of the entry, newest first, numbered from 1.
+    /// --copy N copies version N to the clipboard; --restore N makes it the current password again.
+    /// --depth N sets how many previous passwords are kept per entry, in every vault.
+    /// Example Usage: rvault history github johndoe --restore 1
+    History {
+        #[arg(short, long)]
+        vault: Option<String>,
+        #[arg(required_unless_present = "depth")]
+        platform: Option<String>,
+        #[arg(required_unless_present = "depth")]
+        id: Option<String>,
+        #[arg(long, value_name = "N", conflicts_with = "restore")]
+        copy: Option<usize>,
+        #[arg(long, value_name = "N")]
+        restore: Option<usize>,
+        #[arg(long, value_name = "N", conflicts_with_all = ["platform", "copy", "restore"])]
+        depth: Option<usize>,
     },
     /// Updates the password in the specified platform via id in the given vault
     /// If no vault is given, the pair will be added to the CURRENT_VAULT.
     /// Example Usage: rvault update instagram johndoe:4321jd
     ///
-    /// Removes the id:password pair in the given vault for the given platform via id
+    /// Moves the id:password pair in the given vault for the given platform via id to the trash.
     /// If no vault is given, the pair will be removed from the CURRENT_VAULT.
     /// Example Usage: rvault remove instagram johndoe
     Remove {
@@ -39,9 +143,35 @@ pub enum Commands {
         platform: String,
         id: String,
     },
-    /// Creates a new vault with the given name.
-    /// Example Usage: rvault create my_secret_vault
+    /// Lists, restores or purges removed entries. Removed entries are purged automatically
+    /// after 30 days unless `rvault trash retention` says otherwise.
+    /// Example Usage: rvault trash restore instagram johndoe
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommands,
+    },
+    /// Shows or verifies the encrypted audit log of vault operations.
+    /// Example Usage: rvault audit log --since 2026-01-01 --operation get
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommands,
+    },
+    /// Creates a new vault with the given name. Names can hold spaces and any Unicode text.
+    /// Example Usage: rvault create "Client – ACME Prod"
     Create { vault_name: Option<String> },
+    /// Lists, renames, deletes or copies vaults and moves entries between them.
+    /// Example Usage: rvault vault move-entry github johndoe --to work
+    Vault {
+        #[command(subcommand)]
+        command: VaultCommands,
+    },
+    /// Encrypts the platform and id of every entry in the given vault, in place.
+    /// Entries can still be looked up by platform and id once the vault is unlocked.
+    /// Example Usage: rvault encrypt-metadata --vault work
+    EncryptMetadata {
+        #[arg(short, long)]
+        vault: Option<String>,
+    },
     /// Generates a random, unique password under the given constraints
     Generate {
         #[arg(short, long, default_value_t = 12)]
@@ -60,6 +190,27 @@ pub enum Commands {
         #[command(subcommand)]
         command: BackupCommands,
     },
+    /// Writes a printable emergency kit with a new recovery key and restore instructions.
+    /// Example Usage: rvault emergency-kit --out kit.md
+    EmergencyKit {
+        #[arg(long)]
+        out: String,
+    },
+    /// Sets a new master password using a recovery key from an emergency kit.
+    /// Example Usage: rvault recover
+    Recover {},
+    /// Adds, removes or lists keystore key slots that can unlock the vault.
+    /// Example Usage: rvault keyslot add recovery
+    Keyslot {
+        #[command(subcommand)]
+        command: KeyslotCommands,
+    },
+    /// Shows information about the master keystore.
+    /// Example Usage: rvault keystore info
+    Keystore {
+        #[command(subcommand)]
+        command: KeystoreCommands,
+    },
     /// Prints this device's public RVault recipient code.
     Identity {},
     /// Exports selected entries to an encrypted binary file for a recipient.
@@ -87,15 +238,56 @@ pub enum Commands {
         #[arg(long)]
         skip_all: bool,
     },
+    /// Merges the entries of another RVault database, or of a backup, into this one. Entries
+    /// changed on both sides are asked about unless --keep picks a side for all of them.
+    /// A database with another master key needs its keystore; a backup asks for its password.
+    /// Example Usage: rvault merge desktop.sqlite --keystore desktop-keystore.rvault
+    Merge {
+        path: String,
+        #[arg(long)]
+        keystore: Option<String>,
+        #[arg(long, value_enum)]
+        keep: Option<MergeKeep>,
+    },
+    /// Syncs every vault with other devices through a shared folder, such as one kept in step
+    /// by Syncthing or Nextcloud. Only encrypted operation logs are written to the folder.
+    /// Example Usage: rvault sync now --dir ~/Sync/rvault
+    /// Example Usage: rvault sync git --remote git@example.com:me/vault.git
+    Sync {
+        #[command(subcommand)]
+        command: SyncCommands,
+    },
+    /// Shares a vault with teammates through a folder of its own, sealed with a vault key
+    /// wrapped to each member's public code from `rvault identity`.
+    /// Example Usage: rvault share add-member --vault Team --folder ~/Sync/team rvault1-abc
+    Share {
+        #[command(subcommand)]
+        command: ShareCommands,
+    },
     /// Unlocks the vault in order to use it, prompts master password. It automatically locks after a certain amount of time.
+    /// The prompt also accepts a recovery key; --keyfile unlocks through a keyfile slot instead.
     /// Example Usage: rvault unlock
-    Unlock {},
+    Unlock {
+        #[arg(long, value_name = "PATH")]
+        keyfile: Option<String>,
+    },
     /// Locks the vault after using it. It automatically locks after a certain amount of time.
     /// Example Usage: rvault lock
     Lock {},
     /// Must run if user runs the app for the first time, it prompts and sets the master password.
-    /// Example Usage: rvault setup
-    Setup {},
+    /// The keystore KDF is calibrated so unlocking takes about --unlock-ms milliseconds on this machine.
+    /// Example Usage: rvault setup --unlock-ms 750
+    Setup {
+        #[arg(long, value_name = "MS")]
+        unlock_ms: Option<u64>,
+    },
+    /// Changes the master password. Stored entries are kept as they are.
+    /// Example Usage: rvault passwd
+    Passwd {},
+    /// Generates a new master encryption key and re-encrypts every entry with it.
+    /// Recovery and keyfile slots are removed and the current session is ended.
+    /// Example Usage: rvault rotate-key
+    RotateKey {},
     /// Enables or disables RVault browser integration.
     Browser {
         #[command(subcommand)]
@@ -124,6 +316,161 @@ pub enum BackupCommands {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub enum TrashCommands {
+    /// Lists the entries in the trash of the given vault.
+    List {
+        #[arg(short, long)]
+        vault: Option<String>,
+    },
+    /// Moves an entry out of the trash.
+    Restore {
+        #[arg(short, long)]
+        vault: Option<String>,
+        platform: String,
+        id: String,
+    },
+    /// Permanently deletes an entry in the trash, or every one with --all.
+    Purge {
+        #[arg(short, long)]
+        vault: Option<String>,
+        #[arg(required_unless_present = "all")]
+        platform: Option<String>,
+        #[arg(required_unless_present = "all")]
+        id: Option<String>,
+        #[arg(long, conflicts_with_all = ["platform", "id"])]
+        all: bool,
+    },
+    /// Shows or sets how many days removed entries are kept; 0 keeps them until purged.
+    Retention { days: Option<u32> },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuditCommands {
+    /// Lists audit records, oldest first.
+    Log {
+        /// Only records from this day on, in local time.
+        #[arg(long, value_name = "YYYY-MM-DD")]
+        since: Option<chrono::NaiveDate>,
+        #[arg(long, value_name = "OPERATION")]
+        operation: Option<AuditOperation>,
+        #[arg(long)]
+        platform: Option<String>,
+    },
+    /// Checks that no audit record was edited, reordered or deleted.
+    Verify {},
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VaultCommands {
+    /// Lists every vault with its entry count and when it was last modified.
+    List {},
+    /// Renames a vault. The default vault keeps its name.
+    Rename { name: String, new_name: String },
+    /// Permanently deletes a vault with its entries, trash and history.
+    Delete {
+        name: String,
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Copies a vault and its entries to a new vault.
+    Copy { name: String, new_name: String },
+    /// Moves an entry with its history, tags and folder to another vault.
+    MoveEntry {
+        #[arg(short, long)]
+        vault: Option<String>,
+        platform: String,
+        id: String,
+        #[arg(long, value_name = "VAULT")]
+        to: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SyncCommands {
+    /// Writes the local changes to the sync folder and applies those of the other devices.
+    /// --dir sets the sync folder, which is remembered for later syncs.
+    Now {
+        #[arg(long, value_name = "PATH")]
+        dir: Option<String>,
+    },
+    /// Shows this device's ID, the sync folder, unsynced changes and the other devices' logs.
+    Status {},
+    /// Stops syncing through the sync folder and the git remote, for example before
+    /// `rvault rotate-key`. A later `rvault sync now --dir` sends the changes made since.
+    Stop {},
+    /// Syncs every vault with a git repository holding one encrypted file per entry, which
+    /// keeps the history of every entry. --remote sets the repository, which is remembered.
+    Git {
+        #[arg(long, value_name = "PATH_OR_URL")]
+        remote: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ShareCommands {
+    /// Wraps the vault key to a member's public code. Sharing a vault for the first time needs
+    /// --folder, which the members keep in step with a tool such as Syncthing; you become its
+    /// owner and first member.
+    AddMember {
+        #[arg(short, long)]
+        vault: Option<String>,
+        #[arg(long, value_name = "PATH")]
+        folder: Option<String>,
+        code: String,
+    },
+    /// Removes a member and rotates the vault key, sending every entry again under the new key.
+    RemoveMember {
+        #[arg(short, long)]
+        vault: Option<String>,
+        code: String,
+    },
+    /// Joins the vault shared through --folder as a new vault called --vault.
+    Join {
+        #[arg(short, long)]
+        vault: String,
+        #[arg(long, value_name = "PATH")]
+        folder: String,
+    },
+    /// Syncs a shared vault through its folder, or every shared vault without --vault.
+    Sync {
+        #[arg(short, long)]
+        vault: Option<String>,
+    },
+    /// Lists the public codes the vault key is wrapped to, the owner first.
+    ListMembers {
+        #[arg(short, long)]
+        vault: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeyslotCommands {
+    /// Adds a recovery key or keyfile slot. Prompts for the master password.
+    Add {
+        #[command(subcommand)]
+        slot: NewKeySlot,
+    },
+    /// Removes a key slot by id. The master password slot cannot be removed.
+    Remove { id: u32 },
+    /// Lists the key slots and their KDF parameters.
+    List {},
+}
+
+#[derive(Debug, Subcommand)]
+pub enum NewKeySlot {
+    /// Generates a recovery key and prints it once.
+    Recovery {},
+    /// Uses the keyfile at PATH, creating a random one if it does not exist.
+    Keyfile { path: String },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeystoreCommands {
+    /// Prints the keystore format and its Argon2id parameters.
+    Info {},
+}
+
 #[derive(Debug, Subcommand)]
 pub enum BrowserCommands {
     /// Enables RVault browser integration.
@@ -146,17 +493,371 @@ pub enum Browser {
     Firefox,
 }
 
+/// Side kept by `rvault merge --keep` for entries changed in both databases.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum MergeKeep {
+    Local,
+    Other,
+    /// Keeps the local entry and adds the other one under a new user ID.
+    Both,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum HostCommands {
     /// Runs stdio native messaging mode.
     Serve,
 }
 
+/// Values of the typed secret kinds for `rvault add --kind`.
+#[derive(Debug, Default, Args)]
+pub struct TypedSecretArgs {
+    /// Text of a secure note.
+    #[arg(long, help_heading = "Secure note")]
+    pub text: Option<String>,
+    #[arg(long, help_heading = "Card")]
+    pub holder: Option<String>,
+    #[arg(long, help_heading = "Card")]
+    pub number: Option<String>,
+    /// Expiry as MM/YY or MM/YYYY.
+    #[arg(long, help_heading = "Card")]
+    pub expiry: Option<CardExpiry>,
+    #[arg(long, help_heading = "Card")]
+    pub cvv: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub name: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub email: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub phone: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub address: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub city: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub postal_code: Option<String>,
+    #[arg(long, help_heading = "Identity")]
+    pub country: Option<String>,
+    #[arg(long, help_heading = "API key")]
+    pub token: Option<String>,
+    /// Date the token expires, as YYYY-MM-DD.
+    #[arg(long, help_heading = "API key")]
+    pub expires: Option<chrono::NaiveDate>,
+}
+
+impl TypedSecretArgs {
+    /// Builds the secret of a `kind` entry, or `None` for logins. Flags of other kinds are
+    /// rejected rather than silently dropped.
+    pub fn into_secret(self, kind: EntryKind) -> Result<Option<TypedSecret>, String> {
+        let given: Vec<(&str, EntryKind)> = [
+            ("--text", self.text.is_some(), EntryKind::Note),
+            ("--holder", self.holder.is_some(), EntryKind::Card),
+            ("--number", self.number.is_some(), EntryKind::Card),
+            ("--expiry", self.expiry.is_some(), EntryKind::Card),
+            ("--cvv", self.cvv.is_some(), EntryKind::Card),
+            ("--name", self.name.is_some(), EntryKind::Identity),
+            ("--email", self.email.is_some(), EntryKind::Identity),
+            ("--phone", self.phone.is_some(), EntryKind::Identity),
+            ("--address", self.address.is_some(), EntryKind::Identity),
+            ("--city", self.city.is_some(), EntryKind::Identity),
+            (
+                "--postal-code",
+                self.postal_code.is_some(),
+                EntryKind::Identity,
+            ),
+            ("--country", self.country.is_some(), EntryKind::Identity),
+            ("--token", self.token.is_some(), EntryKind::ApiKey),
+            ("--expires", self.expires.is_some(), EntryKind::ApiKey),
+        ]
+        .into_iter()
+        .filter(|(_, set, _)| *set)
+        .map(|(flag, _, flag_kind)| (flag, flag_kind))
+        .collect();
+        if let Some((flag, _)) = given.iter().find(|(_, flag_kind)| *flag_kind != kind) {
+            return Err(format!("{flag} cannot be used with --kind {kind}"));
+        }
+        let secret = match kind {
+            EntryKind::Login => return Ok(None),
+            EntryKind::Note => TypedSecret::Note(SecureNote::new(
+                self.text.ok_or("--kind note needs --text")?,
+            )?),
+            EntryKind::Card => TypedSecret::Card(Card::new(
+                self.holder.as_deref().unwrap_or_default(),
+                self.number.as_deref().ok_or("--kind card needs --number")?,
+                self.expiry.ok_or("--kind card needs --expiry")?,
+                self.cvv.as_deref().unwrap_or_default(),
+            )?),
+            EntryKind::Identity => {
+                let mut identity = Identity::default();
+                identity.full_name = self.name.unwrap_or_default();
+                identity.email = self.email.unwrap_or_default();
+                identity.phone = self.phone.unwrap_or_default();
+                identity.address = self.address.unwrap_or_default();
+                identity.city = self.city.unwrap_or_default();
+                identity.postal_code = self.postal_code.unwrap_or_default();
+                identity.country = self.country.unwrap_or_default();
+                TypedSecret::Identity(identity.validated()?)
+            }
+            EntryKind::ApiKey => TypedSecret::ApiKey(ApiKey::new(
+                self.token
+                    .as_deref()
+                    .ok_or("--kind api-key needs --token")?,
+                self.expires,
+            )?),
+        };
+        Ok(Some(secret))
+    }
+}
+
+fn parse_field(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err("custom fields must use NAME=VALUE format".to_string()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use clap::Parser;
 
+    #[test]
+    fn passwd_parses_without_arguments() {
+        let cli = Cli::parse_from(["rvault", "passwd"]);
+
+        assert!(matches!(cli.command, Some(Commands::Passwd {})));
+    }
+
+    #[test]
+    fn history_parses_restore_and_standalone_depth() {
+        let cli = Cli::parse_from(["rvault", "history", "github", "alice", "--restore", "2"]);
+        match cli.command {
+            Some(Commands::History {
+                platform, restore, ..
+            }) => {
+                assert_eq!(platform.as_deref(), Some("github"));
+                assert_eq!(restore, Some(2));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let cli = Cli::parse_from(["rvault", "history", "--depth", "5"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::History {
+                platform: None,
+                depth: Some(5),
+                ..
+            })
+        ));
+        assert!(Cli::try_parse_from(["rvault", "history", "github"]).is_err());
+        assert!(
+            Cli::try_parse_from(["rvault", "history", "github", "alice", "--depth", "5"]).is_err()
+        );
+    }
+
+    #[test]
+    fn tag_parses_repeated_tags_and_folder() {
+        let cli = Cli::parse_from([
+            "rvault",
+            "tag",
+            "github",
+            "alice",
+            "--add",
+            "work",
+            "--add",
+            "dev",
+            "--remove",
+            "old",
+            "--folder",
+            "Dev/Hosting",
+        ]);
+
+        match cli.command {
+            Some(Commands::Tag {
+                add,
+                remove,
+                folder,
+                ..
+            }) => {
+                assert_eq!(add, vec!["work", "dev"]);
+                assert_eq!(remove, vec!["old"]);
+                assert_eq!(folder.as_deref(), Some("Dev/Hosting"));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn url_parses_urls_and_match_mode() {
+        let cli = Cli::parse_from([
+            "rvault",
+            "url",
+            "github",
+            "alice",
+            "--add",
+            "github.com",
+            "--match",
+            "starts-with",
+            "--remove",
+            "https://old.example",
+        ]);
+
+        match cli.command {
+            Some(Commands::Url {
+                add, mode, remove, ..
+            }) => {
+                assert_eq!(add, vec!["github.com"]);
+                assert_eq!(mode, MatchMode::StartsWith);
+                assert_eq!(remove, vec!["https://old.example"]);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert!(
+            Cli::try_parse_from(["rvault", "url", "github", "alice", "--match", "fuzzy"]).is_err()
+        );
+    }
+
+    #[test]
+    fn encrypt_metadata_parses_optional_vault() {
+        let cli = Cli::parse_from(["rvault", "encrypt-metadata", "--vault", "work"]);
+
+        match cli.command {
+            Some(Commands::EncryptMetadata { vault }) => assert_eq!(vault.as_deref(), Some("work")),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn add_parses_notes_and_custom_fields() {
+        let cli = Cli::parse_from([
+            "rvault",
+            "add",
+            "bank",
+            "alice:pw",
+            "--note",
+            "Branch: Main St.",
+            "--field",
+            "account=12=34",
+            "--secret-field",
+            "pin=0000",
+        ]);
+
+        match cli.command {
+            Some(Commands::Add {
+                note,
+                fields,
+                secret_fields,
+                ..
+            }) => {
+                assert_eq!(note.as_deref(), Some("Branch: Main St."));
+                assert_eq!(fields, vec![("account".to_string(), "12=34".to_string())]);
+                assert_eq!(secret_fields, vec![("pin".to_string(), "0000".to_string())]);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert!(
+            Cli::try_parse_from(["rvault", "add", "bank", "alice:pw", "--field", "x"]).is_err()
+        );
+    }
+
+    #[test]
+    fn totp_parses_set_and_rejects_set_with_remove() {
+        let cli = Cli::parse_from(["rvault", "totp", "github", "alice", "--set"]);
+        match cli.command {
+            Some(Commands::Totp {
+                platform,
+                id,
+                set,
+                remove,
+                ..
+            }) => {
+                assert_eq!((platform.as_str(), id.as_str()), ("github", "alice"));
+                assert!(set && !remove);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert!(
+            Cli::try_parse_from(["rvault", "totp", "github", "alice", "--set", "--remove"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rotate_key_parses_without_arguments() {
+        let cli = Cli::parse_from(["rvault", "rotate-key"]);
+
+        assert!(matches!(cli.command, Some(Commands::RotateKey {})));
+    }
+
+    #[test]
+    fn setup_parses_unlock_target() {
+        let cli = Cli::parse_from(["rvault", "setup", "--unlock-ms", "750"]);
+
+        match cli.command {
+            Some(Commands::Setup { unlock_ms }) => assert_eq!(unlock_ms, Some(750)),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn keyslot_commands_parse() {
+        let cli = Cli::parse_from(["rvault", "keyslot", "add", "keyfile", "rvault.key"]);
+        match cli.command {
+            Some(Commands::Keyslot {
+                command:
+                    KeyslotCommands::Add {
+                        slot: NewKeySlot::Keyfile { path },
+                    },
+            }) => assert_eq!(path, "rvault.key"),
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let cli = Cli::parse_from(["rvault", "keyslot", "remove", "2"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Keyslot {
+                command: KeyslotCommands::Remove { id: 2 }
+            })
+        ));
+    }
+
+    #[test]
+    fn emergency_kit_parses_output_path() {
+        let cli = Cli::parse_from(["rvault", "emergency-kit", "--out", "kit.txt"]);
+
+        match cli.command {
+            Some(Commands::EmergencyKit { out }) => assert_eq!(out, "kit.txt"),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn unlock_parses_keyfile() {
+        let cli = Cli::parse_from(["rvault", "unlock", "--keyfile", "rvault.key"]);
+
+        match cli.command {
+            Some(Commands::Unlock { keyfile }) => {
+                assert_eq!(keyfile.as_deref(), Some("rvault.key"))
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn keystore_info_parses() {
+        let cli = Cli::parse_from(["rvault", "keystore", "info"]);
+
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Keystore {
+                command: KeystoreCommands::Info {}
+            })
+        ));
+    }
+
     #[test]
     fn browser_enable_parses_without_extension_id() {
         let cli = Cli::parse_from(["rvault", "browser", "enable"]);
@@ -218,6 +919,207 @@ mod tests {
         }
     }
 
+    #[test]
+    fn trash_purge_takes_an_entry_or_all() {
+        let cli = Cli::parse_from(["rvault", "trash", "purge", "github", "alice"]);
+        match cli.command {
+            Some(Commands::Trash {
+                command:
+                    TrashCommands::Purge {
+                        platform, id, all, ..
+                    },
+            }) => {
+                assert_eq!(platform.as_deref(), Some("github"));
+                assert_eq!(id.as_deref(), Some("alice"));
+                assert!(!all);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert!(Cli::try_parse_from(["rvault", "trash", "purge", "--all"]).is_ok());
+        assert!(Cli::try_parse_from(["rvault", "trash", "purge"]).is_err());
+        assert!(
+            Cli::try_parse_from(["rvault", "trash", "purge", "github", "alice", "--all"]).is_err()
+        );
+    }
+
+    #[test]
+    fn audit_log_parses_filters() {
+        let cli = Cli::parse_from([
+            "rvault",
+            "audit",
+            "log",
+            "--since",
+            "2026-01-01",
+            "--operation",
+            "backup-create",
+        ]);
+        match cli.command {
+            Some(Commands::Audit {
+                command:
+                    AuditCommands::Log {
+                        since,
+                        operation,
+                        platform,
+                    },
+            }) => {
+                assert_eq!(since, chrono::NaiveDate::from_ymd_opt(2026, 1, 1));
+                assert_eq!(operation, Some(AuditOperation::BackupCreate));
+                assert_eq!(platform, None);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert!(Cli::try_parse_from(["rvault", "audit", "log", "--since", "yesterday"]).is_err());
+        assert!(Cli::try_parse_from(["rvault", "audit", "log", "--operation", "read"]).is_err());
+    }
+
+    #[test]
+    fn search_joins_query_words_and_rejects_a_vault_with_all_vaults() {
+        let cli = Cli::parse_from(["rvault", "search", "--all-vaults", "gthub", "work"]);
+        match cli.command {
+            Some(Commands::Search {
+                vault,
+                all_vaults,
+                query,
+            }) => {
+                assert_eq!(vault, None);
+                assert!(all_vaults);
+                assert_eq!(query, ["gthub", "work"]);
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+        assert!(
+            Cli::try_parse_from(["rvault", "search", "-v", "work", "--all-vaults", "git"]).is_err()
+        );
+        assert!(Cli::try_parse_from(["rvault", "search"]).is_err());
+    }
+
+    #[test]
+    fn add_builds_typed_secrets_from_the_flags_of_their_kind() {
+        let parse = |args: &[&str]| match Cli::try_parse_from(["rvault", "add"].iter().chain(args))
+        {
+            Ok(Cli {
+                command: Some(Commands::Add { kind, typed, .. }),
+            }) => typed.into_secret(kind),
+            other => panic!("unexpected parse: {other:?}"),
+        };
+        let card = parse(&[
+            "visa",
+            "johndoe",
+            "--kind",
+            "card",
+            "--number",
+            "4111 1111 1111 1111",
+            "--expiry",
+            "09/27",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(card.kind(), EntryKind::Card);
+        assert_eq!(card.primary_value(), "4111111111111111");
+        assert!(parse(&["github", "alice:pw"]).unwrap().is_none());
+        assert!(
+            parse(&[
+                "visa",
+                "johndoe",
+                "--kind",
+                "card",
+                "--number",
+                "4111111111111112",
+                "--expiry",
+                "09/27"
+            ])
+            .is_err()
+        );
+        assert!(parse(&["visa", "johndoe", "--kind", "card", "--token", "t"]).is_err());
+        let key = parse(&[
+            "ci",
+            "deploy",
+            "--kind",
+            "api-key",
+            "--token",
+            "t0k3n",
+            "--expires",
+            "2027-01-31",
+        ])
+        .unwrap()
+        .unwrap();
+        assert!(key.is_expired(chrono::NaiveDate::from_ymd_opt(2027, 2, 1).unwrap()));
+        assert!(Cli::try_parse_from(["rvault", "add", "x", "y", "--kind", "wallet"]).is_err());
+    }
+
+    #[test]
+    fn sync_now_parses_an_optional_folder() {
+        let cli = Cli::parse_from(["rvault", "sync", "now", "--dir", "/tmp/rvault-sync"]);
+        match cli.command {
+            Some(Commands::Sync {
+                command: SyncCommands::Now { dir },
+            }) => assert_eq!(dir.as_deref(), Some("/tmp/rvault-sync")),
+            other => panic!("unexpected command: {other:?}"),
+        }
+        let cli = Cli::parse_from(["rvault", "sync", "status"]);
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Sync {
+                command: SyncCommands::Status {}
+            })
+        ));
+        let cli = Cli::parse_from(["rvault", "sync", "git", "--remote", "git@host:vault.git"]);
+        match cli.command {
+            Some(Commands::Sync {
+                command: SyncCommands::Git { remote },
+            }) => assert_eq!(remote.as_deref(), Some("git@host:vault.git")),
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn vault_commands_parse_names_and_target_vault() {
+        let cli = Cli::parse_from(["rvault", "vault", "rename", "work", "clients"]);
+        match cli.command {
+            Some(Commands::Vault {
+                command: VaultCommands::Rename { name, new_name },
+            }) => {
+                assert_eq!(name, "work");
+                assert_eq!(new_name, "clients");
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        let cli = Cli::parse_from([
+            "rvault",
+            "vault",
+            "move-entry",
+            "-v",
+            "work",
+            "github",
+            "johndoe",
+            "--to",
+            "personal",
+        ]);
+        match cli.command {
+            Some(Commands::Vault {
+                command:
+                    VaultCommands::MoveEntry {
+                        vault,
+                        platform,
+                        id,
+                        to,
+                    },
+            }) => {
+                assert_eq!(vault.as_deref(), Some("work"));
+                assert_eq!((platform.as_str(), id.as_str()), ("github", "johndoe"));
+                assert_eq!(to, "personal");
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+
+        assert!(
+            Cli::try_parse_from(["rvault", "vault", "move-entry", "github", "johndoe"]).is_err()
+        );
+    }
+
     #[test]
     fn backup_restore_parses_confirmation_flag() {
         let cli = Cli::parse_from([
@@ -239,6 +1141,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn share_add_member_parses_a_vault_a_folder_and_a_code() {
+        let cli = Cli::parse_from([
+            "rvault",
+            "share",
+            "add-member",
+            "--vault",
+            "Team",
+            "--folder",
+            "/sync/team",
+            "rvault1-x",
+        ]);
+        match cli.command {
+            Some(Commands::Share {
+                command:
+                    ShareCommands::AddMember {
+                        vault,
+                        folder,
+                        code,
+                    },
+            }) => {
+                assert_eq!(vault.as_deref(), Some("Team"));
+                assert_eq!(folder.as_deref(), Some("/sync/team"));
+                assert_eq!(code, "rvault1-x");
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
     #[test]
     fn identity_parses_as_top_level_command() {
         let cli = Cli::parse_from(["rvault", "identity"]);
@@ -273,6 +1204,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn merge_parses_path_keystore_and_side() {
+        let cli = Cli::parse_from([
+            "rvault",
+            "merge",
+            "desktop.sqlite",
+            "--keystore",
+            "desktop.rvault",
+            "--keep",
+            "both",
+        ]);
+
+        match cli.command {
+            Some(Commands::Merge {
+                path,
+                keystore,
+                keep,
+            }) => {
+                assert_eq!(path, "desktop.sqlite");
+                assert_eq!(keystore.as_deref(), Some("desktop.rvault"));
+                assert_eq!(keep, Some(MergeKeep::Both));
+            }
+            other => panic!("unexpected command: {other:?}"),
+        }
+    }
+
     #[test]
     fn import_parses_path_and_conflict_flags() {
         let cli = Cli::parse_from(["rvault", "import", "gmail.rvault-export", "--skip-all"]);
@@ -2,11 +2,14 @@ use base64::{Engine as _, engine::general_purpose::STANDARD as Base64};
 use rvault_core::{
     SecretKey, backup,
     config::Config,
-    crypto, identity,
+    crypto,
+    domain::EntryUrl,
+    identity,
     portable_export::{self, ExportEntry},
     session,
     storage::{
-        Database, EntryRepository, EntrySelector, EntryUpdate, NewEntry, StorageError, Table,
+        AuditEvent, AuditOperation, AuditSource, BatchOp, CustomField, Database, EntryMetadata,
+        EntryRepository, EntrySelector, EntryUpdate, NewEntry, StorageError, VaultInfo,
     },
     vault::Vault,
 };
@@ -39,6 +42,13 @@ enum HostRequest {
     List {
         query: Option<String>,
         vault: Option<String>,
+        #[serde(rename = "includeVaults")]
+        include_vaults: Option<bool>,
+    },
+    #[serde(rename = "matchOrigin")]
+    MatchOrigin {
+        origin: String,
+        vault: Option<String>,
     },
     #[serde(rename = "get")]
     Get {
@@ -47,12 +57,22 @@ enum HostRequest {
         user_id: String,
         vault: Option<String>,
     },
+    #[serde(rename = "totp")]
+    Totp {
+        platform: String,
+        #[serde(rename = "userId")]
+        user_id: String,
+        vault: Option<String>,
+    },
     #[serde(rename = "create")]
     Create {
         platform: String,
         #[serde(rename = "userId")]
         user_id: String,
         password: String,
+        notes: Option<String>,
+        fields: Option<Vec<CustomField>>,
+        urls: Option<Vec<EntryUrl>>,
         vault: Option<String>,
     },
     #[serde(rename = "update")]
@@ -63,6 +83,11 @@ enum HostRequest {
         #[serde(rename = "newUserId")]
         new_user_id: String,
         password: String,
+        notes: Option<String>,
+        fields: Option<Vec<CustomField>>,
+        urls: Option<Vec<EntryUrl>>,
+        #[serde(rename = "expectedRevision")]
+        expected_revision: Option<i64>,
         vault: Option<String>,
     },
     #[serde(rename = "delete")]
@@ -145,6 +170,13 @@ struct HostImportDecision {
     action: String,
 }
 
+/// How an import resolves entries that already exist.
+struct ImportChoices {
+    overwrite_all: bool,
+    skip_all: bool,
+    decisions: Vec<HostImportDecision>,
+}
+
 #[derive(Serialize)]
 struct HostError {
     code: &'static str,
@@ -168,6 +200,12 @@ fn handle_request(request: HostRequest) -> Result<Value, String> {
         HostRequest::Status => status(),
         HostRequest::Unlock { master_password } => unlock(master_password),
         HostRequest::Lock => {
+            if let Some(key) = browser_session_key() {
+                record_audit(
+                    &key,
+                    AuditEvent::new(AuditOperation::Lock, AuditSource::Browser),
+                );
+            }
             let _ = session::end_session();
             Ok(json!({ "locked": true }))
         }
@@ -175,26 +213,39 @@ fn handle_request(request: HostRequest) -> Result<Value, String> {
             let _ = session::end_browser_session();
             Ok(json!({ "locked": true }))
         }
-        HostRequest::List { query, vault } => {
+        HostRequest::List {
+            query,
+            vault,
+            include_vaults,
+        } => with_unlocked_repository(vault, |repository, key| {
+            let entries = repository
+                .search(Some(key), query.as_deref().unwrap_or_default())
+                .map_err(typed_storage_error)?
+                .iter()
+                .map(|hit| entry_json(&hit.entry))
+                .collect::<Vec<_>>();
+            if !include_vaults.unwrap_or(false) {
+                return Ok(json!({ "entries": entries }));
+            }
+            let db = Database::new().map_err(|e| storage_error(e.to_string()))?;
+            let vaults = db
+                .vaults()
+                .map_err(typed_storage_error)?
+                .iter()
+                .map(vault_json)
+                .collect::<Vec<_>>();
+            Ok(json!({ "entries": entries, "vaults": vaults }))
+        }),
+        HostRequest::MatchOrigin { origin, vault } => {
             with_unlocked_repository(vault, |repository, _key| {
-                let normalized_query = query.unwrap_or_default().to_lowercase();
                 let entries = repository
-                    .list_metadata()
+                    .match_origin(&origin)
                     .map_err(typed_storage_error)?
                     .into_iter()
-                    .filter(|entry| {
-                        normalized_query.is_empty()
-                            || entry.platform.to_lowercase().contains(&normalized_query)
-                            || entry.user_id.to_lowercase().contains(&normalized_query)
-                    })
-                    .map(|entry| {
-                        json!({
-                            "platform": entry.platform,
-                            "userId": entry.user_id,
-                            "pinned": entry.pinned,
-                            "createdAt": entry.created_at,
-                            "updatedAt": entry.updated_at,
-                        })
+                    .map(|(entry, quality)| {
+                        let mut value = entry_json(&entry);
+                        value["match"] = json!(quality);
+                        value
                     })
                     .collect::<Vec<_>>();
                 Ok(json!({ "entries": entries }))
@@ -204,50 +255,115 @@ fn handle_request(request: HostRequest) -> Result<Value, String> {
             platform,
             user_id,
             vault,
-        } => with_unlocked_repository(vault, |repository, key| {
-            let entry = repository
-                .get(key, EntrySelector::new(&platform, &user_id))
-                .map_err(typed_storage_error)?;
-            let password = std::str::from_utf8(entry.secret.expose())
-                .map_err(|_| storage_error("Stored password is not valid text."))?;
-            Ok(json!({ "password": password }))
-        }),
+        } => {
+            let event =
+                browser_event(AuditOperation::Get, vault.as_deref()).for_entry(&platform, &user_id);
+            with_unlocked_repository(vault, |repository, key| {
+                let entry = repository
+                    .get(key, EntrySelector::new(&platform, &user_id))
+                    .map_err(typed_storage_error)?;
+                let password = std::str::from_utf8(entry.secret.expose())
+                    .map_err(|_| storage_error("Stored password is not valid text."))?;
+                record_audit(key, event);
+                Ok(json!({
+                    "password": password,
+                    "kind": entry.metadata.kind,
+                    "revision": entry.metadata.revision,
+                    "notes": entry.details.notes,
+                    "fields": entry.details.fields,
+                    "urls": entry.metadata.urls,
+                }))
+            })
+        }
+        HostRequest::Totp {
+            platform,
+            user_id,
+            vault,
+        } => {
+            let event =
+                browser_event(AuditOperation::Get, vault.as_deref()).for_entry(&platform, &user_id);
+            with_unlocked_repository(vault, |repository, key| {
+                let entry = repository
+                    .get(key, EntrySelector::new(&platform, &user_id))
+                    .map_err(typed_storage_error)?;
+                let totp = entry
+                    .totp
+                    .ok_or_else(|| error("not_found", "The entry has no TOTP secret."))?;
+                let code = totp.code_now();
+                record_audit(key, event);
+                Ok(json!({
+                    "code": code.code,
+                    "remaining": code.remaining,
+                    "period": totp.period(),
+                }))
+            })
+        }
         HostRequest::Create {
             platform,
             user_id,
             password,
+            notes,
+            fields,
+            urls,
             vault,
-        } => with_unlocked_repository(vault, |repository, key| {
-            add_or_update_entry(repository, key, &platform, &user_id, password.as_bytes())
-                .map_err(typed_storage_error)?;
-            Ok(json!({ "saved": true }))
-        }),
+        } => {
+            let event =
+                browser_event(AuditOperation::Add, vault.as_deref()).for_entry(&platform, &user_id);
+            with_unlocked_repository(vault, |repository, key| {
+                let urls = urls.map(validated_urls).transpose()?;
+                let entry = entry_update(&user_id, &password, notes.as_deref(), fields.as_deref());
+                add_or_update_entry(repository, key, &platform, entry)
+                    .map_err(typed_storage_error)?;
+                if let Some(urls) = urls {
+                    repository
+                        .set_urls(EntrySelector::new(&platform, &user_id), &urls)
+                        .map_err(typed_storage_error)?;
+                }
+                record_audit(key, event);
+                Ok(json!({ "saved": true }))
+            })
+        }
         HostRequest::Update {
             platform,
             old_user_id,
             new_user_id,
             password,
+            notes,
+            fields,
+            urls,
+            expected_revision,
             vault,
-        } => with_unlocked_repository(vault, |repository, key| {
-            repository
-                .update(
-                    key,
-                    EntrySelector::new(&platform, &old_user_id),
-                    EntryUpdate::new(&new_user_id, password.as_bytes()),
-                )
-                .map_err(typed_storage_error)?;
-            Ok(json!({ "saved": true }))
-        }),
+        } => {
+            let event = browser_event(AuditOperation::Update, vault.as_deref())
+                .for_entry(&platform, &new_user_id);
+            with_unlocked_repository(vault, |repository, key| {
+                let urls = urls.map(validated_urls).transpose()?;
+                let mut update =
+                    entry_update(&new_user_id, &password, notes.as_deref(), fields.as_deref());
+                update.expected_revision = expected_revision;
+                update.urls = urls.as_deref();
+                repository
+                    .update(key, EntrySelector::new(&platform, &old_user_id), update)
+                    .map_err(typed_storage_error)?;
+                record_audit(key, event);
+                Ok(json!({ "saved": true }))
+            })
+        }
         HostRequest::Delete {
             platform,
             user_id,
             vault,
-        } => with_unlocked_repository(vault, |repository, _key| {
-            repository
-                .remove(EntrySelector::new(&platform, &user_id))
-                .map_err(typed_storage_error)?;
-            Ok(json!({ "deleted": true }))
-        }),
+        } => {
+            let event = browser_event(AuditOperation::Remove, vault.as_deref())
+                .for_entry(&platform, &user_id);
+            with_unlocked_repository(vault, |repository, key| {
+                repository
+                    .remove(EntrySelector::new(&platform, &user_id))
+                    .map_err(typed_storage_error)?;
+                record_audit(key, event);
+                Ok(json!({ "deleted": true }))
+            })
+        }
         HostRequest::Generate {
             length,
             special_characters,
@@ -270,14 +386,23 @@ fn handle_request(request: HostRequest) -> Result<Value, String> {
         } => {
             let path = transfer_path(&token)?;
             backup::restore_backup_file(&master_password, &path).map_err(storage_error)?;
+            // The restored files may hold a different key than the current session.
+            if let Some(key) = master_key(&master_password) {
+                record_audit(
+                    &key,
+                    AuditEvent::new(AuditOperation::BackupRestore, AuditSource::Browser),
+                );
+            }
             Ok(json!({ "restored": true }))
         }
         HostRequest::Export { to, entries, vault } => {
+            let event = browser_event(AuditOperation::Export, vault.as_deref());
             with_unlocked_repository(vault, |repository, key| {
                 let export_entries = build_export_entries(repository, key, &entries)?;
                 let bytes = portable_export::create_export_bytes(&to, &export_entries)
                     .map_err(storage_error)?;
                 let token = write_transfer_file(&bytes)?;
+                record_audit(key, event);
                 Ok(json!({
                     "token": token,
                     "size": bytes.len(),
@@ -287,9 +412,9 @@ fn handle_request(request: HostRequest) -> Result<Value, String> {
             })
         }
         HostRequest::ImportPreview { token, vault } => {
-            with_unlocked_table(vault, |db, table, key| {
-                let entries = decrypt_transfer_export(key, &token)?;
-                let conflicts = import_conflicts(db, table, &entries)?;
+            with_unlocked_repository(vault, |repository, key| {
+                let entries = decrypt_transfer_export(key.as_bytes(), &token)?;
+                let conflicts = import_conflicts(repository, &entries)?;
                 Ok(json!({
                     "entries": entries.iter().map(entry_metadata_json).collect::<Vec<_>>(),
                     "conflicts": conflicts,
@@ -302,17 +427,25 @@ fn handle_request(request: HostRequest) -> Result<Value, String> {
             overwrite_all,
             skip_all,
             decisions,
-        } => with_unlocked_table(vault, |db, table, key| {
-            apply_import(
-                db,
-                table,
-                key,
-                &token,
-                overwrite_all.unwrap_or(false),
-                skip_all.unwrap_or(false),
-                decisions.unwrap_or_default(),
-            )
-        }),
+        } => {
+            let event = browser_event(AuditOperation::Import, vault.as_deref());
+            let imported = with_unlocked_repository(vault, |repository, key| {
+                apply_import(
+                    repository,
+                    key,
+                    &token,
+                    ImportChoices {
+                        overwrite_all: overwrite_all.unwrap_or(false),
+                        skip_all: skip_all.unwrap_or(false),
+                        decisions: decisions.unwrap_or_default(),
+                    },
+                )
+            })?;
+            if let Some(key) = browser_session_key() {
+                record_audit(&key, event);
+            }
+            Ok(imported)
+        }
         HostRequest::DownloadChunk {
             token,
             offset,
@@ -364,6 +497,10 @@ fn unlock(master_password: String) -> Result<Value, String> {
         session::start_session(&encryption_key).map_err(|e| storage_error(e.to_string()))?;
     session::write_current(&token).map_err(storage_error)?;
     session::start_browser_session(&token).map_err(storage_error)?;
+    record_audit(
+        &SecretKey::from_bytes(encryption_key),
+        AuditEvent::new(AuditOperation::Unlock, AuditSource::Browser),
+    );
     Ok(json!({ "locked": false }))
 }
 
@@ -375,16 +512,6 @@ where
     operation(&key)
 }
 
-fn with_unlocked_table<F>(vault: Option<String>, operation: F) -> Result<Value, String>
-where
-    F: FnOnce(&Database, &Table, &[u8]) -> Result<Value, String>,
-{
-    let key = session::get_key_from_browser_session().map_err(|e| error("locked", e))?;
-    let db = Database::new().map_err(|e| storage_error(e.to_string()))?;
-    let table = Table::new(&db, vault).map_err(|e| storage_error(e.to_string()))?;
-    operation(&db, &table, &key)
-}
-
 fn with_unlocked_repository<F>(vault: Option<String>, operation: F) -> Result<Value, String>
 where
     F: FnOnce(&EntryRepository<'_>, &SecretKey) -> Result<Value, String>,
@@ -395,21 +522,55 @@ where
         .map_err(|_| error("locked", "Invalid browser session key."))?;
     let key = SecretKey::from_bytes(key);
     let db = Database::new().map_err(|e| storage_error(e.to_string()))?;
-    let repository =
-        EntryRepository::new(&db, vault).map_err(|_| storage_error("Storage operation failed."))?;
+    let repository = EntryRepository::unlocked(&db, vault, &key)
+        .map_err(|_| storage_error("Storage operation failed."))?;
     operation(&repository, &key)
 }
 
+fn browser_session_key() -> Option<SecretKey> {
+    let key = session::get_key_from_browser_session().ok()?;
+    let key: [u8; 32] = key.as_slice().try_into().ok()?;
+    Some(SecretKey::from_bytes(key))
+}
+
+/// Encryption key unlocked by `master_password` with the current configuration.
+pub(crate) fn master_key(master_password: &str) -> Option<SecretKey> {
+    let config = Config::new().ok()?;
+    let stored_hash = config.master_password_hash?;
+    Vault::get_encryption_key(master_password, &stored_hash)
+        .ok()
+        .map(SecretKey::from_bytes)
+}
+
+fn browser_event(operation: AuditOperation, vault: Option<&str>) -> AuditEvent {
+    AuditEvent::new(operation, AuditSource::Browser).in_vault(vault)
+}
+
+/// Appends `event` to the audit log. A failure is only reported on stderr, which the native
+/// messaging channel leaves alone, so that recording never fails the operation itself.
+pub(crate) fn record_audit(key: &SecretKey, event: AuditEvent) {
+    let recorded = Database::new()
+        .map_err(|e| e.to_string())
+        .and_then(|db| db.record_audit(key, &event).map_err(|e| e.to_string()));
+    if let Err(e) = recorded {
+        eprintln!("⚠️ Could not write the audit log: {e}");
+    }
+}
+
 fn create_backup_transfer(master_password: String) -> Result<Value, String> {
     let config = Config::new().map_err(|e| storage_error(e.to_string()))?;
     let Some(stored_hash) = config.master_password_hash.as_deref() else {
         return Err(error("setup_required", "RVault has not been set up."));
     };
-    let _ = Vault::get_encryption_key(&master_password, stored_hash)
+    let key = Vault::get_encryption_key(&master_password, stored_hash)
         .map_err(|e| error("unlock_failed", e))?;
     let token = new_transfer_token();
     let path = transfer_path(&token)?;
     backup::create_backup_file(&master_password, &path).map_err(storage_error)?;
+    record_audit(
+        &SecretKey::from_bytes(key),
+        AuditEvent::new(AuditOperation::BackupCreate, AuditSource::Browser),
+    );
     let size = fs::metadata(&path)
         .map_err(|e| storage_error(e.to_string()))?
         .len();
@@ -448,6 +609,9 @@ fn build_export_entries(
                 pinned: entry.metadata.pinned,
                 created_at: entry.metadata.created_at,
                 updated_at: entry.metadata.updated_at,
+                notes: entry.details.notes.clone(),
+                fields: entry.details.fields.clone(),
+                kind: entry.metadata.kind,
             })
         })
         .collect()
@@ -459,50 +623,50 @@ fn decrypt_transfer_export(key: &[u8], token: &str) -> Result<Vec<ExportEntry>,
     portable_export::decrypt_export_bytes(&identity, &bytes).map_err(storage_error)
 }
 
-#[allow(deprecated)] // 1.4 import boundary: preserves imported timestamps and pin state.
 fn import_conflicts(
-    db: &Database,
-    table: &Table,
+    repository: &EntryRepository<'_>,
     entries: &[ExportEntry],
 ) -> Result<Vec<Value>, String> {
     entries
         .iter()
-        .filter_map(
-            |entry| match table.entry_exists(db, &entry.platform, &entry.user_id) {
+        .filter_map(|entry| {
+            match repository.contains(EntrySelector::new(&entry.platform, &entry.user_id)) {
                 Ok(true) => Some(Ok(json!({
                     "platform": entry.platform,
                     "userId": entry.user_id,
                 }))),
                 Ok(false) => None,
                 Err(e) => Some(Err(storage_error(e.to_string()))),
-            },
-        )
+            }
+        })
         .collect()
 }
 
-#[allow(deprecated)] // 1.4 import boundary: preserves imported timestamps and pin state.
+/// Imports the chosen entries of a transfer in one batch, so a failure imports none of them.
 fn apply_import(
-    db: &Database,
-    table: &Table,
-    key: &[u8],
+    repository: &EntryRepository<'_>,
+    key: &SecretKey,
     token: &str,
-    overwrite_all: bool,
-    skip_all: bool,
-    decisions: Vec<HostImportDecision>,
+    choices: ImportChoices,
 ) -> Result<Value, String> {
+    let ImportChoices {
+        overwrite_all,
+        skip_all,
+        decisions,
+    } = choices;
     if overwrite_all && skip_all {
         return Err(error(
             "invalid_request",
             "overwriteAll and skipAll cannot both be true.",
         ));
     }
-    let entries = decrypt_transfer_export(key, token)?;
-    let mut imported = 0;
+    let entries = decrypt_transfer_export(key.as_bytes(), token)?;
+    let mut selected = Vec::new();
     let mut skipped = 0;
-    for entry in entries {
-        let exists = table
-            .entry_exists(db, &entry.platform, &entry.user_id)
-            .map_err(|e| storage_error(e.to_string()))?;
+    for entry in &entries {
+        let exists = repository
+            .contains(EntrySelector::new(&entry.platform, &entry.user_id))
+            .map_err(typed_storage_error)?;
         let should_import = if exists {
             if skip_all {
                 false
@@ -529,15 +693,22 @@ fn apply_import(
             true
         };
         if should_import {
-            table
-                .import_entry_with_key_result(db, key, &entry)
-                .map_err(|e| storage_error(e.to_string()))?;
-            imported += 1;
+            selected.push(entry);
         } else {
             skipped += 1;
         }
     }
-    Ok(json!({ "imported": imported, "skipped": skipped }))
+    let report = repository
+        .apply_batch(key, selected.iter().map(|entry| BatchOp::Import(entry)))
+        .map_err(typed_storage_error)?;
+    if let Some((index, _)) = report.first_failure() {
+        let entry = selected[index];
+        return Err(storage_error(format!(
+            "Nothing was imported: {} / {} could not be saved.",
+            entry.platform, entry.user_id
+        )));
+    }
+    Ok(json!({ "imported": selected.len(), "skipped": skipped }))
 }
 
 fn entry_metadata_json(entry: &ExportEntry) -> Value {
@@ -649,10 +820,65 @@ fn typed_storage_error(failure: StorageError) -> String {
         | StorageError::Database(_)
         | StorageError::Io(_)
         | StorageError::Crypto(_) => storage_error("Storage operation failed."),
+        StorageError::MetadataLocked => error("locked", "Vault metadata is encrypted."),
+        StorageError::InvalidSecret(kind) => error(
+            "invalid_request",
+            format!("The secret is not a valid {kind} secret."),
+        ),
+        StorageError::RevisionMismatch { actual, .. } => error(
+            "revision_mismatch",
+            format!("The entry was changed elsewhere and is now at revision {actual}; reload it."),
+        ),
         _ => storage_error("Storage operation failed."),
     }
 }
 
+fn entry_json(entry: &EntryMetadata) -> Value {
+    json!({
+        "platform": entry.platform,
+        "userId": entry.user_id,
+        "kind": entry.kind,
+        "revision": entry.revision,
+        "pinned": entry.pinned,
+        "createdAt": entry.created_at,
+        "updatedAt": entry.updated_at,
+        "folder": entry.folder,
+        "tags": entry.tags,
+        "urls": entry.urls,
+    })
+}
+
+fn vault_json(vault: &VaultInfo) -> Value {
+    json!({
+        "name": vault.name,
+        "entries": vault.entries,
+        "encryptedMetadata": vault.encrypted_metadata,
+        "shared": vault.shared,
+        "createdAt": vault.created_at,
+        "modifiedAt": vault.modified_at,
+    })
+}
+
+/// Checks URLs sent by the extension the same way the CLI does.
+fn validated_urls(urls: Vec<EntryUrl>) -> Result<Vec<EntryUrl>, String> {
+    urls.into_iter()
+        .map(|url| EntryUrl::new(&url.url, url.mode).map_err(|e| error("invalid_request", e)))
+        .collect()
+}
+
+/// Builds an update that only replaces notes and fields the request supplied.
+fn entry_update<'a>(
+    user_id: &'a str,
+    password: &'a str,
+    notes: Option<&'a str>,
+    fields: Option<&'a [CustomField]>,
+) -> EntryUpdate<'a> {
+    let mut update = EntryUpdate::new(user_id, password.as_bytes());
+    update.notes = notes;
+    update.fields = fields;
+    update
+}
+
 fn recover_duplicate_add(
     add_result: Result<(), StorageError>,
     update: impl FnOnce() -> Result<(), StorageError>,
@@ -663,23 +889,20 @@ fn recover_duplicate_add(
     }
 }
 
+/// Adds `entry` under `platform`, or updates the entry with the same user ID. Notes and fields
+/// left as `None` are kept on an existing entry and empty on a new one.
 pub(crate) fn add_or_update_entry(
     repository: &EntryRepository<'_>,
     key: &SecretKey,
     platform: &str,
-    user_id: &str,
-    password: &[u8],
+    entry: EntryUpdate<'_>,
 ) -> Result<(), StorageError> {
-    recover_duplicate_add(
-        repository.add(key, NewEntry::new(platform, user_id, password)),
-        || {
-            repository.update(
-                key,
-                EntrySelector::new(platform, user_id),
-                EntryUpdate::new(user_id, password),
-            )
-        },
-    )
+    let new_entry = NewEntry::new(platform, entry.user_id, entry.secret)
+        .with_notes(entry.notes.unwrap_or_default())
+        .with_fields(entry.fields.unwrap_or_default());
+    recover_duplicate_add(repository.add(key, new_entry), || {
+        repository.update(key, EntrySelector::new(platform, entry.user_id), entry)
+    })
 }
 
 #[cfg(test)]
@@ -733,4 +956,42 @@ mod tests {
 
         assert!(matches!(result, Err(StorageError::NotFound)));
     }
+
+    #[test]
+    fn request_urls_are_validated_like_the_cli() {
+        let urls: Vec<EntryUrl> = serde_json::from_str(
+            r#"[{"url":"example.com"},{"url":"https://example.com/admin","match":"starts-with"}]"#,
+        )
+        .unwrap();
+        let urls = validated_urls(urls).unwrap();
+        assert_eq!(urls[0].url, "https://example.com");
+
+        let invalid: Vec<EntryUrl> =
+            serde_json::from_str(r#"[{"url":"(","match":"regex"}]"#).unwrap();
+        let response = validated_urls(invalid).unwrap_err();
+        let value: serde_json::Value = serde_json::from_str(&response).expect("json response");
+        assert_eq!(value["error"]["code"], "invalid_request");
+    }
+
+    #[test]
+    fn stale_updates_report_a_revision_mismatch() {
+        let request: HostRequest = serde_json::from_str(
+            r#"{"type":"update","platform":"github","oldUserId":"alice","newUserId":"alice","password":"secret","expectedRevision":3}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            request,
+            HostRequest::Update {
+                expected_revision: Some(3),
+                ..
+            }
+        ));
+
+        let response = typed_storage_error(StorageError::RevisionMismatch {
+            expected: 3,
+            actual: 4,
+        });
+        let value: serde_json::Value = serde_json::from_str(&response).expect("json response");
+        assert_eq!(value["error"]["code"], "revision_mismatch");
+    }
 }
@@ -3,20 +3,33 @@ mod extension_api;
 mod host;
 mod native;
 
-use crate::cli::{BackupCommands, Cli, Commands};
+use crate::cli::{
+    AuditCommands, BackupCommands, Cli, Commands, KeyslotCommands, KeystoreCommands, MergeKeep,
+    NewKeySlot, ShareCommands, SyncCommands, TrashCommands, VaultCommands,
+};
+use crate::extension_api::record_audit;
 use clap::Parser;
 use std::{
     fs,
     io::{self, Write},
     path::Path,
+    time::Duration,
 };
 
 // Import everything needed from the new library
 use rvault_core::keystore::keystore_path;
 use rvault_core::{
-    SecretKey, SessionKey, backup, clipboard, config, crypto, identity, keystore, portable_export,
-    session, storage,
-    storage::{EntryRepository, EntrySelector, Table},
+    SecretKey, SessionKey, backup, clipboard, config, crypto,
+    domain::{EntryUrl, MatchMode},
+    identity, keystore,
+    kind::TypedSecret,
+    portable_export, session, storage,
+    storage::{
+        AuditEvent, AuditFilter, AuditIntegrity, AuditOperation, AuditSource, BatchOp, CustomField,
+        EntryDetails, EntryMetadata, EntryRepository, EntrySelector, EntryUpdate, MergeEntry,
+        MergeOutcome, MergePlan, NewEntry, SearchHit, StorageError,
+    },
+    totp::TotpSecret,
     vault,
 }; // Special case import for path
 
@@ -58,9 +71,11 @@ fn main() {
     let mut config = config::Config::new().unwrap();
     // The 'Setup' command is special and can be run at any time.
     let is_protected_command = match &command {
-        Commands::Setup {} => {
+        Commands::Setup { unlock_ms } => {
             if config.master_password_hash.is_some() {
-                println!("⚠️ RVault has already been set up. To reset, delete your config file.");
+                println!(
+                    "⚠️ RVault has already been set up. To change the master password, run 'rvault passwd'."
+                );
                 return;
             }
             println!("Setting up RVault for the first time...");
@@ -73,6 +88,21 @@ fn main() {
                 eprintln!("❌ Passwords do not match. Aborting setup.");
                 return;
             }
+            if let Some(unlock_ms) = unlock_ms {
+                config.unlock_target_ms = *unlock_ms;
+            }
+            println!(
+                "Calibrating key derivation for a ~{} ms unlock...",
+                config.unlock_target_ms
+            );
+            let kdf_params =
+                match keystore::calibrate_kdf(Duration::from_millis(config.unlock_target_ms)) {
+                    Ok(params) => params,
+                    Err(e) => {
+                        eprintln!("❌ Calibration failed: {e}");
+                        return;
+                    }
+                };
             let hashed = crypto::hash_data(master_password.as_bytes())
                 .map_err(|e| e.to_string())
                 .unwrap();
@@ -81,9 +111,15 @@ fn main() {
 
             // create keystore file
             let path = keystore_path().unwrap();
-            keystore::create_key_vault(&master_password, &path)
-                .map_err(|e| eprintln!("❌ Keystore create failed: {e}"))
-                .ok();
+            match keystore::create_key_vault_with_params(&master_password, &path, &kdf_params) {
+                Ok(()) => println!(
+                    "✅ RVault is ready (Argon2id t={}, m={} MiB, p={}).",
+                    kdf_params.t,
+                    kdf_params.m / 1024,
+                    kdf_params.p
+                ),
+                Err(e) => eprintln!("❌ Keystore create failed: {e}"),
+            }
             return;
         }
 
@@ -100,20 +136,31 @@ fn main() {
             }
             return;
         }
-        Commands::Unlock {} => {
-            let master_password = rpassword::prompt_password("Enter Master Password: ").unwrap();
+        Commands::Unlock { keyfile } => {
             let Some(stored_hash) = config.master_password_hash.as_ref() else {
                 eprintln!("❌ RVault has not been set up. Please run 'rvault setup' first.");
                 return;
             };
-            // Your existing logic for verifying the password and getting the key is correct.
-            match vault::Vault::get_encryption_key(&master_password, stored_hash) {
+            let unlocked = match keyfile {
+                Some(keyfile) => vault::Vault::get_encryption_key_from_keyfile(Path::new(keyfile)),
+                None => {
+                    let master_password =
+                        rpassword::prompt_password("Enter Master Password: ").unwrap();
+                    vault::Vault::get_encryption_key(&master_password, stored_hash)
+                }
+            };
+            match unlocked {
                 Ok(encryption_key) => {
                     match session::start_session(&encryption_key) {
                         Ok(token) => {
                             session::write_current(&token)
                                 .expect("Failed to write current session file");
                             eprintln!("✅ Vault unlocked."); // Use eprintln for user messages
+                            upgrade_entry_keys(&encryption_key);
+                            record_audit(
+                                &SecretKey::from_bytes(encryption_key),
+                                AuditEvent::new(AuditOperation::Unlock, AuditSource::Cli),
+                            );
                         }
                         Err(e) => eprintln!("❌ Failed to start session: {}", e),
                     }
@@ -122,9 +169,44 @@ fn main() {
             }
             return;
         }
+        Commands::Passwd {} => {
+            if config.master_password_hash.is_none() {
+                eprintln!("❌ RVault has not been set up. Please run 'rvault setup' first.");
+                return;
+            }
+            let current_password =
+                rpassword::prompt_password("Enter current master password: ").unwrap();
+            let new_password = rpassword::prompt_password("Enter new master password: ").unwrap();
+            let new_password_confirm =
+                rpassword::prompt_password("Confirm new master password: ").unwrap();
+            if new_password != new_password_confirm {
+                eprintln!("❌ Passwords do not match. Master password was not changed.");
+                return;
+            }
+            match vault::Vault::change_master_password(&current_password, &new_password) {
+                Ok(()) => println!("✅ Master password changed."),
+                Err(e) => eprintln!("❌ Password change failed: {}", e),
+            }
+            return;
+        }
+        Commands::RotateKey {} => {
+            let Some(stored_hash) = config.master_password_hash.as_deref() else {
+                eprintln!("❌ RVault has not been set up. Please run 'rvault setup' first.");
+                return;
+            };
+            handle_rotate_key_command(stored_hash);
+            return;
+        }
         Commands::Lock {} => {
+            let key = SessionKey::load().ok();
             match session::end_session() {
                 Ok(_) => {
+                    if let Some(key) = key {
+                        record_audit(
+                            &key,
+                            AuditEvent::new(AuditOperation::Lock, AuditSource::Cli),
+                        );
+                    }
                     println!("Vault has been locked.")
                 }
                 Err(e) => eprintln!("Error: {}", e),
@@ -135,6 +217,51 @@ fn main() {
             handle_backup_command(command, &config);
             return;
         }
+        Commands::EmergencyKit { out } => {
+            if config.master_password_hash.is_none() {
+                eprintln!("❌ RVault has not been set up. Please run 'rvault setup' first.");
+                return;
+            }
+            let master_password = rpassword::prompt_password("Enter Master Password: ").unwrap();
+            match rvault_core::emergency_kit::create_emergency_kit(&master_password, Path::new(out))
+            {
+                Ok(id) => {
+                    println!("✅ Emergency kit written to {out} (recovery key slot {id}).");
+                    println!(
+                        "⚠️ Print it or move it offline, then delete the file from this computer."
+                    );
+                }
+                Err(e) => eprintln!("❌ Emergency kit failed: {e}"),
+            }
+            return;
+        }
+        Commands::Recover {} => {
+            if config.master_password_hash.is_none() {
+                eprintln!("❌ RVault has not been set up. Please run 'rvault setup' first.");
+                return;
+            }
+            let recovery_key = rpassword::prompt_password("Enter recovery key: ").unwrap();
+            let new_password = rpassword::prompt_password("Enter new master password: ").unwrap();
+            let new_password_confirm =
+                rpassword::prompt_password("Confirm new master password: ").unwrap();
+            if new_password != new_password_confirm {
+                eprintln!("❌ Passwords do not match. Master password was not changed.");
+                return;
+            }
+            match vault::Vault::recover_master_password(&recovery_key, &new_password) {
+                Ok(()) => println!("✅ Master password reset. Run 'rvault unlock' to continue."),
+                Err(e) => eprintln!("❌ Recovery failed: {e}"),
+            }
+            return;
+        }
+        Commands::Keyslot { command } => {
+            handle_keyslot_command(command);
+            return;
+        }
+        Commands::Keystore { command } => {
+            handle_keystore_command(command);
+            return;
+        }
         Commands::Browser { .. } | Commands::Host { .. } => false,
         _ => true,
     };
@@ -164,63 +291,275 @@ fn main() {
             let _ = EntryRepository::new(&db, vault_name).unwrap();
             println!("Storage created successfully!");
         }
+        Commands::EncryptMetadata { vault } => {
+            let db = storage::Database::new().unwrap();
+            match db.encrypt_metadata(&ek, vault) {
+                Ok(count) => println!("✅ Metadata of {count} entries is now encrypted."),
+                Err(e) => eprintln!("❌ Could not encrypt metadata: {e}"),
+            }
+        }
         Commands::Add {
             vault,
             platform,
             id_and_password,
+            kind,
+            typed,
+            note,
+            fields,
+            secret_fields,
         } => {
-            let db = storage::Database::new().unwrap();
-            if let Ok(repository) = EntryRepository::new(&db, vault) {
-                let Some((user_id, password)) = id_and_password.split_once(':') else {
-                    eprintln!("Error: entry must use USER_ID:PASSWORD format");
+            let typed_secret = match typed.into_secret(kind) {
+                Ok(secret) => secret,
+                Err(e) => {
+                    eprintln!("Error: {e}");
                     return;
+                }
+            };
+            let db = storage::Database::new().unwrap();
+            let event = cli_event(AuditOperation::Add, vault.as_deref());
+            if let Ok(repository) = EntryRepository::unlocked(&db, vault, &ek) {
+                let (user_id, password) = match (&typed_secret, id_and_password.split_once(':')) {
+                    (Some(_), _) => (id_and_password.as_str(), ""),
+                    (None, Some(pair)) => pair,
+                    (None, None) => {
+                        eprintln!("Error: entry must use USER_ID:PASSWORD format");
+                        return;
+                    }
                 };
                 let user_id_owned = user_id.to_string();
-                match extension_api::add_or_update_entry(
-                    &repository,
-                    &ek,
-                    &platform,
-                    user_id,
-                    password.as_bytes(),
-                ) {
-                    Ok(()) => println!(
-                        "Account {} in {} has been added successfully!",
-                        user_id_owned, platform
-                    ),
+                let fields: Vec<CustomField> = fields
+                    .into_iter()
+                    .map(|(name, value)| CustomField::new(name, value, false))
+                    .chain(
+                        secret_fields
+                            .into_iter()
+                            .map(|(name, value)| CustomField::new(name, value, true)),
+                    )
+                    .collect();
+                let result = if let Some(secret) = &typed_secret {
+                    let secret = secret.to_bytes();
+                    let entry = NewEntry::new(&platform, user_id, secret.expose())
+                        .with_kind(kind)
+                        .with_notes(note.as_deref().unwrap_or_default())
+                        .with_fields(&fields);
+                    repository.add(&ek, entry)
+                } else {
+                    let mut entry = EntryUpdate::new(user_id, password.as_bytes());
+                    if let Some(note) = &note {
+                        entry = entry.with_notes(note);
+                    }
+                    if !fields.is_empty() {
+                        entry = entry.with_fields(&fields);
+                    }
+                    extension_api::add_or_update_entry(&repository, &ek, &platform, entry)
+                };
+                match result {
+                    Ok(()) => {
+                        record_audit(&ek, event.for_entry(&platform, &user_id_owned));
+                        println!(
+                            "Account {} in {} has been added successfully!",
+                            user_id_owned, platform
+                        )
+                    }
+                    Err(StorageError::Conflict | StorageError::NotFound)
+                        if repository.trash().is_ok_and(|trash| {
+                            trash.iter().any(|trashed| {
+                                trashed.metadata.platform == platform
+                                    && trashed.metadata.user_id == user_id_owned
+                            })
+                        }) =>
+                    {
+                        eprintln!(
+                            "Error: {user_id_owned} in {platform} is in the trash; restore or purge it first with `rvault trash`"
+                        )
+                    }
                     Err(error) => eprintln!("Error: {error}"),
                 }
             }
         }
+        Commands::Totp {
+            vault,
+            platform,
+            id,
+            set,
+            remove,
+        } => {
+            let db = storage::Database::new().unwrap();
+            let event = cli_event(AuditOperation::Get, vault.as_deref()).for_entry(&platform, &id);
+            match EntryRepository::unlocked(&db, vault, &ek) {
+                Ok(repository) => {
+                    handle_totp_command(&repository, &ek, &platform, &id, set, remove, event)
+                }
+                Err(e) => eprintln!("Error opening vault: {e}"),
+            }
+        }
+        Commands::History {
+            vault,
+            platform,
+            id,
+            copy,
+            restore,
+            depth,
+        } => {
+            let db = storage::Database::new().unwrap();
+            if let Some(depth) = depth {
+                match db.set_history_depth(depth) {
+                    Ok(()) => println!("✅ Keeping up to {depth} previous passwords per entry."),
+                    Err(e) => eprintln!("❌ Could not change history depth: {e}"),
+                }
+                return;
+            }
+            // clap requires both unless --depth was given.
+            let (Some(platform), Some(id)) = (platform, id) else {
+                return;
+            };
+            let event = cli_event(AuditOperation::Get, vault.as_deref()).for_entry(&platform, &id);
+            match EntryRepository::unlocked(&db, vault, &ek) {
+                Ok(repository) => handle_history_command(
+                    &repository,
+                    &ek,
+                    EntrySelector::new(&platform, &id),
+                    copy,
+                    restore,
+                    event,
+                ),
+                Err(e) => eprintln!("Error opening vault: {e}"),
+            }
+        }
+        Commands::List { vault, tag, folder } => {
+            let db = storage::Database::new().unwrap();
+            let entries =
+                EntryRepository::unlocked(&db, vault, &ek).and_then(|repository| match &tag {
+                    Some(tag) => repository.list_tagged(tag),
+                    None => repository.list_metadata(),
+                });
+            match entries {
+                Ok(entries) => print_entry_list(&entries, folder.as_deref()),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        }
+        Commands::Search {
+            vault,
+            all_vaults,
+            query,
+        } => {
+            let db = storage::Database::new().unwrap();
+            let query = query.join(" ");
+            let hits = if all_vaults {
+                db.search_vaults(&ek, &query)
+            } else {
+                EntryRepository::unlocked(&db, vault, &ek).and_then(|repository| {
+                    let hits = repository.search(Some(&ek), &query)?;
+                    Ok(hits.into_iter().map(|hit| (String::new(), hit)).collect())
+                })
+            };
+            match hits {
+                Ok(hits) => print_search_hits(&hits, all_vaults),
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        }
+        Commands::Tag {
+            vault,
+            platform,
+            id,
+            add,
+            remove,
+            folder,
+        } => {
+            let db = storage::Database::new().unwrap();
+            let event =
+                cli_event(AuditOperation::Update, vault.as_deref()).for_entry(&platform, &id);
+            match EntryRepository::unlocked(&db, vault, &ek) {
+                Ok(repository) => handle_tag_command(
+                    &repository,
+                    &ek,
+                    EntrySelector::new(&platform, &id),
+                    &add,
+                    &remove,
+                    folder.as_deref(),
+                    event,
+                ),
+                Err(e) => eprintln!("Error opening vault: {e}"),
+            }
+        }
+        Commands::Url {
+            vault,
+            platform,
+            id,
+            add,
+            mode,
+            remove,
+        } => {
+            let db = storage::Database::new().unwrap();
+            let event =
+                cli_event(AuditOperation::Update, vault.as_deref()).for_entry(&platform, &id);
+            match EntryRepository::unlocked(&db, vault, &ek) {
+                Ok(repository) => handle_url_command(
+                    &repository,
+                    &ek,
+                    EntrySelector::new(&platform, &id),
+                    &add,
+                    mode,
+                    &remove,
+                    event,
+                ),
+                Err(e) => eprintln!("Error opening vault: {e}"),
+            }
+        }
         Commands::Remove {
             vault,
             platform,
             id,
         } => {
             let db = storage::Database::new().unwrap();
-            if let Ok(repository) = EntryRepository::new(&db, vault) {
+            let event =
+                cli_event(AuditOperation::Remove, vault.as_deref()).for_entry(&platform, &id);
+            if let Ok(repository) = EntryRepository::unlocked(&db, vault, &ek) {
                 match repository.remove(EntrySelector::new(&platform, &id)) {
-                    Ok(()) => println!(
-                        "Account {} in {} has been removed successfully!",
-                        id, platform
-                    ),
+                    Ok(()) => {
+                        record_audit(&ek, event);
+                        println!(
+                            "Account {id} in {platform} has been moved to the trash. Undo with `rvault trash restore {platform} {id}`."
+                        )
+                    }
                     Err(error) => eprintln!("Error: {error}"),
                 }
             }
         }
+        Commands::Trash { command } => handle_trash_command(command, &ek),
+        Commands::Vault { command } => handle_vault_command(command, &ek),
+        Commands::Audit { command } => handle_audit_command(command, &ek),
         Commands::Get {
             vault,
             platform,
             id,
+            reveal,
         } => {
             let db = storage::Database::new().unwrap();
-            if let Ok(repository) = EntryRepository::new(&db, vault) {
+            let event = cli_event(AuditOperation::Get, vault.as_deref()).for_entry(&platform, &id);
+            if let Ok(repository) = EntryRepository::unlocked(&db, vault, &ek) {
                 match repository.get(&ek, EntrySelector::new(&platform, &id)) {
-                    Ok(entry) => match std::str::from_utf8(entry.secret.expose()) {
-                        Ok(password) => {
-                            clipboard::copy_text(password.to_string());
-                            println!("Password has been copied! You can use it now.");
+                    Ok(entry) => match entry.typed_secret() {
+                        Ok(Some(secret)) => {
+                            clipboard::copy_text(secret.primary_value().to_string());
+                            record_audit(&ek, event);
+                            println!(
+                                "{} has been copied! You can use it now.",
+                                secret.primary_label()
+                            );
+                            print_typed_secret(&secret, reveal);
+                            print_entry_details(&entry.details, reveal);
                         }
-                        Err(error) => eprintln!("Error: {error}"),
+                        Ok(None) => match std::str::from_utf8(entry.secret.expose()) {
+                            Ok(password) => {
+                                clipboard::copy_text(password.to_string());
+                                record_audit(&ek, event);
+                                println!("Password has been copied! You can use it now.");
+                                print_entry_details(&entry.details, reveal);
+                            }
+                            Err(error) => eprintln!("Error: {error}"),
+                        },
+                        Err(e) => eprintln!("Error: {e}"),
                     },
                     Err(e) => eprintln!("Error: {}", e),
                 }
@@ -239,11 +578,15 @@ fn main() {
         } => match collect_export_selectors(entry, selected) {
             Ok(selectors) => {
                 let db = storage::Database::new().unwrap();
-                match EntryRepository::new(&db, vault) {
+                let event = cli_event(AuditOperation::Export, vault.as_deref());
+                match EntryRepository::unlocked(&db, vault, &ek) {
                     Ok(repository) => match build_export_entries(&repository, &ek, &selectors) {
                         Ok(entries) => match portable_export::create_export_bytes(&to, &entries) {
                             Ok(bytes) => match fs::write(&out, bytes) {
-                                Ok(_) => println!("Encrypted export written to {out}"),
+                                Ok(_) => {
+                                    record_audit(&ek, event);
+                                    println!("Encrypted export written to {out}")
+                                }
                                 Err(e) => eprintln!("Error writing export: {e}"),
                             },
                             Err(e) => eprintln!("Error creating export: {e}"),
@@ -266,11 +609,13 @@ fn main() {
                 return;
             }
             let db = storage::Database::new().unwrap();
-            match Table::new(&db, vault) {
-                Ok(table) => {
-                    match import_entries_from_file(&db, &table, &ek, &path, overwrite_all, skip_all)
+            let event = cli_event(AuditOperation::Import, vault.as_deref());
+            match EntryRepository::unlocked(&db, vault, &ek) {
+                Ok(repository) => {
+                    match import_entries_from_file(&repository, &ek, &path, overwrite_all, skip_all)
                     {
                         Ok((imported, skipped)) => {
+                            record_audit(&ek, event);
                             println!("Imported {imported} entries. Skipped {skipped} entries.")
                         }
                         Err(e) => eprintln!("Error importing export: {e}"),
@@ -279,6 +624,26 @@ fn main() {
                 Err(e) => eprintln!("Error opening vault: {e}"),
             }
         }
+        Commands::Merge {
+            path,
+            keystore,
+            keep,
+        } => match merge_from_file(&ek, Path::new(&path), keystore.as_deref(), keep) {
+            Ok(plan) => {
+                record_audit(&ek, cli_event(AuditOperation::Import, None));
+                println!(
+                    "✅ Merged {path}: {} added, {} updated, {} kept, {} added beside a local entry, {} unchanged.",
+                    plan.count(MergeOutcome::Add),
+                    plan.count(MergeOutcome::TakeOther),
+                    plan.count(MergeOutcome::KeepLocal),
+                    plan.count(MergeOutcome::KeepBoth),
+                    plan.count(MergeOutcome::Unchanged),
+                );
+            }
+            Err(e) => eprintln!("❌ Merge failed: {e}"),
+        },
+        Commands::Sync { command } => handle_sync_command(command, &ek),
+        Commands::Share { command } => handle_share_command(command, &ek),
         _ => todo!(),
     }
 }
@@ -292,12 +657,21 @@ fn handle_backup_command(command: &BackupCommands, config: &config::Config) {
             };
             let master_password =
                 rpassword::prompt_password("Enter Master Password for backup: ").unwrap();
-            if let Err(e) = vault::Vault::get_encryption_key(&master_password, stored_hash) {
-                eprintln!("❌ Backup failed: {e}");
-                return;
-            }
+            let key = match vault::Vault::get_encryption_key(&master_password, stored_hash) {
+                Ok(key) => SecretKey::from_bytes(key),
+                Err(e) => {
+                    eprintln!("❌ Backup failed: {e}");
+                    return;
+                }
+            };
             match backup::create_backup_file(&master_password, Path::new(out)) {
-                Ok(_) => println!("Encrypted backup written to {out}"),
+                Ok(_) => {
+                    record_audit(
+                        &key,
+                        AuditEvent::new(AuditOperation::BackupCreate, AuditSource::Cli),
+                    );
+                    println!("Encrypted backup written to {out}")
+                }
                 Err(e) => eprintln!("❌ Backup failed: {e}"),
             }
         }
@@ -308,21 +682,208 @@ fn handle_backup_command(command: &BackupCommands, config: &config::Config) {
             }
             let master_password = rpassword::prompt_password("Enter backup password: ").unwrap();
             match backup::restore_backup_file(&master_password, Path::new(path)) {
-                Ok(_) => println!("Backup restored. RVault local data was replaced."),
+                Ok(_) => {
+                    // The restored files may hold a different key than before.
+                    if let Some(key) = extension_api::master_key(&master_password) {
+                        record_audit(
+                            &key,
+                            AuditEvent::new(AuditOperation::BackupRestore, AuditSource::Cli),
+                        );
+                    }
+                    println!("Backup restored. RVault local data was replaced.")
+                }
                 Err(e) => eprintln!("❌ Restore failed: {e}"),
             }
         }
     }
 }
 
+fn handle_keyslot_command(command: &KeyslotCommands) {
+    let path = match keystore_path() {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("❌ Error: {e}");
+            return;
+        }
+    };
+    match command {
+        KeyslotCommands::Add { slot } => {
+            let master_password = rpassword::prompt_password("Enter Master Password: ").unwrap();
+            match slot {
+                NewKeySlot::Recovery {} => {
+                    let recovery_key = rvault_core::recovery::RecoveryKey::generate();
+                    match keystore::add_key_slot(
+                        &master_password,
+                        keystore::SlotKind::Recovery,
+                        recovery_key.as_bytes(),
+                        &path,
+                    ) {
+                        Ok(id) => {
+                            println!("✅ Recovery key added as slot {id}.");
+                            println!();
+                            println!("    {}", recovery_key.display().as_str());
+                            println!();
+                            println!(
+                                "⚠️ Write it down and keep it offline. It is shown only once and unlocks the vault like the master password."
+                            );
+                        }
+                        Err(e) => eprintln!("❌ Could not add key slot: {e}"),
+                    }
+                }
+                NewKeySlot::Keyfile { path: keyfile } => {
+                    let keyfile = Path::new(keyfile);
+                    if !keyfile.exists() {
+                        if let Err(e) = keystore::create_keyfile(keyfile) {
+                            eprintln!("❌ {e}");
+                            return;
+                        }
+                        println!("Created keyfile {}", keyfile.display());
+                    }
+                    let result = keystore::keyfile_secret(keyfile).and_then(|secret| {
+                        keystore::add_key_slot(
+                            &master_password,
+                            keystore::SlotKind::Keyfile,
+                            secret.as_slice(),
+                            &path,
+                        )
+                    });
+                    match result {
+                        Ok(id) => println!(
+                            "✅ Keyfile added as slot {id}. Unlock with 'rvault unlock --keyfile {}'.",
+                            keyfile.display()
+                        ),
+                        Err(e) => eprintln!("❌ Could not add key slot: {e}"),
+                    }
+                }
+            }
+        }
+        KeyslotCommands::Remove { id } => {
+            let master_password = rpassword::prompt_password("Enter Master Password: ").unwrap();
+            match keystore::remove_key_slot(&master_password, *id, &path) {
+                Ok(kind) => println!("✅ Removed {} slot {id}.", kind.name()),
+                Err(e) => eprintln!("❌ Could not remove key slot: {e}"),
+            }
+        }
+        KeyslotCommands::List {} => match keystore::list_key_slots(&path) {
+            Ok(slots) => {
+                println!("{:<4} {:<10} KDF", "ID", "KIND");
+                for slot in slots {
+                    println!(
+                        "{:<4} {:<10} argon2id t={} m={} MiB p={}",
+                        slot.id,
+                        slot.kind.name(),
+                        slot.kdf_params.t,
+                        slot.kdf_params.m / 1024,
+                        slot.kdf_params.p
+                    );
+                }
+            }
+            Err(e) => eprintln!("❌ Could not read keystore: {e}"),
+        },
+    }
+}
+
+fn handle_keystore_command(command: &KeystoreCommands) {
+    match command {
+        KeystoreCommands::Info {} => {
+            let path = match keystore_path() {
+                Ok(path) => path,
+                Err(e) => {
+                    eprintln!("❌ Error: {e}");
+                    return;
+                }
+            };
+            match keystore::keystore_format(&path) {
+                Ok(keystore::KeystoreFormat::Legacy) => {
+                    println!("Keystore: {}", path.display());
+                    println!("Format: legacy (upgraded on the next unlock)");
+                    println!("KDF: argon2id with library defaults");
+                }
+                Ok(keystore::KeystoreFormat::Versioned {
+                    version,
+                    kdf_params,
+                }) => {
+                    println!("Keystore: {}", path.display());
+                    println!("Format: versioned (v{version})");
+                    println!("KDF: argon2id");
+                    println!("Iterations (t): {}", kdf_params.t);
+                    println!(
+                        "Memory (m): {} KiB ({} MiB)",
+                        kdf_params.m,
+                        kdf_params.m / 1024
+                    );
+                    println!("Parallelism (p): {}", kdf_params.p);
+                }
+                Err(e) => eprintln!("❌ Could not read keystore: {e}"),
+            }
+        }
+    }
+}
+
+/// Moves entries written by older releases to the current per-entry key scheme.
+fn upgrade_entry_keys(encryption_key: &[u8; 32]) {
+    let Ok(db) = storage::Database::new() else {
+        return;
+    };
+    let legacy = db.legacy_entry_count().unwrap_or(0);
+    if legacy == 0 {
+        return;
+    }
+    eprintln!("Upgrading {legacy} entries to faster per-entry keys. This runs once.");
+    let key = SecretKey::from_bytes(*encryption_key);
+    if let Err(e) = db.upgrade_entry_keys(&key) {
+        eprintln!("⚠️ Entry upgrade failed: {e}. Entries stay readable and upgrade when opened.");
+    }
+}
+
+fn handle_rotate_key_command(stored_hash: &str) {
+    let master_password = rpassword::prompt_password("Enter Master Password: ").unwrap();
+    print!("Every entry will be re-encrypted with a new key. Type ROTATE to continue: ");
+    let _ = io::stdout().flush();
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() || input.trim() != "ROTATE" {
+        println!("Key rotation cancelled.");
+        return;
+    }
+    match vault::Vault::rotate_master_key(&master_password, stored_hash) {
+        Ok(report) => {
+            println!(
+                "✅ Master key rotated. {} entries re-encrypted.",
+                report.entries
+            );
+            for slot in &report.dropped_slots {
+                println!(
+                    "⚠️ Removed {} slot {}; it only unlocked the old key.",
+                    slot.kind.name(),
+                    slot.id
+                );
+            }
+            if !report.dropped_slots.is_empty() {
+                println!(
+                    "   Re-create them with 'rvault emergency-kit' or 'rvault keyslot add'. Old emergency kits no longer work."
+                );
+            }
+            println!("Run 'rvault unlock' to start a new session.");
+        }
+        Err(e) => eprintln!("❌ Key rotation failed: {e}"),
+    }
+}
+
 fn confirm_restore() -> bool {
-    print!("This will replace local RVault data. Type RESTORE to continue: ");
+    confirm_typed(
+        "This will replace local RVault data. Type RESTORE to continue: ",
+        "RESTORE",
+    )
+}
+
+fn confirm_typed(prompt: &str, expected: &str) -> bool {
+    print!("{prompt}");
     let _ = io::stdout().flush();
     let mut input = String::new();
     if io::stdin().read_line(&mut input).is_err() {
         return false;
     }
-    input.trim() == "RESTORE"
+    input.trim() == expected
 }
 
 fn collect_export_selectors(
@@ -354,6 +915,746 @@ fn collect_export_selectors(
     Ok(selectors)
 }
 
+fn handle_totp_command(
+    repository: &EntryRepository<'_>,
+    encryption_key: &SecretKey,
+    platform: &str,
+    id: &str,
+    set: bool,
+    remove: bool,
+    read_event: AuditEvent,
+) {
+    let selector = EntrySelector::new(platform, id);
+    if set {
+        let input = rpassword::prompt_password("TOTP secret or otpauth:// URI: ").unwrap();
+        let totp = match TotpSecret::parse(&input) {
+            Ok(totp) => totp,
+            Err(e) => {
+                eprintln!("❌ {e}");
+                return;
+            }
+        };
+        match repository.set_totp(encryption_key, selector, Some(&totp)) {
+            Ok(()) => {
+                record_audit(
+                    encryption_key,
+                    AuditEvent {
+                        operation: AuditOperation::Update,
+                        ..read_event
+                    },
+                );
+                println!("✅ TOTP secret saved for {id} in {platform}.")
+            }
+            Err(e) => eprintln!("❌ Could not save TOTP secret: {e}"),
+        }
+        return;
+    }
+    if remove {
+        match repository.set_totp(encryption_key, selector, None) {
+            Ok(()) => {
+                record_audit(
+                    encryption_key,
+                    AuditEvent {
+                        operation: AuditOperation::Update,
+                        ..read_event
+                    },
+                );
+                println!("✅ TOTP secret removed from {id} in {platform}.")
+            }
+            Err(e) => eprintln!("❌ Could not remove TOTP secret: {e}"),
+        }
+        return;
+    }
+    match repository.get(encryption_key, selector) {
+        Ok(entry) => match entry.totp {
+            Some(totp) => {
+                let code = totp.code_now();
+                clipboard::copy_text(code.code);
+                record_audit(encryption_key, read_event);
+                println!(
+                    "TOTP code has been copied! It is valid for {} more seconds.",
+                    code.remaining
+                );
+            }
+            None => eprintln!("❌ {id} in {platform} has no TOTP secret. Add one with --set."),
+        },
+        Err(e) => eprintln!("Error: {e}"),
+    }
+}
+
+fn print_entry_list(entries: &[EntryMetadata], folder: Option<&str>) {
+    let entries: Vec<&EntryMetadata> = entries
+        .iter()
+        .filter(|entry| folder.is_none_or(|folder| entry.in_folder(folder)))
+        .collect();
+    if entries.is_empty() {
+        println!("No entries found.");
+        return;
+    }
+    println!(
+        "{:<24} {:<24} {:<9} {:<20} TAGS",
+        "PLATFORM", "ID", "KIND", "FOLDER"
+    );
+    for entry in entries {
+        println!(
+            "{:<24} {:<24} {:<9} {:<20} {}",
+            entry.platform,
+            entry.user_id,
+            entry.kind,
+            entry.folder.as_deref().unwrap_or("/"),
+            entry.tags.join(", ")
+        );
+    }
+}
+
+/// Prints search results, with the vault of each entry when several vaults were searched.
+fn print_search_hits(hits: &[(String, SearchHit)], with_vault: bool) {
+    if hits.is_empty() {
+        println!("No entries found.");
+        return;
+    }
+    if with_vault {
+        print!("{:<20} ", "VAULT");
+    }
+    println!("{:<24} {:<24} {:<20} TAGS", "PLATFORM", "ID", "FOLDER");
+    for (vault, SearchHit { entry, .. }) in hits {
+        if with_vault {
+            print!("{vault:<20} ");
+        }
+        println!(
+            "{:<24} {:<24} {:<20} {}",
+            entry.platform,
+            entry.user_id,
+            entry.folder.as_deref().unwrap_or("/"),
+            entry.tags.join(", ")
+        );
+    }
+}
+
+fn handle_tag_command(
+    repository: &EntryRepository<'_>,
+    encryption_key: &SecretKey,
+    selector: EntrySelector<'_>,
+    add: &[String],
+    remove: &[String],
+    folder: Option<&str>,
+    update_event: AuditEvent,
+) {
+    let (platform, id) = (selector.platform, selector.user_id);
+    let changes = add
+        .iter()
+        .try_for_each(|tag| repository.add_tag(selector, tag))
+        .and_then(|()| {
+            remove
+                .iter()
+                .try_for_each(|tag| repository.remove_tag(selector, tag))
+        })
+        .and_then(|()| match folder {
+            Some(folder) => repository.set_folder(selector, Some(folder)),
+            None => Ok(()),
+        });
+    if let Err(e) = changes {
+        eprintln!("❌ Could not update {id} in {platform}: {e}");
+        return;
+    }
+    if !add.is_empty() || !remove.is_empty() || folder.is_some() {
+        record_audit(encryption_key, update_event);
+    }
+    match repository.get(encryption_key, selector) {
+        Ok(entry) => {
+            println!(
+                "Folder: {}",
+                entry.metadata.folder.as_deref().unwrap_or("/")
+            );
+            if entry.metadata.tags.is_empty() {
+                println!("Tags: none");
+            } else {
+                println!("Tags: {}", entry.metadata.tags.join(", "));
+            }
+        }
+        Err(e) => eprintln!("Error: {e}"),
+    }
+}
+
+fn handle_url_command(
+    repository: &EntryRepository<'_>,
+    encryption_key: &SecretKey,
+    selector: EntrySelector<'_>,
+    add: &[String],
+    mode: MatchMode,
+    remove: &[String],
+    update_event: AuditEvent,
+) {
+    let (platform, id) = (selector.platform, selector.user_id);
+    let mut urls = match repository.get(encryption_key, selector) {
+        Ok(entry) => entry.metadata.urls,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return;
+        }
+    };
+    if !add.is_empty() || !remove.is_empty() {
+        urls.retain(|url| {
+            !remove.iter().any(|removed| {
+                url.url == removed.trim()
+                    || EntryUrl::new(removed, url.mode).is_ok_and(|removed| removed.url == url.url)
+            })
+        });
+        for url in add {
+            match EntryUrl::new(url, mode) {
+                Ok(url) if !urls.contains(&url) => urls.push(url),
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("❌ {e}");
+                    return;
+                }
+            }
+        }
+        if let Err(e) = repository.set_urls(selector, &urls) {
+            eprintln!("❌ Could not update {id} in {platform}: {e}");
+            return;
+        }
+        record_audit(encryption_key, update_event);
+    }
+    if urls.is_empty() {
+        println!("URLs: none");
+    }
+    for url in urls {
+        println!("{} ({})", url.url, url.mode);
+    }
+}
+
+fn handle_history_command(
+    repository: &EntryRepository<'_>,
+    encryption_key: &SecretKey,
+    selector: EntrySelector<'_>,
+    copy: Option<usize>,
+    restore: Option<usize>,
+    read_event: AuditEvent,
+) {
+    let history = match repository.history(encryption_key, selector) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return;
+        }
+    };
+    let (platform, id) = (selector.platform, selector.user_id);
+    if let Some(version) = copy.or(restore) {
+        let Some(entry) = version.checked_sub(1).and_then(|index| history.get(index)) else {
+            eprintln!(
+                "❌ {id} in {platform} has no version {version}. Run `rvault history {platform} {id}` to list them."
+            );
+            return;
+        };
+        if copy.is_some() {
+            clipboard::copy_text(String::from_utf8_lossy(entry.secret.expose()).into_owned());
+            record_audit(encryption_key, read_event);
+            println!("Version {version} has been copied!");
+            return;
+        }
+        match repository.restore_history(encryption_key, selector, entry.id) {
+            Ok(()) => {
+                record_audit(
+                    encryption_key,
+                    AuditEvent {
+                        operation: AuditOperation::Update,
+                        ..read_event
+                    },
+                );
+                println!(
+                    "✅ Restored version {version} of {id} in {platform}. The replaced password is now version 1."
+                )
+            }
+            Err(e) => eprintln!("❌ Could not restore version {version}: {e}"),
+        }
+        return;
+    }
+    if history.is_empty() {
+        println!("{id} in {platform} has no previous passwords.");
+        return;
+    }
+    println!("Previous passwords of {id} in {platform}, newest first:");
+    for (index, entry) in history.iter().enumerate() {
+        println!(
+            "  {}. replaced {}",
+            index + 1,
+            local_time(entry.replaced_at)
+        );
+    }
+    println!("Copy one with --copy N or make it current again with --restore N.");
+}
+
+fn handle_trash_command(command: TrashCommands, encryption_key: &SecretKey) {
+    let db = storage::Database::new().unwrap();
+    let open = |vault| {
+        EntryRepository::unlocked(&db, vault, encryption_key)
+            .map_err(|e| eprintln!("Error opening vault: {e}"))
+            .ok()
+    };
+    match command {
+        TrashCommands::List { vault } => {
+            let Some(repository) = open(vault) else {
+                return;
+            };
+            match repository.trash() {
+                Ok(entries) if entries.is_empty() => println!("The trash is empty."),
+                Ok(entries) => {
+                    println!("{:<24} {:<24} REMOVED", "PLATFORM", "ID");
+                    for entry in entries {
+                        println!(
+                            "{:<24} {:<24} {}",
+                            entry.metadata.platform,
+                            entry.metadata.user_id,
+                            local_time(entry.deleted_at)
+                        );
+                    }
+                }
+                Err(e) => eprintln!("Error: {e}"),
+            }
+        }
+        TrashCommands::Restore {
+            vault,
+            platform,
+            id,
+        } => {
+            let Some(repository) = open(vault) else {
+                return;
+            };
+            match repository.restore_trashed(EntrySelector::new(&platform, &id)) {
+                Ok(()) => println!("✅ Restored {id} in {platform}."),
+                Err(e) => eprintln!("❌ Could not restore {id} in {platform}: {e}"),
+            }
+        }
+        TrashCommands::Purge {
+            vault,
+            platform,
+            id,
+            ..
+        } => {
+            let Some(repository) = open(vault) else {
+                return;
+            };
+            // clap requires both unless --all was given.
+            match (platform, id) {
+                (Some(platform), Some(id)) => {
+                    match repository.purge_trashed(EntrySelector::new(&platform, &id)) {
+                        Ok(()) => println!("✅ Permanently deleted {id} in {platform}."),
+                        Err(e) => eprintln!("❌ Could not purge {id} in {platform}: {e}"),
+                    }
+                }
+                _ => match repository.empty_trash() {
+                    Ok(count) => println!("✅ Permanently deleted {count} entries."),
+                    Err(e) => eprintln!("❌ Could not empty the trash: {e}"),
+                },
+            }
+        }
+        TrashCommands::Retention { days } => {
+            let result = match days {
+                Some(days) => db.set_trash_retention_days(days).map(|()| days),
+                None => db.trash_retention_days(),
+            };
+            match result {
+                Ok(0) => println!("Removed entries are kept until purged."),
+                Ok(days) => println!("Removed entries are purged after {days} days."),
+                Err(e) => eprintln!("❌ Could not access the trash retention: {e}"),
+            }
+        }
+    }
+}
+
+fn handle_vault_command(command: VaultCommands, encryption_key: &SecretKey) {
+    let db = storage::Database::new().unwrap();
+    match command {
+        VaultCommands::List {} => match db.vaults() {
+            Ok(vaults) => {
+                println!("{:<24} {:>8}  MODIFIED", "NAME", "ENTRIES");
+                for vault in vaults {
+                    let marker = match (vault.shared, vault.encrypted_metadata) {
+                        (true, true) => "  (shared, encrypted metadata)",
+                        (true, false) => "  (shared)",
+                        (false, true) => "  (encrypted metadata)",
+                        (false, false) => "",
+                    };
+                    println!(
+                        "{:<24} {:>8}  {}{marker}",
+                        vault.name,
+                        vault.entries,
+                        local_time(vault.modified_at)
+                    );
+                }
+            }
+            Err(e) => eprintln!("❌ Could not list the vaults: {e}"),
+        },
+        VaultCommands::Rename { name, new_name } => match db.rename_vault(&name, &new_name) {
+            Ok(()) => println!("✅ Renamed vault {name} to {new_name}."),
+            Err(e) => eprintln!("❌ Could not rename vault {name}: {e}"),
+        },
+        VaultCommands::Delete { name, yes } => {
+            let prompt = format!(
+                "This will permanently delete vault {name} and every entry in it. Type {name} to continue: "
+            );
+            if !yes && !confirm_typed(&prompt, &name) {
+                println!("Delete cancelled.");
+                return;
+            }
+            match db.delete_vault(&name) {
+                Ok(()) => println!("✅ Deleted vault {name}."),
+                Err(e) => eprintln!("❌ Could not delete vault {name}: {e}"),
+            }
+        }
+        VaultCommands::Copy { name, new_name } => match db.copy_vault(&name, &new_name) {
+            Ok(count) => println!("✅ Copied {count} entries from {name} to {new_name}."),
+            Err(e) => eprintln!("❌ Could not copy vault {name}: {e}"),
+        },
+        VaultCommands::MoveEntry {
+            vault,
+            platform,
+            id,
+            to,
+        } => {
+            let repositories =
+                EntryRepository::unlocked(&db, vault, encryption_key).and_then(|source| {
+                    let target = EntryRepository::unlocked(&db, Some(to.clone()), encryption_key)?;
+                    Ok((source, target))
+                });
+            let result = repositories.and_then(|(source, target)| {
+                source.move_to(encryption_key, EntrySelector::new(&platform, &id), &target)
+            });
+            match result {
+                Ok(()) => println!("✅ Moved {id} in {platform} to vault {to}."),
+                Err(e) => eprintln!("❌ Could not move {id} in {platform}: {e}"),
+            }
+        }
+    }
+}
+
+fn handle_sync_command(command: SyncCommands, encryption_key: &SecretKey) {
+    let db = storage::Database::new().unwrap();
+    match command {
+        SyncCommands::Now { dir } => {
+            if let Some(dir) = dir
+                && let Err(e) = db.set_sync_folder(Path::new(&dir))
+            {
+                eprintln!("❌ Could not set the sync folder: {e}");
+                return;
+            }
+            match db.sync_folder() {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    eprintln!(
+                        "❌ No sync folder is set. Run `rvault sync now --dir <PATH>` first."
+                    );
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("❌ Sync failed: {e}");
+                    return;
+                }
+            }
+            match db.sync_now(encryption_key) {
+                Ok(report) => {
+                    if report.applied > 0 {
+                        record_audit(encryption_key, cli_event(AuditOperation::Import, None));
+                    }
+                    println!(
+                        "✅ Synced: {} local changes sent, {} received, {} applied.",
+                        report.sent, report.received, report.applied
+                    );
+                }
+                Err(e) => eprintln!("❌ Sync failed: {e}"),
+            }
+        }
+        SyncCommands::Status {} => match db.sync_status(encryption_key) {
+            Ok(status) => {
+                println!("Device: {}", status.device_id);
+                match &status.folder {
+                    Some(folder) => println!("Folder: {}", folder.display()),
+                    None => println!("Folder: not set"),
+                }
+                println!("Unsynced local changes: {}", status.pending);
+                if status.devices.is_empty() {
+                    println!("No other devices have synced yet.");
+                }
+                for device in status.devices {
+                    println!(
+                        "Device {}: {} operations, {} waiting",
+                        device.device_id,
+                        device.operations,
+                        device.operations.saturating_sub(device.replayed)
+                    );
+                }
+            }
+            Err(e) => eprintln!("❌ Could not read the sync status: {e}"),
+        },
+        SyncCommands::Stop {} => match db.stop_sync() {
+            Ok(()) => println!("✅ This machine no longer syncs through a folder or git."),
+            Err(e) => eprintln!("❌ Could not stop syncing: {e}"),
+        },
+        SyncCommands::Git { remote } => {
+            if let Some(remote) = remote
+                && let Err(e) = db.set_git_sync_remote(&remote)
+            {
+                eprintln!("❌ Could not set the git remote: {e}");
+                return;
+            }
+            match db.git_sync_remote() {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    eprintln!(
+                        "❌ No git remote is set. Run `rvault sync git --remote <PATH_OR_URL>` first."
+                    );
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("❌ Git sync failed: {e}");
+                    return;
+                }
+            }
+            let checkout = match storage::database_path() {
+                Ok(path) => path.with_file_name("git-sync"),
+                Err(e) => {
+                    eprintln!("❌ Git sync failed: {e}");
+                    return;
+                }
+            };
+            match db.sync_git(encryption_key, &checkout) {
+                Ok(report) => {
+                    if report.pulled > 0 {
+                        record_audit(encryption_key, cli_event(AuditOperation::Import, None));
+                    }
+                    println!(
+                        "✅ Synced with git: {} entries pulled, {} pushed, {} changed on both sides.",
+                        report.pulled, report.pushed, report.conflicts
+                    );
+                }
+                Err(e) => eprintln!("❌ Git sync failed: {e}"),
+            }
+        }
+    }
+}
+
+fn handle_share_command(command: ShareCommands, encryption_key: &SecretKey) {
+    let db = storage::Database::new().unwrap();
+    let identity = || {
+        identity::load_or_create_identity(encryption_key.as_bytes())
+            .map_err(|e| eprintln!("❌ Could not load your identity: {e}"))
+    };
+    match command {
+        ShareCommands::AddMember {
+            vault,
+            folder,
+            code,
+        } => {
+            let Ok(identity) = identity() else {
+                return;
+            };
+            let folder = folder.as_deref().map(Path::new);
+            match db.add_vault_member(vault.as_deref(), encryption_key, &identity, &code, folder) {
+                Ok(()) => {
+                    // Wrapping the vault key hands every entry in it to the new member.
+                    record_audit(
+                        encryption_key,
+                        cli_event(AuditOperation::Export, vault.as_deref()),
+                    );
+                    println!("✅ Added {code} to the vault.");
+                }
+                Err(e) => eprintln!("❌ Could not add {code}: {e}"),
+            }
+        }
+        ShareCommands::RemoveMember { vault, code } => {
+            let Ok(identity) = identity() else {
+                return;
+            };
+            match db.remove_vault_member(vault.as_deref(), encryption_key, &identity, &code) {
+                Ok(count) => {
+                    println!(
+                        "✅ Removed {code}. The vault key was rotated and {count} entries sent again under it."
+                    );
+                    println!("Remaining members pick up the new key with `rvault share sync`.");
+                }
+                Err(e) => eprintln!("❌ Could not remove {code}: {e}"),
+            }
+        }
+        ShareCommands::Join { vault, folder } => {
+            let Ok(identity) = identity() else {
+                return;
+            };
+            match db.join_vault(&vault, encryption_key, &identity, Path::new(&folder)) {
+                Ok(report) => {
+                    if report.applied > 0 {
+                        record_audit(
+                            encryption_key,
+                            cli_event(AuditOperation::Import, Some(&vault)),
+                        );
+                    }
+                    println!("✅ Joined {vault} with {} entries.", report.applied);
+                    println!(
+                        "Compare the owner's code from `rvault share list-members` with them."
+                    );
+                }
+                Err(e) => eprintln!("❌ Could not join the vault: {e}"),
+            }
+        }
+        ShareCommands::Sync { vault } => {
+            let Ok(identity) = identity() else {
+                return;
+            };
+            let names = match vault {
+                Some(vault) => vec![vault],
+                None => match db.vaults() {
+                    Ok(vaults) => vaults
+                        .into_iter()
+                        .filter(|vault| vault.shared)
+                        .map(|vault| vault.name)
+                        .collect(),
+                    Err(e) => {
+                        eprintln!("❌ Could not list the vaults: {e}");
+                        return;
+                    }
+                },
+            };
+            if names.is_empty() {
+                println!("No vault is shared.");
+            }
+            for name in names {
+                match db.sync_shared_vault(Some(&name), encryption_key, &identity) {
+                    Ok(report) => {
+                        if report.applied > 0 {
+                            record_audit(
+                                encryption_key,
+                                cli_event(AuditOperation::Import, Some(&name)),
+                            );
+                        }
+                        println!(
+                            "✅ Synced {name}: {} local changes sent, {} received, {} applied.",
+                            report.sent, report.received, report.applied
+                        );
+                    }
+                    Err(e) => eprintln!("❌ Could not sync {name}: {e}"),
+                }
+            }
+        }
+        ShareCommands::ListMembers { vault } => {
+            match db.vault_members(vault.as_deref(), encryption_key) {
+                Ok(members) if members.is_empty() => println!("This vault is not shared."),
+                Ok(members) => {
+                    println!("{:<52} ADDED", "PUBLIC CODE");
+                    for (index, member) in members.iter().enumerate() {
+                        let owner = if index == 0 { " (owner)" } else { "" };
+                        println!(
+                            "{:<52} {}{owner}",
+                            member.public_code,
+                            local_time(member.added_at)
+                        );
+                    }
+                }
+                Err(e) => eprintln!("❌ Could not list the members: {e}"),
+            }
+        }
+    }
+}
+
+fn handle_audit_command(command: AuditCommands, encryption_key: &SecretKey) {
+    let db = storage::Database::new().unwrap();
+    match command {
+        AuditCommands::Log {
+            since,
+            operation,
+            platform,
+        } => {
+            let since = since.and_then(|day| {
+                day.and_hms_opt(0, 0, 0)?
+                    .and_local_timezone(chrono::Local)
+                    .earliest()
+                    .map(|start| start.timestamp())
+            });
+            let filter = AuditFilter {
+                since,
+                operation,
+                platform,
+            };
+            match db.audit_log(encryption_key, &filter) {
+                Ok(records) if records.is_empty() => println!("No audit records found."),
+                Ok(records) => {
+                    println!(
+                        "{:<22} {:<16} {:<8} {:<12} ENTRY",
+                        "TIME", "OPERATION", "SOURCE", "VAULT"
+                    );
+                    for record in records {
+                        let event = record.event;
+                        let entry = match (event.platform, event.user_id) {
+                            (Some(platform), Some(user_id)) => format!("{platform} / {user_id}"),
+                            _ => String::new(),
+                        };
+                        println!(
+                            "{:<22} {:<16} {:<8} {:<12} {entry}",
+                            local_time(record.at),
+                            event.operation,
+                            event.source,
+                            event.vault.as_deref().unwrap_or("default"),
+                        );
+                    }
+                }
+                Err(e) => eprintln!("❌ Could not read the audit log: {e}"),
+            }
+        }
+        AuditCommands::Verify {} => match db.verify_audit_log(encryption_key) {
+            Ok(AuditIntegrity::Intact { records }) => {
+                println!("✅ The audit log is intact ({records} records).")
+            }
+            Ok(AuditIntegrity::Broken { record_id }) => {
+                eprintln!("❌ The audit log was tampered with at record {record_id}.")
+            }
+            Ok(AuditIntegrity::Truncated { records }) => eprintln!(
+                "❌ Records were deleted from the end of the audit log; {records} remain."
+            ),
+            Err(e) => eprintln!("❌ Could not verify the audit log: {e}"),
+        },
+    }
+}
+
+fn cli_event(operation: AuditOperation, vault: Option<&str>) -> AuditEvent {
+    AuditEvent::new(operation, AuditSource::Cli).in_vault(vault)
+}
+
+fn local_time(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .with_timezone(&chrono::Local)
+        .format("%B %d %Y %H:%M")
+        .to_string()
+}
+
+fn print_typed_secret(secret: &TypedSecret, reveal: bool) {
+    println!("Kind: {}", secret.kind());
+    for (label, value, masked) in secret.fields() {
+        let value = if masked && !reveal {
+            "•••••• (use --reveal to show)"
+        } else {
+            &value
+        };
+        println!("{label}: {value}");
+    }
+    if secret.is_expired(chrono::Local::now().date_naive()) {
+        println!("⚠️ This {} has expired.", secret.kind());
+    }
+}
+
+fn print_entry_details(details: &EntryDetails, reveal: bool) {
+    if !details.notes.is_empty() {
+        println!("Notes: {}", details.notes);
+    }
+    for field in &details.fields {
+        let value = if field.secret && !reveal {
+            "•••••• (use --reveal to show)"
+        } else {
+            &field.value
+        };
+        println!("{}: {value}", field.name);
+    }
+}
+
 fn build_export_entries(
     repository: &EntryRepository<'_>,
     encryption_key: &SecretKey,
@@ -375,15 +1676,17 @@ fn build_export_entries(
                 pinned: entry.metadata.pinned,
                 created_at: entry.metadata.created_at,
                 updated_at: entry.metadata.updated_at,
+                notes: entry.details.notes.clone(),
+                fields: entry.details.fields.clone(),
+                kind: entry.metadata.kind,
             })
         })
         .collect()
 }
 
-#[allow(deprecated)] // 1.4 import boundary: preserves imported timestamps and pin state.
+/// Imports the entries of an export file in one batch, so a failure imports none of them.
 fn import_entries_from_file(
-    db: &storage::Database,
-    table: &Table,
+    repository: &EntryRepository<'_>,
     encryption_key: &SecretKey,
     path: &str,
     overwrite_all: bool,
@@ -392,12 +1695,12 @@ fn import_entries_from_file(
     let identity = identity::load_or_create_identity(encryption_key.as_bytes())?;
     let bytes = fs::read(path).map_err(|e| format!("read export: {e}"))?;
     let entries = portable_export::decrypt_export_bytes(&identity, &bytes)?;
-    let mut imported = 0;
+    let mut selected = Vec::new();
     let mut skipped = 0;
 
-    for entry in entries {
-        let exists = table
-            .entry_exists(db, &entry.platform, &entry.user_id)
+    for entry in &entries {
+        let exists = repository
+            .contains(EntrySelector::new(&entry.platform, &entry.user_id))
             .map_err(|e| e.to_string())?;
         let should_import = if exists {
             if skip_all {
@@ -405,7 +1708,7 @@ fn import_entries_from_file(
             } else if overwrite_all {
                 true
             } else {
-                match prompt_import_conflict(&entry)? {
+                match prompt_import_conflict(entry)? {
                     ImportChoice::Overwrite => true,
                     ImportChoice::Skip => false,
                     ImportChoice::Cancel => return Err("import cancelled".to_string()),
@@ -416,16 +1719,159 @@ fn import_entries_from_file(
         };
 
         if should_import {
-            table
-                .import_entry_with_key_result(db, encryption_key.as_bytes(), &entry)
-                .map_err(|e| e.to_string())?;
-            imported += 1;
+            selected.push(entry);
         } else {
             skipped += 1;
         }
     }
 
-    Ok((imported, skipped))
+    let report = repository
+        .apply_batch(
+            encryption_key,
+            selected.iter().map(|entry| BatchOp::Import(entry)),
+        )
+        .map_err(|e| e.to_string())?;
+    if let Some((index, error)) = report.first_failure() {
+        let entry = selected[index];
+        return Err(format!(
+            "{} / {}: {error}; nothing was imported",
+            entry.platform, entry.user_id
+        ));
+    }
+    Ok((selected.len(), skipped))
+}
+
+/// Merges the database at `path`, or the database inside the backup at `path`, into the local
+/// one. A database with another master key is unlocked through its `keystore`. Both are read
+/// from a temporary copy, so the other file is never migrated or written to.
+fn merge_from_file(
+    key: &SecretKey,
+    path: &Path,
+    keystore: Option<&str>,
+    keep: Option<MergeKeep>,
+) -> Result<MergePlan, String> {
+    let local_path = storage::database_path().map_err(|e| e.to_string())?;
+    if fs::canonicalize(path).ok() == fs::canonicalize(&local_path).ok() {
+        return Err("cannot merge the vault database into itself".to_string());
+    }
+    let bytes = fs::read(path).map_err(|e| format!("read {}: {e}", path.display()))?;
+    let db = storage::Database::new().map_err(|e| e.to_string())?;
+    // The directory is private to this user and removed with the copy when it is dropped, on
+    // every return path.
+    let dir = tempfile::Builder::new()
+        .prefix("rvault-merge-")
+        .tempdir()
+        .map_err(|e| format!("create temporary directory: {e}"))?;
+    let copy = dir.path().join("other.sqlite");
+    if backup::validate_backup_envelope(&bytes).is_ok() {
+        let password = rpassword::prompt_password("Enter backup password: ")
+            .map_err(|e| format!("read password: {e}"))?;
+        backup::extract_backup_database(&password, &bytes, &copy).and_then(|other_key| {
+            storage::Database::open(&copy)
+                .map_err(|e| e.to_string())
+                .and_then(|other| merge_databases(&db, key, &other, &other_key, keep))
+        })
+    } else {
+        merge_from_database(&db, key, path, &copy, keystore, keep)
+    }
+}
+
+fn merge_from_database(
+    db: &storage::Database,
+    key: &SecretKey,
+    path: &Path,
+    copy: &Path,
+    keystore: Option<&str>,
+    keep: Option<MergeKeep>,
+) -> Result<MergePlan, String> {
+    let other_key = match keystore {
+        Some(keystore) => {
+            let password =
+                rpassword::prompt_password("Enter the other keystore's master password: ")
+                    .map_err(|e| format!("read password: {e}"))?;
+            Some(SecretKey::from_bytes(keystore::load_key_from_vault(
+                &password,
+                Path::new(keystore),
+            )?))
+        }
+        None => None,
+    };
+    let other = storage::Database::open_copy(path, copy).map_err(|e| e.to_string())?;
+    merge_databases(db, key, &other, other_key.as_ref().unwrap_or(key), keep)
+}
+
+fn merge_databases(
+    db: &storage::Database,
+    key: &SecretKey,
+    other: &storage::Database,
+    other_key: &SecretKey,
+    keep: Option<MergeKeep>,
+) -> Result<MergePlan, String> {
+    let mut plan = db
+        .plan_merge(key, other, other_key)
+        .map_err(|e| format!("read entries: {e}"))?;
+    for entry in plan.conflicts_mut() {
+        // An entry cannot change its kind, so a different kind is kept beside it.
+        let same_kind = entry
+            .local
+            .as_ref()
+            .is_some_and(|local| local.metadata.kind == entry.other.metadata.kind);
+        entry.outcome = match keep {
+            Some(MergeKeep::Local) => MergeOutcome::KeepLocal,
+            Some(MergeKeep::Other) if same_kind => MergeOutcome::TakeOther,
+            Some(MergeKeep::Other | MergeKeep::Both) => MergeOutcome::KeepBoth,
+            None => prompt_merge_conflict(entry, same_kind)?,
+        };
+    }
+    db.apply_merge(key, &plan)
+        .map_err(|e| format!("{e}; nothing was merged"))?;
+    Ok(plan)
+}
+
+fn prompt_merge_conflict(entry: &MergeEntry, same_kind: bool) -> Result<MergeOutcome, String> {
+    let other = &entry.other.metadata;
+    println!(
+        "Entry {} / {} in vault {} was changed in both databases.",
+        other.platform, other.user_id, entry.vault
+    );
+    if let Some(local) = &entry.local {
+        if local.secret.expose() == entry.other.secret.expose() {
+            println!("  the passwords match; notes, fields, TOTP secrets or URLs differ");
+        }
+        let local = &local.metadata;
+        println!(
+            "  local: {} entry updated {}, revision {}",
+            local.kind,
+            local_time(local.updated_at),
+            local.revision
+        );
+    }
+    println!(
+        "  other: {} entry updated {}, revision {}",
+        other.kind,
+        local_time(other.updated_at),
+        other.revision
+    );
+    let choices = if same_kind {
+        "[l]ocal, [o]ther, [b]oth, [c]ancel"
+    } else {
+        "[l]ocal, [b]oth, [c]ancel"
+    };
+    loop {
+        print!("Keep {choices}: ");
+        let _ = io::stdout().flush();
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .map_err(|e| format!("read conflict choice: {e}"))?;
+        match input.trim().to_lowercase().as_str() {
+            "l" | "local" => return Ok(MergeOutcome::KeepLocal),
+            "o" | "other" if same_kind => return Ok(MergeOutcome::TakeOther),
+            "b" | "both" => return Ok(MergeOutcome::KeepBoth),
+            "c" | "cancel" => return Err("merge cancelled; nothing was merged".to_string()),
+            _ => eprintln!("Please enter one of {choices}."),
+        }
+    }
 }
 
 enum ImportChoice {
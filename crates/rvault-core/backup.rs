@@ -3,7 +3,8 @@ use crate::{
     config::config_path,
     crypto::{decrypt_bytes_with_key, derive_key, encrypt_bytes_with_key},
     identity::identity_path,
-    keystore::keystore_path,
+    keystore::{keystore_path, load_key_from_keystore_bytes},
+    secret::SecretKey,
     storage::database_path,
 };
 use chrono::{DateTime, Utc};
@@ -59,6 +60,31 @@ pub fn decrypt_backup_bytes(master_password: &str, bytes: &[u8]) -> Result<Backu
     decode_backup_payload(&payload_bytes)
 }
 
+/// Writes the database inside a backup to `path`, which must not exist yet and is made readable
+/// by its owner only, and returns the master encryption key of the backup's keystore. The
+/// database can then be opened with [`crate::storage::Database::open`], for example to merge it.
+pub fn extract_backup_database(
+    master_password: &str,
+    bytes: &[u8],
+    path: &Path,
+) -> Result<SecretKey, String> {
+    let payload = decrypt_backup_bytes(master_password, bytes)?;
+    let key = load_key_from_keystore_bytes(master_password, &payload.keystore)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("create {}: {e}", path.display()))?;
+    file.write_all(&payload.database)
+        .map_err(|e| format!("write {}: {e}", path.display()))?;
+    Ok(SecretKey::from_bytes(key))
+}
+
 pub fn validate_backup_envelope(bytes: &[u8]) -> Result<(), String> {
     decode_envelope(bytes, BACKUP_MAGIC).map(|_| ())
 }
@@ -187,6 +213,11 @@ fn restore_payload_to_targets(
     payload: &BackupPayload,
     targets: &RestoreTargets,
 ) -> Result<(), String> {
+    // The database runs in WAL mode; pages still in its write-ahead log would otherwise be
+    // replayed over the restored file.
+    if targets.database.exists() {
+        sqlite_snapshot::checkpoint_database(&targets.database)?;
+    }
     let suffix = restore_temp_suffix();
     let restore_items = vec![
         (targets.config.clone(), Some(payload.config.as_slice())),
@@ -431,6 +462,92 @@ mod tests {
         assert_eq!(restored, payload());
     }
 
+    #[test]
+    fn backup_database_snapshot_keeps_typed_entries() {
+        use crate::kind::{ApiKey, EntryKind, TypedSecret};
+        use crate::secret::SecretKey;
+        use crate::storage::{Database, EntryRepository, EntrySelector, NewEntry};
+
+        let root =
+            std::env::temp_dir().join(format!("rvault-backup-kind-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("create test root");
+        let source = root.join("source.sqlite");
+        let restored = root.join("restored.sqlite");
+        let key = SecretKey::from_bytes([7; 32]);
+        let token = TypedSecret::ApiKey(ApiKey::new("t0k3n", None).expect("valid API key"));
+        {
+            let db = Database::open(&source).expect("open source database");
+            EntryRepository::new(&db, None)
+                .expect("open repository")
+                .add(
+                    &key,
+                    NewEntry::new("ci", "deploy", token.to_bytes().expose())
+                        .with_kind(EntryKind::ApiKey),
+                )
+                .expect("add typed entry");
+        }
+        let mut payload = payload();
+        payload.database = sqlite_snapshot::snapshot_database(&source).expect("snapshot");
+
+        let backup =
+            create_backup_bytes("correct horse battery staple", &payload).expect("create backup");
+        let decrypted =
+            decrypt_backup_bytes("correct horse battery staple", &backup).expect("decrypt backup");
+        fs::write(&restored, &decrypted.database).expect("write restored database");
+
+        let db = Database::open(&restored).expect("open restored database");
+        let entry = EntryRepository::new(&db, None)
+            .expect("open repository")
+            .get(&key, EntrySelector::new("ci", "deploy"))
+            .expect("typed entry restored");
+        assert_eq!(entry.metadata.kind, EntryKind::ApiKey);
+        assert_eq!(entry.typed_secret().expect("typed secret"), Some(token));
+
+        drop(db);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn extracted_backup_database_opens_with_the_backup_key() {
+        use crate::keystore::{create_key_vault, load_key_from_vault};
+        use crate::storage::{Database, EntryRepository, EntrySelector, NewEntry};
+
+        let root =
+            std::env::temp_dir().join(format!("rvault-backup-extract-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("create test root");
+        let keystore = root.join("keystore.rvault");
+        let source = root.join("source.sqlite");
+        let extracted = root.join("extracted.sqlite");
+        create_key_vault("password", &keystore).expect("create keystore");
+        let key = SecretKey::from_bytes(load_key_from_vault("password", &keystore).unwrap());
+        {
+            let db = Database::open(&source).expect("open source database");
+            EntryRepository::new(&db, None)
+                .expect("open repository")
+                .add(&key, NewEntry::new("github", "alice", b"secret"))
+                .expect("add entry");
+        }
+        let mut payload = payload();
+        payload.keystore = fs::read(&keystore).expect("read keystore");
+        payload.database = sqlite_snapshot::snapshot_database(&source).expect("snapshot");
+        let backup = create_backup_bytes("password", &payload).expect("create backup");
+
+        assert!(extract_backup_database("wrong", &backup, &extracted).is_err());
+        let key = extract_backup_database("password", &backup, &extracted).expect("extract");
+        assert!(extract_backup_database("password", &backup, &extracted).is_err());
+        let db = Database::open(&extracted).expect("open extracted database");
+        let entry = EntryRepository::new(&db, None)
+            .expect("open repository")
+            .get(&key, EntrySelector::new("github", "alice"))
+            .expect("entry extracted");
+        assert_eq!(entry.secret.expose(), b"secret");
+
+        drop(db);
+        let _ = fs::remove_dir_all(&root);
+    }
+
     #[test]
     fn backup_bytes_reject_wrong_password() {
         let backup =
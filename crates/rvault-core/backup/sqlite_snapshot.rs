@@ -39,6 +39,25 @@ pub(super) fn snapshot_database(source_path: &Path) -> Result<Vec<u8>, String> {
     }
 }
 
+/// Moves every page of the database's write-ahead log into the database file and empties the
+/// log.
+pub(super) fn checkpoint_database(path: &Path) -> Result<(), String> {
+    let connection =
+        Connection::open(path).map_err(|error| format!("open database for checkpoint: {error}"))?;
+    connection
+        .busy_timeout(Duration::from_secs(5))
+        .and_then(|()| {
+            connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                row.get::<_, i64>(0)
+            })
+        })
+        .map_err(|error| format!("checkpoint database: {error}"))
+        .and_then(|busy| match busy {
+            0 => Ok(()),
+            _ => Err("checkpoint database: the database is in use".to_string()),
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -72,4 +91,25 @@ mod tests {
         drop(source);
         fs::remove_dir_all(root).unwrap();
     }
+
+    #[test]
+    fn checkpoint_empties_the_write_ahead_log() {
+        let root =
+            std::env::temp_dir().join(format!("rvault-backup-test-{}", rand::random::<u64>()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("source.sqlite");
+        let source = Connection::open(&path).unwrap();
+        source.pragma_update(None, "journal_mode", "WAL").unwrap();
+        source
+            .execute("CREATE TABLE entries (value TEXT NOT NULL)", [])
+            .unwrap();
+        let wal = root.join("source.sqlite-wal");
+        assert!(fs::metadata(&wal).unwrap().len() > 0);
+
+        checkpoint_database(&path).unwrap();
+
+        assert_eq!(fs::metadata(&wal).unwrap().len(), 0);
+        drop(source);
+        fs::remove_dir_all(root).unwrap();
+    }
 }
@@ -1,6 +1,7 @@
 pub const BACKUP_MAGIC: &[u8; 4] = b"RVBK";
 pub const EXPORT_MAGIC: &[u8; 4] = b"RVEX";
 pub const IDENTITY_MAGIC: &[u8; 4] = b"RVID";
+pub const VAULT_KEY_MAGIC: &[u8; 4] = b"RVVK";
 pub const ENVELOPE_VERSION: u8 = 1;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -14,11 +14,18 @@ pub struct Config {
     pub session_timeout: String,
     #[serde(default = "default_theme")]
     pub theme: String,
+    /// Unlock time, in milliseconds, that setup calibrates the keystore KDF for.
+    #[serde(default = "default_unlock_target_ms")]
+    pub unlock_target_ms: u64,
 }
 
 fn default_theme() -> String {
     "Catppuccin".to_string()
 }
+
+fn default_unlock_target_ms() -> u64 {
+    500
+}
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -28,6 +35,7 @@ impl Default for Config {
             last_used_vault: String::from("main"),
             session_timeout: String::from("60"),
             theme: default_theme(),
+            unlock_target_ms: default_unlock_target_ms(),
         }
     }
 }
@@ -6,7 +6,7 @@ use base64::engine::general_purpose::STANDARD as Base64;
 use base64::prelude::*;
 use chacha20poly1305::{
     ChaCha20Poly1305, Nonce,
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
 };
 use clap::ValueEnum;
 use rand::seq::{IndexedRandom, SliceRandom};
@@ -97,6 +97,53 @@ pub fn decrypt(key: &SecretKey, ciphertext: &Ciphertext) -> Result<SecretBytes,
         .map_err(|_| CryptoError::AuthenticationFailed)
 }
 
+/// Encrypts bytes and authenticates `aad` alongside them without encrypting it.
+pub fn encrypt_with_aad(
+    key: &SecretKey,
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Ciphertext, CryptoError> {
+    let cipher = ChaCha20Poly1305::new_from_slice(key.as_bytes()).map_err(|_| {
+        CryptoError::InvalidKeyLength {
+            actual: key.as_bytes().len(),
+        }
+    })?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let bytes = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CryptoError::AuthenticationFailed)?;
+    Ciphertext::try_from_parts(nonce.as_slice(), bytes)
+}
+
+/// Decrypts ciphertext produced by [`encrypt_with_aad`]; fails if `aad` differs.
+pub fn decrypt_with_aad(
+    key: &SecretKey,
+    ciphertext: &Ciphertext,
+    aad: &[u8],
+) -> Result<SecretBytes, CryptoError> {
+    let cipher = ChaCha20Poly1305::new_from_slice(key.as_bytes()).map_err(|_| {
+        CryptoError::InvalidKeyLength {
+            actual: key.as_bytes().len(),
+        }
+    })?;
+    cipher
+        .decrypt(
+            Nonce::from_slice(ciphertext.nonce()),
+            Payload {
+                msg: ciphertext.bytes(),
+                aad,
+            },
+        )
+        .map(SecretBytes::new)
+        .map_err(|_| CryptoError::AuthenticationFailed)
+}
+
 /// Generates a password or returns a typed length error.
 pub fn try_generate_password(length: u8, special_characters: bool) -> Result<String, CryptoError> {
     let minimum = if special_characters { 4 } else { 3 };
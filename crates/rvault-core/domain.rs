@@ -0,0 +1,426 @@
+//! Login URLs and matching them against the origin of a page.
+//!
+//! Hosts are compared after normalization, and base domains are found with the Public Suffix
+//! List embedded from `domain/public_suffix_list.dat`, so `login.example.co.uk` and
+//! `www.example.co.uk` share a base domain while `alice.github.io` and `bob.github.io` do not.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+const PUBLIC_SUFFIX_LIST: &str = include_str!("domain/public_suffix_list.dat");
+
+/// How a stored URL is compared with an origin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchMode {
+    /// Any host under the same registrable domain.
+    #[default]
+    BaseDomain,
+    /// Only the same host.
+    Host,
+    /// Any URL with the stored scheme, host and port whose path starts with the stored path's
+    /// segments.
+    StartsWith,
+    /// Any URL the stored regular expression matches in full.
+    Regex,
+    /// Never suggested automatically.
+    Never,
+}
+
+impl MatchMode {
+    pub const ALL: [Self; 5] = [
+        Self::BaseDomain,
+        Self::Host,
+        Self::StartsWith,
+        Self::Regex,
+        Self::Never,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::BaseDomain => "base-domain",
+            Self::Host => "host",
+            Self::StartsWith => "starts-with",
+            Self::Regex => "regex",
+            Self::Never => "never",
+        }
+    }
+}
+
+impl fmt::Display for MatchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MatchMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == name)
+            .ok_or_else(|| format!("unknown match mode: {name}"))
+    }
+}
+
+/// How closely an origin matched an entry; later variants are better matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchQuality {
+    BaseDomain,
+    Host,
+    /// A starts-with or regex rule matched the full URL.
+    Url,
+}
+
+/// A login URL stored with an entry. Regex rules are compiled once, when the URL is created or
+/// deserialized.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StoredUrl")]
+pub struct EntryUrl {
+    pub url: String,
+    #[serde(default, rename = "match")]
+    pub mode: MatchMode,
+    #[serde(skip)]
+    pattern: Option<Pattern>,
+}
+
+#[derive(Deserialize)]
+struct StoredUrl {
+    url: String,
+    #[serde(default, rename = "match")]
+    mode: MatchMode,
+}
+
+impl From<StoredUrl> for EntryUrl {
+    /// Stored rules were validated when added; a pattern that no longer compiles matches nothing.
+    fn from(stored: StoredUrl) -> Self {
+        let pattern = match stored.mode {
+            MatchMode::Regex => Pattern::new(&stored.url).ok(),
+            _ => None,
+        };
+        Self {
+            url: stored.url,
+            mode: stored.mode,
+            pattern,
+        }
+    }
+}
+
+/// A regex rule anchored to the whole URL, compared by its source.
+#[derive(Clone, Debug)]
+struct Pattern(Regex);
+
+impl Pattern {
+    fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(&format!("^(?:{pattern})$")).map(Self)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl EntryUrl {
+    /// Validates a URL for `mode`. Host-based modes need a host and get `https://` added when no
+    /// scheme is given; regex rules must compile.
+    pub fn new(url: &str, mode: MatchMode) -> Result<Self, String> {
+        let url = url.trim();
+        if url.is_empty() {
+            return Err("URL is empty".to_string());
+        }
+        let (url, pattern) = match mode {
+            MatchMode::Regex => {
+                let pattern =
+                    Pattern::new(url).map_err(|error| format!("invalid URL pattern: {error}"))?;
+                (url.to_string(), Some(pattern))
+            }
+            _ => {
+                normalize_host(url).ok_or_else(|| format!("URL has no valid host: {url}"))?;
+                if url.contains("://") {
+                    (url.to_string(), None)
+                } else {
+                    (format!("https://{url}"), None)
+                }
+            }
+        };
+        Ok(Self { url, mode, pattern })
+    }
+
+    /// How `origin`, a page origin or full URL, matches this URL, if at all.
+    pub fn matches(&self, origin: &str) -> Option<MatchQuality> {
+        let origin = origin.trim();
+        match self.mode {
+            MatchMode::Never => None,
+            MatchMode::StartsWith => {
+                let prefix = UrlParts::parse(&self.url)?;
+                let url = UrlParts::parse(origin)?;
+                (prefix.scheme == url.scheme
+                    && prefix.host == url.host
+                    && prefix.port == url.port
+                    && url.segments.starts_with(&prefix.segments))
+                .then_some(MatchQuality::Url)
+            }
+            MatchMode::Regex => self
+                .pattern
+                .as_ref()?
+                .0
+                .is_match(origin)
+                .then_some(MatchQuality::Url),
+            MatchMode::Host | MatchMode::BaseDomain => {
+                let host = normalize_host(&self.url)?;
+                let origin_host = normalize_host(origin)?;
+                if host == origin_host {
+                    Some(MatchQuality::Host)
+                } else if self.mode == MatchMode::BaseDomain
+                    && base_domain(&host) == base_domain(&origin_host)
+                {
+                    Some(MatchQuality::BaseDomain)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Best match of `origin` against any of `urls`.
+pub fn best_match(urls: &[EntryUrl], origin: &str) -> Option<MatchQuality> {
+    urls.iter().filter_map(|url| url.matches(origin)).max()
+}
+
+/// The parts of a URL a starts-with rule compares.
+struct UrlParts<'a> {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+    segments: Vec<&'a str>,
+}
+
+impl<'a> UrlParts<'a> {
+    /// Splits a URL with a scheme; the port is filled in from the scheme when left out.
+    fn parse(url: &'a str) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        let scheme = scheme.to_lowercase();
+        let host = normalize_host(url)?;
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let authority = &rest[..authority_end];
+        let authority = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let port = match authority.rsplit_once(':') {
+            Some((_, port)) if !port.contains(']') && !port.is_empty() => Some(port.parse().ok()?),
+            _ => match scheme.as_str() {
+                "http" => Some(80),
+                "https" => Some(443),
+                _ => None,
+            },
+        };
+        let path = rest[authority_end..]
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+        Some(Self {
+            scheme,
+            host,
+            port,
+            segments,
+        })
+    }
+}
+
+/// Lowercase host of a URL, origin or bare host name, without port, user info or trailing dot.
+pub fn normalize_host(input: &str) -> Option<String> {
+    let input = input.trim();
+    let rest = input.split_once("://").map_or(input, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = if let Some(ipv6) = authority.strip_prefix('[') {
+        format!("[{}]", ipv6.split(']').next()?)
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) if port.chars().all(|c| c.is_ascii_digit()) => host.to_string(),
+            _ => authority.to_string(),
+        }
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '.' | '_' | '[' | ']' | ':'));
+    valid.then_some(host)
+}
+
+/// Registrable domain of a normalized host: its public suffix plus one label. Hosts that are a
+/// public suffix themselves, IP addresses and single-label names are returned unchanged.
+pub fn base_domain(host: &str) -> String {
+    if host.starts_with('[') || host.parse::<std::net::Ipv4Addr>().is_ok() {
+        return host.to_string();
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let suffix_len = public_suffix_len(&labels);
+    if suffix_len >= labels.len() {
+        return host.to_string();
+    }
+    labels[labels.len() - suffix_len - 1..].join(".")
+}
+
+/// Number of trailing labels forming the public suffix, following the list's rules: the
+/// longest matching rule wins, exceptions beat wildcards, and unlisted TLDs are suffixes.
+fn public_suffix_len(labels: &[&str]) -> usize {
+    let rules = public_suffix_rules();
+    for start in 0..labels.len() {
+        let candidate = labels[start..].join(".");
+        if rules.contains(format!("!{candidate}").as_str()) {
+            return labels.len() - start - 1;
+        }
+        if rules.contains(candidate.as_str()) {
+            return labels.len() - start;
+        }
+        if start + 1 < labels.len() {
+            let wildcard = format!("*.{}", labels[start + 1..].join("."));
+            if rules.contains(wildcard.as_str()) {
+                return labels.len() - start;
+            }
+        }
+    }
+    1
+}
+
+fn public_suffix_rules() -> &'static HashSet<&'static str> {
+    static RULES: OnceLock<HashSet<&'static str>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        PUBLIC_SUFFIX_LIST
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("//"))
+            .filter_map(|line| line.split_whitespace().next())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_are_normalized_from_origins_urls_and_bare_names() {
+        assert_eq!(
+            normalize_host("https://User@Login.Example.com:8443/path?q#f").as_deref(),
+            Some("login.example.com")
+        );
+        assert_eq!(
+            normalize_host("example.com.").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(normalize_host("http://[::1]:80/").as_deref(), Some("[::1]"));
+        assert_eq!(normalize_host("https:///path"), None);
+        assert_eq!(normalize_host("exa mple.com"), None);
+    }
+
+    #[test]
+    fn base_domains_follow_public_suffix_rules() {
+        assert_eq!(base_domain("login.example.co.uk"), "example.co.uk");
+        assert_eq!(base_domain("a.b.example.com"), "example.com");
+        assert_eq!(base_domain("alice.github.io"), "alice.github.io");
+        assert_eq!(base_domain("co.uk"), "co.uk");
+        // `*.ck` makes every second-level name a suffix, except `www.ck`.
+        assert_eq!(base_domain("shop.example.ck"), "shop.example.ck");
+        assert_eq!(base_domain("login.www.ck"), "www.ck");
+        assert_eq!(
+            base_domain("intranet.unlisted-tld"),
+            "intranet.unlisted-tld"
+        );
+        assert_eq!(base_domain("192.168.1.1"), "192.168.1.1");
+        assert_eq!(base_domain("localhost"), "localhost");
+    }
+
+    #[test]
+    fn match_modes_rank_matches() {
+        let url = |url, mode| EntryUrl::new(url, mode).unwrap();
+        let base = url("accounts.example.co.uk", MatchMode::BaseDomain);
+        assert_eq!(base.url, "https://accounts.example.co.uk");
+        assert_eq!(
+            base.matches("https://accounts.example.co.uk"),
+            Some(MatchQuality::Host)
+        );
+        assert_eq!(
+            base.matches("https://www.example.co.uk"),
+            Some(MatchQuality::BaseDomain)
+        );
+        assert_eq!(base.matches("https://example.org.uk"), None);
+        assert_eq!(
+            url("alice.github.io", MatchMode::BaseDomain).matches("https://bob.github.io"),
+            None
+        );
+        assert_eq!(
+            url("example.com", MatchMode::Host).matches("https://www.example.com"),
+            None
+        );
+        let prefix = url("https://example.com/admin", MatchMode::StartsWith);
+        assert_eq!(
+            prefix.matches("https://EXAMPLE.com/admin/login"),
+            Some(MatchQuality::Url)
+        );
+        assert_eq!(prefix.matches("https://example.com/"), None);
+        assert_eq!(prefix.matches("https://example.com/administrator"), None);
+        assert_eq!(prefix.matches("http://example.com/admin"), None);
+        assert_eq!(prefix.matches("https://example.com:8443/admin"), None);
+        assert_eq!(
+            prefix.matches("https://example.com:443/admin?next=/"),
+            Some(MatchQuality::Url)
+        );
+        let host_only = url("github.com", MatchMode::StartsWith);
+        assert_eq!(
+            host_only.matches("https://github.com/login"),
+            Some(MatchQuality::Url)
+        );
+        assert_eq!(
+            host_only.matches("https://github.com.evil.example/login"),
+            None
+        );
+        assert_eq!(host_only.matches("https://github.com@evil.example/"), None);
+        let pattern = url(r"https://[a-z]+\.example\.com/.*", MatchMode::Regex);
+        assert_eq!(
+            pattern.matches("https://eu.example.com/login"),
+            Some(MatchQuality::Url)
+        );
+        assert_eq!(
+            pattern.matches("https://evil.example/?https://eu.example.com/"),
+            None
+        );
+        let loaded: EntryUrl =
+            serde_json::from_str(&serde_json::to_string(&pattern).unwrap()).unwrap();
+        assert_eq!(loaded, pattern);
+        assert_eq!(
+            loaded.matches("https://eu.example.com/login"),
+            Some(MatchQuality::Url)
+        );
+        assert_eq!(
+            url("example.com", MatchMode::Never).matches("https://example.com"),
+            None
+        );
+        assert_eq!(
+            best_match(&[base, prefix], "https://accounts.example.co.uk"),
+            Some(MatchQuality::Host)
+        );
+        assert!(EntryUrl::new("(", MatchMode::Regex).is_err());
+        assert_eq!("starts-with".parse(), Ok(MatchMode::StartsWith));
+    }
+}
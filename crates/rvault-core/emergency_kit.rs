@@ -0,0 +1,146 @@
+//! Printable emergency kit: a recovery key registered as a keystore slot, the identity public
+//! code, and instructions for regaining access.
+
+use crate::{
+    identity,
+    keystore::{self, SlotKind, keystore_path},
+    recovery::RecoveryKey,
+};
+use chrono::{DateTime, Utc};
+use std::{fs::OpenOptions, io::Write, path::Path};
+use zeroize::Zeroizing;
+
+/// Generates a recovery key, registers it as a keystore slot and writes the kit to `out_path`.
+///
+/// Returns the id of the new recovery slot. The kit is written with owner-only permissions and
+/// never overwrites an existing file; if it cannot be written, the slot is removed again so no
+/// recovery key exists that was not saved.
+pub fn create_emergency_kit(master_password: &str, out_path: &Path) -> Result<u32, String> {
+    let keystore = keystore_path()?;
+    let mek = Zeroizing::new(keystore::load_key_from_vault(master_password, &keystore)?);
+    let identity = identity::load_or_create_identity(mek.as_slice())?;
+    let identity_code = identity::public_code_from_key(&identity.public_key);
+
+    let recovery_key = RecoveryKey::generate();
+    let slot_id = keystore::add_key_slot(
+        master_password,
+        SlotKind::Recovery,
+        recovery_key.as_bytes(),
+        &keystore,
+    )?;
+    let kit = render_emergency_kit(&recovery_key, slot_id, &identity_code, Utc::now());
+    if let Err(error) = write_private_new(out_path, kit.as_bytes()) {
+        let _ = keystore::remove_key_slot(master_password, slot_id, &keystore);
+        return Err(error);
+    }
+    Ok(slot_id)
+}
+
+/// Renders the kit as Markdown that also reads well as plain text.
+pub fn render_emergency_kit(
+    recovery_key: &RecoveryKey,
+    slot_id: u32,
+    identity_code: &str,
+    created_at: DateTime<Utc>,
+) -> Zeroizing<String> {
+    Zeroizing::new(format!(
+        "# RVault Emergency Kit
+
+Created: {created}
+
+Keep this page offline and private. Anyone holding the recovery key below can open your vault.
+
+## Recovery key (key slot {slot_id})
+
+    {recovery_key}
+
+## Identity public code
+
+    {identity_code}
+
+This code lets other people send you encrypted RVault exports. It is not secret.
+
+## Regaining access
+
+1. On a new machine, install RVault and restore your latest backup with
+   `rvault backup restore <backup-file>`. Backups made before this kit was created do not
+   contain this recovery key.
+2. Run `rvault recover` and enter the recovery key exactly as printed above. Case, spaces and
+   dashes do not matter.
+3. Choose a new master password. Your entries and other key slots are kept.
+4. Create a new kit with `rvault emergency-kit --out <file>` and remove this one's slot with
+   `rvault keyslot remove {slot_id}`.
+
+To unlock once without changing the master password, type the recovery key at the
+`rvault unlock` prompt.
+",
+        created = created_at.format("%Y-%m-%d %H:%M UTC"),
+        recovery_key = recovery_key.display().as_str(),
+    ))
+}
+
+fn write_private_new(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("create {}: {e}", path.display()))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn kit_contains_the_recovery_key_identity_and_instructions() {
+        let recovery_key = RecoveryKey::generate();
+        let created_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 0).unwrap();
+
+        let kit = render_emergency_kit(&recovery_key, 3, "rvault1-public", created_at);
+
+        assert!(kit.contains("Created: 2026-01-02 03:04 UTC"));
+        assert!(kit.contains(recovery_key.display().as_str()));
+        assert!(kit.contains("rvault1-public"));
+        assert!(kit.contains("rvault recover"));
+        assert!(kit.contains("rvault keyslot remove 3"));
+    }
+
+    #[test]
+    fn printed_recovery_key_parses_back() {
+        let recovery_key = RecoveryKey::generate();
+        let kit = render_emergency_kit(&recovery_key, 1, "rvault1-public", Utc::now());
+
+        let printed = kit
+            .lines()
+            .skip_while(|line| !line.starts_with("## Recovery key"))
+            .nth(2)
+            .unwrap();
+
+        assert_eq!(
+            RecoveryKey::parse(printed).unwrap().as_bytes(),
+            recovery_key.as_bytes()
+        );
+    }
+
+    #[test]
+    fn kits_never_overwrite_existing_files() {
+        let path = std::env::temp_dir().join(format!(
+            "rvault-emergency-kit-test-{}.md",
+            rand::random::<u64>()
+        ));
+        write_private_new(&path, b"first").unwrap();
+
+        assert!(write_private_new(&path, b"second").is_err());
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//! Typo-tolerant matching of search queries against entry fields.
+//!
+//! A query is split into lowercase terms. A term matches a field exactly, as a prefix of the
+//! field or of one of its words, as a substring, or within a few typos of a word. An entry
+//! matches when every term matches one of its fields; its score adds up the best match of each
+//! term, weighted by the field it was found in.
+
+/// Parts of an entry a query is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchField {
+    Platform,
+    UserId,
+    Tag,
+    Url,
+    Notes,
+}
+
+impl SearchField {
+    fn weight(self) -> u32 {
+        match self {
+            Self::Platform => 4,
+            Self::UserId => 3,
+            Self::Tag => 2,
+            Self::Url => 2,
+            Self::Notes => 1,
+        }
+    }
+}
+
+/// Scores of the ways a term can match a field, best first.
+const EXACT: u32 = 100;
+const FIELD_PREFIX: u32 = 90;
+const WORD_PREFIX: u32 = 80;
+const SUBSTRING: u32 = 60;
+const ONE_TYPO: u32 = 40;
+const TWO_TYPOS: u32 = 25;
+
+/// A parsed search query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    terms: Vec<String>,
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Self {
+        Self {
+            terms: query.split_whitespace().map(str::to_lowercase).collect(),
+        }
+    }
+
+    /// Whether the query has no terms and so matches every entry.
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Lowercase terms, in query order.
+    pub fn terms(&self) -> &[String] {
+        &self.terms
+    }
+
+    /// Score of an entry with these fields, or `None` unless every term matches one of them.
+    /// Every entry scores 0 for an empty query.
+    pub fn score(&self, fields: &[(SearchField, &str)]) -> Option<u32> {
+        let fields: Vec<(SearchField, String)> = fields
+            .iter()
+            .map(|(field, text)| (*field, text.to_lowercase()))
+            .collect();
+        self.terms.iter().try_fold(0, |total, term| {
+            let best = fields
+                .iter()
+                .map(|(field, text)| term_score(term, text) * field.weight())
+                .max()
+                .unwrap_or(0);
+            (best > 0).then_some(total + best)
+        })
+    }
+}
+
+/// Typos a term of `length` characters may have and still match: none below 4 characters, one
+/// up to 8 and two from 9.
+fn typo_budget(length: usize) -> usize {
+    match length {
+        0..=3 => 0,
+        4..=8 => 1,
+        _ => 2,
+    }
+}
+
+/// Whether every field text that `term` matches shares one of the term's trigrams, so that a
+/// trigram index finds all of them. An edit changes at most four trigrams of a term.
+pub fn found_by_trigrams(term: &str) -> bool {
+    let length = term.chars().count();
+    length >= 3 && length - 2 > 4 * typo_budget(length)
+}
+
+/// How well a lowercase `term` matches a lowercase `text`, or 0.
+fn term_score(term: &str, text: &str) -> u32 {
+    if text == term {
+        return EXACT;
+    }
+    if text.starts_with(term) {
+        return FIELD_PREFIX;
+    }
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty());
+    if words.clone().any(|word| word.starts_with(term)) {
+        return WORD_PREFIX;
+    }
+    if text.contains(term) {
+        return SUBSTRING;
+    }
+    let term: Vec<char> = term.chars().collect();
+    let budget = typo_budget(term.len());
+    if budget == 0 {
+        return 0;
+    }
+    let typos = words
+        .map(|word| word_typos(&term, &word.chars().collect::<Vec<_>>()))
+        .min()
+        .unwrap_or(usize::MAX);
+    match typos {
+        1 => ONE_TYPO,
+        2 if budget >= 2 => TWO_TYPOS,
+        _ => 0,
+    }
+}
+
+/// Fewest edits turning `term` into `word` or into a prefix of it about as long as the term,
+/// so that a term can be both misspelled and unfinished.
+fn word_typos(term: &[char], word: &[char]) -> usize {
+    let shortest = term.len().saturating_sub(2).max(1);
+    let longest = (term.len() + 2).min(word.len());
+    (shortest..=longest)
+        .map(|length| edit_distance(term, &word[..length]))
+        .chain(std::iter::once(edit_distance(term, word)))
+        .min()
+        .unwrap_or(usize::MAX)
+}
+
+/// Edit distance counting insertions, deletions, substitutions and swaps of adjacent
+/// characters (optimal string alignment).
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let width = b.len() + 1;
+    let mut rows = vec![0; (a.len() + 1) * width];
+    for (j, cell) in rows.iter_mut().enumerate().take(width) {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        rows[i * width] = i;
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (rows[(i - 1) * width + j] + 1)
+                .min(rows[i * width + j - 1] + 1)
+                .min(rows[(i - 1) * width + j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[(i - 2) * width + j - 2] + 1);
+            }
+            rows[i * width + j] = distance;
+        }
+    }
+    rows[a.len() * width + b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, platform: &str) -> Option<u32> {
+        Query::parse(query).score(&[(SearchField::Platform, platform)])
+    }
+
+    #[test]
+    fn exact_and_prefix_matches_rank_above_substrings_and_typos() {
+        let exact = score("github", "GitHub").unwrap();
+        let prefix = score("git", "GitHub").unwrap();
+        let word = score("hub", "Docker Hub").unwrap();
+        let substring = score("thu", "GitHub").unwrap();
+        let typo = score("gihtub", "GitHub").unwrap();
+        assert!(exact > prefix && prefix > word && word > substring && substring > typo);
+    }
+
+    #[test]
+    fn typos_are_tolerated_by_term_length() {
+        assert!(score("gthub", "github").is_some());
+        assert!(score("githbu", "github").is_some());
+        assert!(score("amazn", "amazon.com").is_some());
+        assert!(score("gxt", "github").is_none());
+        assert!(score("bitbucket", "github").is_none());
+        assert!(score("cloudflrae", "cloudflare").is_some());
+    }
+
+    #[test]
+    fn every_term_must_match_a_field_and_fields_are_weighted() {
+        let query = Query::parse("github work");
+        let fields = [
+            (SearchField::Platform, "GitHub"),
+            (SearchField::UserId, "alice@example.com"),
+            (SearchField::Tag, "work"),
+        ];
+        assert!(query.score(&fields).is_some());
+        assert!(query.score(&fields[..2]).is_none());
+        let as_platform = Query::parse("acme")
+            .score(&[(SearchField::Platform, "acme"), (SearchField::Notes, "")]);
+        let as_notes = Query::parse("acme")
+            .score(&[(SearchField::Platform, "x"), (SearchField::Notes, "acme")]);
+        assert!(as_platform > as_notes);
+        assert_eq!(Query::parse("  ").score(&[]), Some(0));
+    }
+}
@@ -26,6 +26,20 @@ pub fn generate_identity_bytes(encryption_key: &[u8]) -> Result<Vec<u8>, String>
     ))
 }
 
+/// Re-encrypts stored identity bytes from `old_key` to `new_key`, keeping the same keypair.
+pub fn reencrypt_identity_bytes(
+    old_key: &[u8],
+    new_key: &[u8],
+    bytes: &[u8],
+) -> Result<Vec<u8>, String> {
+    let identity = load_identity_from_bytes(old_key, bytes)?;
+    let (nonce, ciphertext) = encrypt_bytes_with_key(new_key, &identity.private_key)?;
+    Ok(encode_envelope(
+        IDENTITY_MAGIC,
+        &[nonce.to_vec(), ciphertext],
+    ))
+}
+
 pub fn load_identity_from_bytes(
     encryption_key: &[u8],
     bytes: &[u8],
@@ -113,4 +127,19 @@ mod tests {
 
         assert!(err.contains("decrypt"));
     }
+
+    #[test]
+    fn reencrypted_identity_keeps_the_keypair_under_the_new_key() {
+        let bytes = generate_identity_bytes(&VAULT_KEY).expect("generate identity");
+        let identity = load_identity_from_bytes(&VAULT_KEY, &bytes).expect("load identity");
+
+        let rotated =
+            reencrypt_identity_bytes(&VAULT_KEY, &[8; 32], &bytes).expect("re-encrypt identity");
+
+        assert!(load_identity_from_bytes(&VAULT_KEY, &rotated).is_err());
+        assert_eq!(
+            load_identity_from_bytes(&[8; 32], &rotated).expect("load rotated identity"),
+            identity
+        );
+    }
 }
@@ -1,37 +1,116 @@
 use crate::{
-    crypto::{Ciphertext, decrypt, encrypt, generate_key},
+    crypto::{Ciphertext, decrypt, decrypt_with_aad, encrypt_with_aad},
     secret::SecretKey,
 };
 use argon2::Argon2;
 use argon2::{Algorithm, Params, Version};
 use base64::engine::general_purpose::STANDARD as Base64;
 use base64::prelude::*;
-use chacha20poly1305::Key;
 use directories::ProjectDirs;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use std::{fs, path::Path};
-use zeroize::{Zeroize, Zeroizing};
+use zeroize::Zeroizing;
 
 const MAGIC: &str = "RVAULT";
 const AAD: &[u8] = b"rvault-keystore-v1";
+const SLOT_AAD: &[u8] = b"rvault-keystore-slot-v2";
 const KEYSTORE_NAME: &str = "keystore.rvault"; // file name
+const KEYSTORE_VERSION: u32 = 2;
+const KDF_ARGON2ID: &str = "argon2id";
 const EK_LEN: usize = 32;
 const SALT_LEN: usize = 16;
 const NONCE_LEN: usize = 12;
+const KEYFILE_LEN: usize = 64;
+/// Upper bound for the memory cost read from a keystore header (4 GiB), so a damaged or hostile
+/// file cannot make unlock allocate without limit before the header is authenticated.
+const MAX_KDF_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+/// Calibration stops growing memory at 1 GiB so setup stays usable on large machines.
+const MAX_CALIBRATED_MEMORY_KIB: u32 = 1024 * 1024;
+const MAX_CALIBRATED_PASSES: u32 = 64;
+/// Unlock time targeted by [`calibrate_kdf`] when none is configured.
+pub const DEFAULT_UNLOCK_TARGET: Duration = Duration::from_millis(500);
 
-#[derive(Serialize, Deserialize)]
-struct KdfParams {
-    t: u32, // iterations
-    m: u32, // memory (KiB)
-    p: u32, // parallelism
+/// Argon2id cost parameters stored in the keystore header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub t: u32, // iterations
+    pub m: u32, // memory (KiB)
+    pub p: u32, // parallelism
+}
+
+impl KdfParams {
+    /// Parameters of `Argon2::default()`, which unwraps legacy raw keystores.
+    pub const LEGACY: KdfParams = KdfParams {
+        t: 2,
+        m: 19 * 1024,
+        p: 1,
+    };
+
+    /// Returns true when every cost is at least as high as `other`.
+    pub fn at_least(&self, other: &KdfParams) -> bool {
+        self.t >= other.t && self.m >= other.m && self.p >= other.p
+    }
+}
+
+impl Default for KdfParams {
+    /// Cost used for new keystores and for upgrading weaker ones: 64 MiB, 3 passes, 1 lane.
+    fn default() -> Self {
+        Self {
+            t: 3,
+            m: 64 * 1024,
+            p: 1,
+        }
+    }
+}
+
+/// What unwraps a key slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlotKind {
+    /// The master password. Every keystore has exactly one.
+    Password,
+    /// A generated recovery key, see [`crate::recovery::RecoveryKey`].
+    Recovery,
+    /// The SHA-256 digest of a local keyfile.
+    Keyfile,
 }
 
+impl SlotKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SlotKind::Password => "password",
+            SlotKind::Recovery => "recovery",
+            SlotKind::Keyfile => "keyfile",
+        }
+    }
+}
+
+/// Public description of a key slot; contains nothing secret.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeySlotInfo {
+    pub id: u32,
+    pub kind: SlotKind,
+    pub kdf_params: KdfParams,
+}
+
+/// Keystore with independent key slots, each wrapping the same MEK.
 #[derive(Serialize, Deserialize)]
 struct KeystoreFile {
     magic: String,
     version: u32,
+    slots: Vec<KeySlot>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KeySlot {
+    id: u32,
+    kind: SlotKind,
     kdf: String,           // "argon2id"
     kdf_params: KdfParams, // t,m,p
     salt_b64: String,
@@ -39,6 +118,243 @@ struct KeystoreFile {
     wrapped_ek_b64: String,
 }
 
+impl KeySlot {
+    /// Associated data binding the slot header to its wrapped key.
+    fn aad(&self) -> Result<Vec<u8>, String> {
+        let header = (
+            MAGIC,
+            KEYSTORE_VERSION,
+            self.id,
+            self.kind,
+            &self.kdf,
+            &self.kdf_params,
+            &self.salt_b64,
+        );
+        let mut aad = SLOT_AAD.to_vec();
+        aad.extend(serde_json::to_vec(&header).map_err(|e| format!("encode header: {e}"))?);
+        Ok(aad)
+    }
+
+    fn info(&self) -> KeySlotInfo {
+        KeySlotInfo {
+            id: self.id,
+            kind: self.kind,
+            kdf_params: self.kdf_params,
+        }
+    }
+}
+
+/// Single-password keystore written before key slots existed.
+#[derive(Serialize, Deserialize)]
+struct KeystoreFileV1 {
+    magic: String,
+    version: u32,
+    kdf: String,
+    kdf_params: KdfParams,
+    salt_b64: String,
+    nonce_b64: String,
+    wrapped_ek_b64: String,
+}
+
+impl KeystoreFileV1 {
+    fn aad(&self) -> Result<Vec<u8>, String> {
+        let header = (
+            &self.magic,
+            self.version,
+            &self.kdf,
+            &self.kdf_params,
+            &self.salt_b64,
+        );
+        let mut aad = AAD.to_vec();
+        aad.extend(serde_json::to_vec(&header).map_err(|e| format!("encode header: {e}"))?);
+        Ok(aad)
+    }
+}
+
+#[derive(Deserialize)]
+struct KeystoreProbe {
+    magic: String,
+    version: u32,
+}
+
+enum StoredKeystore {
+    Legacy(Vec<u8>),
+    V1(KeystoreFileV1),
+    Slots(KeystoreFile),
+}
+
+impl StoredKeystore {
+    fn parse(file_bytes: Vec<u8>) -> Result<Self, String> {
+        // Legacy keystores start with a random salt, so anything that is not a JSON document
+        // carrying our magic is treated as the raw layout.
+        let Some(probe) = serde_json::from_slice::<KeystoreProbe>(&file_bytes)
+            .ok()
+            .filter(|probe| probe.magic == MAGIC)
+        else {
+            return Ok(StoredKeystore::Legacy(file_bytes));
+        };
+        match probe.version {
+            1 => serde_json::from_slice(&file_bytes)
+                .map(StoredKeystore::V1)
+                .map_err(|_| "Invalid or corrupt vault file.".to_string()),
+            KEYSTORE_VERSION => serde_json::from_slice(&file_bytes)
+                .map(StoredKeystore::Slots)
+                .map_err(|_| "Invalid or corrupt vault file.".to_string()),
+            version => Err(format!(
+                "Unsupported keystore version {version}. Please update RVault."
+            )),
+        }
+    }
+
+    fn read(path: &Path) -> Result<Self, String> {
+        let file_bytes = fs::read(path).map_err(|e| format!("Failed to read vault file: {}", e))?;
+        Self::parse(file_bytes)
+    }
+
+    fn format(&self) -> KeystoreFormat {
+        match self {
+            StoredKeystore::Legacy(_) => KeystoreFormat::Legacy,
+            StoredKeystore::V1(file) => KeystoreFormat::Versioned {
+                version: file.version,
+                kdf_params: file.kdf_params,
+            },
+            StoredKeystore::Slots(file) => KeystoreFormat::Versioned {
+                version: file.version,
+                kdf_params: file
+                    .slots
+                    .iter()
+                    .find(|slot| slot.kind == SlotKind::Password)
+                    .map(|slot| slot.kdf_params)
+                    .unwrap_or_default(),
+            },
+        }
+    }
+
+    fn slots(&self) -> Vec<KeySlotInfo> {
+        match self {
+            StoredKeystore::Legacy(_) => vec![KeySlotInfo {
+                id: 0,
+                kind: SlotKind::Password,
+                kdf_params: KdfParams::LEGACY,
+            }],
+            StoredKeystore::V1(file) => vec![KeySlotInfo {
+                id: 0,
+                kind: SlotKind::Password,
+                kdf_params: file.kdf_params,
+            }],
+            StoredKeystore::Slots(file) => file.slots.iter().map(KeySlot::info).collect(),
+        }
+    }
+
+    /// Unwraps the MEK with the master password.
+    fn open_with_password(&self, master_password: &str) -> Result<Zeroizing<[u8; EK_LEN]>, String> {
+        match self {
+            StoredKeystore::Legacy(bytes) => open_legacy(master_password, bytes),
+            StoredKeystore::V1(file) => open_v1(master_password, file),
+            StoredKeystore::Slots(_) => {
+                self.open_with(SlotKind::Password, master_password.as_bytes())
+            }
+        }
+    }
+
+    /// Tries every slot of `kind` with `secret`.
+    fn open_with(&self, kind: SlotKind, secret: &[u8]) -> Result<Zeroizing<[u8; EK_LEN]>, String> {
+        let StoredKeystore::Slots(file) = self else {
+            if kind == SlotKind::Password {
+                return Err("Use the master password to open this keystore.".to_string());
+            }
+            return Err(format!("This keystore has no {} slot.", kind.name()));
+        };
+        let mut candidates = file
+            .slots
+            .iter()
+            .filter(|slot| slot.kind == kind)
+            .peekable();
+        if candidates.peek().is_none() {
+            return Err(format!("This keystore has no {} slot.", kind.name()));
+        }
+        for slot in candidates {
+            if let Ok(mek) = open_slot(slot, secret) {
+                return Ok(mek);
+            }
+        }
+        Err(format!("The {} does not match any key slot.", kind.name()))
+    }
+
+    /// Returns a slot keystore with the password slot wrapped under `master_password`.
+    ///
+    /// Other slots are kept. The password slot keeps its KDF parameters unless they are weaker
+    /// than the defaults.
+    fn with_password(
+        &self,
+        mek: &[u8; EK_LEN],
+        master_password: &str,
+    ) -> Result<KeystoreFile, String> {
+        let current = self.format();
+        let kdf_params = match current {
+            KeystoreFormat::Versioned { kdf_params, .. }
+                if kdf_params.at_least(&KdfParams::default()) =>
+            {
+                kdf_params
+            }
+            _ => KdfParams::default(),
+        };
+        let mut slots = match self {
+            StoredKeystore::Slots(file) => file.slots.clone(),
+            _ => Vec::new(),
+        };
+        let id = slots
+            .iter()
+            .find(|slot| slot.kind == SlotKind::Password)
+            .map(|slot| slot.id)
+            .unwrap_or(0);
+        slots.retain(|slot| slot.kind != SlotKind::Password);
+        slots.insert(
+            0,
+            seal_slot(
+                id,
+                SlotKind::Password,
+                master_password.as_bytes(),
+                mek,
+                &kdf_params,
+            )?,
+        );
+        Ok(KeystoreFile {
+            magic: MAGIC.to_string(),
+            version: KEYSTORE_VERSION,
+            slots,
+        })
+    }
+}
+
+impl KeystoreFile {
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec_pretty(self).map_err(|e| format!("encode keystore: {e}"))
+    }
+}
+
+/// On-disk layout detected while reading a keystore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeystoreFormat {
+    /// Raw `[salt][nonce][ciphertext]` written before versioned keystores, using Argon2 defaults.
+    Legacy,
+    /// Self-describing JSON keystore; `kdf_params` are those of the master password slot.
+    Versioned { version: u32, kdf_params: KdfParams },
+}
+
+impl KeystoreFormat {
+    /// Returns true when the keystore should be rewritten with `target` parameters.
+    pub fn needs_upgrade(&self, target: &KdfParams) -> bool {
+        match self {
+            KeystoreFormat::Legacy => true,
+            KeystoreFormat::Versioned {
+                version,
+                kdf_params,
+            } => *version < KEYSTORE_VERSION || !kdf_params.at_least(target),
+        }
+    }
+}
+
 pub fn keystore_path() -> Result<PathBuf, String> {
     if let Some(pd) = ProjectDirs::from("io.github", "ata-sesli", "RVault") {
         let dir = pd.config_dir();
@@ -49,42 +365,77 @@ pub fn keystore_path() -> Result<PathBuf, String> {
     }
 }
 
-fn derive_kek(master_password: &[u8], salt: &[u8], k: &KdfParams) -> Result<Key, String> {
+fn derive_kek(secret: &[u8], salt: &[u8], k: &KdfParams) -> Result<SecretKey, String> {
+    if k.m > MAX_KDF_MEMORY_KIB {
+        return Err(format!(
+            "Keystore KDF memory cost {} KiB is too large.",
+            k.m
+        ));
+    }
     // Output length = 32 bytes (for ChaCha20Poly1305 key)
     let params =
         Params::new(k.m, k.t, k.p, Some(EK_LEN)).map_err(|e| format!("Argon2 params: {e}"))?;
     let a2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
 
-    let mut out = [0u8; EK_LEN];
-    a2.hash_password_into(master_password, salt, &mut out)
+    let mut out = Zeroizing::new([0u8; EK_LEN]);
+    a2.hash_password_into(secret, salt, out.as_mut())
         .map_err(|e| format!("Argon2 derive: {e}"))?;
-    let key = Key::from_slice(&out).to_owned();
-    out.zeroize();
-    Ok(key)
+    Ok(SecretKey::from_bytes(*out))
 }
-/// Creates a new, encrypted vault file containing a newly generated Master Encryption Key (MEK).
-pub fn create_key_vault(master_password: &str, path: &Path) -> Result<(), String> {
-    // 1. Generate a new, random 32-byte Master Encryption Key (MEK). This is the key we will protect.
-    let mek = generate_key();
-    // 2) Derive KEK from master + raw 16-byte salt
-    let mut salt = [0u8; SALT_LEN];
-    rand::rng().fill_bytes(&mut salt);
 
-    let mut kek = Zeroizing::new([0u8; EK_LEN]);
-    let argon2 = Argon2::default();
-    let _ = argon2
-        .hash_password_into(master_password.as_bytes(), &salt, kek.as_mut())
-        .map_err(|_| "Failed to derive key.".to_string())?;
+/// Benchmarks Argon2id on this machine and picks parameters whose unlock takes about `target`.
+///
+/// Memory is raised first, then the number of passes. The result is never weaker than
+/// [`KdfParams::default`], so slow machines may take longer than `target` to unlock.
+pub fn calibrate_kdf(target: Duration) -> Result<KdfParams, String> {
+    calibrate_kdf_with(target, |params| {
+        let started = Instant::now();
+        derive_kek(b"rvault-calibration", &[0u8; SALT_LEN], params)?;
+        Ok(started.elapsed())
+    })
+}
 
-    // 3. Encrypt the MEK using the KEK.
-    let kek = SecretKey::from_bytes(*kek);
-    let ciphertext = encrypt(&kek, mek.as_bytes()).map_err(|error| error.to_string())?;
+fn calibrate_kdf_with(
+    target: Duration,
+    mut measure: impl FnMut(&KdfParams) -> Result<Duration, String>,
+) -> Result<KdfParams, String> {
+    let floor = KdfParams::default();
+    let mut params = KdfParams { t: 1, ..floor };
+    let mut pass = measure(&params)?;
+    // Keep doubling memory while the minimum number of passes would still fit the target.
+    while pass * 2 * floor.t <= target && params.m * 2 <= MAX_CALIBRATED_MEMORY_KIB {
+        params.m *= 2;
+        pass = measure(&params)?;
+    }
+    let passes = target.as_secs_f64() / pass.as_secs_f64().max(f64::EPSILON);
+    params.t = (passes.floor() as u32).clamp(floor.t, MAX_CALIBRATED_PASSES);
+    Ok(params)
+}
+
+/// Creates a new, encrypted vault file containing a newly generated Master Encryption Key (MEK).
+pub fn create_key_vault(master_password: &str, path: &Path) -> Result<(), String> {
+    create_key_vault_with_params(master_password, path, &KdfParams::default())
+}
 
-    // 4) Write raw: [salt][nonce][ct]
-    let mut out = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.bytes().len());
-    out.extend_from_slice(&salt);
-    out.extend_from_slice(ciphertext.nonce());
-    out.extend_from_slice(ciphertext.bytes());
+/// Creates a new keystore whose password slot is derived with the given Argon2id parameters.
+pub fn create_key_vault_with_params(
+    master_password: &str,
+    path: &Path,
+    kdf_params: &KdfParams,
+) -> Result<(), String> {
+    // 1. Generate a new, random 32-byte Master Encryption Key (MEK). This is the key we will protect.
+    let mek = Zeroizing::new(crate::crypto::generate_raw_key());
+    let file = KeystoreFile {
+        magic: MAGIC.to_string(),
+        version: KEYSTORE_VERSION,
+        slots: vec![seal_slot(
+            0,
+            SlotKind::Password,
+            master_password.as_bytes(),
+            &mek,
+            kdf_params,
+        )?],
+    };
 
     // ensure parent exists (avoids ENOENT on first run)
     if let Some(parent) = path.parent() {
@@ -92,14 +443,281 @@ pub fn create_key_vault(master_password: &str, path: &Path) -> Result<(), String
     }
 
     // actually write the keystore
-    std::fs::write(path, &out).map_err(|e| format!("write keystore: {e}"))
+    std::fs::write(path, file.to_bytes()?).map_err(|e| format!("write keystore: {e}"))
+}
+
+/// Decrypts the MEK with `old_password` and returns keystore bytes wrapping the same MEK under
+/// `new_password`.
+///
+/// The file at `path` is left untouched so callers can commit the new bytes together with the
+/// matching master password hash. Recovery and keyfile slots are kept as they are.
+pub fn rewrap_key_vault(
+    old_password: &str,
+    new_password: &str,
+    path: &Path,
+) -> Result<Vec<u8>, String> {
+    let stored = StoredKeystore::read(path)?;
+    let mek = stored.open_with_password(old_password)?;
+    stored.with_password(&mek, new_password)?.to_bytes()
+}
+
+fn seal_slot(
+    id: u32,
+    kind: SlotKind,
+    secret: &[u8],
+    mek: &[u8; EK_LEN],
+    kdf_params: &KdfParams,
+) -> Result<KeySlot, String> {
+    // 2) Derive KEK from the slot secret + raw 16-byte salt
+    let mut salt = [0u8; SALT_LEN];
+    rand::rng().fill_bytes(&mut salt);
+    let kek = derive_kek(secret, &salt, kdf_params)?;
+
+    // 3. Encrypt the MEK using the KEK, authenticating the slot header alongside it.
+    let mut slot = KeySlot {
+        id,
+        kind,
+        kdf: KDF_ARGON2ID.to_string(),
+        kdf_params: *kdf_params,
+        salt_b64: Base64.encode(salt),
+        nonce_b64: String::new(),
+        wrapped_ek_b64: String::new(),
+    };
+    let ciphertext =
+        encrypt_with_aad(&kek, mek, &slot.aad()?).map_err(|error| error.to_string())?;
+    slot.nonce_b64 = Base64.encode(ciphertext.nonce());
+    slot.wrapped_ek_b64 = Base64.encode(ciphertext.bytes());
+    Ok(slot)
+}
+
+/// Unwraps the MEK through a recovery or keyfile slot and returns keystore bytes whose password
+/// slot wraps it under `new_password`.
+///
+/// Like [`rewrap_key_vault`], the file at `path` is left untouched.
+pub fn reset_password_with_slot(
+    kind: SlotKind,
+    secret: &[u8],
+    new_password: &str,
+    path: &Path,
+) -> Result<Vec<u8>, String> {
+    let stored = StoredKeystore::read(path)?;
+    let mek = stored.open_with(kind, secret)?;
+    stored.with_password(&mek, new_password)?.to_bytes()
+}
+
+/// Returns keystore bytes wrapping `new_mek` under the master password, for key rotation.
+///
+/// Only the password slot can be rewrapped, since recovery keys and keyfiles are not available
+/// here; the slots that are dropped are returned so callers can tell the user to re-create them.
+pub fn rotate_key_vault(
+    master_password: &str,
+    new_mek: &[u8; EK_LEN],
+    path: &Path,
+) -> Result<(Vec<u8>, Vec<KeySlotInfo>), String> {
+    let stored = StoredKeystore::read(path)?;
+    stored.open_with_password(master_password)?;
+    let mut file = stored.with_password(new_mek, master_password)?;
+    let dropped = file
+        .slots
+        .iter()
+        .filter(|slot| slot.kind != SlotKind::Password)
+        .map(KeySlot::info)
+        .collect();
+    file.slots.retain(|slot| slot.kind == SlotKind::Password);
+    Ok((file.to_bytes()?, dropped))
 }
 
 /// Loads and decrypts the Master Encryption Key (MEK) from the vault file.
 pub fn load_key_from_vault(master_password: &str, path: &Path) -> Result<[u8; EK_LEN], String> {
-    let file_bytes = fs::read(path).map_err(|e| format!("Failed to read vault file: {}", e))?;
+    let mek = StoredKeystore::read(path)?.open_with_password(master_password)?;
+    Ok(*mek)
+}
+
+/// Loads the MEK from the bytes of a keystore file, such as the one inside a backup.
+pub fn load_key_from_keystore_bytes(
+    master_password: &str,
+    bytes: &[u8],
+) -> Result<[u8; EK_LEN], String> {
+    let mek = StoredKeystore::parse(bytes.to_vec())?.open_with_password(master_password)?;
+    Ok(*mek)
+}
+
+/// Loads the MEK through a recovery or keyfile slot.
+pub fn load_key_with_slot(
+    kind: SlotKind,
+    secret: &[u8],
+    path: &Path,
+) -> Result<[u8; EK_LEN], String> {
+    let mek = StoredKeystore::read(path)?.open_with(kind, secret)?;
+    Ok(*mek)
+}
+
+/// Loads the MEK and, when the keystore is legacy or weaker than the default parameters,
+/// rewrites it in the current format.
+///
+/// The upgrade is best effort: if it cannot be written, the old keystore stays in place and
+/// the MEK is still returned.
+pub fn unlock_key_vault(master_password: &str, path: &Path) -> Result<[u8; EK_LEN], String> {
+    let stored = StoredKeystore::read(path)?;
+    let mek = stored.open_with_password(master_password)?;
+    if stored.format().needs_upgrade(&KdfParams::default()) {
+        let _ = stored
+            .with_password(&mek, master_password)
+            .and_then(|file| file.to_bytes())
+            .and_then(|bytes| replace(path, &bytes));
+    }
+    Ok(*mek)
+}
+
+/// Reports the keystore layout and KDF parameters without needing the master password.
+pub fn keystore_format(path: &Path) -> Result<KeystoreFormat, String> {
+    Ok(StoredKeystore::read(path)?.format())
+}
+
+/// Lists the key slots without needing the master password.
+pub fn list_key_slots(path: &Path) -> Result<Vec<KeySlotInfo>, String> {
+    Ok(StoredKeystore::read(path)?.slots())
+}
+
+/// Adds a recovery or keyfile slot wrapping the MEK under `secret` and returns its id.
+///
+/// The master password authorizes the change; legacy keystores are converted to key slots.
+pub fn add_key_slot(
+    master_password: &str,
+    kind: SlotKind,
+    secret: &[u8],
+    path: &Path,
+) -> Result<u32, String> {
+    if kind == SlotKind::Password {
+        return Err("The master password slot is changed with 'rvault passwd'.".to_string());
+    }
+    let stored = StoredKeystore::read(path)?;
+    let mek = stored.open_with_password(master_password)?;
+    let mut file = match stored {
+        StoredKeystore::Slots(file) => file,
+        other => other.with_password(&mek, master_password)?,
+    };
+    let id = file.slots.iter().map(|slot| slot.id + 1).max().unwrap_or(0);
+    file.slots
+        .push(seal_slot(id, kind, secret, &mek, &KdfParams::default())?);
+    replace(path, &file.to_bytes()?)?;
+    Ok(id)
+}
+
+/// Removes the slot with `id`. The master password slot cannot be removed.
+pub fn remove_key_slot(master_password: &str, id: u32, path: &Path) -> Result<SlotKind, String> {
+    let stored = StoredKeystore::read(path)?;
+    stored.open_with_password(master_password)?;
+    let StoredKeystore::Slots(mut file) = stored else {
+        return Err(format!("Key slot {id} does not exist."));
+    };
+    let Some(position) = file.slots.iter().position(|slot| slot.id == id) else {
+        return Err(format!("Key slot {id} does not exist."));
+    };
+    let kind = file.slots[position].kind;
+    if kind == SlotKind::Password {
+        return Err("The master password slot cannot be removed.".to_string());
+    }
+    file.slots.remove(position);
+    replace(path, &file.to_bytes()?)?;
+    Ok(kind)
+}
+
+/// Reads a keyfile and returns the slot secret derived from it.
+pub fn keyfile_secret(path: &Path) -> Result<Zeroizing<[u8; 32]>, String> {
+    let contents = Zeroizing::new(
+        fs::read(path).map_err(|e| format!("read keyfile {}: {e}", path.display()))?,
+    );
+    if contents.is_empty() {
+        return Err(format!("Keyfile {} is empty.", path.display()));
+    }
+    Ok(Zeroizing::new(Sha256::digest(contents.as_slice()).into()))
+}
 
-    // 1. Parse the file: [32-byte salt][12-byte nonce][encrypted MEK]
+/// Writes a new random keyfile readable only by the current user. Fails if `path` exists.
+pub fn create_keyfile(path: &Path) -> Result<(), String> {
+    let mut contents = Zeroizing::new([0u8; KEYFILE_LEN]);
+    rand::rng().fill_bytes(contents.as_mut());
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .map_err(|e| format!("create keyfile {}: {e}", path.display()))?;
+    file.write_all(contents.as_slice())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("write keyfile {}: {e}", path.display()))
+}
+
+fn open_slot(slot: &KeySlot, secret: &[u8]) -> Result<Zeroizing<[u8; EK_LEN]>, String> {
+    if slot.kdf != KDF_ARGON2ID {
+        return Err(format!("Unsupported keystore KDF '{}'.", slot.kdf));
+    }
+    unwrap_mek(
+        secret,
+        &slot.kdf_params,
+        &slot.salt_b64,
+        &slot.nonce_b64,
+        &slot.wrapped_ek_b64,
+        &slot.aad()?,
+    )
+}
+
+fn open_v1(
+    master_password: &str,
+    file: &KeystoreFileV1,
+) -> Result<Zeroizing<[u8; EK_LEN]>, String> {
+    if file.kdf != KDF_ARGON2ID {
+        return Err(format!("Unsupported keystore KDF '{}'.", file.kdf));
+    }
+    unwrap_mek(
+        master_password.as_bytes(),
+        &file.kdf_params,
+        &file.salt_b64,
+        &file.nonce_b64,
+        &file.wrapped_ek_b64,
+        &file.aad()?,
+    )
+}
+
+fn unwrap_mek(
+    secret: &[u8],
+    kdf_params: &KdfParams,
+    salt_b64: &str,
+    nonce_b64: &str,
+    wrapped_b64: &str,
+    aad: &[u8],
+) -> Result<Zeroizing<[u8; EK_LEN]>, String> {
+    let salt = Base64
+        .decode(salt_b64)
+        .map_err(|_| "Invalid or corrupt vault file.".to_string())?;
+    let nonce = Base64
+        .decode(nonce_b64)
+        .map_err(|_| "Invalid or corrupt vault file.".to_string())?;
+    let wrapped = Base64
+        .decode(wrapped_b64)
+        .map_err(|_| "Invalid or corrupt vault file.".to_string())?;
+
+    let kek = derive_kek(secret, &salt, kdf_params)?;
+    let ciphertext =
+        Ciphertext::try_from_parts(&nonce, wrapped).map_err(|error| error.to_string())?;
+    let mek = decrypt_with_aad(&kek, &ciphertext, aad).map_err(|error| error.to_string())?;
+    let mek: [u8; EK_LEN] = mek
+        .expose()
+        .try_into()
+        .map_err(|_| "Decrypted key has incorrect length.".to_string())?;
+    Ok(Zeroizing::new(mek))
+}
+
+fn open_legacy(
+    master_password: &str,
+    file_bytes: &[u8],
+) -> Result<Zeroizing<[u8; EK_LEN]>, String> {
+    // 1. Parse the file: [16-byte salt][12-byte nonce][encrypted MEK]
     if file_bytes.len() < SALT_LEN + NONCE_LEN {
         return Err("Invalid or corrupt vault file.".to_string());
     }
@@ -114,7 +732,7 @@ pub fn load_key_from_vault(master_password: &str, path: &Path) -> Result<[u8; EK
         .hash_password_into(master_password.as_bytes(), salt, kek.as_mut())
         .map_err(|_| "Failed to derive key.".to_string())?;
 
-    // 3. Decrypt the MEK using the KEK.
+    // 3. Decrypt the MEK using the KEK. Legacy keystores store the MEK as base64 text.
     let kek = SecretKey::from_bytes(*kek);
     let ciphertext =
         Ciphertext::try_from_parts(nonce, encrypted_mek).map_err(|error| error.to_string())?;
@@ -125,8 +743,421 @@ pub fn load_key_from_vault(master_password: &str, path: &Path) -> Result<[u8; EK
             .map_err(|e| e.to_string())?,
     );
 
-    mek_bytes
+    let mek: [u8; EK_LEN] = mek_bytes
         .as_slice()
         .try_into()
-        .map_err(|_| "Decrypted key has incorrect length.".to_string())
+        .map_err(|_| "Decrypted key has incorrect length.".to_string())?;
+    Ok(Zeroizing::new(mek))
+}
+
+fn replace(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, bytes).map_err(|e| format!("write keystore: {e}"))?;
+    fs::rename(&tmp, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp);
+        format!("replace keystore: {e}")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_keystore() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("rvault-keystore-test-{}", rand::random::<u64>()))
+            .join(KEYSTORE_NAME)
+    }
+
+    #[test]
+    fn rewrapped_keystore_keeps_the_same_mek_under_the_new_password() {
+        let path = temporary_keystore();
+        create_key_vault("old-password", &path).unwrap();
+        let original = load_key_from_vault("old-password", &path).unwrap();
+
+        let bytes = rewrap_key_vault("old-password", "new-password", &path).unwrap();
+        assert_eq!(
+            load_key_from_vault("old-password", &path).unwrap(),
+            original
+        );
+
+        fs::write(&path, bytes).unwrap();
+        assert_eq!(
+            load_key_from_vault("new-password", &path).unwrap(),
+            original
+        );
+        assert!(load_key_from_vault("old-password", &path).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rewrap_rejects_the_wrong_current_password() {
+        let path = temporary_keystore();
+        create_key_vault("old-password", &path).unwrap();
+
+        assert!(rewrap_key_vault("wrong-password", "new-password", &path).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    fn write_legacy_keystore(master_password: &str, path: &Path) -> [u8; EK_LEN] {
+        let mek = crate::crypto::generate_raw_key();
+        let mut salt = [0u8; SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        let mut kek = [0u8; EK_LEN];
+        Argon2::default()
+            .hash_password_into(master_password.as_bytes(), &salt, &mut kek)
+            .unwrap();
+        let ciphertext =
+            crate::crypto::encrypt(&SecretKey::from_bytes(kek), Base64.encode(mek).as_bytes())
+                .unwrap();
+        let mut out = salt.to_vec();
+        out.extend_from_slice(ciphertext.nonce());
+        out.extend_from_slice(ciphertext.bytes());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, out).unwrap();
+        mek
+    }
+
+    #[test]
+    fn calibration_raises_memory_then_passes_up_to_the_target() {
+        // Simulate 10 ms per pass for every 64 MiB of memory.
+        let params = calibrate_kdf_with(Duration::from_millis(500), |params| {
+            Ok(Duration::from_millis(
+                10 * u64::from(params.m / (64 * 1024)),
+            ))
+        })
+        .unwrap();
+
+        assert_eq!(params.m, 1024 * 1024);
+        assert_eq!(params.t, 3);
+        assert_eq!(params.p, 1);
+
+        let params = calibrate_kdf_with(Duration::from_millis(500), |params| {
+            Ok(Duration::from_millis(
+                50 * u64::from(params.m / (64 * 1024)),
+            ))
+        })
+        .unwrap();
+
+        assert_eq!(params.m, 128 * 1024);
+        assert_eq!(params.t, 5);
+    }
+
+    #[test]
+    fn calibration_never_goes_below_the_default_cost() {
+        let params =
+            calibrate_kdf_with(Duration::from_millis(100), |_| Ok(Duration::from_secs(1))).unwrap();
+
+        assert_eq!(params, KdfParams::default());
+    }
+
+    fn cheap_params() -> KdfParams {
+        KdfParams {
+            t: 1,
+            m: 1024,
+            p: 1,
+        }
+    }
+
+    #[test]
+    fn new_keystores_use_the_versioned_format() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("password", &path, &cheap_params()).unwrap();
+
+        assert_eq!(
+            keystore_format(&path).unwrap(),
+            KeystoreFormat::Versioned {
+                version: KEYSTORE_VERSION,
+                kdf_params: cheap_params(),
+            }
+        );
+        assert!(load_key_from_vault("password", &path).is_ok());
+        assert!(load_key_from_vault("wrong-password", &path).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn legacy_keystores_are_still_readable() {
+        let path = temporary_keystore();
+        let mek = write_legacy_keystore("password", &path);
+
+        assert_eq!(keystore_format(&path).unwrap(), KeystoreFormat::Legacy);
+        assert_eq!(load_key_from_vault("password", &path).unwrap(), mek);
+        assert_eq!(keystore_format(&path).unwrap(), KeystoreFormat::Legacy);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unlock_upgrades_legacy_and_weak_keystores() {
+        let path = temporary_keystore();
+        let mek = write_legacy_keystore("password", &path);
+
+        assert_eq!(unlock_key_vault("password", &path).unwrap(), mek);
+        assert_eq!(
+            keystore_format(&path).unwrap(),
+            KeystoreFormat::Versioned {
+                version: KEYSTORE_VERSION,
+                kdf_params: KdfParams::default(),
+            }
+        );
+        assert_eq!(load_key_from_vault("password", &path).unwrap(), mek);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        let path = temporary_keystore();
+        create_key_vault_with_params("password", &path, &cheap_params()).unwrap();
+        let mek = load_key_from_vault("password", &path).unwrap();
+        assert_eq!(unlock_key_vault("password", &path).unwrap(), mek);
+        assert!(matches!(
+            keystore_format(&path).unwrap(),
+            KeystoreFormat::Versioned { kdf_params, .. } if kdf_params == KdfParams::default()
+        ));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn wrong_password_does_not_upgrade_a_legacy_keystore() {
+        let path = temporary_keystore();
+        write_legacy_keystore("password", &path);
+        let before = fs::read(&path).unwrap();
+
+        assert!(unlock_key_vault("wrong-password", &path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn tampered_header_is_rejected() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("password", &path, &cheap_params()).unwrap();
+        let mut file: KeystoreFile = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        file.slots[0].kdf_params.t = 2;
+        fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+
+        assert!(load_key_from_vault("password", &path).is_err());
+
+        file.slots[0].kdf_params.t = 1;
+        file.version = KEYSTORE_VERSION + 1;
+        fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+        let error = load_key_from_vault("password", &path).unwrap_err();
+        assert!(error.contains("Unsupported keystore version"));
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rewrap_keeps_stronger_kdf_params() {
+        let path = temporary_keystore();
+        let strong = KdfParams {
+            t: 4,
+            ..KdfParams::default()
+        };
+        create_key_vault_with_params("old-password", &path, &strong).unwrap();
+
+        let bytes = rewrap_key_vault("old-password", "new-password", &path).unwrap();
+        fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(
+            keystore_format(&path).unwrap(),
+            KeystoreFormat::Versioned { kdf_params, .. } if kdf_params == strong
+        ));
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    fn write_v1_keystore(master_password: &str, path: &Path) -> [u8; EK_LEN] {
+        let mek = crate::crypto::generate_raw_key();
+        let mut salt = [0u8; SALT_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        let mut file = KeystoreFileV1 {
+            magic: MAGIC.to_string(),
+            version: 1,
+            kdf: KDF_ARGON2ID.to_string(),
+            kdf_params: cheap_params(),
+            salt_b64: Base64.encode(salt),
+            nonce_b64: String::new(),
+            wrapped_ek_b64: String::new(),
+        };
+        let kek = derive_kek(master_password.as_bytes(), &salt, &cheap_params()).unwrap();
+        let ciphertext = encrypt_with_aad(&kek, &mek, &file.aad().unwrap()).unwrap();
+        file.nonce_b64 = Base64.encode(ciphertext.nonce());
+        file.wrapped_ek_b64 = Base64.encode(ciphertext.bytes());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, serde_json::to_vec(&file).unwrap()).unwrap();
+        mek
+    }
+
+    #[test]
+    fn single_password_v1_keystores_are_read_and_upgraded_to_slots() {
+        let path = temporary_keystore();
+        let mek = write_v1_keystore("password", &path);
+
+        assert_eq!(load_key_from_vault("password", &path).unwrap(), mek);
+        assert_eq!(unlock_key_vault("password", &path).unwrap(), mek);
+
+        assert_eq!(
+            list_key_slots(&path).unwrap(),
+            vec![KeySlotInfo {
+                id: 0,
+                kind: SlotKind::Password,
+                kdf_params: KdfParams::default(),
+            }]
+        );
+        assert_eq!(load_key_from_vault("password", &path).unwrap(), mek);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recovery_and_keyfile_slots_unwrap_the_same_mek() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("password", &path, &cheap_params()).unwrap();
+        let mek = load_key_from_vault("password", &path).unwrap();
+        let keyfile = path.with_file_name("rvault.key");
+        create_keyfile(&keyfile).unwrap();
+        let keyfile_secret = keyfile_secret(&keyfile).unwrap();
+
+        assert!(load_key_with_slot(SlotKind::Recovery, b"recovery", &path).is_err());
+        assert_eq!(
+            add_key_slot("password", SlotKind::Recovery, b"recovery", &path).unwrap(),
+            1
+        );
+        assert_eq!(
+            add_key_slot(
+                "password",
+                SlotKind::Keyfile,
+                keyfile_secret.as_slice(),
+                &path
+            )
+            .unwrap(),
+            2
+        );
+        assert!(add_key_slot("wrong-password", SlotKind::Recovery, b"other", &path).is_err());
+
+        assert_eq!(
+            load_key_with_slot(SlotKind::Recovery, b"recovery", &path).unwrap(),
+            mek
+        );
+        assert_eq!(
+            load_key_with_slot(SlotKind::Keyfile, keyfile_secret.as_slice(), &path).unwrap(),
+            mek
+        );
+        assert!(load_key_with_slot(SlotKind::Recovery, b"guess", &path).is_err());
+        assert!(load_key_with_slot(SlotKind::Keyfile, b"recovery", &path).is_err());
+        assert!(load_key_from_vault("recovery", &path).is_err());
+        assert_eq!(
+            list_key_slots(&path)
+                .unwrap()
+                .iter()
+                .map(|slot| (slot.id, slot.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, SlotKind::Password),
+                (1, SlotKind::Recovery),
+                (2, SlotKind::Keyfile),
+            ]
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn removing_slots_keeps_the_master_password_slot() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("password", &path, &cheap_params()).unwrap();
+        let id = add_key_slot("password", SlotKind::Recovery, b"recovery", &path).unwrap();
+
+        assert!(remove_key_slot("wrong-password", id, &path).is_err());
+        assert!(remove_key_slot("password", 0, &path).is_err());
+        assert!(remove_key_slot("password", 42, &path).is_err());
+        assert_eq!(
+            remove_key_slot("password", id, &path).unwrap(),
+            SlotKind::Recovery
+        );
+
+        assert!(load_key_with_slot(SlotKind::Recovery, b"recovery", &path).is_err());
+        assert_eq!(list_key_slots(&path).unwrap().len(), 1);
+        assert!(load_key_from_vault("password", &path).is_ok());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn password_rewrap_keeps_other_slots() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("old-password", &path, &cheap_params()).unwrap();
+        let mek = load_key_from_vault("old-password", &path).unwrap();
+        add_key_slot("old-password", SlotKind::Recovery, b"recovery", &path).unwrap();
+
+        let bytes = rewrap_key_vault("old-password", "new-password", &path).unwrap();
+        fs::write(&path, bytes).unwrap();
+
+        assert_eq!(load_key_from_vault("new-password", &path).unwrap(), mek);
+        assert_eq!(
+            load_key_with_slot(SlotKind::Recovery, b"recovery", &path).unwrap(),
+            mek
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn keyfiles_are_not_overwritten() {
+        let path = temporary_keystore();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let keyfile = path.with_file_name("rvault.key");
+        create_keyfile(&keyfile).unwrap();
+        let before = fs::read(&keyfile).unwrap();
+
+        assert_eq!(before.len(), KEYFILE_LEN);
+        assert!(create_keyfile(&keyfile).is_err());
+        assert_eq!(fs::read(&keyfile).unwrap(), before);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recovery_slot_resets_a_forgotten_password() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("forgotten", &path, &cheap_params()).unwrap();
+        let mek = load_key_from_vault("forgotten", &path).unwrap();
+        add_key_slot("forgotten", SlotKind::Recovery, b"recovery", &path).unwrap();
+
+        assert!(
+            reset_password_with_slot(SlotKind::Recovery, b"guess", "new-password", &path).is_err()
+        );
+        let bytes =
+            reset_password_with_slot(SlotKind::Recovery, b"recovery", "new-password", &path)
+                .unwrap();
+        fs::write(&path, bytes).unwrap();
+
+        assert_eq!(load_key_from_vault("new-password", &path).unwrap(), mek);
+        assert!(load_key_from_vault("forgotten", &path).is_err());
+        assert_eq!(
+            load_key_with_slot(SlotKind::Recovery, b"recovery", &path).unwrap(),
+            mek
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rotation_wraps_the_new_mek_and_drops_other_slots() {
+        let path = temporary_keystore();
+        create_key_vault_with_params("password", &path, &cheap_params()).unwrap();
+        add_key_slot("password", SlotKind::Recovery, b"recovery", &path).unwrap();
+        let new_mek = [9u8; EK_LEN];
+
+        assert!(rotate_key_vault("wrong-password", &new_mek, &path).is_err());
+        let (bytes, dropped) = rotate_key_vault("password", &new_mek, &path).unwrap();
+        fs::write(&path, bytes).unwrap();
+
+        assert_eq!(load_key_from_vault("password", &path).unwrap(), new_mek);
+        assert_eq!(
+            dropped.iter().map(|slot| slot.kind).collect::<Vec<_>>(),
+            vec![SlotKind::Recovery]
+        );
+        assert_eq!(list_key_slots(&path).unwrap().len(), 1);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
 }
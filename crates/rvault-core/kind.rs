@@ -0,0 +1,438 @@
+//! Kinds of entries beyond logins.
+//!
+//! Every entry records its [`EntryKind`]. A login stores its password as the secret; the other
+//! kinds store a [`TypedSecret`] in its place, serialized as JSON and encrypted like a password,
+//! so card numbers, identities and tokens never reach the database in plaintext.
+
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::secret::SecretBytes;
+
+/// What an entry holds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    /// A password for a platform.
+    #[default]
+    Login,
+    Note,
+    Card,
+    Identity,
+    ApiKey,
+}
+
+impl EntryKind {
+    pub const ALL: [Self; 5] = [
+        Self::Login,
+        Self::Note,
+        Self::Card,
+        Self::Identity,
+        Self::ApiKey,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Note => "note",
+            Self::Card => "card",
+            Self::Identity => "identity",
+            Self::ApiKey => "api-key",
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for EntryKind {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| format!("unknown entry kind: {name}"))
+    }
+}
+
+/// The secret of an entry that is not a login.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum TypedSecret {
+    Note(SecureNote),
+    Card(Card),
+    Identity(Identity),
+    ApiKey(ApiKey),
+}
+
+impl TypedSecret {
+    pub fn kind(&self) -> EntryKind {
+        match self {
+            Self::Note(_) => EntryKind::Note,
+            Self::Card(_) => EntryKind::Card,
+            Self::Identity(_) => EntryKind::Identity,
+            Self::ApiKey(_) => EntryKind::ApiKey,
+        }
+    }
+
+    /// JSON stored as the entry secret.
+    pub fn to_bytes(&self) -> SecretBytes {
+        SecretBytes::new(serde_json::to_vec(self).expect("typed secrets always serialize"))
+    }
+
+    /// Reads the secret of an entry of `kind`. Logins have no typed secret.
+    pub fn from_bytes(kind: EntryKind, bytes: &[u8]) -> Result<Self, String> {
+        let secret: Self = serde_json::from_slice(bytes)
+            .map_err(|error| format!("invalid {kind} entry: {error}"))?;
+        if secret.kind() != kind {
+            return Err(format!(
+                "entry holds a {} instead of a {kind}",
+                secret.kind()
+            ));
+        }
+        Ok(secret)
+    }
+
+    /// The value copied to the clipboard for this entry.
+    pub fn primary_value(&self) -> &str {
+        match self {
+            Self::Note(note) => &note.text,
+            Self::Card(card) => &card.number,
+            Self::Identity(identity) => &identity.full_name,
+            Self::ApiKey(key) => &key.token,
+        }
+    }
+
+    /// What [`Self::primary_value`] is, for messages.
+    pub fn primary_label(&self) -> &'static str {
+        match self {
+            Self::Note(_) => "Note",
+            Self::Card(_) => "Card number",
+            Self::Identity(_) => "Name",
+            Self::ApiKey(_) => "Token",
+        }
+    }
+
+    /// Whether a card or API key has expired by `today`.
+    pub fn is_expired(&self, today: NaiveDate) -> bool {
+        match self {
+            Self::Card(card) => card.expiry.is_expired(today),
+            Self::ApiKey(key) => key.expires_on.is_some_and(|date| date < today),
+            Self::Note(_) | Self::Identity(_) => false,
+        }
+    }
+
+    /// Labelled values for views, with whether each should be masked until revealed.
+    pub fn fields(&self) -> Vec<(&'static str, String, bool)> {
+        match self {
+            Self::Note(note) => vec![("Note", note.text.clone(), true)],
+            Self::Card(card) => vec![
+                ("Cardholder", card.holder.clone(), false),
+                ("Number", card.number.clone(), true),
+                ("Expiry", card.expiry.to_string(), false),
+                ("CVV", card.cvv.clone(), true),
+            ],
+            Self::Identity(identity) => vec![
+                ("Name", identity.full_name.clone(), false),
+                ("Email", identity.email.clone(), false),
+                ("Phone", identity.phone.clone(), false),
+                ("Address", identity.address.clone(), false),
+                ("City", identity.city.clone(), false),
+                ("Postal code", identity.postal_code.clone(), false),
+                ("Country", identity.country.clone(), false),
+            ],
+            Self::ApiKey(key) => vec![
+                ("Token", key.token.clone(), true),
+                (
+                    "Expires",
+                    key.expires_on
+                        .map_or_else(|| "never".to_string(), |date| date.to_string()),
+                    false,
+                ),
+            ],
+        }
+        .into_iter()
+        .filter(|(_, value, _)| !value.is_empty())
+        .collect()
+    }
+}
+
+/// Free text kept encrypted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecureNote {
+    pub text: String,
+}
+
+impl SecureNote {
+    pub fn new(text: impl Into<String>) -> Result<Self, String> {
+        let text = text.into();
+        if text.trim().is_empty() {
+            return Err("note is empty".to_string());
+        }
+        Ok(Self { text })
+    }
+}
+
+impl Drop for SecureNote {
+    fn drop(&mut self) {
+        self.text.zeroize();
+    }
+}
+
+/// A payment card. The number passes the Luhn check and is stored without separators.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Card {
+    pub holder: String,
+    pub number: String,
+    pub expiry: CardExpiry,
+    /// Empty when not stored.
+    #[serde(default)]
+    pub cvv: String,
+}
+
+impl Card {
+    /// Validates a card. Spaces and dashes in the number are dropped.
+    pub fn new(holder: &str, number: &str, expiry: CardExpiry, cvv: &str) -> Result<Self, String> {
+        let number: String = number.chars().filter(|c| *c != ' ' && *c != '-').collect();
+        if !(12..=19).contains(&number.len()) || !number.chars().all(|c| c.is_ascii_digit()) {
+            return Err("card number must have 12 to 19 digits".to_string());
+        }
+        if !luhn_valid(&number) {
+            return Err("card number fails the Luhn check".to_string());
+        }
+        let cvv = cvv.trim();
+        if !cvv.is_empty()
+            && (!(3..=4).contains(&cvv.len()) || !cvv.chars().all(|c| c.is_ascii_digit()))
+        {
+            return Err("CVV must have 3 or 4 digits".to_string());
+        }
+        Ok(Self {
+            holder: holder.trim().to_string(),
+            number,
+            expiry,
+            cvv: cvv.to_string(),
+        })
+    }
+
+    /// The number with all but its last four digits hidden.
+    pub fn masked_number(&self) -> String {
+        let visible = self.number.len().saturating_sub(4);
+        format!("•••• {}", &self.number[visible..])
+    }
+}
+
+impl Drop for Card {
+    fn drop(&mut self) {
+        self.number.zeroize();
+        self.cvv.zeroize();
+    }
+}
+
+/// Whether a string of ASCII digits passes the Luhn checksum.
+pub fn luhn_valid(digits: &str) -> bool {
+    if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(index, digit)| {
+            let digit = u32::from(digit - b'0');
+            if index % 2 == 1 {
+                let doubled = digit * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                digit
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Month and year after which a card is no longer valid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CardExpiry {
+    pub month: u32,
+    pub year: i32,
+}
+
+impl CardExpiry {
+    pub fn new(month: u32, year: i32) -> Result<Self, String> {
+        if !(1..=12).contains(&month) {
+            return Err(format!("expiry month must be 1 to 12, not {month}"));
+        }
+        Ok(Self { month, year })
+    }
+
+    /// Cards are valid through the last day of their expiry month.
+    pub fn is_expired(self, today: NaiveDate) -> bool {
+        (self.year, self.month) < (today.year(), today.month())
+    }
+}
+
+impl fmt::Display for CardExpiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}/{}", self.month, self.year)
+    }
+}
+
+impl FromStr for CardExpiry {
+    type Err = String;
+
+    /// Parses `MM/YY` or `MM/YYYY`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expiry must be MM/YY or MM/YYYY, not {value}");
+        let (month, year) = value.trim().split_once('/').ok_or_else(invalid)?;
+        let month: u32 = month.parse().map_err(|_| invalid())?;
+        let year = match year.len() {
+            2 => 2000 + year.parse::<i32>().map_err(|_| invalid())?,
+            4 => year.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        Self::new(month, year)
+    }
+}
+
+/// Personal details and a postal address. Every part is optional, but not all of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    #[serde(default)]
+    pub full_name: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub phone: String,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub city: String,
+    #[serde(default)]
+    pub postal_code: String,
+    #[serde(default)]
+    pub country: String,
+}
+
+impl Identity {
+    /// Trims every part and rejects identities without any.
+    pub fn validated(mut self) -> Result<Self, String> {
+        for part in self.parts_mut() {
+            *part = part.trim().to_string();
+        }
+        if self.parts_mut().iter().all(|part| part.is_empty()) {
+            return Err("identity is empty".to_string());
+        }
+        Ok(self)
+    }
+
+    fn parts_mut(&mut self) -> [&mut String; 7] {
+        [
+            &mut self.full_name,
+            &mut self.email,
+            &mut self.phone,
+            &mut self.address,
+            &mut self.city,
+            &mut self.postal_code,
+            &mut self.country,
+        ]
+    }
+}
+
+impl Drop for Identity {
+    fn drop(&mut self) {
+        for part in self.parts_mut() {
+            part.zeroize();
+        }
+    }
+}
+
+/// An API token with the date after which it stops working, if it has one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub token: String,
+    #[serde(default)]
+    pub expires_on: Option<NaiveDate>,
+}
+
+impl ApiKey {
+    pub fn new(token: &str, expires_on: Option<NaiveDate>) -> Result<Self, String> {
+        let token = token.trim();
+        if token.is_empty() {
+            return Err("API token is empty".to_string());
+        }
+        Ok(Self {
+            token: token.to_string(),
+            expires_on,
+        })
+    }
+}
+
+impl Drop for ApiKey {
+    fn drop(&mut self) {
+        self.token.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn cards_need_a_luhn_valid_number_and_a_real_expiry() {
+        let expiry: CardExpiry = "09/27".parse().unwrap();
+        assert_eq!(
+            expiry,
+            CardExpiry {
+                month: 9,
+                year: 2027
+            }
+        );
+        assert_eq!("09/2027".parse::<CardExpiry>().unwrap(), expiry);
+        assert!("13/27".parse::<CardExpiry>().is_err());
+        assert!("0927".parse::<CardExpiry>().is_err());
+
+        let card = Card::new(" Ada Lovelace ", "4111 1111-1111 1111", expiry, "123").unwrap();
+        assert_eq!(card.number, "4111111111111111");
+        assert_eq!(card.holder, "Ada Lovelace");
+        assert_eq!(card.masked_number(), "•••• 1111");
+        assert!(Card::new("", "4111 1111 1111 1112", expiry, "").is_err());
+        assert!(Card::new("", "4111", expiry, "").is_err());
+        assert!(Card::new("", "4111111111111111", expiry, "12a").is_err());
+
+        assert!(!expiry.is_expired(date("2027-09-30")));
+        assert!(expiry.is_expired(date("2027-10-01")));
+    }
+
+    #[test]
+    fn typed_secrets_round_trip_only_as_their_own_kind() {
+        let key = TypedSecret::ApiKey(ApiKey::new("tok_123", Some(date("2026-01-31"))).unwrap());
+        let bytes = key.to_bytes();
+        assert_eq!(
+            TypedSecret::from_bytes(EntryKind::ApiKey, bytes.expose()).unwrap(),
+            key
+        );
+        assert!(TypedSecret::from_bytes(EntryKind::Card, bytes.expose()).is_err());
+        assert!(TypedSecret::from_bytes(EntryKind::Note, b"plain password").is_err());
+        assert!(key.is_expired(date("2026-02-01")));
+        assert!(!key.is_expired(date("2026-01-31")));
+        assert_eq!(key.primary_value(), "tok_123");
+
+        let mut identity = Identity::default();
+        identity.full_name = " Ada ".to_string();
+        assert_eq!(identity.validated().unwrap().full_name, "Ada");
+        assert!(Identity::default().validated().is_err());
+        assert_eq!("api-key".parse::<EntryKind>().unwrap(), EntryKind::ApiKey);
+    }
+}
@@ -11,13 +11,20 @@ pub mod backup;
 pub mod binary;
 
 pub mod crypto;
+pub mod domain;
+pub mod emergency_kit;
+pub mod fuzzy;
 pub mod identity;
 pub mod keystore;
+pub mod kind;
+pub mod recovery;
+pub mod rotation;
 
 pub mod portable_export;
 pub mod secret;
 pub mod session;
 pub mod storage;
+pub mod totp;
 pub mod vault;
 
 pub mod clipboard;
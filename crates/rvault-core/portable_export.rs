@@ -1,14 +1,25 @@
 use crate::{
-    binary::{EXPORT_MAGIC, decode_envelope, encode_envelope},
+    binary::{EXPORT_MAGIC, VAULT_KEY_MAGIC, decode_envelope, encode_envelope},
     crypto::{decrypt_bytes_with_key, encrypt_bytes_with_key},
     identity::{IdentityKeypair, parse_public_code},
+    kind::EntryKind,
+    storage::CustomField,
 };
 use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
 use sha2::Sha256;
 use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
 
 const EXPORT_PAYLOAD_MAGIC: &[u8; 8] = b"RVEXPAY1";
+/// Version 2 appends notes and custom fields to every entry. Exports without either are still
+/// written as version 1 so older RVault releases can import them.
+const EXPORT_PAYLOAD_MAGIC_V2: &[u8; 8] = b"RVEXPAY2";
+/// Version 3 also appends the kind of every entry, whose password then holds its typed secret.
+/// It is only written when an export holds entries other than logins.
+const EXPORT_PAYLOAD_MAGIC_V3: &[u8; 8] = b"RVEXPAY3";
 const EXPORT_HKDF_SALT: &[u8] = b"rvault-export-v1";
+const VAULT_KEY_HKDF_SALT: &[u8] = b"rvault-vault-key-v1";
+const MEMBER_TAG_HKDF_SALT: &[u8] = b"rvault-member-tag-v1";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ExportEntry {
@@ -18,67 +29,165 @@ pub struct ExportEntry {
     pub pinned: bool,
     pub created_at: i64,
     pub updated_at: i64,
+    pub notes: String,
+    pub fields: Vec<CustomField>,
+    pub kind: EntryKind,
 }
 
 pub fn create_export_bytes(
     recipient_public_code: &str,
     entries: &[ExportEntry],
 ) -> Result<Vec<u8>, String> {
+    let payload = encode_export_payload(entries)?;
+    let fields = seal_for_recipient(EXPORT_HKDF_SALT, recipient_public_code, &payload)?;
+    Ok(encode_envelope(EXPORT_MAGIC, &fields))
+}
+
+pub fn decrypt_export_bytes(
+    identity: &IdentityKeypair,
+    bytes: &[u8],
+) -> Result<Vec<ExportEntry>, String> {
+    let envelope = decode_envelope(bytes, EXPORT_MAGIC)?;
+    if envelope.fields.len() != 4 {
+        return Err("invalid export envelope field count".to_string());
+    }
+    let payload = open_as_recipient(identity, EXPORT_HKDF_SALT, &envelope.fields, "export")?;
+    decode_export_payload(&payload)
+}
+
+/// Wraps the key of a shared vault to the identity behind `recipient_public_code`, with the same
+/// key agreement as exports but a key derivation of its own.
+pub fn wrap_vault_key(
+    recipient_public_code: &str,
+    vault_key: &[u8; 32],
+) -> Result<Vec<u8>, String> {
+    let fields = seal_for_recipient(VAULT_KEY_HKDF_SALT, recipient_public_code, vault_key)?;
+    Ok(encode_envelope(VAULT_KEY_MAGIC, &fields))
+}
+
+/// Opens a vault key wrapped with [`wrap_vault_key`] to `identity`.
+pub fn unwrap_vault_key(identity: &IdentityKeypair, bytes: &[u8]) -> Result<[u8; 32], String> {
+    let envelope = decode_envelope(bytes, VAULT_KEY_MAGIC)?;
+    if envelope.fields.len() != 4 {
+        return Err("invalid vault key envelope field count".to_string());
+    }
+    let key = open_as_recipient(identity, VAULT_KEY_HKDF_SALT, &envelope.fields, "vault key")?;
+    key.try_into()
+        .map_err(|_| "invalid vault key length".to_string())
+}
+
+/// Authenticates `message` from `author` to the identity behind `member_public_code`. The tag is
+/// an HMAC-SHA256 under a key agreed between the two static X25519 keys, so only the author and
+/// that member can produce it.
+pub fn member_tag(
+    author: &IdentityKeypair,
+    member_public_code: &str,
+    message: &[u8],
+) -> Result<[u8; 32], String> {
+    let member_public = parse_public_code(member_public_code)?;
+    let mac = member_mac(author, &member_public, &author.public_key, &member_public)?;
+    Ok(mac.chain_update(message).finalize().into_bytes().into())
+}
+
+/// Checks a tag [`member_tag`] wrote to `identity` as the author behind `author_public_code`.
+pub fn verify_member_tag(
+    identity: &IdentityKeypair,
+    author_public_code: &str,
+    message: &[u8],
+    tag: &[u8],
+) -> Result<(), String> {
+    let author_public = parse_public_code(author_public_code)?;
+    member_mac(
+        identity,
+        &author_public,
+        &author_public,
+        &identity.public_key,
+    )?
+    .chain_update(message)
+    .verify_slice(tag)
+    .map_err(|_| "member tag does not match".to_string())
+}
+
+fn member_mac(
+    identity: &IdentityKeypair,
+    peer_public: &[u8; 32],
+    author_public: &[u8],
+    member_public: &[u8],
+) -> Result<Hmac<Sha256>, String> {
+    let secret = StaticSecret::from(identity.private_key);
+    let shared = secret.diffie_hellman(&PublicKey::from(*peer_public));
+    let key = derive_export_key(
+        MEMBER_TAG_HKDF_SALT,
+        shared.as_bytes(),
+        author_public,
+        member_public,
+    )?;
+    Hmac::<Sha256>::new_from_slice(&key).map_err(|e| format!("member tag key: {e}"))
+}
+
+/// Encrypts `payload` to a recipient with an ephemeral X25519 key. Returns the envelope fields:
+/// the ephemeral public key, the recipient's public key, the nonce and the ciphertext.
+fn seal_for_recipient(
+    salt: &[u8],
+    recipient_public_code: &str,
+    payload: &[u8],
+) -> Result<[Vec<u8>; 4], String> {
     let recipient_public_key = parse_public_code(recipient_public_code)?;
     let recipient_public = PublicKey::from(recipient_public_key);
     let ephemeral_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
     let ephemeral_public = PublicKey::from(&ephemeral_secret);
     let shared = ephemeral_secret.diffie_hellman(&recipient_public);
     let key = derive_export_key(
+        salt,
         shared.as_bytes(),
         ephemeral_public.as_bytes(),
         &recipient_public_key,
     )?;
-    let payload = encode_export_payload(entries)?;
-    let (nonce, ciphertext) = encrypt_bytes_with_key(&key, &payload)?;
-    Ok(encode_envelope(
-        EXPORT_MAGIC,
-        &[
-            ephemeral_public.as_bytes().to_vec(),
-            recipient_public_key.to_vec(),
-            nonce.to_vec(),
-            ciphertext,
-        ],
-    ))
+    let (nonce, ciphertext) = encrypt_bytes_with_key(&key, payload)?;
+    Ok([
+        ephemeral_public.as_bytes().to_vec(),
+        recipient_public_key.to_vec(),
+        nonce.to_vec(),
+        ciphertext,
+    ])
 }
 
-pub fn decrypt_export_bytes(
+/// Decrypts the fields written by [`seal_for_recipient`]. `what` names the payload in errors.
+fn open_as_recipient(
     identity: &IdentityKeypair,
-    bytes: &[u8],
-) -> Result<Vec<ExportEntry>, String> {
-    let envelope = decode_envelope(bytes, EXPORT_MAGIC)?;
-    if envelope.fields.len() != 4 {
-        return Err("invalid export envelope field count".to_string());
-    }
-    let ephemeral_public: [u8; 32] = envelope.fields[0]
+    salt: &[u8],
+    fields: &[Vec<u8>],
+    what: &str,
+) -> Result<Vec<u8>, String> {
+    let ephemeral_public: [u8; 32] = fields[0]
         .clone()
         .try_into()
-        .map_err(|_| "invalid export ephemeral key length".to_string())?;
-    let recipient_public: [u8; 32] = envelope.fields[1]
+        .map_err(|_| format!("invalid {what} ephemeral key length"))?;
+    let recipient_public: [u8; 32] = fields[1]
         .clone()
         .try_into()
-        .map_err(|_| "invalid export recipient key length".to_string())?;
+        .map_err(|_| format!("invalid {what} recipient key length"))?;
     if recipient_public != identity.public_key {
-        return Err("export is encrypted for a different recipient".to_string());
+        return Err(format!("{what} is encrypted for a different recipient"));
     }
     let secret = StaticSecret::from(identity.private_key);
     let shared = secret.diffie_hellman(&PublicKey::from(ephemeral_public));
-    let key = derive_export_key(shared.as_bytes(), &ephemeral_public, &recipient_public)?;
-    let payload = decrypt_bytes_with_key(&key, &envelope.fields[2], &envelope.fields[3])?;
-    decode_export_payload(&payload)
+    let key = derive_export_key(
+        salt,
+        shared.as_bytes(),
+        &ephemeral_public,
+        &recipient_public,
+    )?;
+    decrypt_bytes_with_key(&key, &fields[2], &fields[3])
 }
 
 fn derive_export_key(
+    salt: &[u8],
     shared_secret: &[u8],
     ephemeral_public: &[u8],
     recipient_public: &[u8],
 ) -> Result<[u8; 32], String> {
-    let hk = Hkdf::<Sha256>::new(Some(EXPORT_HKDF_SALT), shared_secret);
+    let hk = Hkdf::<Sha256>::new(Some(salt), shared_secret);
     let mut info = Vec::with_capacity(ephemeral_public.len() + recipient_public.len());
     info.extend_from_slice(ephemeral_public);
     info.extend_from_slice(recipient_public);
@@ -89,8 +198,19 @@ fn derive_export_key(
 }
 
 fn encode_export_payload(entries: &[ExportEntry]) -> Result<Vec<u8>, String> {
+    let with_kinds = entries.iter().any(|entry| entry.kind != EntryKind::Login);
+    let with_details = with_kinds
+        || entries
+            .iter()
+            .any(|entry| !entry.notes.is_empty() || !entry.fields.is_empty());
     let mut out = Vec::new();
-    out.extend_from_slice(EXPORT_PAYLOAD_MAGIC);
+    out.extend_from_slice(if with_kinds {
+        EXPORT_PAYLOAD_MAGIC_V3
+    } else if with_details {
+        EXPORT_PAYLOAD_MAGIC_V2
+    } else {
+        EXPORT_PAYLOAD_MAGIC
+    });
     let count: u32 = entries
         .len()
         .try_into()
@@ -103,6 +223,23 @@ fn encode_export_payload(entries: &[ExportEntry]) -> Result<Vec<u8>, String> {
         out.push(u8::from(entry.pinned));
         out.extend_from_slice(&entry.created_at.to_le_bytes());
         out.extend_from_slice(&entry.updated_at.to_le_bytes());
+        if with_details {
+            push_string(&mut out, &entry.notes)?;
+            let field_count: u32 = entry
+                .fields
+                .len()
+                .try_into()
+                .map_err(|_| "too many export fields".to_string())?;
+            out.extend_from_slice(&field_count.to_le_bytes());
+            for field in &entry.fields {
+                push_string(&mut out, &field.name)?;
+                push_string(&mut out, &field.value)?;
+                out.push(u8::from(field.secret));
+            }
+        }
+        if with_kinds {
+            push_string(&mut out, entry.kind.name())?;
+        }
     }
     Ok(out)
 }
@@ -111,9 +248,16 @@ fn decode_export_payload(bytes: &[u8]) -> Result<Vec<ExportEntry>, String> {
     if bytes.len() < EXPORT_PAYLOAD_MAGIC.len() + 4 {
         return Err("truncated export payload".to_string());
     }
-    if &bytes[..EXPORT_PAYLOAD_MAGIC.len()] != EXPORT_PAYLOAD_MAGIC {
+    let magic = &bytes[..EXPORT_PAYLOAD_MAGIC.len()];
+    let (with_details, with_kinds) = if magic == EXPORT_PAYLOAD_MAGIC_V3 {
+        (true, true)
+    } else if magic == EXPORT_PAYLOAD_MAGIC_V2 {
+        (true, false)
+    } else if magic == EXPORT_PAYLOAD_MAGIC {
+        (false, false)
+    } else {
         return Err("invalid export payload magic".to_string());
-    }
+    };
     let mut cursor = EXPORT_PAYLOAD_MAGIC.len();
     let count = read_u32(bytes, &mut cursor)? as usize;
     let mut entries = Vec::with_capacity(count);
@@ -128,6 +272,26 @@ fn decode_export_payload(bytes: &[u8]) -> Result<Vec<ExportEntry>, String> {
         cursor += 1;
         let created_at = read_i64(bytes, &mut cursor)?;
         let updated_at = read_i64(bytes, &mut cursor)?;
+        let mut notes = String::new();
+        let mut fields = Vec::new();
+        if with_details {
+            notes = read_string(bytes, &mut cursor)?;
+            let field_count = read_u32(bytes, &mut cursor)?;
+            for _ in 0..field_count {
+                let name = read_string(bytes, &mut cursor)?;
+                let value = read_string(bytes, &mut cursor)?;
+                let Some(&secret) = bytes.get(cursor) else {
+                    return Err("truncated export payload field".to_string());
+                };
+                cursor += 1;
+                fields.push(CustomField::new(name, value, secret == 1));
+            }
+        }
+        let kind = if with_kinds {
+            read_string(bytes, &mut cursor)?.parse()?
+        } else {
+            EntryKind::Login
+        };
         entries.push(ExportEntry {
             platform,
             user_id,
@@ -135,6 +299,9 @@ fn decode_export_payload(bytes: &[u8]) -> Result<Vec<ExportEntry>, String> {
             pinned,
             created_at,
             updated_at,
+            notes,
+            fields,
+            kind,
         });
     }
     if cursor != bytes.len() {
@@ -203,6 +370,9 @@ mod tests {
             pinned: false,
             created_at: 10,
             updated_at: 20,
+            notes: String::new(),
+            fields: Vec::new(),
+            kind: EntryKind::Login,
         }]
     }
 
@@ -223,6 +393,40 @@ mod tests {
         assert_eq!(imported, entries());
     }
 
+    #[test]
+    fn payload_carries_notes_and_fields_and_stays_v1_without_them() {
+        let mut with_details = entries();
+        with_details[0].notes = "Security question: first pet".to_string();
+        with_details[0].fields = vec![
+            CustomField::new("Account number", "12345678", false),
+            CustomField::new("Answer", "Rex", true),
+        ];
+
+        let payload = encode_export_payload(&with_details).unwrap();
+        assert!(payload.starts_with(EXPORT_PAYLOAD_MAGIC_V2));
+        assert_eq!(decode_export_payload(&payload).unwrap(), with_details);
+
+        let payload = encode_export_payload(&entries()).unwrap();
+        assert!(payload.starts_with(EXPORT_PAYLOAD_MAGIC));
+        assert_eq!(decode_export_payload(&payload).unwrap(), entries());
+    }
+
+    #[test]
+    fn payload_carries_entry_kinds_only_when_needed() {
+        let mut typed = entries();
+        typed[0].kind = EntryKind::ApiKey;
+        typed[0].password = r#"{"kind":"api-key","token":"tok_123"}"#.to_string();
+
+        let payload = encode_export_payload(&typed).unwrap();
+        assert!(payload.starts_with(EXPORT_PAYLOAD_MAGIC_V3));
+        assert_eq!(decode_export_payload(&payload).unwrap(), typed);
+
+        let mut unknown = payload.clone();
+        let kind_at = unknown.len() - "api-key".len();
+        unknown[kind_at..].copy_from_slice(b"unknown");
+        assert!(decode_export_payload(&unknown).is_err());
+    }
+
     #[test]
     fn export_bytes_reject_wrong_recipient() {
         let recipient_bytes = generate_identity_bytes(&RECIPIENT_KEY).expect("recipient identity");
@@ -237,4 +441,39 @@ mod tests {
 
         assert!(err.contains("recipient") || err.contains("decrypt"));
     }
+
+    #[test]
+    fn vault_keys_unwrap_only_for_their_recipient() {
+        let recipient_bytes = generate_identity_bytes(&RECIPIENT_KEY).expect("recipient identity");
+        let recipient =
+            load_identity_from_bytes(&RECIPIENT_KEY, &recipient_bytes).expect("load recipient");
+        let wrong_bytes = generate_identity_bytes(&WRONG_KEY).expect("wrong identity");
+        let wrong = load_identity_from_bytes(&WRONG_KEY, &wrong_bytes).expect("load wrong");
+        let public_code = public_code_from_key(&recipient.public_key);
+
+        let wrapped = wrap_vault_key(&public_code, &[7; 32]).expect("wrap vault key");
+
+        assert!(wrapped.starts_with(crate::binary::VAULT_KEY_MAGIC));
+        assert_eq!(unwrap_vault_key(&recipient, &wrapped).unwrap(), [7; 32]);
+        assert!(unwrap_vault_key(&wrong, &wrapped).is_err());
+        let export = create_export_bytes(&public_code, &entries()).expect("create export");
+        assert!(unwrap_vault_key(&recipient, &export).is_err());
+    }
+
+    #[test]
+    fn member_tags_verify_only_between_author_and_member() {
+        let author_bytes = generate_identity_bytes(&RECIPIENT_KEY).expect("author identity");
+        let author = load_identity_from_bytes(&RECIPIENT_KEY, &author_bytes).expect("load author");
+        let member_bytes = generate_identity_bytes(&WRONG_KEY).expect("member identity");
+        let member = load_identity_from_bytes(&WRONG_KEY, &member_bytes).expect("load member");
+        let author_code = public_code_from_key(&author.public_key);
+        let member_code = public_code_from_key(&member.public_key);
+
+        let tag = member_tag(&author, &member_code, b"roster").expect("tag roster");
+
+        assert!(verify_member_tag(&member, &author_code, b"roster", &tag).is_ok());
+        assert!(verify_member_tag(&member, &author_code, b"rosters", &tag).is_err());
+        assert!(verify_member_tag(&member, &member_code, b"roster", &tag).is_err());
+        assert!(verify_member_tag(&author, &author_code, b"roster", &tag).is_err());
+    }
 }
@@ -0,0 +1,150 @@
+//! Human-readable recovery keys that can unwrap the MEK through a keystore recovery slot.
+//!
+//! A recovery key is 22 random bytes followed by a 3-byte SHA-256 checksum, written as eight
+//! groups of five Crockford base32 characters, e.g. `7K2QX-M4PZD-...`.
+
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+/// Number of random bytes carried by a recovery key.
+pub const RECOVERY_SECRET_LEN: usize = 22;
+const CHECKSUM_LEN: usize = 3;
+const ENCODED_LEN: usize = 40;
+const GROUP_LEN: usize = 5;
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A parsed recovery key. The secret bytes are zeroized on drop.
+pub struct RecoveryKey(Zeroizing<[u8; RECOVERY_SECRET_LEN]>);
+
+impl RecoveryKey {
+    /// Generates a new random recovery key.
+    pub fn generate() -> Self {
+        let mut secret = Zeroizing::new([0u8; RECOVERY_SECRET_LEN]);
+        rand::rng().fill_bytes(secret.as_mut());
+        Self(secret)
+    }
+
+    /// Parses a recovery key, ignoring case, spaces and dashes.
+    ///
+    /// Crockford look-alikes are accepted (`O` for `0`, `I`/`L` for `1`), and the checksum catches
+    /// most typos before any key derivation runs.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut symbols = Zeroizing::new(Vec::with_capacity(ENCODED_LEN));
+        for ch in input.chars() {
+            if ch == '-' || ch.is_whitespace() {
+                continue;
+            }
+            let ch = match ch.to_ascii_uppercase() {
+                'O' => '0',
+                'I' | 'L' => '1',
+                other => other,
+            };
+            let value = ALPHABET
+                .iter()
+                .position(|&symbol| symbol as char == ch)
+                .ok_or_else(|| "Recovery key contains an invalid character.".to_string())?;
+            symbols.push(value as u8);
+        }
+        if symbols.len() != ENCODED_LEN {
+            return Err("Recovery key has the wrong length.".to_string());
+        }
+
+        let mut bytes = Zeroizing::new([0u8; RECOVERY_SECRET_LEN + CHECKSUM_LEN]);
+        let mut buffer = 0u16;
+        let mut bits = 0;
+        let mut index = 0;
+        for &symbol in symbols.iter() {
+            buffer = (buffer << 5) | u16::from(symbol);
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes[index] = (buffer >> bits) as u8;
+                index += 1;
+            }
+        }
+
+        let (secret, checksum) = bytes.split_at(RECOVERY_SECRET_LEN);
+        if checksum != &Sha256::digest(secret)[..CHECKSUM_LEN] {
+            return Err("Recovery key checksum does not match. Check it for typos.".to_string());
+        }
+        let mut out = Zeroizing::new([0u8; RECOVERY_SECRET_LEN]);
+        out.copy_from_slice(secret);
+        Ok(Self(out))
+    }
+
+    /// Returns the secret bytes used as key slot input.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+
+    /// Formats the key as dash-separated groups for display or printing.
+    pub fn display(&self) -> Zeroizing<String> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(RECOVERY_SECRET_LEN + CHECKSUM_LEN));
+        bytes.extend_from_slice(self.0.as_slice());
+        bytes.extend_from_slice(&Sha256::digest(self.0.as_slice())[..CHECKSUM_LEN]);
+
+        let mut out = Zeroizing::new(String::with_capacity(ENCODED_LEN + ENCODED_LEN / GROUP_LEN));
+        let mut buffer = 0u16;
+        let mut bits = 0;
+        let mut written = 0;
+        for &byte in bytes.iter() {
+            buffer = (buffer << 8) | u16::from(byte);
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                if written > 0 && written % GROUP_LEN == 0 {
+                    out.push('-');
+                }
+                out.push(ALPHABET[usize::from((buffer >> bits) & 0x1f)] as char);
+                written += 1;
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_keys_round_trip_through_display() {
+        let key = RecoveryKey::generate();
+        let shown = key.display();
+
+        assert_eq!(shown.len(), ENCODED_LEN + ENCODED_LEN / GROUP_LEN - 1);
+        assert_eq!(shown.split('-').count(), ENCODED_LEN / GROUP_LEN);
+        assert_eq!(
+            RecoveryKey::parse(&shown).unwrap().as_bytes(),
+            key.as_bytes()
+        );
+    }
+
+    #[test]
+    fn parsing_is_lenient_about_case_separators_and_look_alikes() {
+        let key = RecoveryKey::generate();
+        let shown = key
+            .display()
+            .to_lowercase()
+            .replace('-', " ")
+            .replace('0', "o");
+
+        assert_eq!(
+            RecoveryKey::parse(&shown).unwrap().as_bytes(),
+            key.as_bytes()
+        );
+    }
+
+    #[test]
+    fn parsing_rejects_typos_and_bad_lengths() {
+        let key = RecoveryKey::generate();
+        let mut shown = key.display().to_string();
+        let replacement = if shown.starts_with('A') { "B" } else { "A" };
+        shown.replace_range(0..1, replacement);
+
+        assert!(RecoveryKey::parse(&shown).is_err());
+        assert!(RecoveryKey::parse("ABCDE-FGHJK").is_err());
+        assert!(RecoveryKey::parse("correct horse battery staple").is_err());
+    }
+}
@@ -0,0 +1,368 @@
+//! Master encryption key (MEK) rotation.
+//!
+//! Rotation re-encrypts every entry and `identity.rvault` under a new MEK and rewraps the
+//! keystore. The new keystore and identity are staged next to the originals and only swapped in
+//! once the SQLite transaction that re-encrypted the entries has committed. The database records
+//! a fingerprint of its key, so an interrupted rotation can be finished or discarded on the next
+//! unlock without the master password.
+
+use crate::{
+    crypto::generate_raw_key,
+    identity::{identity_path, reencrypt_identity_bytes},
+    keystore::{self, KeySlotInfo, keystore_path},
+    secret::SecretKey,
+    storage::Database,
+    vault::write_synced,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use zeroize::Zeroizing;
+
+const FINGERPRINT_DOMAIN: &[u8] = b"rvault-key-fingerprint-v1";
+
+/// Outcome of a completed rotation.
+pub struct RotationReport {
+    /// Number of entries re-encrypted across all vault tables.
+    pub entries: usize,
+    /// Recovery and keyfile slots that could not be rewrapped and were removed.
+    pub dropped_slots: Vec<KeySlotInfo>,
+}
+
+pub(crate) struct RotationFiles {
+    keystore: PathBuf,
+    identity: PathBuf,
+}
+
+impl RotationFiles {
+    pub(crate) fn current() -> Result<Self, String> {
+        Ok(Self {
+            keystore: keystore_path()?,
+            identity: identity_path()?,
+        })
+    }
+
+    fn staged_keystore(&self) -> PathBuf {
+        with_suffix(&self.keystore, ".rotating")
+    }
+
+    fn staged_identity(&self) -> PathBuf {
+        with_suffix(&self.identity, ".rotating")
+    }
+
+    /// Holds the new key's fingerprint; written last, so its presence means staging completed.
+    fn marker(&self) -> PathBuf {
+        with_suffix(&self.keystore, ".rotation")
+    }
+
+    fn has_staged_files(&self) -> bool {
+        self.marker().exists() || self.staged_keystore().exists() || self.staged_identity().exists()
+    }
+}
+
+/// Returns a non-secret fingerprint identifying `key`.
+pub fn key_fingerprint(key: &SecretKey) -> String {
+    let digest = Sha256::new()
+        .chain_update(FINGERPRINT_DOMAIN)
+        .chain_update(key.as_bytes())
+        .finalize();
+    digest[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+pub(crate) fn rotate_master_key_at(
+    db: &Database,
+    files: &RotationFiles,
+    master_password: &str,
+) -> Result<RotationReport, String> {
+    let old_key = SecretKey::from_bytes(keystore::load_key_from_vault(
+        master_password,
+        &files.keystore,
+    )?);
+    let new_key = SecretKey::from_bytes(*Zeroizing::new(generate_raw_key()));
+    let (keystore_bytes, dropped_slots) =
+        keystore::rotate_key_vault(master_password, new_key.as_bytes(), &files.keystore)?;
+    let identity_bytes = if files.identity.exists() {
+        let bytes = fs::read(&files.identity).map_err(|e| format!("read identity: {e}"))?;
+        Some(reencrypt_identity_bytes(
+            old_key.as_bytes(),
+            new_key.as_bytes(),
+            &bytes,
+        )?)
+    } else {
+        None
+    };
+    let fingerprint = key_fingerprint(&new_key);
+
+    let entries = db
+        .reencrypt_entries(&old_key, &new_key, &fingerprint, || {
+            stage(
+                files,
+                &keystore_bytes,
+                identity_bytes.as_deref(),
+                &fingerprint,
+            )
+        })
+        .map_err(|error| {
+            discard_staged(files);
+            match error {
+                crate::storage::StorageError::Crypto(_) => {
+                    "An entry could not be decrypted with the current key; nothing was changed."
+                        .to_string()
+                }
+                error => format!("Re-encrypting entries failed: {error}"),
+            }
+        })?;
+    // The database now uses the new key; from here on the staged files must win.
+    finish(files)?;
+    Ok(RotationReport {
+        entries,
+        dropped_slots,
+    })
+}
+
+/// Finishes or discards a rotation interrupted by a crash.
+///
+/// `database_fingerprint` is only called when staged files exist. If it matches the staged
+/// marker the database committed, so the staged files are moved into place; otherwise they are
+/// discarded and the old keystore stays valid.
+pub(crate) fn recover_interrupted_rotation(
+    files: &RotationFiles,
+    database_fingerprint: impl FnOnce() -> Result<Option<String>, String>,
+) -> Result<(), String> {
+    if !files.has_staged_files() {
+        return Ok(());
+    }
+    let staged_fingerprint = fs::read_to_string(files.marker()).ok();
+    let committed = match staged_fingerprint {
+        Some(staged) => database_fingerprint()?.as_deref() == Some(staged.trim()),
+        None => false,
+    };
+    if committed {
+        finish(files)
+    } else {
+        discard_staged(files);
+        Ok(())
+    }
+}
+
+fn stage(
+    files: &RotationFiles,
+    keystore_bytes: &[u8],
+    identity_bytes: Option<&[u8]>,
+    fingerprint: &str,
+) -> std::io::Result<()> {
+    let io_error = |message: String| std::io::Error::other(message);
+    write_synced(&files.staged_keystore(), keystore_bytes).map_err(io_error)?;
+    if let Some(identity_bytes) = identity_bytes {
+        write_synced(&files.staged_identity(), identity_bytes).map_err(io_error)?;
+    }
+    write_synced(&files.marker(), fingerprint.as_bytes()).map_err(io_error)
+}
+
+fn finish(files: &RotationFiles) -> Result<(), String> {
+    let staged_identity = files.staged_identity();
+    if staged_identity.exists() {
+        fs::rename(&staged_identity, &files.identity)
+            .map_err(|e| format!("replace identity: {e}"))?;
+    }
+    let staged_keystore = files.staged_keystore();
+    if staged_keystore.exists() {
+        fs::rename(&staged_keystore, &files.keystore)
+            .map_err(|e| format!("replace keystore: {e}"))?;
+    }
+    fs::remove_file(files.marker()).map_err(|e| format!("remove rotation marker: {e}"))
+}
+
+fn discard_staged(files: &RotationFiles) {
+    // The marker goes first so a partially discarded rotation is never mistaken for a complete one.
+    let _ = fs::remove_file(files.marker());
+    let _ = fs::remove_file(files.staged_keystore());
+    let _ = fs::remove_file(files.staged_identity());
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{generate_identity_bytes, load_identity_from_bytes};
+    use crate::keystore::{SlotKind, add_key_slot, create_key_vault, load_key_from_vault};
+    use crate::storage::{EntryRepository, EntrySelector, NewEntry};
+
+    struct Fixture {
+        root: PathBuf,
+        files: RotationFiles,
+        db: Database,
+        old_key: SecretKey,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn fixture() -> Fixture {
+        let root =
+            std::env::temp_dir().join(format!("rvault-rotation-test-{}", rand::random::<u64>()));
+        fs::create_dir_all(&root).unwrap();
+        let files = RotationFiles {
+            keystore: root.join("keystore.rvault"),
+            identity: root.join("identity.rvault"),
+        };
+        create_key_vault("password", &files.keystore).unwrap();
+        let old_key =
+            SecretKey::from_bytes(load_key_from_vault("password", &files.keystore).unwrap());
+        fs::write(
+            &files.identity,
+            generate_identity_bytes(old_key.as_bytes()).unwrap(),
+        )
+        .unwrap();
+        let db = Database::open(&root.join("vault.sqlite")).unwrap();
+        EntryRepository::new(&db, None)
+            .unwrap()
+            .add(&old_key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        EntryRepository::new(&db, Some("work".to_string()))
+            .unwrap()
+            .add(&old_key, NewEntry::new("gitlab", "bob", b"two"))
+            .unwrap();
+        Fixture {
+            root,
+            files,
+            db,
+            old_key,
+        }
+    }
+
+    fn secret(
+        db: &Database,
+        table: Option<&str>,
+        key: &SecretKey,
+        platform: &str,
+        user: &str,
+    ) -> Vec<u8> {
+        EntryRepository::new(db, table.map(str::to_string))
+            .unwrap()
+            .get(key, EntrySelector::new(platform, user))
+            .unwrap()
+            .secret
+            .expose()
+            .to_vec()
+    }
+
+    #[test]
+    fn rotation_moves_entries_identity_and_keystore_to_a_new_key() {
+        let fixture = fixture();
+        add_key_slot(
+            "password",
+            SlotKind::Recovery,
+            b"recovery",
+            &fixture.files.keystore,
+        )
+        .unwrap();
+        let identity = load_identity_from_bytes(
+            fixture.old_key.as_bytes(),
+            &fs::read(&fixture.files.identity).unwrap(),
+        )
+        .unwrap();
+
+        let report = rotate_master_key_at(&fixture.db, &fixture.files, "password").unwrap();
+
+        let new_key = SecretKey::from_bytes(
+            load_key_from_vault("password", &fixture.files.keystore).unwrap(),
+        );
+        assert_ne!(new_key.as_bytes(), fixture.old_key.as_bytes());
+        assert_eq!(report.entries, 2);
+        assert_eq!(report.dropped_slots.len(), 1);
+        assert_eq!(
+            secret(&fixture.db, None, &new_key, "github", "alice"),
+            b"one"
+        );
+        assert_eq!(
+            secret(&fixture.db, Some("work"), &new_key, "gitlab", "bob"),
+            b"two"
+        );
+        assert_eq!(
+            load_identity_from_bytes(
+                new_key.as_bytes(),
+                &fs::read(&fixture.files.identity).unwrap()
+            )
+            .unwrap(),
+            identity
+        );
+        assert_eq!(
+            fixture.db.key_fingerprint().unwrap(),
+            Some(key_fingerprint(&new_key))
+        );
+        assert!(!fixture.files.has_staged_files());
+    }
+
+    #[test]
+    fn wrong_password_changes_nothing() {
+        let fixture = fixture();
+        let keystore = fs::read(&fixture.files.keystore).unwrap();
+
+        assert!(rotate_master_key_at(&fixture.db, &fixture.files, "wrong").is_err());
+
+        assert_eq!(fs::read(&fixture.files.keystore).unwrap(), keystore);
+        assert_eq!(
+            secret(&fixture.db, None, &fixture.old_key, "github", "alice"),
+            b"one"
+        );
+        assert!(!fixture.files.has_staged_files());
+    }
+
+    #[test]
+    fn recovery_discards_staged_files_when_the_database_did_not_commit() {
+        let fixture = fixture();
+        let keystore = fs::read(&fixture.files.keystore).unwrap();
+        stage(
+            &fixture.files,
+            b"new keystore",
+            Some(b"new identity"),
+            "new",
+        )
+        .unwrap();
+
+        recover_interrupted_rotation(&fixture.files, || Ok(None)).unwrap();
+
+        assert!(!fixture.files.has_staged_files());
+        assert_eq!(fs::read(&fixture.files.keystore).unwrap(), keystore);
+        assert!(load_key_from_vault("password", &fixture.files.keystore).is_ok());
+    }
+
+    #[test]
+    fn recovery_finishes_a_rotation_whose_database_committed() {
+        let fixture = fixture();
+        stage(
+            &fixture.files,
+            b"new keystore",
+            Some(b"new identity"),
+            "new",
+        )
+        .unwrap();
+
+        recover_interrupted_rotation(&fixture.files, || Ok(Some("new".to_string()))).unwrap();
+
+        assert!(!fixture.files.has_staged_files());
+        assert_eq!(fs::read(&fixture.files.keystore).unwrap(), b"new keystore");
+        assert_eq!(fs::read(&fixture.files.identity).unwrap(), b"new identity");
+    }
+
+    #[test]
+    fn recovery_does_not_touch_the_database_without_staged_files() {
+        let fixture = fixture();
+
+        recover_interrupted_rotation(&fixture.files, || panic!("database opened")).unwrap();
+    }
+}
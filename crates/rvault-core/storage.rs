@@ -1,20 +1,48 @@
 use crate::{clipboard::copy_text, error::DatabaseError, secret::SecretKey, vault::VaultEntry};
 use chrono::Utc;
 use directories::ProjectDirs;
-use rusqlite::{Connection, params};
-use std::path::PathBuf;
+use rusqlite::{Connection, OpenFlags, OptionalExtension, params};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+mod audit;
+mod batch;
+mod details;
 mod error;
+mod history;
+mod merge;
+mod metadata;
 mod migration;
 mod repository;
-
+mod rotation;
+mod search;
+mod sharing;
+mod sync;
+mod tags;
+mod trash;
+mod vaults;
+
+pub use audit::{
+    AuditEvent, AuditFilter, AuditIntegrity, AuditOperation, AuditRecord, AuditSource,
+};
+pub use batch::{BatchOp, BatchReport};
+pub use details::{CustomField, EntryDetails};
 pub use error::StorageError;
+pub use history::{DEFAULT_HISTORY_DEPTH, HistoryEntry};
+pub use merge::{MergeEntry, MergeOutcome, MergePlan};
 pub use repository::{
     DecryptedEntry, EntryMetadata, EntryRepository, EntrySelector, EntryUpdate, NewEntry,
 };
+pub use search::SearchHit;
+pub use sharing::VaultMember;
+pub use sync::{DeviceLog, GitSyncReport, SyncReport, SyncStatus};
+pub use trash::{DEFAULT_TRASH_RETENTION_DAYS, TrashedEntry};
+pub use vaults::{DEFAULT_VAULT, VaultInfo};
 
 const CURRENT_DB_PATH: &str = "RVAULT_CURRENT_DB_PATH";
 const CURRENT_VAULT_NAME: &str = "RVAULT_CURRENT_VAULT_NAME";
+/// How long a connection waits for another connection's write lock.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Database {
     connection: Connection,
@@ -22,9 +50,44 @@ pub struct Database {
 impl Database {
     pub fn new() -> Result<Self, DatabaseError> {
         let final_path = database_path()?;
-        let connection = Connection::open(&final_path)?;
+        Self::open(&final_path)
+    }
+
+    /// Opens the database file at `path` instead of the default location.
+    ///
+    /// The database is switched to write-ahead logging so readers never block the writer, and
+    /// a connection waits up to [`BUSY_TIMEOUT`] for another process's write to finish instead
+    /// of failing with "database is locked".
+    pub fn open(path: &Path) -> Result<Self, DatabaseError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
         Ok(Self { connection })
     }
+
+    /// Opens a copy of the database at `path`, written to the new file `copy`.
+    ///
+    /// The original is opened read-only, so schema migrations, trash purging and the switch to
+    /// WAL mode only ever touch the copy. Used to read another machine's database. The copy is
+    /// readable by its owner only.
+    pub fn open_copy(path: &Path, copy: &Path) -> Result<Self, DatabaseError> {
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        // VACUUM INTO fills an empty file and keeps its permissions.
+        options.open(copy)?;
+        let source = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        source.busy_timeout(BUSY_TIMEOUT)?;
+        source.execute("VACUUM INTO ?1", [copy.to_string_lossy()])?;
+        Self::open(copy)
+    }
 }
 
 pub fn database_path() -> Result<PathBuf, DatabaseError> {
@@ -40,47 +103,49 @@ pub fn database_path() -> Result<PathBuf, DatabaseError> {
 
 pub struct Table {
     table_name: String,
+    encrypted_metadata: bool,
 }
 impl Table {
     pub fn new(db: &Database, table_name: Option<String>) -> Result<Self, DatabaseError> {
         let connection = &db.connection;
-        let full_table_name = match table_name {
-            Some(name) => {
-                if Self::is_valid_identifier(&name) {
-                    name
-                } else {
-                    return Err(DatabaseError::Sqlite(
-                        rusqlite::Error::InvalidParameterName(name),
-                    ));
-                }
-            }
-            None => String::from("main"),
+        let name = table_name.unwrap_or_else(|| String::from(DEFAULT_VAULT));
+        if !vaults::is_valid_name(&name) {
+            return Err(DatabaseError::Sqlite(
+                rusqlite::Error::InvalidParameterName(name),
+            ));
+        }
+        // Files from before the vault registry name each table after its vault, and their
+        // oldest migrations only upgrade the table being opened.
+        let legacy_table = if Self::is_valid_identifier(&name) && table_exists(connection, &name)? {
+            name.as_str()
+        } else {
+            DEFAULT_VAULT
         };
-        let query = format!(
-            "CREATE TABLE IF NOT EXISTS {} (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                platform TEXT NOT NULL,
-                user_id TEXT NOT NULL,
-                password TEXT NOT NULL,
-                nonce TEXT,
-                salt TEXT,
-                pinned BOOLEAN DEFAULT FALSE,
-                created_at INTEGER DEFAULT 0,
-                updated_at INTEGER DEFAULT 0,
-                UNIQUE(platform, user_id)
-                )",
-            full_table_name
-        );
-        connection.execute(&query, [])?;
-        migration::migrate(connection, &full_table_name)?;
+        create_entry_table(connection, legacy_table)?;
+        migration::migrate(connection, legacy_table)?;
+        let full_table_name = vaults::open(connection, &name)?;
+        let encrypted_metadata = metadata::is_encrypted(connection, &full_table_name)?;
         Ok(Self {
             table_name: full_table_name,
+            encrypted_metadata,
         })
     }
+
+    /// The deprecated row APIs address entries by plaintext identity and cannot serve tables
+    /// whose metadata is encrypted.
+    fn require_plaintext_metadata(&self) -> Result<(), DatabaseError> {
+        if self.encrypted_metadata {
+            return Err(map_storage_error(StorageError::MetadataLocked));
+        }
+        Ok(())
+    }
     #[deprecated(
         note = "no equally safe replacement exists; use EntryRepository::add with SecretKey"
     )]
     pub fn add_entry(&self, db: &Database, platform: String, id_and_password: String) {
+        if self.encrypted_metadata {
+            return;
+        }
         let (user_id, password) = id_and_password.split_once(':').unwrap();
         let query = format!(
             "INSERT INTO {} (platform,user_id,password)
@@ -108,10 +173,11 @@ impl Table {
         platform: String,
         user_id: String,
     ) -> Result<(), DatabaseError> {
+        self.require_plaintext_metadata()?;
         // Legacy/plaintext path: keep behavior for existing rows
         let query: String = format!(
             "SELECT password FROM {}
-             WHERE platform = (?1) AND user_id = (?2)",
+             WHERE platform = (?1) AND user_id = (?2) AND deleted_at IS NULL",
             &self.table_name
         );
         let password_result =
@@ -176,6 +242,7 @@ impl Table {
         user_id: String,
         password: String,
     ) -> Result<(), DatabaseError> {
+        self.require_plaintext_metadata()?;
         let key = secret_key_from_slice(encryption_key)?;
         let (ciphertext, nonce, salt) =
             repository::encrypt_entry(&key, password.as_bytes()).map_err(map_storage_error)?;
@@ -183,18 +250,29 @@ impl Table {
         let now = Utc::now().timestamp();
 
         let query = format!(
-            "INSERT INTO {} (platform, user_id, password, nonce, salt, pinned, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, FALSE, ?6, ?7)
+            "INSERT INTO {} (platform, user_id, password, nonce, salt, key_scheme, pinned, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, FALSE, ?7, ?8)
              ON CONFLICT(platform, user_id) DO UPDATE SET
              password = ?3,
              nonce = ?4,
              salt = ?5,
-             updated_at = ?7;",
+             key_scheme = ?6,
+             updated_at = ?8,
+             deleted_at = NULL;",
              &self.table_name
         );
         db.connection.execute(
             &query,
-            params![platform, user_id, ciphertext, nonce, salt, now, now],
+            params![
+                platform,
+                user_id,
+                ciphertext,
+                nonce,
+                salt,
+                repository::KeyScheme::CURRENT,
+                now,
+                now
+            ],
         )?;
         Ok(())
     }
@@ -215,15 +293,16 @@ impl Table {
         platform: String,
         user_id: String,
     ) -> Result<(), DatabaseError> {
+        self.require_plaintext_metadata()?;
         let query = format!(
-            "DELETE FROM {}
-             WHERE platform = (?1) AND user_id = (?2)
+            "UPDATE {} SET deleted_at = ?3
+             WHERE platform = (?1) AND user_id = (?2) AND deleted_at IS NULL
             ",
             &self.table_name
         );
         let affected = db
             .connection
-            .execute(&query, [platform.to_string(), user_id.to_string()])?;
+            .execute(&query, params![platform, user_id, Utc::now().timestamp()])?;
         if affected == 0 {
             return Err(DatabaseError::Sqlite(rusqlite::Error::QueryReturnedNoRows));
         }
@@ -248,8 +327,9 @@ impl Table {
         platform: &str,
         user_id: &str,
     ) -> Result<bool, DatabaseError> {
+        self.require_plaintext_metadata()?;
         let query = format!(
-            "SELECT EXISTS(SELECT 1 FROM {} WHERE platform = ?1 AND user_id = ?2)",
+            "SELECT EXISTS(SELECT 1 FROM {} WHERE platform = ?1 AND user_id = ?2 AND deleted_at IS NULL)",
             &self.table_name
         );
         let exists: bool = db
@@ -259,7 +339,7 @@ impl Table {
     }
 
     #[deprecated(
-        note = "no typed replacement preserves imported timestamps and pin state; isolate this import-only adapter before 2.0"
+        note = "use EntryRepository::apply_batch with BatchOp::Import, which also keeps imported timestamps and pin state"
     )]
     pub fn import_entry_with_key_result(
         &self,
@@ -277,8 +357,26 @@ impl Table {
         entry: &crate::portable_export::ExportEntry,
     ) -> Result<(), DatabaseError> {
         let key = secret_key_from_slice(encryption_key)?;
-        let (ciphertext, nonce, salt) = repository::encrypt_entry(&key, entry.password.as_bytes())
-            .map_err(map_storage_error)?;
+        self.import_entry(&db.connection, &key, entry)
+            .map_err(map_storage_error)
+    }
+
+    /// Adds an exported entry, or overwrites the entry with its identity, keeping the
+    /// timestamps, pin and kind of the export.
+    fn import_entry(
+        &self,
+        connection: &Connection,
+        key: &SecretKey,
+        entry: &crate::portable_export::ExportEntry,
+    ) -> Result<(), StorageError> {
+        let cipher = self
+            .encrypted_metadata
+            .then(|| metadata::MetadataCipher::new(key))
+            .transpose()?;
+        let identity = self.stored_identity(cipher.as_ref(), &entry.platform, &entry.user_id)?;
+        repository::check_secret(entry.kind, entry.password.as_bytes())?;
+        let (ciphertext, nonce, salt) = repository::encrypt_entry(key, entry.password.as_bytes())?;
+        let details = details::seal(key, &entry.notes, &entry.fields)?;
         let now = Utc::now().timestamp();
         let created_at = if entry.created_at > 0 {
             entry.created_at
@@ -291,27 +389,36 @@ impl Table {
             now
         };
         let query = format!(
-            "INSERT INTO {} (platform, user_id, password, nonce, salt, pinned, created_at, updated_at)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "INSERT INTO {} (platform, user_id, password, nonce, salt, key_scheme, pinned, created_at, updated_at, metadata, details, kind)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
              ON CONFLICT(platform, user_id) DO UPDATE SET
              password = ?3,
              nonce = ?4,
              salt = ?5,
-             pinned = ?6,
-             updated_at = ?8;",
+             key_scheme = ?6,
+             pinned = ?7,
+             updated_at = ?9,
+             metadata = ?10,
+             details = ?11,
+             kind = ?12,
+             deleted_at = NULL;",
             &self.table_name
         );
-        db.connection.execute(
+        connection.execute(
             &query,
             params![
-                entry.platform,
-                entry.user_id,
+                identity.platform,
+                identity.user_id,
                 ciphertext,
                 nonce,
                 salt,
+                repository::KeyScheme::CURRENT,
                 entry.pinned,
                 created_at,
-                updated_at
+                updated_at,
+                identity.metadata,
+                details,
+                entry.kind
             ],
         )?;
         Ok(())
@@ -348,6 +455,7 @@ impl Table {
         new_user_id: &str,
         new_password: &str,
     ) -> Result<(), DatabaseError> {
+        self.require_plaintext_metadata()?;
         let key = secret_key_from_slice(encryption_key)?;
         let (ciphertext, nonce, salt) =
             repository::encrypt_entry(&key, new_password.as_bytes()).map_err(map_storage_error)?;
@@ -357,7 +465,7 @@ impl Table {
         // We must ensure that if we are changing the user_id, the new user_id doesn't already exist for this platform
         if old_user_id != new_user_id {
             let check_query = format!(
-                "SELECT COUNT(*) FROM {} WHERE platform = ?1 AND user_id = ?2",
+                "SELECT COUNT(*) FROM {} WHERE platform = ?1 AND user_id = ?2 AND deleted_at IS NULL",
                 &self.table_name
             );
             let count: i64 =
@@ -372,7 +480,7 @@ impl Table {
         }
 
         let query = format!(
-            "UPDATE {} SET user_id = ?1, password = ?2, nonce = ?3, salt = ?4, updated_at = ?5 WHERE platform = ?6 AND user_id = ?7",
+            "UPDATE {} SET user_id = ?1, password = ?2, nonce = ?3, salt = ?4, key_scheme = ?5, updated_at = ?6 WHERE platform = ?7 AND user_id = ?8 AND deleted_at IS NULL",
             &self.table_name
         );
 
@@ -383,6 +491,7 @@ impl Table {
                 ciphertext,
                 nonce,
                 salt,
+                repository::KeyScheme::CURRENT,
                 now,
                 platform,
                 old_user_id
@@ -411,9 +520,10 @@ impl Table {
         platform: String,
         user_id: String,
     ) -> Result<bool, DatabaseError> {
+        self.require_plaintext_metadata()?;
         // Check current state
         let query_check = format!(
-            "SELECT pinned FROM {} WHERE platform = ?1 AND user_id = ?2",
+            "SELECT pinned FROM {} WHERE platform = ?1 AND user_id = ?2 AND deleted_at IS NULL",
             &self.table_name
         );
         let current_pinned: bool =
@@ -423,7 +533,7 @@ impl Table {
         if !current_pinned {
             // Check cap
             let query_count = format!(
-                "SELECT COUNT(*) FROM {} WHERE pinned = TRUE",
+                "SELECT COUNT(*) FROM {} WHERE pinned = TRUE AND deleted_at IS NULL",
                 &self.table_name
             );
             let count: i64 = db
@@ -436,7 +546,7 @@ impl Table {
 
         let new_state = !current_pinned;
         let query_update = format!(
-            "UPDATE {} SET pinned = ?1 WHERE platform = ?2 AND user_id = ?3",
+            "UPDATE {} SET pinned = ?1 WHERE platform = ?2 AND user_id = ?3 AND deleted_at IS NULL",
             &self.table_name
         );
         let affected = db
@@ -468,8 +578,9 @@ impl Table {
         platform: String,
         user_id: String,
     ) -> Result<String, DatabaseError> {
+        self.require_plaintext_metadata()?;
         let query = format!(
-            "SELECT password, nonce, salt FROM {} WHERE platform = (?1) AND user_id = (?2)",
+            "SELECT password, nonce, salt, key_scheme FROM {} WHERE platform = (?1) AND user_id = (?2) AND deleted_at IS NULL",
             &self.table_name
         );
 
@@ -480,14 +591,16 @@ impl Table {
                         row.get::<_, String>(0)?,
                         row.get::<_, String>(1)?,
                         row.get::<_, String>(2)?,
+                        row.get::<_, repository::KeyScheme>(3)?,
                     ))
                 });
 
         match row {
-            Ok((ciphertext, nonce, salt_str)) => {
+            Ok((ciphertext, nonce, salt_str, scheme)) => {
                 let key = secret_key_from_slice(encryption_key)?;
-                let plaintext = repository::decrypt_entry(&key, &ciphertext, &nonce, &salt_str)
-                    .map_err(map_storage_error)?;
+                let plaintext =
+                    repository::decrypt_entry(&key, &ciphertext, &nonce, &salt_str, scheme)
+                        .map_err(map_storage_error)?;
                 std::str::from_utf8(plaintext.expose())
                     .map(str::to_owned)
                     .map_err(|error| DatabaseError::Crypto(error.to_string()))
@@ -523,8 +636,9 @@ impl Table {
     }
 
     fn list_impl(&self, db: &Database) -> Result<Vec<VaultEntry>, DatabaseError> {
+        self.require_plaintext_metadata()?;
         let query = format!(
-            "SELECT id, platform, user_id, password, salt, nonce, pinned, created_at, updated_at FROM {} ORDER BY pinned DESC, platform ASC",
+            "SELECT id, platform, user_id, password, salt, nonce, pinned, created_at, updated_at FROM {} WHERE deleted_at IS NULL ORDER BY pinned DESC, platform ASC",
             &self.table_name
         );
         let mut statement = db.connection.prepare(&query)?;
@@ -553,6 +667,48 @@ impl Table {
     }
 }
 
+fn table_exists(connection: &Connection, table_name: &str) -> rusqlite::Result<bool> {
+    connection
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1 COLLATE NOCASE",
+            [table_name],
+            |_| Ok(()),
+        )
+        .optional()
+        .map(|row| row.is_some())
+}
+
+/// Creates an entry table with the current schema unless it already exists.
+fn create_entry_table(connection: &Connection, table_name: &str) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {table_name} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                platform TEXT NOT NULL,
+                user_id TEXT NOT NULL,
+                password TEXT NOT NULL,
+                nonce TEXT,
+                salt TEXT,
+                key_scheme INTEGER NOT NULL DEFAULT 1,
+                pinned BOOLEAN DEFAULT FALSE,
+                created_at INTEGER DEFAULT 0,
+                updated_at INTEGER DEFAULT 0,
+                metadata TEXT,
+                details TEXT,
+                totp TEXT,
+                folder TEXT,
+                urls TEXT,
+                deleted_at INTEGER,
+                kind TEXT NOT NULL DEFAULT 'login',
+                revision INTEGER NOT NULL DEFAULT 1,
+                UNIQUE(platform, user_id)
+            )",
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
 fn secret_key_from_slice(key: &[u8]) -> Result<SecretKey, DatabaseError> {
     let bytes: [u8; 32] = key
         .try_into()
@@ -567,9 +723,23 @@ fn map_storage_error(error: StorageError) -> DatabaseError {
             rusqlite::ffi::Error::new(19),
             Some("entry conflict".to_string()),
         )),
+        error @ StorageError::RevisionMismatch { .. } => DatabaseError::Sqlite(
+            rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(19), Some(error.to_string())),
+        ),
         StorageError::Schema(error) | StorageError::Database(error) => DatabaseError::Sqlite(error),
         StorageError::Io(error) => DatabaseError::Io(error),
+        error @ (StorageError::Git { .. }
+        | StorageError::Sharing(_)
+        | StorageError::SyncConfigured) => {
+            DatabaseError::Io(std::io::Error::other(error.to_string()))
+        }
         StorageError::Crypto(error) => DatabaseError::Crypto(error.to_string()),
+        error @ StorageError::MetadataLocked => DatabaseError::Crypto(error.to_string()),
+        error @ (StorageError::InvalidVaultName(_)
+        | StorageError::DefaultVault
+        | StorageError::InvalidSecret(_)) => {
+            DatabaseError::Sqlite(rusqlite::Error::InvalidParameterName(error.to_string()))
+        }
     }
 }
 
@@ -593,12 +763,24 @@ mod tests {
     }
 
     #[test]
-    fn invalid_custom_vault_name_is_rejected() {
+    fn display_vault_names_are_kept_out_of_sql() {
         let db = memory_db();
 
-        let table = Table::new(&db, Some("bad-name".to_string()));
+        let table = Table::new(&db, Some("Client – ACME Prod 🚀".to_string())).unwrap();
+        let again = Table::new(&db, Some("client – acme prod 🚀".to_string())).unwrap();
+
+        assert!(Table::is_valid_identifier(&table.table_name));
+        assert_eq!(table.table_name, again.table_name);
+    }
+
+    #[test]
+    fn invalid_custom_vault_name_is_rejected() {
+        let db = memory_db();
 
-        assert!(table.is_err());
+        for name in ["", " padded", "line\nbreak"] {
+            assert!(Table::new(&db, Some(name.to_string())).is_err());
+        }
+        assert!(Table::new(&db, Some("x".repeat(65))).is_err());
     }
 
     #[test]
@@ -707,6 +889,44 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn legacy_row_api_rejects_encrypted_metadata_tables_except_import() {
+        let db = memory_db();
+        let key = crate::secret::SecretKey::from_bytes([7_u8; 32]);
+        db.encrypt_metadata(&key, None).unwrap();
+        let table = Table::new(&db, None).unwrap();
+        let entry = crate::portable_export::ExportEntry {
+            platform: "github".to_string(),
+            user_id: "alice".to_string(),
+            password: "secret".to_string(),
+            pinned: true,
+            created_at: 10,
+            updated_at: 20,
+            notes: String::new(),
+            fields: Vec::new(),
+            kind: crate::kind::EntryKind::Login,
+        };
+
+        table
+            .import_entry_with_key_impl(&db, key.as_bytes(), &entry)
+            .unwrap();
+
+        assert!(table.entry_exists_impl(&db, "github", "alice").is_err());
+        assert!(table.list_impl(&db).is_err());
+        let repository = EntryRepository::unlocked(&db, None, &key).unwrap();
+        assert!(
+            repository
+                .contains(EntrySelector::new("github", "alice"))
+                .unwrap()
+        );
+        let imported = repository
+            .get(&key, EntrySelector::new("github", "alice"))
+            .unwrap();
+        assert!(imported.metadata.pinned);
+        assert_eq!(imported.metadata.created_at, 10);
+        assert_eq!(imported.secret.expose(), b"secret");
+    }
+
     #[test]
     fn typed_api_classifies_missing_storage_row() {
         let db = memory_db();
@@ -737,6 +957,51 @@ mod tests {
         assert!(matches!(error, StorageError::Conflict));
     }
 
+    #[test]
+    fn connections_to_one_file_share_it_in_wal_mode() {
+        let root =
+            std::env::temp_dir().join(format!("rvault-storage-test-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("vault.sqlite");
+        let key = crate::secret::SecretKey::from_bytes([7_u8; 32]);
+        let first = Database::open(&path).unwrap();
+        let second = Database::open(&path).unwrap();
+        let mode: String = first
+            .connection
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+        let selector = EntrySelector::new("github", "alice");
+        let one = EntryRepository::new(&first, None).unwrap();
+        one.add(&key, NewEntry::new("github", "alice", b"first"))
+            .unwrap();
+        let two = EntryRepository::new(&second, None).unwrap();
+        let read = two.get(&key, selector).unwrap().metadata.revision;
+
+        // A reader's open transaction does not block the other connection's write.
+        let reading = second.connection.unchecked_transaction().unwrap();
+        let _: i64 = reading
+            .query_row("SELECT COUNT(*) FROM main", [], |row| row.get(0))
+            .unwrap();
+        one.update(&key, selector, EntryUpdate::new("alice", b"second"))
+            .unwrap();
+        drop(reading);
+
+        let stale = two.update(
+            &key,
+            selector,
+            EntryUpdate::new("alice", b"third").with_expected_revision(read),
+        );
+        assert!(matches!(
+            stale,
+            Err(StorageError::RevisionMismatch { expected, actual })
+                if expected == read && actual == read + 1
+        ));
+        drop((one, two));
+        drop((first, second));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
     fn repository_with_ten_pins<'a>(db: &'a Database, key: &SecretKey) -> EntryRepository<'a> {
         let repository = EntryRepository::new(db, None).unwrap();
         for index in 0..10 {
@@ -0,0 +1,522 @@
+//! Encrypted, tamper-evident audit log.
+//!
+//! Every record is sealed with the MEK like notes and linked to the previous one with a keyed
+//! HMAC-SHA256 over the previous link, its id and its sealed bytes. A MAC of the record count
+//! and the last link is kept as the log head in the settings table, so editing, reordering or
+//! deleting a record, including the newest ones, breaks verification. Replacing the whole
+//! database with an older copy cannot be detected from the database alone.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::Utc;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::details;
+use super::migration::{AUDIT_TABLE, META_TABLE};
+use super::{Database, StorageError};
+use crate::crypto::CryptoError;
+use crate::secret::SecretKey;
+
+const CHAIN_KEY_INFO: &[u8] = b"rvault-audit-chain-v1";
+const AUDIT_HEAD: &str = "audit_head";
+
+/// What happened to the vault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOperation {
+    Unlock,
+    Lock,
+    /// A secret was read: copied, revealed or used for a TOTP code.
+    Get,
+    Add,
+    Update,
+    Remove,
+    Export,
+    Import,
+    BackupCreate,
+    BackupRestore,
+}
+
+impl AuditOperation {
+    pub const ALL: [Self; 10] = [
+        Self::Unlock,
+        Self::Lock,
+        Self::Get,
+        Self::Add,
+        Self::Update,
+        Self::Remove,
+        Self::Export,
+        Self::Import,
+        Self::BackupCreate,
+        Self::BackupRestore,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unlock => "unlock",
+            Self::Lock => "lock",
+            Self::Get => "get",
+            Self::Add => "add",
+            Self::Update => "update",
+            Self::Remove => "remove",
+            Self::Export => "export",
+            Self::Import => "import",
+            Self::BackupCreate => "backup-create",
+            Self::BackupRestore => "backup-restore",
+        }
+    }
+}
+
+impl fmt::Display for AuditOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AuditOperation {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|operation| operation.name() == name)
+            .ok_or_else(|| format!("unknown operation: {name}"))
+    }
+}
+
+/// Which RVault front end performed an operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditSource {
+    Cli,
+    Tui,
+    /// The native messaging host serving the browser extension.
+    Browser,
+}
+
+impl fmt::Display for AuditSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Cli => "cli",
+            Self::Tui => "tui",
+            Self::Browser => "browser",
+        })
+    }
+}
+
+/// One operation to record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub operation: AuditOperation,
+    pub source: AuditSource,
+    /// Vault table, `None` for the default vault or operations on the whole database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(operation: AuditOperation, source: AuditSource) -> Self {
+        Self {
+            operation,
+            source,
+            vault: None,
+            platform: None,
+            user_id: None,
+        }
+    }
+
+    pub fn in_vault(mut self, vault: Option<&str>) -> Self {
+        self.vault = vault.map(str::to_string);
+        self
+    }
+
+    pub fn for_entry(mut self, platform: &str, user_id: &str) -> Self {
+        self.platform = Some(platform.to_string());
+        self.user_id = Some(user_id.to_string());
+        self
+    }
+}
+
+/// A decrypted audit record.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Unix time of the operation.
+    pub at: i64,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Which records [`Database::audit_log`] returns. Every filter left as `None` matches all.
+#[derive(Clone, Debug, Default)]
+pub struct AuditFilter {
+    /// Only records at or after this Unix time.
+    pub since: Option<i64>,
+    pub operation: Option<AuditOperation>,
+    pub platform: Option<String>,
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        self.since.is_none_or(|since| record.at >= since)
+            && self
+                .operation
+                .is_none_or(|operation| record.event.operation == operation)
+            && self
+                .platform
+                .as_deref()
+                .is_none_or(|platform| record.event.platform.as_deref() == Some(platform))
+    }
+}
+
+/// Result of [`Database::verify_audit_log`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditIntegrity {
+    /// Every record is present and unmodified.
+    Intact { records: usize },
+    /// A record was edited, inserted, reordered or deleted; this is the first record whose link
+    /// does not match.
+    Broken { record_id: i64 },
+    /// The chain holds, but records were removed from its end or the head was edited.
+    Truncated { records: usize },
+}
+
+impl Database {
+    /// Appends an operation to the audit log.
+    pub fn record_audit(&self, key: &SecretKey, event: &AuditEvent) -> Result<(), StorageError> {
+        // Opening the default table creates the audit and settings tables on older files.
+        self.vault_tables()?;
+        let chain_key = chain_key(key)?;
+        let record = AuditRecord {
+            at: Utc::now().timestamp(),
+            event: event.clone(),
+        };
+        let json = serde_json::to_vec(&record).expect("audit records always serialize");
+        let sealed = details::seal_bytes(key, &json)?;
+        let transaction = self.connection.unchecked_transaction()?;
+        let (count, last_link): (i64, Option<String>) = transaction.query_row(
+            &format!(
+                "SELECT COUNT(*), (SELECT chain FROM {AUDIT_TABLE} ORDER BY id DESC LIMIT 1) FROM {AUDIT_TABLE}"
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let id: i64 = transaction.query_row(
+            &format!("SELECT COALESCE(MAX(id), 0) + 1 FROM {AUDIT_TABLE}"),
+            [],
+            |row| row.get(0),
+        )?;
+        let link = link(
+            &chain_key,
+            last_link.as_deref().unwrap_or_default(),
+            id,
+            &sealed,
+        );
+        transaction.execute(
+            &format!("INSERT INTO {AUDIT_TABLE} (id, record, chain) VALUES (?1, ?2, ?3)"),
+            params![id, sealed, link],
+        )?;
+        write_head(&transaction, &chain_key, count as usize + 1, &link)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Decrypted audit records matching `filter`, oldest first.
+    pub fn audit_log(
+        &self,
+        key: &SecretKey,
+        filter: &AuditFilter,
+    ) -> Result<Vec<AuditRecord>, StorageError> {
+        self.vault_tables()?;
+        let mut records = Vec::new();
+        for (_, sealed, _) in rows(&self.connection)? {
+            let record = open_record(key, &sealed)?;
+            if filter.matches(&record) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Checks the hash chain of the audit log. Only the MEK can produce valid links, so this
+    /// detects any change made without it.
+    pub fn verify_audit_log(&self, key: &SecretKey) -> Result<AuditIntegrity, StorageError> {
+        self.vault_tables()?;
+        verify(&self.connection, &chain_key(key)?)
+    }
+}
+
+/// Moves the audit log from `old_key` to `new_key`, resealing every record and rebuilding the
+/// chain. A log that does not verify under `old_key` is left alone and fails the rotation, so a
+/// key rotation cannot launder tampering.
+pub(super) fn reseal_all(
+    transaction: &Transaction<'_>,
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+) -> Result<(), StorageError> {
+    if !matches!(
+        verify(transaction, &chain_key(old_key)?)?,
+        AuditIntegrity::Intact { .. }
+    ) {
+        return Err(CryptoError::AuthenticationFailed.into());
+    }
+    let chain_key = chain_key(new_key)?;
+    let rows = rows(transaction)?;
+    let mut last_link = String::new();
+    for (id, sealed, _) in &rows {
+        let sealed = details::reseal(old_key, new_key, Some(sealed))?
+            .ok_or(CryptoError::AuthenticationFailed)?;
+        last_link = link(&chain_key, &last_link, *id, &sealed);
+        transaction.execute(
+            &format!("UPDATE {AUDIT_TABLE} SET record = ?1, chain = ?2 WHERE id = ?3"),
+            params![sealed, last_link, id],
+        )?;
+    }
+    if !rows.is_empty() {
+        write_head(transaction, &chain_key, rows.len(), &last_link)?;
+    }
+    Ok(())
+}
+
+fn verify(connection: &Connection, chain_key: &SecretKey) -> Result<AuditIntegrity, StorageError> {
+    let rows = rows(connection)?;
+    let mut last_link = String::new();
+    for (id, sealed, stored) in &rows {
+        last_link = link(chain_key, &last_link, *id, sealed);
+        if &last_link != stored {
+            return Ok(AuditIntegrity::Broken { record_id: *id });
+        }
+    }
+    let stored_head: Option<String> = connection
+        .query_row(
+            &format!("SELECT value FROM {META_TABLE} WHERE name = ?1"),
+            [AUDIT_HEAD],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let intact = match stored_head {
+        Some(stored_head) => stored_head == head(chain_key, rows.len(), &last_link),
+        None => rows.is_empty(),
+    };
+    Ok(if intact {
+        AuditIntegrity::Intact {
+            records: rows.len(),
+        }
+    } else {
+        AuditIntegrity::Truncated {
+            records: rows.len(),
+        }
+    })
+}
+
+/// `(id, sealed record, link)` of every record, oldest first.
+fn rows(connection: &Connection) -> Result<Vec<(i64, String, String)>, StorageError> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT id, record, chain FROM {AUDIT_TABLE} ORDER BY id"
+    ))?;
+    let rows = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+fn open_record(key: &SecretKey, sealed: &str) -> Result<AuditRecord, StorageError> {
+    let json = details::open_bytes(key, sealed)?;
+    serde_json::from_slice(json.expose()).map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+fn chain_key(key: &SecretKey) -> Result<SecretKey, StorageError> {
+    let mut bytes = Zeroizing::new([0_u8; 32]);
+    Hkdf::<Sha256>::new(None, key.as_bytes())
+        .expand(CHAIN_KEY_INFO, bytes.as_mut())
+        .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?;
+    Ok(SecretKey::from_bytes(*bytes))
+}
+
+fn mac(chain_key: &SecretKey, parts: &[&[u8]]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    for part in parts {
+        mac.update(&(part.len() as u64).to_be_bytes());
+        mac.update(part);
+    }
+    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+fn link(chain_key: &SecretKey, previous: &str, id: i64, sealed: &str) -> String {
+    mac(
+        chain_key,
+        &[
+            b"link",
+            previous.as_bytes(),
+            &id.to_be_bytes(),
+            sealed.as_bytes(),
+        ],
+    )
+}
+
+fn head(chain_key: &SecretKey, records: usize, last_link: &str) -> String {
+    let mac = mac(
+        chain_key,
+        &[
+            b"head",
+            &(records as u64).to_be_bytes(),
+            last_link.as_bytes(),
+        ],
+    );
+    format!("{records}:{mac}")
+}
+
+fn write_head(
+    connection: &Connection,
+    chain_key: &SecretKey,
+    records: usize,
+    last_link: &str,
+) -> Result<(), StorageError> {
+    connection.execute(
+        &format!("INSERT OR REPLACE INTO {META_TABLE} (name, value) VALUES (?1, ?2)"),
+        [AUDIT_HEAD.to_string(), head(chain_key, records, last_link)],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn record_sample_log(db: &Database, key: &SecretKey) {
+        for event in [
+            AuditEvent::new(AuditOperation::Unlock, AuditSource::Cli),
+            AuditEvent::new(AuditOperation::Get, AuditSource::Browser).for_entry("github", "alice"),
+            AuditEvent::new(AuditOperation::Get, AuditSource::Tui)
+                .in_vault(Some("work"))
+                .for_entry("gitlab", "alice"),
+            AuditEvent::new(AuditOperation::Lock, AuditSource::Cli),
+        ] {
+            db.record_audit(key, &event).unwrap();
+        }
+    }
+
+    #[test]
+    fn records_are_encrypted_filtered_and_verified() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        record_sample_log(&db, &key);
+
+        let stored: String = db
+            .connection
+            .query_row("SELECT group_concat(record) FROM rvault_audit", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(!stored.contains("github"));
+
+        let all = db.audit_log(&key, &AuditFilter::default()).unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[1].event.source, AuditSource::Browser);
+        let reads = db
+            .audit_log(
+                &key,
+                &AuditFilter {
+                    operation: Some(AuditOperation::Get),
+                    platform: Some("gitlab".to_string()),
+                    ..AuditFilter::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(reads.len(), 1);
+        assert_eq!(reads[0].event.vault.as_deref(), Some("work"));
+        let future = AuditFilter {
+            since: Some(all[0].at + 3600),
+            ..AuditFilter::default()
+        };
+        assert!(db.audit_log(&key, &future).unwrap().is_empty());
+
+        assert_eq!(
+            db.verify_audit_log(&key).unwrap(),
+            AuditIntegrity::Intact { records: 4 }
+        );
+        assert!(matches!(
+            db.verify_audit_log(&SecretKey::from_bytes([8; 32]))
+                .unwrap(),
+            AuditIntegrity::Broken { record_id: 1 }
+        ));
+        assert_eq!("backup-restore".parse(), Ok(AuditOperation::BackupRestore));
+    }
+
+    #[test]
+    fn edits_and_deletions_break_verification() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let tampered = |statement: &str| {
+            let db = memory_db();
+            record_sample_log(&db, &key);
+            db.connection.execute(statement, []).unwrap();
+            db.verify_audit_log(&key).unwrap()
+        };
+
+        assert_eq!(
+            tampered(
+                "UPDATE rvault_audit SET record = (SELECT record FROM rvault_audit WHERE id = 1) WHERE id = 2"
+            ),
+            AuditIntegrity::Broken { record_id: 2 }
+        );
+        assert_eq!(
+            tampered("DELETE FROM rvault_audit WHERE id = 2"),
+            AuditIntegrity::Broken { record_id: 3 }
+        );
+        assert_eq!(
+            tampered("DELETE FROM rvault_audit WHERE id = 4"),
+            AuditIntegrity::Truncated { records: 3 }
+        );
+        assert_eq!(
+            tampered("DELETE FROM rvault_meta WHERE name = 'audit_head'"),
+            AuditIntegrity::Truncated { records: 4 }
+        );
+    }
+
+    #[test]
+    fn key_rotation_rebuilds_an_intact_log_and_refuses_a_tampered_one() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let new_key = SecretKey::from_bytes([8; 32]);
+        record_sample_log(&db, &key);
+
+        db.reencrypt_entries(&key, &new_key, "fingerprint", || Ok(()))
+            .unwrap();
+        assert_eq!(
+            db.verify_audit_log(&new_key).unwrap(),
+            AuditIntegrity::Intact { records: 4 }
+        );
+        assert_eq!(
+            db.audit_log(&new_key, &AuditFilter::default())
+                .unwrap()
+                .len(),
+            4
+        );
+
+        db.connection
+            .execute("DELETE FROM rvault_audit WHERE id = 1", [])
+            .unwrap();
+        assert!(
+            db.reencrypt_entries(&new_key, &key, "fingerprint", || Ok(()))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! Batches of entry changes applied in one transaction.
+//!
+//! Repository methods that write several rows open a [`Savepoint`] rather than a transaction.
+//! Savepoints nest, so those methods stay atomic on their own and also inside the savepoint of
+//! [`EntryRepository::apply_batch`], which commits every change of a batch or none of them.
+
+use std::ops::Deref;
+
+use rusqlite::Connection;
+
+use super::repository::{EntrySelector, EntryUpdate, NewEntry};
+use super::{EntryRepository, StorageError};
+use crate::portable_export::ExportEntry;
+use crate::secret::SecretKey;
+
+/// A change applied by [`EntryRepository::apply_batch`].
+pub enum BatchOp<'a> {
+    Add(NewEntry<'a>),
+    Update {
+        selector: EntrySelector<'a>,
+        update: EntryUpdate<'a>,
+    },
+    Remove(EntrySelector<'a>),
+    Pin {
+        selector: EntrySelector<'a>,
+        pinned: bool,
+    },
+    /// Adds an exported entry, or overwrites the entry with its identity, keeping the
+    /// timestamps, pin and kind of the export.
+    Import(&'a ExportEntry),
+}
+
+/// The result of every operation of a batch, in order.
+#[derive(Debug)]
+pub struct BatchReport {
+    pub results: Vec<Result<(), StorageError>>,
+}
+
+impl BatchReport {
+    /// Whether every operation succeeded, so the batch was committed.
+    pub fn committed(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+
+    /// Index and error of the first operation that failed.
+    pub fn first_failure(&self) -> Option<(usize, &StorageError)> {
+        self.results
+            .iter()
+            .enumerate()
+            .find_map(|(index, result)| result.as_ref().err().map(|error| (index, error)))
+    }
+}
+
+impl EntryRepository<'_> {
+    /// Applies `ops` in order in a single transaction. When any operation fails, nothing is
+    /// written; the later operations still run so the report tells which of them would have
+    /// failed as well. `Err` is only returned when the transaction itself fails.
+    pub fn apply_batch<'b>(
+        &self,
+        key: &SecretKey,
+        ops: impl IntoIterator<Item = BatchOp<'b>>,
+    ) -> Result<BatchReport, StorageError> {
+        let batch = Savepoint::new(&self.db.connection)?;
+        let results: Vec<Result<(), StorageError>> = ops
+            .into_iter()
+            .map(|op| {
+                // Each operation gets its own savepoint, so a failed one leaves no partial
+                // writes behind for the ones after it.
+                let savepoint = Savepoint::new(&batch)?;
+                self.apply(key, op)?;
+                savepoint.commit()?;
+                Ok(())
+            })
+            .collect();
+        let report = BatchReport { results };
+        if report.committed() {
+            batch.commit()?;
+        }
+        Ok(report)
+    }
+
+    fn apply(&self, key: &SecretKey, op: BatchOp<'_>) -> Result<(), StorageError> {
+        match op {
+            BatchOp::Add(entry) => self.add(key, entry),
+            BatchOp::Update { selector, update } => self.update(key, selector, update),
+            BatchOp::Remove(selector) => self.remove(selector),
+            BatchOp::Pin { selector, pinned } => self.set_pinned(selector, pinned),
+            BatchOp::Import(entry) => self.table.import_entry(&self.db.connection, key, entry),
+        }
+    }
+}
+
+/// A savepoint on a shared connection, rolled back when dropped without [`Savepoint::commit`].
+/// Outside a transaction it starts one.
+pub(super) struct Savepoint<'c> {
+    connection: &'c Connection,
+    committed: bool,
+}
+
+impl<'c> Savepoint<'c> {
+    pub(super) fn new(connection: &'c Connection) -> rusqlite::Result<Self> {
+        connection.execute_batch("SAVEPOINT rvault")?;
+        Ok(Self {
+            connection,
+            committed: false,
+        })
+    }
+
+    pub(super) fn commit(mut self) -> rusqlite::Result<()> {
+        self.connection.execute_batch("RELEASE rvault")?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = self
+                .connection
+                .execute_batch("ROLLBACK TO rvault; RELEASE rvault");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kind::EntryKind;
+    use crate::storage::Database;
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn export(platform: &str, password: &str) -> ExportEntry {
+        ExportEntry {
+            platform: platform.to_string(),
+            user_id: "alice".to_string(),
+            password: password.to_string(),
+            pinned: true,
+            created_at: 10,
+            updated_at: 20,
+            notes: String::new(),
+            fields: Vec::new(),
+            kind: EntryKind::Login,
+        }
+    }
+
+    #[test]
+    fn batch_applies_every_operation_in_order() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        repository
+            .add(&key, NewEntry::new("gitlab", "alice", b"old"))
+            .unwrap();
+        let imported = export("github", "imported");
+
+        let report = repository
+            .apply_batch(
+                &key,
+                [
+                    BatchOp::Add(NewEntry::new("aws", "root", b"secret")),
+                    BatchOp::Pin {
+                        selector: EntrySelector::new("aws", "root"),
+                        pinned: true,
+                    },
+                    BatchOp::Update {
+                        selector: EntrySelector::new("gitlab", "alice"),
+                        update: EntryUpdate::new("alice", b"new"),
+                    },
+                    BatchOp::Import(&imported),
+                    BatchOp::Remove(EntrySelector::new("gitlab", "alice")),
+                ],
+            )
+            .unwrap();
+
+        assert!(report.committed());
+        assert_eq!(report.results.len(), 5);
+        let entries = repository.list_metadata().unwrap();
+        let names: Vec<(&str, bool)> = entries
+            .iter()
+            .map(|entry| (entry.platform.as_str(), entry.pinned))
+            .collect();
+        assert_eq!(names, [("aws", true), ("github", true)]);
+        let github = repository
+            .get(&key, EntrySelector::new("github", "alice"))
+            .unwrap();
+        assert_eq!(github.secret.expose(), b"imported");
+        assert_eq!(
+            (github.metadata.created_at, github.metadata.updated_at),
+            (10, 20)
+        );
+        assert_eq!(repository.trash().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_batch_writes_nothing_and_reports_each_operation() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let selector = EntrySelector::new("github", "alice");
+        repository
+            .add(&key, NewEntry::new("github", "alice", b"old"))
+            .unwrap();
+
+        let report = repository
+            .apply_batch(
+                &key,
+                [
+                    BatchOp::Update {
+                        selector,
+                        update: EntryUpdate::new("alice", b"new"),
+                    },
+                    BatchOp::Add(NewEntry::new("aws", "root", b"secret")),
+                    BatchOp::Add(NewEntry::new("github", "alice", b"duplicate")),
+                    BatchOp::Remove(EntrySelector::new("missing", "nobody")),
+                    BatchOp::Pin {
+                        selector,
+                        pinned: true,
+                    },
+                ],
+            )
+            .unwrap();
+
+        assert!(!report.committed());
+        assert!(matches!(
+            report.first_failure(),
+            Some((2, StorageError::Conflict))
+        ));
+        assert!(matches!(report.results[3], Err(StorageError::NotFound)));
+        assert!(report.results[4].is_ok());
+        let entries = repository.list_metadata().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(!entries[0].pinned);
+        assert_eq!(
+            repository.get(&key, selector).unwrap().secret.expose(),
+            b"old"
+        );
+        assert!(repository.history(&key, selector).unwrap().is_empty());
+
+        // Methods that open their own savepoint still commit outside a batch.
+        repository
+            .update(&key, selector, EntryUpdate::new("alice", b"new"))
+            .unwrap();
+        assert_eq!(repository.history(&key, selector).unwrap().len(), 1);
+    }
+}
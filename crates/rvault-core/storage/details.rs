@@ -0,0 +1,176 @@
+//! Encrypted notes, custom fields and TOTP secrets.
+//!
+//! Each is encrypted like the password, under its own salt and nonce, into a nullable column as
+//! `scheme:salt:nonce:ciphertext`. Notes and fields share the `details` column as JSON; the TOTP
+//! secret is stored in `totp` as its `otpauth://` URI. Entries without them store `NULL`.
+
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::StorageError;
+use super::repository::{KeyScheme, decrypt_entry, encrypt_entry};
+use crate::crypto::CryptoError;
+use crate::secret::{SecretBytes, SecretKey};
+use crate::totp::TotpSecret;
+
+/// A named value stored with an entry, in the order the user entered it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    pub value: String,
+    /// Secret fields are masked in views until revealed, like the password.
+    pub secret: bool,
+}
+
+impl CustomField {
+    pub fn new(name: impl Into<String>, value: impl Into<String>, secret: bool) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            secret,
+        }
+    }
+}
+
+impl Drop for CustomField {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+/// Decrypted notes and custom fields of one entry.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryDetails {
+    pub notes: String,
+    pub fields: Vec<CustomField>,
+}
+
+impl EntryDetails {
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty() && self.fields.is_empty()
+    }
+}
+
+impl Drop for EntryDetails {
+    fn drop(&mut self) {
+        self.notes.zeroize();
+    }
+}
+
+#[derive(Serialize)]
+struct DetailsRef<'a> {
+    notes: &'a str,
+    fields: &'a [CustomField],
+}
+
+/// Encrypts notes and fields for the `details` column; `None` when both are empty.
+pub(super) fn seal(
+    key: &SecretKey,
+    notes: &str,
+    fields: &[CustomField],
+) -> Result<Option<String>, StorageError> {
+    if notes.is_empty() && fields.is_empty() {
+        return Ok(None);
+    }
+    let json = Zeroizing::new(
+        serde_json::to_vec(&DetailsRef { notes, fields }).expect("string fields always serialize"),
+    );
+    seal_bytes(key, &json).map(Some)
+}
+
+/// Decrypts a `details` column value; `NULL` yields empty details.
+pub(super) fn open(key: &SecretKey, sealed: Option<&str>) -> Result<EntryDetails, StorageError> {
+    let Some(sealed) = sealed else {
+        return Ok(EntryDetails::default());
+    };
+    let json = open_bytes(key, sealed)?;
+    serde_json::from_slice(json.expose()).map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+/// Encrypts a TOTP secret for the `totp` column.
+pub(super) fn seal_totp(key: &SecretKey, totp: &TotpSecret) -> Result<String, StorageError> {
+    seal_bytes(key, Zeroizing::new(totp.to_uri()).as_bytes())
+}
+
+/// Decrypts a `totp` column value.
+pub(super) fn open_totp(
+    key: &SecretKey,
+    sealed: Option<&str>,
+) -> Result<Option<TotpSecret>, StorageError> {
+    let Some(sealed) = sealed else {
+        return Ok(None);
+    };
+    let uri = open_bytes(key, sealed)?;
+    let uri = std::str::from_utf8(uri.expose()).map_err(|_| CryptoError::AuthenticationFailed)?;
+    TotpSecret::parse(uri)
+        .map(Some)
+        .map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+/// Moves any sealed column value from `old_key` to `new_key`.
+pub(super) fn reseal(
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+    sealed: Option<&str>,
+) -> Result<Option<String>, StorageError> {
+    sealed
+        .map(|sealed| seal_bytes(new_key, open_bytes(old_key, sealed)?.expose()))
+        .transpose()
+}
+
+pub(super) fn seal_bytes(key: &SecretKey, plaintext: &[u8]) -> Result<String, StorageError> {
+    let (ciphertext, nonce, salt) = encrypt_entry(key, plaintext)?;
+    Ok(format!(
+        "{}:{salt}:{nonce}:{ciphertext}",
+        KeyScheme::CURRENT as i64
+    ))
+}
+
+pub(super) fn open_bytes(key: &SecretKey, sealed: &str) -> Result<SecretBytes, StorageError> {
+    let mut parts = sealed.splitn(4, ':');
+    let (Some(scheme), Some(salt), Some(nonce), Some(ciphertext)) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(CryptoError::AuthenticationFailed.into());
+    };
+    let scheme = scheme
+        .parse::<i64>()
+        .ok()
+        .and_then(|scheme| KeyScheme::try_from(scheme).ok())
+        .ok_or(CryptoError::AuthenticationFailed)?;
+    decrypt_entry(key, ciphertext, nonce, salt, scheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn details_round_trip_and_empty_details_store_null() {
+        let key = SecretKey::from_bytes([4; 32]);
+        let fields = vec![
+            CustomField::new("Account number", "12345678", false),
+            CustomField::new("Mother's maiden name", "Smith", true),
+        ];
+
+        let sealed = seal(&key, "Branch: Main St.", &fields).unwrap();
+
+        assert!(!sealed.as_deref().unwrap().contains("Smith"));
+        let details = open(&key, sealed.as_deref()).unwrap();
+        assert_eq!(details.notes, "Branch: Main St.");
+        assert_eq!(details.fields, fields);
+        assert_eq!(seal(&key, "", &[]).unwrap(), None);
+        assert!(open(&key, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn details_do_not_open_under_another_key() {
+        let key = SecretKey::from_bytes([4; 32]);
+        let other = SecretKey::from_bytes([5; 32]);
+        let sealed = seal(&key, "note", &[]).unwrap();
+
+        assert!(open(&other, sealed.as_deref()).is_err());
+        let resealed = reseal(&key, &other, sealed.as_deref()).unwrap();
+        assert_eq!(open(&other, resealed.as_deref()).unwrap().notes, "note");
+    }
+}
@@ -18,6 +18,34 @@ pub enum StorageError {
     Io(#[from] std::io::Error),
     #[error("cryptographic operation failed")]
     Crypto(#[from] CryptoError),
+    /// The table's metadata is encrypted and the repository was opened without a key.
+    #[error("entry metadata is encrypted; open the vault with its key")]
+    MetadataLocked,
+    /// The vault name is empty, longer than 64 characters, starts or ends with whitespace or
+    /// has control characters.
+    #[error("invalid vault name: {0}")]
+    InvalidVaultName(String),
+    /// The default vault is recreated whenever it is opened, so it cannot be renamed or deleted.
+    #[error("the default vault cannot be renamed or deleted")]
+    DefaultVault,
+    /// The secret of a note, card, identity or API key entry is not a valid secret of its kind.
+    #[error("invalid {0} secret")]
+    InvalidSecret(crate::kind::EntryKind),
+    /// The entry was written since the revision an update expected, by another process or an
+    /// earlier edit.
+    #[error("entry was changed elsewhere: expected revision {expected}, found {actual}")]
+    RevisionMismatch { expected: i64, actual: i64 },
+    /// A git command run to sync the vault failed, or the remote kept changing.
+    #[error("git {command} failed: {message}")]
+    Git { command: String, message: String },
+    /// A public code is malformed or already a member, the members file of a shared vault does
+    /// not check out, or this member was removed from it.
+    #[error("shared vault: {0}")]
+    Sharing(String),
+    /// The other devices syncing with this one through a folder or git keep the current master
+    /// key, so it cannot be rotated until syncing stops.
+    #[error("this vault syncs with other devices; run `rvault sync stop` before rotating its key")]
+    SyncConfigured,
 }
 
 impl From<rusqlite::Error> for StorageError {
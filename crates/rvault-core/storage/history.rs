@@ -0,0 +1,200 @@
+//! Previous secrets of entries.
+//!
+//! When an update replaces an entry's secret, the old one is sealed like notes and kept in the
+//! database-wide history table with the time it was replaced. Only the newest
+//! [`Database::history_depth`] versions of each entry are kept.
+
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+
+use super::details;
+use super::migration::{self, HISTORY_TABLE, META_TABLE};
+use super::{Database, StorageError};
+use crate::secret::{SecretBytes, SecretKey};
+
+/// Versions kept per entry until [`Database::set_history_depth`] changes it.
+pub const DEFAULT_HISTORY_DEPTH: usize = 10;
+
+const HISTORY_DEPTH: &str = "history_depth";
+
+/// A secret an entry held before it was replaced.
+pub struct HistoryEntry {
+    /// Stable identifier used to restore this version.
+    pub id: i64,
+    pub secret: SecretBytes,
+    /// Unix time at which this secret stopped being the current one.
+    pub replaced_at: i64,
+}
+
+impl Database {
+    /// Number of previous secrets kept per entry.
+    pub fn history_depth(&self) -> Result<usize, StorageError> {
+        depth(&self.connection)
+    }
+
+    /// Changes how many previous secrets are kept per entry, dropping older ones right away.
+    /// A depth of zero turns history off.
+    pub fn set_history_depth(&self, depth: usize) -> Result<(), StorageError> {
+        // Opening the default table creates the history and settings tables on older files.
+        self.vault_tables()?;
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute(
+            &format!("INSERT OR REPLACE INTO {META_TABLE} (name, value) VALUES (?1, ?2)"),
+            [HISTORY_DEPTH.to_string(), depth.to_string()],
+        )?;
+        transaction.execute(
+            &format!(
+                "DELETE FROM {HISTORY_TABLE} WHERE id IN (
+                    SELECT id FROM (
+                        SELECT id, ROW_NUMBER() OVER (
+                            PARTITION BY entry_table, entry_id ORDER BY id DESC
+                        ) AS version FROM {HISTORY_TABLE}
+                    ) WHERE version > ?1
+                )"
+            ),
+            [depth as i64],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn depth(connection: &Connection) -> Result<usize, StorageError> {
+    if !migration::meta_table_exists(connection)? {
+        return Ok(DEFAULT_HISTORY_DEPTH);
+    }
+    let value: Option<String> = connection
+        .query_row(
+            &format!("SELECT value FROM {META_TABLE} WHERE name = ?1"),
+            [HISTORY_DEPTH],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_DEPTH))
+}
+
+/// Keeps `secret` as the newest previous version of an entry and drops versions beyond the
+/// configured depth.
+pub(super) fn record(
+    connection: &Connection,
+    key: &SecretKey,
+    table: &str,
+    entry_id: i64,
+    secret: &[u8],
+    replaced_at: i64,
+) -> Result<(), StorageError> {
+    let depth = depth(connection)?;
+    if depth > 0 {
+        connection.execute(
+            &format!(
+                "INSERT INTO {HISTORY_TABLE} (entry_table, entry_id, secret, replaced_at) VALUES (?1, ?2, ?3, ?4)"
+            ),
+            params![table, entry_id, details::seal_bytes(key, secret)?, replaced_at],
+        )?;
+    }
+    connection.execute(
+        &format!(
+            "DELETE FROM {HISTORY_TABLE} WHERE entry_table = ?1 AND entry_id = ?2 AND id NOT IN (
+                SELECT id FROM {HISTORY_TABLE} WHERE entry_table = ?1 AND entry_id = ?2
+                ORDER BY id DESC LIMIT ?3
+            )"
+        ),
+        params![table, entry_id, depth as i64],
+    )?;
+    Ok(())
+}
+
+/// Previous versions of an entry, newest first.
+pub(super) fn list(
+    connection: &Connection,
+    key: &SecretKey,
+    table: &str,
+    entry_id: i64,
+) -> Result<Vec<HistoryEntry>, StorageError> {
+    let mut statement = connection.prepare(&format!(
+        "SELECT id, secret, replaced_at FROM {HISTORY_TABLE} WHERE entry_table = ?1 AND entry_id = ?2 ORDER BY id DESC"
+    ))?;
+    let rows = statement
+        .query_map(params![table, entry_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get(2)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    rows.into_iter()
+        .map(|(id, sealed, replaced_at)| {
+            Ok(HistoryEntry {
+                id,
+                secret: details::open_bytes(key, &sealed)?,
+                replaced_at,
+            })
+        })
+        .collect()
+}
+
+/// Decrypts one previous version of an entry.
+pub(super) fn open(
+    connection: &Connection,
+    key: &SecretKey,
+    table: &str,
+    entry_id: i64,
+    id: i64,
+) -> Result<SecretBytes, StorageError> {
+    let sealed: String = connection
+        .query_row(
+            &format!(
+                "SELECT secret FROM {HISTORY_TABLE} WHERE id = ?1 AND entry_table = ?2 AND entry_id = ?3"
+            ),
+            params![id, table, entry_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or(StorageError::NotFound)?;
+    details::open_bytes(key, &sealed)
+}
+
+/// Drops every previous version of an entry.
+pub(super) fn forget(connection: &Connection, table: &str, entry_id: i64) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!("DELETE FROM {HISTORY_TABLE} WHERE entry_table = ?1 AND entry_id = ?2"),
+        params![table, entry_id],
+    )?;
+    Ok(())
+}
+
+/// Hands every previous version of an entry to the row it was moved to.
+pub(super) fn reassign(
+    connection: &Connection,
+    (table, entry_id): (&str, i64),
+    (new_table, new_entry_id): (&str, i64),
+) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "UPDATE {HISTORY_TABLE} SET entry_table = ?1, entry_id = ?2 WHERE entry_table = ?3 AND entry_id = ?4"
+        ),
+        params![new_table, new_entry_id, table, entry_id],
+    )?;
+    Ok(())
+}
+
+/// Moves every previous version from `old_key` to `new_key`.
+pub(super) fn reseal_all(
+    transaction: &Transaction<'_>,
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+) -> Result<(), StorageError> {
+    let rows: Vec<(i64, Option<String>)> = {
+        let mut statement =
+            transaction.prepare(&format!("SELECT id, secret FROM {HISTORY_TABLE}"))?;
+        statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?
+    };
+    for (id, sealed) in rows {
+        let sealed = details::reseal(old_key, new_key, sealed.as_deref())?;
+        transaction.execute(
+            &format!("UPDATE {HISTORY_TABLE} SET secret = ?1 WHERE id = ?2"),
+            params![sealed, id],
+        )?;
+    }
+    Ok(())
+}
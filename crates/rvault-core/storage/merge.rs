@@ -0,0 +1,685 @@
+//! Merging the entries of another RVault database into this one.
+//!
+//! Entries are matched by vault name and identity. The two databases share no common ancestor
+//! to compare against, so the entries' histories stand in for one: when one side's current
+//! secret is in the other side's history, the other side replaced it and wins. Otherwise the
+//! side with the newer `updated_at`, then the higher revision, wins, unless both sides hold
+//! secrets that neither of them replaced. Those entries were changed independently and are
+//! conflicts the caller resolves before applying the merge. Notes, custom fields, TOTP secrets
+//! and URLs have no history to compare, so entries that differ in any of them are conflicts
+//! too.
+//!
+//! Unless the caller resolves a conflict by dropping one side, a merge loses no secrets or
+//! details: the secret that loses joins the entry's history along with the other side's
+//! previous secrets, [`MergeOutcome::KeepBoth`] keeps the other entry whole beside the local
+//! one, tags are combined, and entries the other database has are added even when their
+//! identity sits in the local trash. Only the folder and pin of the losing side are replaced.
+
+use std::cmp::Ordering;
+
+use rusqlite::params;
+
+use super::batch::Savepoint;
+use super::history::{self, HistoryEntry};
+use super::repository::{DecryptedEntry, EntrySelector, EntryUpdate, NewEntry};
+use super::{Database, EntryRepository, StorageError, vaults};
+use crate::secret::SecretKey;
+
+/// What [`Database::apply_merge`] does with an entry of the other database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// Only the other database has the entry, which is added with its history.
+    Add,
+    /// Both databases hold the same entry.
+    Unchanged,
+    /// The local entry is newer. Secrets only the other entry had join its history; as a
+    /// resolved conflict, the other entry's notes, fields, TOTP secret and URLs are dropped.
+    KeepLocal,
+    /// The other entry is newer and replaces the local one, whose secret joins the history; as
+    /// a resolved conflict, the local notes, fields, TOTP secret and URLs are replaced.
+    TakeOther,
+    /// Keeps the local entry and adds the other one beside it under a new user ID.
+    KeepBoth,
+    /// Both entries changed independently; the caller must pick another outcome.
+    Conflict,
+}
+
+/// An entry of the other database and what merging does with it.
+pub struct MergeEntry {
+    /// Vault holding the entry, in both databases.
+    pub vault: String,
+    /// The local entry with the same identity, outside the trash.
+    pub local: Option<DecryptedEntry>,
+    pub other: DecryptedEntry,
+    other_history: Vec<HistoryEntry>,
+    pub outcome: MergeOutcome,
+}
+
+/// Every entry of the other database, in vault and list order.
+pub struct MergePlan {
+    pub entries: Vec<MergeEntry>,
+}
+
+impl MergeEntry {
+    /// An entry without previous secrets, replayed by sync.
+    pub(super) fn new(
+        vault: &str,
+        local: Option<DecryptedEntry>,
+        other: DecryptedEntry,
+        outcome: MergeOutcome,
+    ) -> Self {
+        Self {
+            vault: vault.to_string(),
+            local,
+            other,
+            other_history: Vec::new(),
+            outcome,
+        }
+    }
+}
+
+impl MergePlan {
+    /// Number of entries with `outcome`.
+    pub fn count(&self, outcome: MergeOutcome) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.outcome == outcome)
+            .count()
+    }
+
+    /// Entries still waiting for the caller to resolve them.
+    pub fn conflicts_mut(&mut self) -> impl Iterator<Item = &mut MergeEntry> {
+        self.entries
+            .iter_mut()
+            .filter(|entry| entry.outcome == MergeOutcome::Conflict)
+    }
+}
+
+impl Database {
+    /// Compares every entry outside the trash of `other`, unlocked with `other_key`, with this
+    /// database. Nothing is written to this database; opening vaults of `other` migrates it and
+    /// purges its expired trash, so open another machine's file with [`Database::open_copy`].
+    pub fn plan_merge(
+        &self,
+        key: &SecretKey,
+        other: &Database,
+        other_key: &SecretKey,
+    ) -> Result<MergePlan, StorageError> {
+        let mut entries = Vec::new();
+        for vault in other.vaults()? {
+            let theirs = EntryRepository::unlocked(other, Some(vault.name.clone()), other_key)?;
+            // Vaults only the other database has are created when the merge is applied.
+            let ours = if vaults::is_registered(&self.connection, &vault.name)? {
+                Some(EntryRepository::unlocked(
+                    self,
+                    Some(vault.name.clone()),
+                    key,
+                )?)
+            } else {
+                None
+            };
+            for metadata in theirs.list_metadata()? {
+                let selector = EntrySelector::new(&metadata.platform, &metadata.user_id);
+                let other_entry = theirs.get(other_key, selector)?;
+                let other_history = theirs.history(other_key, selector)?;
+                let local = match &ours {
+                    Some(ours) => match ours.get(key, selector) {
+                        Ok(entry) => Some((entry, ours.history(key, selector)?)),
+                        Err(StorageError::NotFound) => None,
+                        Err(error) => return Err(error),
+                    },
+                    None => None,
+                };
+                let (local, outcome) = match local {
+                    Some((entry, history)) => {
+                        let outcome = outcome(&entry, &history, &other_entry, &other_history);
+                        (Some(entry), outcome)
+                    }
+                    None => (None, MergeOutcome::Add),
+                };
+                entries.push(MergeEntry {
+                    vault: vault.name.clone(),
+                    local,
+                    other: other_entry,
+                    other_history,
+                    outcome,
+                });
+            }
+        }
+        Ok(MergePlan { entries })
+    }
+
+    /// Applies a merge plan in one transaction. Fails with [`StorageError::Conflict`] while any
+    /// entry is still a [`MergeOutcome::Conflict`].
+    pub fn apply_merge(&self, key: &SecretKey, plan: &MergePlan) -> Result<(), StorageError> {
+        if plan.count(MergeOutcome::Conflict) > 0 {
+            return Err(StorageError::Conflict);
+        }
+        // Opening a vault may create it, which cannot happen inside the savepoint.
+        let mut repositories: Vec<(&str, EntryRepository<'_>)> = Vec::new();
+        for entry in &plan.entries {
+            if !repositories.iter().any(|(vault, _)| *vault == entry.vault) {
+                let repository = EntryRepository::unlocked(self, Some(entry.vault.clone()), key)?;
+                repositories.push((&entry.vault, repository));
+            }
+        }
+        let transaction = Savepoint::new(&self.connection)?;
+        for entry in &plan.entries {
+            let (_, repository) = repositories
+                .iter()
+                .find(|(vault, _)| *vault == entry.vault)
+                .ok_or(StorageError::NotFound)?;
+            repository.merge(key, entry)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+/// Decides an entry both databases hold; see the module documentation.
+fn outcome(
+    local: &DecryptedEntry,
+    local_history: &[HistoryEntry],
+    other: &DecryptedEntry,
+    other_history: &[HistoryEntry],
+) -> MergeOutcome {
+    if same_content(local, other) {
+        return MergeOutcome::Unchanged;
+    }
+    if local.metadata.kind != other.metadata.kind || !same_details(local, other) {
+        return MergeOutcome::Conflict;
+    }
+    let (local_secret, other_secret) = (local.secret.expose(), other.secret.expose());
+    let replaced = |history: &[HistoryEntry], secret: &[u8]| {
+        history.iter().any(|v| v.secret.expose() == secret)
+    };
+    match (
+        local_secret == other_secret,
+        replaced(other_history, local_secret),
+        replaced(local_history, other_secret),
+    ) {
+        (false, true, false) => MergeOutcome::TakeOther,
+        (false, false, true) => MergeOutcome::KeepLocal,
+        (false, false, false) => MergeOutcome::Conflict,
+        _ => match (local.metadata.updated_at, local.metadata.revision)
+            .cmp(&(other.metadata.updated_at, other.metadata.revision))
+        {
+            Ordering::Greater => MergeOutcome::KeepLocal,
+            Ordering::Less => MergeOutcome::TakeOther,
+            Ordering::Equal => MergeOutcome::Conflict,
+        },
+    }
+}
+
+pub(super) fn same_content(local: &DecryptedEntry, other: &DecryptedEntry) -> bool {
+    let (ours, theirs) = (&local.metadata, &other.metadata);
+    ours.kind == theirs.kind
+        && local.secret.expose() == other.secret.expose()
+        && same_details(local, other)
+        && ours.folder == theirs.folder
+        && ours.tags == theirs.tags
+        && ours.pinned == theirs.pinned
+}
+
+/// Whether both entries have the same notes, custom fields, TOTP secret and URLs.
+fn same_details(local: &DecryptedEntry, other: &DecryptedEntry) -> bool {
+    local.details == other.details
+        && local.totp.as_ref().map(|totp| totp.to_uri())
+            == other.totp.as_ref().map(|totp| totp.to_uri())
+        && local.metadata.urls == other.metadata.urls
+}
+
+impl EntryRepository<'_> {
+    /// Applies the outcome of one entry of a merge plan to this vault.
+    pub(super) fn merge(&self, key: &SecretKey, entry: &MergeEntry) -> Result<(), StorageError> {
+        let other = &entry.other.metadata;
+        match (entry.outcome, &entry.local) {
+            (MergeOutcome::Unchanged, _) => Ok(()),
+            (MergeOutcome::Add, _) => self.merge_add(key, entry, &other.user_id),
+            (MergeOutcome::KeepLocal, Some(local)) => self.merge_keep_local(key, entry, local),
+            (MergeOutcome::TakeOther, Some(local)) => self.merge_take_other(key, entry, local),
+            (MergeOutcome::KeepBoth, _) => {
+                let user_id = self.unused_user_id(&other.platform, &other.user_id)?;
+                self.merge_add(key, entry, &user_id)
+            }
+            (MergeOutcome::KeepLocal | MergeOutcome::TakeOther, None)
+            | (MergeOutcome::Conflict, _) => Err(StorageError::Conflict),
+        }
+    }
+
+    /// Adds the other entry under `user_id`. An entry of the same kind with that identity in
+    /// the trash is restored and replaced instead, so its secret is kept in the history.
+    fn merge_add(
+        &self,
+        key: &SecretKey,
+        entry: &MergeEntry,
+        user_id: &str,
+    ) -> Result<(), StorageError> {
+        let other = &entry.other;
+        let selector = EntrySelector::new(&other.metadata.platform, user_id);
+        let trashed = self.trash()?.into_iter().find(|trashed| {
+            trashed.metadata.platform == other.metadata.platform
+                && trashed.metadata.user_id == user_id
+        });
+        if let Some(trashed) = trashed {
+            if trashed.metadata.kind != other.metadata.kind {
+                let user_id = self.unused_user_id(&other.metadata.platform, user_id)?;
+                return self.merge_add(key, entry, &user_id);
+            }
+            self.restore_trashed(selector)?;
+            let local = self.get(key, selector)?;
+            return self.merge_take_other(key, entry, &local);
+        }
+        let mut new = NewEntry::new(&other.metadata.platform, user_id, other.secret.expose())
+            .with_kind(other.metadata.kind)
+            .with_notes(&other.details.notes)
+            .with_fields(&other.details.fields)
+            .with_urls(&other.metadata.urls);
+        if let Some(totp) = &other.totp {
+            new = new.with_totp(totp);
+        }
+        self.add(key, new)?;
+        let id = self
+            .get(key, selector)?
+            .metadata
+            .id
+            .ok_or(StorageError::NotFound)?;
+        self.merge_history(key, selector, id, entry, None)?;
+        self.merge_metadata(
+            selector,
+            id,
+            other,
+            (other.metadata.created_at, other.metadata.updated_at),
+        )
+    }
+
+    fn merge_keep_local(
+        &self,
+        key: &SecretKey,
+        entry: &MergeEntry,
+        local: &DecryptedEntry,
+    ) -> Result<(), StorageError> {
+        // An entry of another kind is a different entry, so none of its secrets or tags belong
+        // to the local one.
+        if local.metadata.kind != entry.other.metadata.kind {
+            return Ok(());
+        }
+        let selector = EntrySelector::new(&local.metadata.platform, &local.metadata.user_id);
+        let id = local.metadata.id.ok_or(StorageError::NotFound)?;
+        self.merge_history(key, selector, id, entry, Some(local))?;
+        for tag in &entry.other.metadata.tags {
+            self.add_tag(selector, tag)?;
+        }
+        Ok(())
+    }
+
+    fn merge_take_other(
+        &self,
+        key: &SecretKey,
+        entry: &MergeEntry,
+        local: &DecryptedEntry,
+    ) -> Result<(), StorageError> {
+        let other = &entry.other;
+        let selector = EntrySelector::new(&local.metadata.platform, &local.metadata.user_id);
+        let id = local.metadata.id.ok_or(StorageError::NotFound)?;
+        // The other side's older secrets go first, so the local one replaced below is the
+        // newest previous version.
+        self.merge_history(key, selector, id, entry, Some(local))?;
+        self.update(
+            key,
+            selector,
+            EntryUpdate::new(&local.metadata.user_id, other.secret.expose())
+                .with_notes(&other.details.notes)
+                .with_fields(&other.details.fields),
+        )?;
+        self.set_totp(key, selector, other.totp.as_ref())?;
+        self.set_urls(selector, &other.metadata.urls)?;
+        self.merge_metadata(
+            selector,
+            id,
+            other,
+            (
+                local.metadata.created_at.min(other.metadata.created_at),
+                other.metadata.updated_at,
+            ),
+        )
+    }
+
+    /// Keeps the other side's previous secrets that this entry's history lacks, oldest first,
+    /// and with a `local` entry to keep, the other side's current secret too.
+    fn merge_history(
+        &self,
+        key: &SecretKey,
+        selector: EntrySelector<'_>,
+        id: i64,
+        entry: &MergeEntry,
+        local: Option<&DecryptedEntry>,
+    ) -> Result<(), StorageError> {
+        let mut known: Vec<Vec<u8>> = self
+            .history(key, selector)?
+            .iter()
+            .map(|version| version.secret.expose().to_vec())
+            .collect();
+        known.extend(local.map(|local| local.secret.expose().to_vec()));
+        let mut versions: Vec<(&[u8], i64)> = entry
+            .other_history
+            .iter()
+            .rev()
+            .map(|version| (version.secret.expose(), version.replaced_at))
+            .collect();
+        if entry.outcome == MergeOutcome::KeepLocal {
+            versions.push((entry.other.secret.expose(), entry.other.metadata.updated_at));
+        }
+        for (secret, replaced_at) in versions {
+            if known.iter().any(|known| known == secret) {
+                continue;
+            }
+            history::record(
+                &self.db.connection,
+                key,
+                &self.table.table_name,
+                id,
+                secret,
+                replaced_at,
+            )?;
+            known.push(secret.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Copies the folder and pin of the other entry, adds its tags and sets the timestamps.
+    /// The pin is dropped when ten entries are already pinned.
+    fn merge_metadata(
+        &self,
+        selector: EntrySelector<'_>,
+        id: i64,
+        other: &DecryptedEntry,
+        (created_at, updated_at): (i64, i64),
+    ) -> Result<(), StorageError> {
+        for tag in &other.metadata.tags {
+            self.add_tag(selector, tag)?;
+        }
+        self.set_folder(selector, other.metadata.folder.as_deref())?;
+        match self.set_pinned(selector, other.metadata.pinned) {
+            Ok(()) | Err(StorageError::Conflict) => {}
+            Err(error) => return Err(error),
+        }
+        self.db.connection.execute(
+            &format!(
+                "UPDATE {} SET created_at = ?1, updated_at = ?2 WHERE id = ?3",
+                self.table.table_name
+            ),
+            params![created_at, updated_at, id],
+        )?;
+        Ok(())
+    }
+
+    /// `user_id` followed by " (merged)", numbered when that identity is taken as well.
+    fn unused_user_id(&self, platform: &str, user_id: &str) -> Result<String, StorageError> {
+        let taken = self.trash()?;
+        let mut number = 1;
+        loop {
+            let candidate = match number {
+                1 => format!("{user_id} (merged)"),
+                number => format!("{user_id} (merged {number})"),
+            };
+            let selector = EntrySelector::new(platform, &candidate);
+            let trashed = taken.iter().any(|trashed| {
+                trashed.metadata.platform == platform && trashed.metadata.user_id == candidate
+            });
+            if !trashed && !self.contains(selector)? {
+                return Ok(candidate);
+            }
+            number += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kind::{EntryKind, SecureNote, TypedSecret};
+    use rusqlite::Connection;
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn secret(db: &Database, key: &SecretKey, vault: &str, user_id: &str) -> Vec<u8> {
+        EntryRepository::unlocked(db, Some(vault.to_string()), key)
+            .unwrap()
+            .get(key, EntrySelector::new("github", user_id))
+            .unwrap()
+            .secret
+            .expose()
+            .to_vec()
+    }
+
+    #[test]
+    fn merge_adds_new_entries_and_follows_replaced_secrets() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let other_key = SecretKey::from_bytes([9; 32]);
+        let (laptop, desktop) = (memory_db(), memory_db());
+        let ours = EntryRepository::new(&laptop, None).unwrap();
+        let theirs = EntryRepository::new(&desktop, None).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        for (repository, key) in [(&ours, &key), (&theirs, &other_key)] {
+            repository
+                .add(key, NewEntry::new("github", "alice", b"one"))
+                .unwrap();
+            repository
+                .add(key, NewEntry::new("gitlab", "alice", b"same"))
+                .unwrap();
+        }
+        theirs
+            .update(&other_key, github, EntryUpdate::new("alice", b"two"))
+            .unwrap();
+        theirs.add_tag(github, "work").unwrap();
+        theirs
+            .add(&other_key, NewEntry::new("aws", "root", b"new"))
+            .unwrap();
+        EntryRepository::unlocked(&desktop, Some("Travel".to_string()), &other_key)
+            .unwrap()
+            .add(&other_key, NewEntry::new("github", "bob", b"trip"))
+            .unwrap();
+
+        let plan = laptop.plan_merge(&key, &desktop, &other_key).unwrap();
+        let outcomes: Vec<(&str, MergeOutcome)> = plan
+            .entries
+            .iter()
+            .map(|entry| (entry.other.metadata.platform.as_str(), entry.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("aws", MergeOutcome::Add),
+                ("github", MergeOutcome::TakeOther),
+                ("gitlab", MergeOutcome::Unchanged),
+                ("github", MergeOutcome::Add),
+            ]
+        );
+        assert!(laptop.vaults().unwrap().iter().all(|v| v.name != "Travel"));
+        laptop.apply_merge(&key, &plan).unwrap();
+
+        let github_entry = ours.get(&key, github).unwrap();
+        assert_eq!(github_entry.secret.expose(), b"two");
+        assert_eq!(github_entry.metadata.tags, ["work"]);
+        let history = ours.history(&key, github).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].secret.expose(), b"one");
+        assert_eq!(secret(&laptop, &key, "Travel", "bob"), b"trip");
+        assert!(ours.contains(EntrySelector::new("aws", "root")).unwrap());
+
+        // Merging the other way keeps the laptop's newer secret and changes nothing else.
+        let plan = desktop.plan_merge(&other_key, &laptop, &key).unwrap();
+        assert_eq!(plan.count(MergeOutcome::Add), 0);
+        assert_eq!(plan.count(MergeOutcome::Conflict), 0);
+    }
+
+    #[test]
+    fn independent_changes_conflict_until_resolved_and_keep_both_secrets() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let (laptop, desktop) = (memory_db(), memory_db());
+        let ours = EntryRepository::new(&laptop, None).unwrap();
+        let theirs = EntryRepository::new(&desktop, None).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        ours.add(&key, NewEntry::new("github", "alice", b"laptop"))
+            .unwrap();
+        theirs
+            .add(&key, NewEntry::new("github", "alice", b"desktop"))
+            .unwrap();
+        ours.add(&key, NewEntry::new("gitlab", "alice", b"kept"))
+            .unwrap();
+        ours.remove(EntrySelector::new("gitlab", "alice")).unwrap();
+        theirs
+            .add(&key, NewEntry::new("gitlab", "alice", b"revived"))
+            .unwrap();
+
+        let mut plan = laptop.plan_merge(&key, &desktop, &key).unwrap();
+        assert_eq!(plan.count(MergeOutcome::Conflict), 1);
+        assert!(matches!(
+            laptop.apply_merge(&key, &plan),
+            Err(StorageError::Conflict)
+        ));
+
+        let mut both = plan;
+        for entry in both.conflicts_mut() {
+            entry.outcome = MergeOutcome::KeepBoth;
+        }
+        laptop.apply_merge(&key, &both).unwrap();
+        assert_eq!(secret(&laptop, &key, "main", "alice"), b"laptop");
+        assert_eq!(secret(&laptop, &key, "main", "alice (merged)"), b"desktop");
+        let gitlab = EntrySelector::new("gitlab", "alice");
+        assert_eq!(ours.get(&key, gitlab).unwrap().secret.expose(), b"revived");
+        assert_eq!(
+            ours.history(&key, gitlab).unwrap()[0].secret.expose(),
+            b"kept"
+        );
+
+        plan = laptop.plan_merge(&key, &desktop, &key).unwrap();
+        let conflict = plan.conflicts_mut().next().unwrap();
+        conflict.outcome = MergeOutcome::KeepLocal;
+        laptop.apply_merge(&key, &plan).unwrap();
+        assert_eq!(ours.get(&key, github).unwrap().secret.expose(), b"laptop");
+        assert_eq!(
+            ours.history(&key, github).unwrap()[0].secret.expose(),
+            b"desktop"
+        );
+    }
+
+    #[test]
+    fn notes_edited_on_both_sides_conflict_and_keep_both_survives() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let (laptop, desktop) = (memory_db(), memory_db());
+        let ours = EntryRepository::new(&laptop, None).unwrap();
+        let theirs = EntryRepository::new(&desktop, None).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        for (repository, notes) in [(&ours, "laptop notes"), (&theirs, "desktop notes")] {
+            repository
+                .add(&key, NewEntry::new("github", "alice", b"same"))
+                .unwrap();
+            repository
+                .update(
+                    &key,
+                    github,
+                    EntryUpdate::new("alice", b"same").with_notes(notes),
+                )
+                .unwrap();
+        }
+
+        let mut plan = laptop.plan_merge(&key, &desktop, &key).unwrap();
+        assert_eq!(plan.count(MergeOutcome::Conflict), 1);
+        for entry in plan.conflicts_mut() {
+            entry.outcome = MergeOutcome::KeepBoth;
+        }
+        laptop.apply_merge(&key, &plan).unwrap();
+
+        assert_eq!(
+            ours.get(&key, github).unwrap().details.notes,
+            "laptop notes"
+        );
+        assert_eq!(
+            ours.get(&key, EntrySelector::new("github", "alice (merged)"))
+                .unwrap()
+                .details
+                .notes,
+            "desktop notes"
+        );
+    }
+
+    #[test]
+    fn keeping_the_local_entry_over_one_of_another_kind_leaves_it_unchanged() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let (laptop, desktop) = (memory_db(), memory_db());
+        let ours = EntryRepository::new(&laptop, None).unwrap();
+        let theirs = EntryRepository::new(&desktop, None).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        ours.add(&key, NewEntry::new("github", "alice", b"password"))
+            .unwrap();
+        let note = TypedSecret::Note(SecureNote::new("recovery codes").unwrap());
+        theirs
+            .add(
+                &key,
+                NewEntry::new("github", "alice", note.to_bytes().expose())
+                    .with_kind(EntryKind::Note),
+            )
+            .unwrap();
+        theirs.add_tag(github, "notes").unwrap();
+
+        let mut plan = laptop.plan_merge(&key, &desktop, &key).unwrap();
+        assert_eq!(plan.count(MergeOutcome::Conflict), 1);
+        for entry in plan.conflicts_mut() {
+            entry.outcome = MergeOutcome::KeepLocal;
+        }
+        laptop.apply_merge(&key, &plan).unwrap();
+
+        let entry = ours.get(&key, github).unwrap();
+        assert_eq!(entry.secret.expose(), b"password");
+        assert_eq!(entry.metadata.kind, EntryKind::Login);
+        assert!(entry.metadata.tags.is_empty());
+        assert!(ours.history(&key, github).unwrap().is_empty());
+    }
+
+    #[test]
+    fn merging_from_a_copy_leaves_the_other_file_untouched() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let root =
+            std::env::temp_dir().join(format!("rvault-merge-copy-{}", rand::random::<u64>()));
+        std::fs::create_dir_all(&root).unwrap();
+        let (path, copy) = (root.join("desktop.sqlite"), root.join("copy.sqlite"));
+        {
+            let desktop = Database {
+                connection: Connection::open(&path).unwrap(),
+            };
+            EntryRepository::new(&desktop, None)
+                .unwrap()
+                .add(&key, NewEntry::new("github", "alice", b"desktop"))
+                .unwrap();
+            // Leave the file one schema version behind, as an older release would.
+            desktop
+                .connection
+                .pragma_update(None, "user_version", 17)
+                .unwrap();
+        }
+        let original = std::fs::read(&path).unwrap();
+
+        let laptop = memory_db();
+        EntryRepository::new(&laptop, None).unwrap();
+        let other = Database::open_copy(&path, &copy).unwrap();
+        let plan = laptop.plan_merge(&key, &other, &key).unwrap();
+        laptop.apply_merge(&key, &plan).unwrap();
+
+        assert_eq!(secret(&laptop, &key, "main", "alice"), b"desktop");
+        assert_eq!(std::fs::read(&path).unwrap(), original);
+        assert!(!root.join("desktop.sqlite-wal").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&copy).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
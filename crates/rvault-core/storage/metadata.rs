@@ -0,0 +1,581 @@
+//! Encrypted entry metadata.
+//!
+//! In encrypted mode a table's `platform` and `user_id` columns hold keyed HMAC blind indexes
+//! instead of the values themselves, so the `UNIQUE(platform, user_id)` constraint and every
+//! `WHERE platform = ? AND user_id = ?` lookup keep working. The real values are sealed with a
+//! MEK-derived key into the `metadata` column, authenticated together with both indexes.
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::{Database, StorageError, Table, migration};
+use crate::crypto::{Ciphertext, CryptoError, decrypt_with_aad, encrypt_with_aad};
+use crate::secret::{SecretBytes, SecretKey};
+
+const INDEX_KEY_INFO: &[u8] = b"rvault-blind-index-v1";
+const SEAL_KEY_INFO: &[u8] = b"rvault-metadata-key-v1";
+const INDEX_PREFIX: &str = "bi1:";
+const MODE_PREFIX: &str = "metadata_mode.";
+const ENCRYPTED_MODE: &str = "encrypted";
+
+/// Entry columns holding one optional metadata value, sealed under the column name in
+/// encrypted mode.
+pub(super) const SEALED_COLUMNS: [&str; 2] = ["folder", "urls"];
+
+/// Keys for blind indexes and sealed metadata, derived from the MEK.
+pub(super) struct MetadataCipher {
+    index_key: SecretKey,
+    seal_key: SecretKey,
+}
+
+/// Column values identifying one row.
+pub(super) struct StoredIdentity {
+    pub(super) platform: String,
+    pub(super) user_id: String,
+    pub(super) metadata: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedIdentity {
+    platform: String,
+    user_id: String,
+}
+
+impl MetadataCipher {
+    pub(super) fn new(key: &SecretKey) -> Result<Self, StorageError> {
+        let hkdf = Hkdf::<Sha256>::new(None, key.as_bytes());
+        let mut index_key = Zeroizing::new([0_u8; 32]);
+        let mut seal_key = Zeroizing::new([0_u8; 32]);
+        hkdf.expand(INDEX_KEY_INFO, index_key.as_mut())
+            .and_then(|_| hkdf.expand(SEAL_KEY_INFO, seal_key.as_mut()))
+            .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?;
+        Ok(Self {
+            index_key: SecretKey::from_bytes(*index_key),
+            seal_key: SecretKey::from_bytes(*seal_key),
+        })
+    }
+
+    /// Blind index for one field; the field name is mixed in so equal values in different
+    /// columns do not share an index.
+    fn index(&self, field: &str, value: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.index_key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(field.as_bytes());
+        mac.update(&[0]);
+        mac.update(value.as_bytes());
+        format!(
+            "{INDEX_PREFIX}{}",
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        )
+    }
+
+    pub(super) fn selector(&self, platform: &str, user_id: &str) -> (String, String) {
+        (
+            self.index("platform", platform),
+            self.index("user_id", user_id),
+        )
+    }
+
+    pub(super) fn seal(
+        &self,
+        platform: &str,
+        user_id: &str,
+    ) -> Result<StoredIdentity, StorageError> {
+        let (platform_index, user_index) = self.selector(platform, user_id);
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(&SealedIdentity {
+                platform: platform.to_string(),
+                user_id: user_id.to_string(),
+            })
+            .expect("string fields always serialize"),
+        );
+        let sealed = self.seal_bytes(&plaintext, &aad(&platform_index, &user_index))?;
+        Ok(StoredIdentity {
+            metadata: Some(sealed),
+            platform: platform_index,
+            user_id: user_index,
+        })
+    }
+
+    pub(super) fn open(
+        &self,
+        platform_index: &str,
+        user_index: &str,
+        sealed: &str,
+    ) -> Result<(String, String), StorageError> {
+        let plaintext = self.open_bytes(sealed, &aad(platform_index, user_index))?;
+        let identity: SealedIdentity = serde_json::from_slice(plaintext.expose())
+            .map_err(|_| CryptoError::AuthenticationFailed)?;
+        Ok((identity.platform, identity.user_id))
+    }
+
+    /// Blind index for a tag.
+    pub(super) fn tag_index(&self, tag: &str) -> String {
+        self.index("tag", tag)
+    }
+
+    /// Seals a single metadata value, such as a tag or folder path, bound to its field name.
+    pub(super) fn seal_value(&self, field: &str, value: &str) -> Result<String, StorageError> {
+        self.seal_bytes(value.as_bytes(), field.as_bytes())
+    }
+
+    pub(super) fn open_value(&self, field: &str, sealed: &str) -> Result<String, StorageError> {
+        let plaintext = self.open_bytes(sealed, field.as_bytes())?;
+        String::from_utf8(plaintext.expose().to_vec())
+            .map_err(|_| CryptoError::AuthenticationFailed.into())
+    }
+
+    fn seal_bytes(&self, plaintext: &[u8], aad: &[u8]) -> Result<String, StorageError> {
+        let ciphertext = encrypt_with_aad(&self.seal_key, plaintext, aad)?;
+        Ok(format!(
+            "{}:{}",
+            BASE64.encode(ciphertext.nonce()),
+            BASE64.encode(ciphertext.bytes())
+        ))
+    }
+
+    fn open_bytes(&self, sealed: &str, aad: &[u8]) -> Result<SecretBytes, StorageError> {
+        let (nonce, bytes) = sealed
+            .split_once(':')
+            .ok_or(CryptoError::AuthenticationFailed)?;
+        let nonce = BASE64.decode(nonce).map_err(CryptoError::InvalidEncoding)?;
+        let bytes = BASE64.decode(bytes).map_err(CryptoError::InvalidEncoding)?;
+        let ciphertext = Ciphertext::try_from_parts(&nonce, bytes)?;
+        decrypt_with_aad(&self.seal_key, &ciphertext, aad).map_err(Into::into)
+    }
+}
+
+fn aad(platform_index: &str, user_index: &str) -> Vec<u8> {
+    format!("{platform_index}\n{user_index}").into_bytes()
+}
+
+impl Table {
+    /// Column values to write for an entry identity.
+    pub(super) fn stored_identity(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        platform: &str,
+        user_id: &str,
+    ) -> Result<StoredIdentity, StorageError> {
+        if !self.encrypted_metadata {
+            return Ok(StoredIdentity {
+                platform: platform.to_string(),
+                user_id: user_id.to_string(),
+                metadata: None,
+            });
+        }
+        cipher
+            .ok_or(StorageError::MetadataLocked)?
+            .seal(platform, user_id)
+    }
+
+    /// Column values to match when looking an entry up.
+    pub(super) fn stored_selector(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        platform: &str,
+        user_id: &str,
+    ) -> Result<(String, String), StorageError> {
+        if !self.encrypted_metadata {
+            return Ok((platform.to_string(), user_id.to_string()));
+        }
+        Ok(cipher
+            .ok_or(StorageError::MetadataLocked)?
+            .selector(platform, user_id))
+    }
+
+    /// Column value to write for one of [`SEALED_COLUMNS`].
+    pub(super) fn stored_value(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        column: &str,
+        value: Option<String>,
+    ) -> Result<Option<String>, StorageError> {
+        match value {
+            Some(value) if self.encrypted_metadata => cipher
+                .ok_or(StorageError::MetadataLocked)?
+                .seal_value(column, &value)
+                .map(Some),
+            value => Ok(value),
+        }
+    }
+
+    /// Real value of one of [`SEALED_COLUMNS`].
+    pub(super) fn read_value(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        column: &str,
+        stored: Option<String>,
+    ) -> Result<Option<String>, StorageError> {
+        match stored {
+            Some(sealed) if self.encrypted_metadata => cipher
+                .ok_or(StorageError::MetadataLocked)?
+                .open_value(column, &sealed)
+                .map(Some),
+            stored => Ok(stored),
+        }
+    }
+
+    /// Real `platform` and `user_id` of a stored row.
+    pub(super) fn read_identity(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        platform: String,
+        user_id: String,
+        metadata: Option<String>,
+    ) -> Result<(String, String), StorageError> {
+        if !self.encrypted_metadata {
+            return Ok((platform, user_id));
+        }
+        let sealed = metadata.ok_or(CryptoError::AuthenticationFailed)?;
+        cipher
+            .ok_or(StorageError::MetadataLocked)?
+            .open(&platform, &user_id, &sealed)
+    }
+}
+
+/// Rewrites a table's [`SEALED_COLUMNS`] for `new` metadata keys. With `old` set to `None` the
+/// stored values are plaintext, as when a table is first converted to encrypted metadata.
+pub(super) fn reseal_columns(
+    transaction: &Transaction<'_>,
+    table: &str,
+    old: Option<&MetadataCipher>,
+    new: &MetadataCipher,
+) -> Result<(), StorageError> {
+    for column in SEALED_COLUMNS {
+        let rows: Vec<(i64, String)> = {
+            let mut statement = transaction.prepare(&format!(
+                "SELECT id, {column} FROM {table} WHERE {column} IS NOT NULL"
+            ))?;
+            statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_, _>>()?
+        };
+        for (id, stored) in rows {
+            let value = match old {
+                Some(old) => old.open_value(column, &stored)?,
+                None => stored,
+            };
+            transaction.execute(
+                &format!("UPDATE {table} SET {column} = ?1 WHERE id = ?2"),
+                params![new.seal_value(column, &value)?, id],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Settings key recording the metadata mode of `table_name`.
+pub(super) fn mode_key(table_name: &str) -> String {
+    format!("{MODE_PREFIX}{table_name}")
+}
+
+/// Whether `table_name` stores its metadata encrypted.
+pub(super) fn is_encrypted(connection: &Connection, table_name: &str) -> rusqlite::Result<bool> {
+    if !migration::meta_table_exists(connection)? {
+        return Ok(false);
+    }
+    let mode: Option<String> = connection
+        .query_row(
+            &format!(
+                "SELECT value FROM {} WHERE name = ?1",
+                migration::META_TABLE
+            ),
+            [mode_key(table_name)],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(mode.as_deref() == Some(ENCRYPTED_MODE))
+}
+
+impl Database {
+    /// Converts a vault table to encrypted metadata in place, in one transaction.
+    ///
+    /// Every row's `platform` and `user_id` are replaced with blind indexes and sealed into the
+    /// `metadata` column; tags, folders and URLs are indexed or sealed the same way. Afterwards the table must be opened with
+    /// [`super::EntryRepository::unlocked`] to list, remove or pin entries. Returns the number of
+    /// converted entries; converting an already encrypted table does nothing.
+    ///
+    /// The plaintext must not survive in the file either: the conversion runs with
+    /// `secure_delete`, rebuilds the search index, and then vacuums the database and truncates
+    /// its write-ahead log.
+    pub fn encrypt_metadata(
+        &self,
+        key: &SecretKey,
+        table_name: Option<String>,
+    ) -> Result<usize, StorageError> {
+        let table = Table::new(self, table_name).map_err(super::repository::map_database_error)?;
+        if table.encrypted_metadata {
+            return Ok(0);
+        }
+        let cipher = MetadataCipher::new(key)?;
+        let secure_delete: bool =
+            self.connection
+                .pragma_query_value(None, "secure_delete", |row| row.get(0))?;
+        self.connection.pragma_update(None, "secure_delete", true)?;
+        let converted = self.convert_metadata(&table, &cipher).and_then(|count| {
+            wipe_free_pages(&self.connection)?;
+            Ok(count)
+        });
+        self.connection
+            .pragma_update(None, "secure_delete", secure_delete)?;
+        converted
+    }
+
+    fn convert_metadata(
+        &self,
+        table: &Table,
+        cipher: &MetadataCipher,
+    ) -> Result<usize, StorageError> {
+        let transaction = self.connection.unchecked_transaction()?;
+        let rows: Vec<(i64, String, String)> = {
+            let mut statement = transaction.prepare(&format!(
+                "SELECT id, platform, user_id FROM {}",
+                table.table_name
+            ))?;
+            statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?
+        };
+        let update = format!(
+            "UPDATE {} SET platform = ?1, user_id = ?2, metadata = ?3 WHERE id = ?4",
+            table.table_name
+        );
+        for (id, platform, user_id) in &rows {
+            let stored = cipher.seal(platform, user_id)?;
+            transaction.execute(
+                &update,
+                params![stored.platform, stored.user_id, stored.metadata, id],
+            )?;
+        }
+        super::tags::reseal_table(&transaction, &table.table_name, None, cipher)?;
+        reseal_columns(&transaction, &table.table_name, None, cipher)?;
+        super::search::rebuild_index(&transaction, &table.table_name)?;
+        transaction.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (name, value) VALUES (?1, ?2)",
+                migration::META_TABLE
+            ),
+            [mode_key(&table.table_name), ENCRYPTED_MODE.to_string()],
+        )?;
+        transaction.commit()?;
+        Ok(rows.len())
+    }
+}
+
+/// Rewrites the database file without its free pages and moves every page out of the
+/// write-ahead log, whose old frames would otherwise still hold overwritten data.
+fn wipe_free_pages(connection: &Connection) -> rusqlite::Result<()> {
+    let checkpoint = |connection: &Connection| {
+        connection.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+    };
+    checkpoint(connection)?;
+    connection.execute_batch("VACUUM")?;
+    checkpoint(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EntryRepository, EntrySelector, EntryUpdate, NewEntry};
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn raw_identities(db: &Database) -> Vec<(String, String)> {
+        let mut statement = db
+            .connection
+            .prepare("SELECT platform, user_id FROM main ORDER BY id")
+            .unwrap();
+        statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn conversion_hides_identities_and_keeps_entries_usable() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let plain = EntryRepository::new(&db, None).unwrap();
+        plain
+            .add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        plain
+            .add(&key, NewEntry::new("gitlab", "bob", b"two"))
+            .unwrap();
+
+        assert_eq!(db.encrypt_metadata(&key, None).unwrap(), 2);
+        assert_eq!(db.encrypt_metadata(&key, None).unwrap(), 0);
+
+        for (platform, user_id) in raw_identities(&db) {
+            assert!(platform.starts_with(INDEX_PREFIX));
+            assert!(user_id.starts_with(INDEX_PREFIX));
+        }
+        let repository = EntryRepository::unlocked(&db, None, &key).unwrap();
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "alice"))
+            .unwrap();
+        assert_eq!(entry.metadata.platform, "github");
+        assert_eq!(entry.secret.expose(), b"one");
+        let listed: Vec<_> = repository
+            .list_metadata()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.platform, entry.user_id))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("github".to_string(), "alice".to_string()),
+                ("gitlab".to_string(), "bob".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn conversion_leaves_no_plaintext_in_the_database_files() {
+        let path =
+            std::env::temp_dir().join(format!("rvault-metadata-test-{}.db", rand::random::<u64>()));
+        let files = |path: &std::path::Path| {
+            ["", "-wal", "-shm"].map(|suffix| {
+                let mut name = path.as_os_str().to_owned();
+                name.push(suffix);
+                std::path::PathBuf::from(name)
+            })
+        };
+        let contents = |path: &std::path::Path| {
+            files(path)
+                .iter()
+                .filter_map(|file| std::fs::read(file).ok())
+                .flatten()
+                .collect::<Vec<u8>>()
+        };
+        let contains = |haystack: &[u8], needle: &[u8]| {
+            haystack
+                .windows(needle.len())
+                .any(|window| window == needle)
+        };
+        let key = SecretKey::from_bytes([7; 32]);
+        {
+            let db = Database::open(&path).unwrap();
+            let plain = EntryRepository::new(&db, None).unwrap();
+            for index in 0..50 {
+                let platform = format!("plaintext-site-{index}");
+                plain
+                    .add(&key, NewEntry::new(&platform, "plaintext-user", b"one"))
+                    .unwrap();
+            }
+            assert!(contains(&contents(&path), b"plaintext-site"));
+
+            assert_eq!(db.encrypt_metadata(&key, None).unwrap(), 50);
+            let raw = contents(&path);
+            assert!(!contains(&raw, b"plaintext-site"));
+            assert!(!contains(&raw, b"plaintext-user"));
+        }
+        for file in files(&path) {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn encrypted_tables_support_every_repository_operation() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        db.encrypt_metadata(&key, None).unwrap();
+        let repository = EntryRepository::unlocked(&db, None, &key).unwrap();
+
+        repository
+            .add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        assert!(matches!(
+            repository.add(&key, NewEntry::new("github", "alice", b"again")),
+            Err(StorageError::Conflict)
+        ));
+        repository
+            .update(
+                &key,
+                EntrySelector::new("github", "alice"),
+                EntryUpdate::new("alice@example.com", b"two"),
+            )
+            .unwrap();
+        repository
+            .set_pinned(EntrySelector::new("github", "alice@example.com"), true)
+            .unwrap();
+        assert!(
+            repository
+                .contains(EntrySelector::new("github", "alice@example.com"))
+                .unwrap()
+        );
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "alice@example.com"))
+            .unwrap();
+        assert_eq!(entry.metadata.user_id, "alice@example.com");
+        assert!(entry.metadata.pinned);
+        assert_eq!(entry.secret.expose(), b"two");
+
+        repository
+            .remove(EntrySelector::new("github", "alice@example.com"))
+            .unwrap();
+        assert!(repository.list_metadata().unwrap().is_empty());
+    }
+
+    #[test]
+    fn encrypted_tables_need_a_key_for_keyless_operations() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        db.encrypt_metadata(&key, None).unwrap();
+        let repository = EntryRepository::new(&db, None).unwrap();
+        repository
+            .add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+
+        assert!(matches!(
+            repository.list_metadata(),
+            Err(StorageError::MetadataLocked)
+        ));
+        assert!(matches!(
+            repository.remove(EntrySelector::new("github", "alice")),
+            Err(StorageError::MetadataLocked)
+        ));
+    }
+
+    #[test]
+    fn sealed_metadata_is_bound_to_its_row() {
+        let cipher = MetadataCipher::new(&SecretKey::from_bytes([7; 32])).unwrap();
+        let alice = cipher.seal("github", "alice").unwrap();
+        let bob = cipher.seal("github", "bob").unwrap();
+
+        assert_eq!(alice.platform, bob.platform);
+        assert_ne!(alice.user_id, bob.user_id);
+        assert!(
+            cipher
+                .open(
+                    &alice.platform,
+                    &alice.user_id,
+                    bob.metadata.as_deref().unwrap()
+                )
+                .is_err()
+        );
+        assert_eq!(
+            cipher
+                .open(
+                    &alice.platform,
+                    &alice.user_id,
+                    alice.metadata.as_deref().unwrap()
+                )
+                .unwrap(),
+            ("github".to_string(), "alice".to_string())
+        );
+    }
+}
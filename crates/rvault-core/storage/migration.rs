@@ -1,5 +1,14 @@
 use crate::error::DatabaseError;
-use rusqlite::{Connection, Transaction};
+use rusqlite::{Connection, OptionalExtension, Transaction};
+
+use super::search;
+
+pub(super) const META_TABLE: &str = "rvault_meta";
+pub(super) const HISTORY_TABLE: &str = "rvault_history";
+pub(super) const TAGS_TABLE: &str = "rvault_tags";
+pub(super) const AUDIT_TABLE: &str = "rvault_audit";
+pub(super) const VAULTS_TABLE: &str = "rvault_vaults";
+const ENTRY_COLUMNS: [&str; 5] = ["platform", "user_id", "password", "nonce", "salt"];
 
 pub(super) fn migrate(connection: &Connection, table_name: &str) -> Result<(), DatabaseError> {
     let transaction = connection.unchecked_transaction()?;
@@ -17,7 +26,67 @@ pub(super) fn migrate(connection: &Connection, table_name: &str) -> Result<(), D
         migrate_2_to_3(&transaction, table_name)?;
         version = 3;
     }
-    if version > 3 {
+    if version < 4 {
+        migrate_3_to_4(&transaction)?;
+        version = 4;
+    }
+    if version < 5 {
+        migrate_4_to_5(&transaction)?;
+        version = 5;
+    }
+    if version < 6 {
+        migrate_5_to_6(&transaction)?;
+        version = 6;
+    }
+    if version < 7 {
+        migrate_6_to_7(&transaction)?;
+        version = 7;
+    }
+    if version < 8 {
+        migrate_7_to_8(&transaction)?;
+        version = 8;
+    }
+    if version < 9 {
+        migrate_8_to_9(&transaction)?;
+        version = 9;
+    }
+    if version < 10 {
+        migrate_9_to_10(&transaction)?;
+        version = 10;
+    }
+    if version < 11 {
+        migrate_10_to_11(&transaction)?;
+        version = 11;
+    }
+    if version < 12 {
+        migrate_11_to_12(&transaction)?;
+        version = 12;
+    }
+    if version < 13 {
+        migrate_12_to_13(&transaction)?;
+        version = 13;
+    }
+    if version < 14 {
+        migrate_13_to_14(&transaction)?;
+        version = 14;
+    }
+    if version < 15 {
+        migrate_14_to_15(&transaction)?;
+        version = 15;
+    }
+    if version < 16 {
+        migrate_15_to_16(&transaction)?;
+        version = 16;
+    }
+    if version < 17 {
+        migrate_16_to_17(&transaction)?;
+        version = 17;
+    }
+    if version < 18 {
+        migrate_17_to_18(&transaction)?;
+        version = 18;
+    }
+    if version > 18 {
         return Err(DatabaseError::Sqlite(rusqlite::Error::InvalidQuery));
     }
     transaction.commit()?;
@@ -39,6 +108,299 @@ fn migrate_2_to_3(transaction: &Transaction<'_>, table_name: &str) -> rusqlite::
     transaction.pragma_update(None, "user_version", 3)
 }
 
+/// Records the entry key scheme per row. Existing rows were written with Argon2 entry keys.
+///
+/// `user_version` covers the whole database, so every entry table is migrated here rather than
+/// only the one being opened.
+fn migrate_3_to_4(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(
+            transaction,
+            &table_name,
+            "key_scheme",
+            "INTEGER NOT NULL DEFAULT 1",
+        )?;
+    }
+    transaction.pragma_update(None, "user_version", 4)
+}
+
+/// Adds the sealed `metadata` column used by encrypted-metadata tables and the database-wide
+/// settings table that records which tables use it.
+fn migrate_4_to_5(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(transaction, &table_name, "metadata", "TEXT")?;
+    }
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {META_TABLE} (name TEXT PRIMARY KEY, value TEXT NOT NULL)"
+        ),
+        [],
+    )?;
+    transaction.pragma_update(None, "user_version", 5)
+}
+
+/// Adds the encrypted `details` column holding notes and custom fields.
+fn migrate_5_to_6(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(transaction, &table_name, "details", "TEXT")?;
+    }
+    transaction.pragma_update(None, "user_version", 6)
+}
+
+/// Adds the encrypted `totp` column holding an entry's TOTP secret.
+fn migrate_6_to_7(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(transaction, &table_name, "totp", "TEXT")?;
+    }
+    transaction.pragma_update(None, "user_version", 7)
+}
+
+/// Adds the database-wide table of previous entry secrets. Rows point at an entry by table name
+/// and row id, so renaming an entry keeps its history.
+fn migrate_7_to_8(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {HISTORY_TABLE} (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entry_table TEXT NOT NULL,
+                entry_id INTEGER NOT NULL,
+                secret TEXT NOT NULL,
+                replaced_at INTEGER NOT NULL
+            )"
+        ),
+        [],
+    )?;
+    transaction.execute(
+        &format!(
+            "CREATE INDEX IF NOT EXISTS {HISTORY_TABLE}_entry ON {HISTORY_TABLE} (entry_table, entry_id)"
+        ),
+        [],
+    )?;
+    transaction.pragma_update(None, "user_version", 8)
+}
+
+/// Adds each entry's `folder` path and the database-wide tags table, which holds one row per
+/// entry and tag.
+fn migrate_8_to_9(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(transaction, &table_name, "folder", "TEXT")?;
+    }
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {TAGS_TABLE} (
+                entry_table TEXT NOT NULL,
+                entry_id INTEGER NOT NULL,
+                tag TEXT NOT NULL,
+                sealed TEXT,
+                PRIMARY KEY (entry_table, entry_id, tag)
+            )"
+        ),
+        [],
+    )?;
+    transaction.pragma_update(None, "user_version", 9)
+}
+
+/// Adds the `urls` column holding an entry's login URLs and match modes as JSON.
+fn migrate_9_to_10(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(transaction, &table_name, "urls", "TEXT")?;
+    }
+    transaction.pragma_update(None, "user_version", 10)
+}
+
+/// Adds `deleted_at`, the Unix time at which an entry was moved to the trash.
+fn migrate_10_to_11(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(transaction, &table_name, "deleted_at", "INTEGER")?;
+    }
+    transaction.pragma_update(None, "user_version", 11)
+}
+
+/// Adds the audit log: sealed records, each with a link of the hash chain.
+fn migrate_11_to_12(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {AUDIT_TABLE} (
+                id INTEGER PRIMARY KEY,
+                record TEXT NOT NULL,
+                chain TEXT NOT NULL
+            )"
+        ),
+        [],
+    )?;
+    transaction.pragma_update(None, "user_version", 12)
+}
+
+/// Adds the vault registry and registers every existing entry table, created when its oldest
+/// entry was, or now for empty tables.
+fn migrate_12_to_13(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    transaction.execute(
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {VAULTS_TABLE} (
+                name TEXT PRIMARY KEY,
+                created_at INTEGER NOT NULL
+            )"
+        ),
+        [],
+    )?;
+    let now = chrono::Utc::now().timestamp();
+    for table_name in entry_tables(transaction)? {
+        transaction.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {VAULTS_TABLE} (name, created_at)
+                 VALUES (?1, COALESCE((SELECT MIN(NULLIF(created_at, 0)) FROM {table_name}), ?2))"
+            ),
+            rusqlite::params![table_name, now],
+        )?;
+    }
+    transaction.pragma_update(None, "user_version", 13)
+}
+
+/// Separates vault names from table names. Registered vaults keep their table, named like the
+/// vault.
+fn migrate_13_to_14(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    add_column_if_missing(transaction, VAULTS_TABLE, "table_name", "TEXT")?;
+    transaction.execute(
+        &format!("UPDATE {VAULTS_TABLE} SET table_name = name WHERE table_name IS NULL"),
+        [],
+    )?;
+    transaction.execute(
+        &format!(
+            "CREATE UNIQUE INDEX IF NOT EXISTS {VAULTS_TABLE}_table_name
+             ON {VAULTS_TABLE} (table_name)"
+        ),
+        [],
+    )?;
+    transaction.pragma_update(None, "user_version", 14)
+}
+
+/// Adds the search index of every entry table.
+fn migrate_14_to_15(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        search::create_index(transaction, &table_name)?;
+    }
+    transaction.pragma_update(None, "user_version", 15)
+}
+
+/// Adds the `kind` column. Every existing entry is a login.
+fn migrate_15_to_16(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(
+            transaction,
+            &table_name,
+            "kind",
+            "TEXT NOT NULL DEFAULT 'login'",
+        )?;
+    }
+    transaction.pragma_update(None, "user_version", 16)
+}
+
+/// Adds the `revision` column and the triggers that bump it. Every existing entry starts at
+/// revision 1.
+fn migrate_16_to_17(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        add_column_if_missing(
+            transaction,
+            &table_name,
+            "revision",
+            "INTEGER NOT NULL DEFAULT 1",
+        )?;
+        create_revision_triggers(transaction, &table_name)?;
+    }
+    transaction.pragma_update(None, "user_version", 17)
+}
+
+/// Recreates the revision triggers so that re-encrypting rows under another key no longer bumps
+/// revisions.
+fn migrate_17_to_18(transaction: &Transaction<'_>) -> rusqlite::Result<()> {
+    for table_name in entry_tables(transaction)? {
+        transaction.execute_batch(&format!("DROP TRIGGER IF EXISTS {table_name}_revision;"))?;
+        create_revision_triggers(transaction, &table_name)?;
+    }
+    transaction.pragma_update(None, "user_version", 18)
+}
+
+/// Creates the triggers that bump an entry's revision whenever its tags are added or removed,
+/// or its row is written with a new `updated_at`, `deleted_at` or `pinned`. Every change to an
+/// entry sets one of those, while re-encrypting rows under another key or key scheme sets none
+/// and keeps the revision. Writes that set the revision themselves are left alone.
+pub(super) fn create_revision_triggers(
+    connection: &Connection,
+    table_name: &str,
+) -> rusqlite::Result<()> {
+    let bump =
+        |id: &str| format!("UPDATE {table_name} SET revision = revision + 1 WHERE id = {id};");
+    connection.execute_batch(&format!(
+        "CREATE TRIGGER IF NOT EXISTS {table_name}_revision
+        AFTER UPDATE OF updated_at, deleted_at, pinned ON {table_name}
+        WHEN NEW.revision = OLD.revision BEGIN
+            {row}
+        END;
+        CREATE TRIGGER IF NOT EXISTS {table_name}_revision_tag_insert AFTER INSERT ON {TAGS_TABLE}
+        WHEN NEW.entry_table = '{table_name}' BEGIN
+            {tag_insert}
+        END;
+        CREATE TRIGGER IF NOT EXISTS {table_name}_revision_tag_delete AFTER DELETE ON {TAGS_TABLE}
+        WHEN OLD.entry_table = '{table_name}' BEGIN
+            {tag_delete}
+        END;",
+        row = bump("NEW.id"),
+        tag_insert = bump("NEW.entry_id"),
+        tag_delete = bump("OLD.entry_id"),
+    ))
+}
+
+/// Drops the revision triggers on the tags table. The trigger on the entry table goes with it.
+pub(super) fn drop_revision_triggers(
+    connection: &Connection,
+    table_name: &str,
+) -> rusqlite::Result<()> {
+    connection.execute_batch(&format!(
+        "DROP TRIGGER IF EXISTS {table_name}_revision_tag_insert;
+        DROP TRIGGER IF EXISTS {table_name}_revision_tag_delete;"
+    ))
+}
+
+pub(super) fn meta_table_exists(connection: &Connection) -> rusqlite::Result<bool> {
+    connection
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [META_TABLE],
+            |_| Ok(()),
+        )
+        .optional()
+        .map(|row| row.is_some())
+}
+
+/// Names of every table that stores vault entries, in creation order.
+pub(super) fn entry_tables(connection: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut statement = connection.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY rowid",
+    )?;
+    let names = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut tables = Vec::new();
+    for name in names {
+        let matching: i64 = connection.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name IN (?2, ?3, ?4, ?5, ?6)",
+            rusqlite::params![
+                name,
+                ENTRY_COLUMNS[0],
+                ENTRY_COLUMNS[1],
+                ENTRY_COLUMNS[2],
+                ENTRY_COLUMNS[3],
+                ENTRY_COLUMNS[4]
+            ],
+            |row| row.get(0),
+        )?;
+        if matching == ENTRY_COLUMNS.len() as i64 {
+            tables.push(name);
+        }
+    }
+    Ok(tables)
+}
+
 fn add_column_if_missing(
     transaction: &Transaction<'_>,
     table_name: &str,
@@ -106,23 +468,60 @@ mod tests {
             .unwrap();
         migrate(&connection, "main").unwrap();
         let columns = columns(&connection);
-        for expected in ["pinned", "created_at", "updated_at"] {
+        for expected in [
+            "pinned",
+            "created_at",
+            "updated_at",
+            "key_scheme",
+            "metadata",
+            "details",
+            "totp",
+            "folder",
+            "urls",
+            "deleted_at",
+            "kind",
+            "revision",
+        ] {
             assert!(columns.iter().any(|column| column == expected));
         }
         let version: i64 = connection
             .pragma_query_value(None, "user_version", |row| row.get(0))
             .unwrap();
-        assert_eq!(version, 3);
+        assert_eq!(version, 18);
+        for table in [HISTORY_TABLE, TAGS_TABLE, AUDIT_TABLE, VAULTS_TABLE] {
+            let count: i64 = connection
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE name = ?1",
+                    [table],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(count, 1);
+        }
         if schema.contains("INSERT INTO") {
             let value: String = connection
                 .query_row("SELECT password FROM main", [], |row| row.get(0))
                 .unwrap();
             assert_eq!(value, "secret");
+            let revision = || -> i64 {
+                connection
+                    .query_row("SELECT revision FROM main", [], |row| row.get(0))
+                    .unwrap()
+            };
+            assert_eq!(revision(), 1);
+            connection
+                .execute("UPDATE main SET password = 'rekeyed'", [])
+                .unwrap();
+            assert_eq!(revision(), 1);
+            connection
+                .execute("UPDATE main SET password = 'changed', updated_at = 1", [])
+                .unwrap();
+            assert_eq!(revision(), 2);
         }
     }
 
     #[test]
-    fn migrates_supported_schema_fixtures_to_version_three() {
+    fn migrates_supported_schema_fixtures_to_version_eighteen() {
         assert_migrates("");
         assert_migrates(
             "CREATE TABLE main (
@@ -145,6 +544,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn key_scheme_migration_covers_every_entry_table_and_marks_rows_legacy() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE main (
+                    id INTEGER PRIMARY KEY, platform TEXT NOT NULL, user_id TEXT NOT NULL,
+                    password TEXT NOT NULL, nonce TEXT, salt TEXT, pinned BOOLEAN DEFAULT FALSE,
+                    created_at INTEGER DEFAULT 0, updated_at INTEGER DEFAULT 0
+                );
+                CREATE TABLE work (
+                    id INTEGER PRIMARY KEY, platform TEXT NOT NULL, user_id TEXT NOT NULL,
+                    password TEXT NOT NULL, nonce TEXT, salt TEXT, pinned BOOLEAN DEFAULT FALSE,
+                    created_at INTEGER DEFAULT 0, updated_at INTEGER DEFAULT 0
+                );
+                CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT);
+                INSERT INTO work VALUES (1, 'example', 'user', 'secret', 'n', 's', FALSE, 0, 0);
+                PRAGMA user_version = 3;",
+            )
+            .unwrap();
+
+        migrate(&connection, "main").unwrap();
+
+        assert_eq!(entry_tables(&connection).unwrap(), vec!["main", "work"]);
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT name, table_name FROM {VAULTS_TABLE} ORDER BY name"
+            ))
+            .unwrap();
+        let registered: Vec<(String, String)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            registered,
+            vec![
+                ("main".to_string(), "main".to_string()),
+                ("work".to_string(), "work".to_string())
+            ]
+        );
+        let scheme: i64 = connection
+            .query_row("SELECT key_scheme FROM work", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(scheme, 1);
+        let mut statement = connection.prepare("PRAGMA table_info(notes)").unwrap();
+        let note_columns: Vec<String> = statement
+            .query_map([], |row| row.get(1))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(note_columns, vec!["id", "body"]);
+    }
+
     #[test]
     fn failed_migration_rolls_back_schema_and_version() {
         let connection = Connection::open_in_memory().unwrap();
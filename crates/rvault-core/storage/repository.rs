@@ -2,20 +2,98 @@ use argon2::{Argon2, password_hash::SaltString};
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64;
 use chrono::Utc;
+use hkdf::Hkdf;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 use rusqlite::{OptionalExtension, params};
+use sha2::Sha256;
+use std::collections::HashMap;
 use zeroize::Zeroizing;
 
+use super::batch::Savepoint;
+use super::details::{self, CustomField, EntryDetails};
+use super::history::{self, HistoryEntry};
+use super::metadata::MetadataCipher;
+use super::search::{self, SearchHit};
+use super::tags::{self, FOLDER_COLUMN};
+use super::trash::{self, TrashedEntry};
 use super::{Database, Table};
 use crate::crypto::{Ciphertext, CryptoError, decrypt, encrypt};
+use crate::domain::{self, EntryUrl, MatchQuality};
+use crate::fuzzy::{Query, SearchField};
+use crate::kind::{EntryKind, TypedSecret};
 use crate::secret::{SecretBytes, SecretKey};
+use crate::totp::TotpSecret;
 
 use super::StorageError;
 
+const HKDF_ENTRY_INFO: &[u8] = b"rvault-entry-key-v2";
+const URLS_COLUMN: &str = "urls";
+/// Row filters for entries in and out of the trash.
+const LIVE: &str = "deleted_at IS NULL";
+const TRASHED: &str = "deleted_at IS NOT NULL";
+
+/// How an entry key is derived from the MEK and the entry salt, stored per row as `key_scheme`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum KeyScheme {
+    /// Argon2 with default parameters, used by rows written by 1.4 and earlier.
+    Argon2 = 1,
+    /// HKDF-SHA256; the MEK is already uniformly random, so no stretching is needed.
+    Hkdf = 2,
+}
+
+impl KeyScheme {
+    pub(super) const CURRENT: Self = Self::Hkdf;
+}
+
+impl ToSql for KeyScheme {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(*self as i64))
+    }
+}
+
+impl TryFrom<i64> for KeyScheme {
+    type Error = i64;
+
+    fn try_from(value: i64) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::Argon2),
+            2 => Ok(Self::Hkdf),
+            other => Err(other),
+        }
+    }
+}
+
+impl FromSql for KeyScheme {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Self::try_from(value.as_i64()?).map_err(FromSqlError::OutOfRange)
+    }
+}
+
+impl ToSql for EntryKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.name()))
+    }
+}
+
+impl FromSql for EntryKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value
+            .as_str()?
+            .parse()
+            .map_err(|error: String| FromSqlError::Other(error.into()))
+    }
+}
+
 /// Borrowed values used to create an encrypted entry.
 pub struct NewEntry<'a> {
     pub platform: &'a str,
     pub user_id: &'a str,
     pub secret: &'a [u8],
+    pub notes: &'a str,
+    pub fields: &'a [CustomField],
+    pub totp: Option<&'a TotpSecret>,
+    pub urls: &'a [EntryUrl],
+    pub kind: EntryKind,
 }
 
 impl<'a> NewEntry<'a> {
@@ -24,8 +102,39 @@ impl<'a> NewEntry<'a> {
             platform,
             user_id,
             secret,
+            notes: "",
+            fields: &[],
+            totp: None,
+            urls: &[],
+            kind: EntryKind::Login,
         }
     }
+
+    /// Marks the entry as `kind`; `secret` must then hold a serialized [`TypedSecret`].
+    pub fn with_kind(mut self, kind: EntryKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    pub fn with_urls(mut self, urls: &'a [EntryUrl]) -> Self {
+        self.urls = urls;
+        self
+    }
+
+    pub fn with_totp(mut self, totp: &'a TotpSecret) -> Self {
+        self.totp = Some(totp);
+        self
+    }
+
+    pub fn with_notes(mut self, notes: &'a str) -> Self {
+        self.notes = notes;
+        self
+    }
+
+    pub fn with_fields(mut self, fields: &'a [CustomField]) -> Self {
+        self.fields = fields;
+        self
+    }
 }
 
 /// Stable identity used to select one entry.
@@ -42,14 +151,53 @@ impl<'a> EntrySelector<'a> {
 }
 
 /// Borrowed replacement values for an existing entry.
+///
+/// Notes and fields are kept as they are unless replaced with [`EntryUpdate::with_notes`] or
+/// [`EntryUpdate::with_fields`].
 pub struct EntryUpdate<'a> {
     pub user_id: &'a str,
     pub secret: &'a [u8],
+    pub notes: Option<&'a str>,
+    pub fields: Option<&'a [CustomField]>,
+    /// Replaces the entry's login URLs in the same write; `None` keeps them.
+    pub urls: Option<&'a [EntryUrl]>,
+    /// Revision the entry must still be at, as read by the caller; see
+    /// [`EntryUpdate::with_expected_revision`].
+    pub expected_revision: Option<i64>,
 }
 
 impl<'a> EntryUpdate<'a> {
     pub fn new(user_id: &'a str, secret: &'a [u8]) -> Self {
-        Self { user_id, secret }
+        Self {
+            user_id,
+            secret,
+            notes: None,
+            fields: None,
+            urls: None,
+            expected_revision: None,
+        }
+    }
+
+    pub fn with_notes(mut self, notes: &'a str) -> Self {
+        self.notes = Some(notes);
+        self
+    }
+
+    pub fn with_fields(mut self, fields: &'a [CustomField]) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+
+    pub fn with_urls(mut self, urls: &'a [EntryUrl]) -> Self {
+        self.urls = Some(urls);
+        self
+    }
+
+    /// Fails the update with [`StorageError::RevisionMismatch`] unless the entry is still at
+    /// `revision`, so changes made since the entry was read are not overwritten.
+    pub fn with_expected_revision(mut self, revision: i64) -> Self {
+        self.expected_revision = Some(revision);
+        self
     }
 }
 
@@ -61,31 +209,115 @@ pub struct EntryMetadata {
     pub pinned: bool,
     pub created_at: i64,
     pub updated_at: i64,
+    /// `/`-separated folder path, or `None` at the top level.
+    pub folder: Option<String>,
+    /// Sorted tags.
+    pub tags: Vec<String>,
+    /// Login URLs, in the order they were added.
+    pub urls: Vec<EntryUrl>,
+    pub kind: EntryKind,
+    /// Starts at 1 and goes up by one on every write to the entry.
+    pub revision: i64,
+}
+
+impl EntryMetadata {
+    /// Whether the entry is in `folder` or one of its subfolders.
+    pub fn in_folder(&self, folder: &str) -> bool {
+        tags::is_within(self.folder.as_deref(), folder)
+    }
 }
 
 /// One decrypted entry with zeroizing secret ownership.
 pub struct DecryptedEntry {
     pub metadata: EntryMetadata,
     pub secret: SecretBytes,
+    pub details: EntryDetails,
+    pub totp: Option<TotpSecret>,
+}
+
+impl DecryptedEntry {
+    /// The card, note, identity or API key held by an entry that is not a login.
+    pub fn typed_secret(&self) -> Result<Option<TypedSecret>, StorageError> {
+        match self.metadata.kind {
+            EntryKind::Login => Ok(None),
+            kind => TypedSecret::from_bytes(kind, self.secret.expose())
+                .map(Some)
+                .map_err(|_| StorageError::InvalidSecret(kind)),
+        }
+    }
+}
+
+/// An entry row as read from the table, before decryption.
+struct StoredEntry {
+    metadata: EntryMetadata,
+    sealed_metadata: Option<String>,
+    ciphertext: String,
+    nonce: String,
+    salt: String,
+    scheme: KeyScheme,
+    details: Option<String>,
+    totp: Option<String>,
+    urls: Option<String>,
 }
 
 /// Canonical encrypted-entry storage boundary.
 pub struct EntryRepository<'a> {
-    db: &'a Database,
-    table: Table,
+    pub(super) db: &'a Database,
+    pub(super) table: Table,
+    cipher: Option<MetadataCipher>,
 }
 
 impl<'a> EntryRepository<'a> {
     pub fn new(db: &'a Database, table_name: Option<String>) -> Result<Self, StorageError> {
         let table = Table::new(db, table_name).map_err(map_database_error)?;
-        Ok(Self { db, table })
+        let repository = Self {
+            db,
+            table,
+            cipher: None,
+        };
+        // Best effort, like upgrading legacy rows in `get`: opening a vault must still work when
+        // expired entries cannot be purged, e.g. on a read-only database.
+        let _ = repository.purge_expired_trash();
+        Ok(repository)
+    }
+
+    /// Opens a repository that can also list, remove and pin entries in tables with encrypted
+    /// metadata. For plaintext tables it behaves like [`EntryRepository::new`].
+    pub fn unlocked(
+        db: &'a Database,
+        table_name: Option<String>,
+        key: &SecretKey,
+    ) -> Result<Self, StorageError> {
+        let mut repository = Self::new(db, table_name)?;
+        if repository.table.encrypted_metadata {
+            repository.cipher = Some(MetadataCipher::new(key)?);
+        }
+        Ok(repository)
+    }
+
+    /// Whether this table stores `platform` and `user_id` encrypted.
+    pub fn has_encrypted_metadata(&self) -> bool {
+        self.table.encrypted_metadata
     }
 
+    /// Adds a new entry. Fails with [`StorageError::Conflict`] when a live or trashed entry
+    /// has the identity; a trashed one must be restored or purged first.
     pub fn add(&self, key: &SecretKey, entry: NewEntry<'_>) -> Result<(), StorageError> {
+        let cipher = self.cipher_for(key)?;
+        let identity =
+            self.table
+                .stored_identity(cipher.as_ref(), entry.platform, entry.user_id)?;
+        check_secret(entry.kind, entry.secret)?;
         let (ciphertext, nonce, salt) = encrypt_entry(key, entry.secret)?;
+        let details = details::seal(key, entry.notes, entry.fields)?;
+        let totp = entry
+            .totp
+            .map(|totp| details::seal_totp(key, totp))
+            .transpose()?;
+        let urls = self.stored_urls(cipher.as_ref(), entry.urls)?;
         let now = Utc::now().timestamp();
         let query = format!(
-            "INSERT INTO {} (platform, user_id, password, nonce, salt, pinned, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, FALSE, ?6, ?7)",
+            "INSERT INTO {} (platform, user_id, metadata, password, nonce, salt, key_scheme, details, totp, urls, kind, pinned, created_at, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, FALSE, ?12, ?13)",
             self.table.table_name
         );
         self.db
@@ -93,11 +325,17 @@ impl<'a> EntryRepository<'a> {
             .execute(
                 &query,
                 params![
-                    entry.platform,
-                    entry.user_id,
+                    identity.platform,
+                    identity.user_id,
+                    identity.metadata,
                     ciphertext,
                     nonce,
                     salt,
+                    KeyScheme::CURRENT,
+                    details,
+                    totp,
+                    urls,
+                    entry.kind,
                     now,
                     now
                 ],
@@ -112,6 +350,13 @@ impl<'a> EntryRepository<'a> {
         selector: EntrySelector<'_>,
         update: EntryUpdate<'_>,
     ) -> Result<(), StorageError> {
+        let cipher = self.cipher_for(key)?;
+        let (platform, user_id) =
+            self.table
+                .stored_selector(cipher.as_ref(), selector.platform, selector.user_id)?;
+        let identity =
+            self.table
+                .stored_identity(cipher.as_ref(), selector.platform, update.user_id)?;
         if selector.user_id != update.user_id {
             let query = format!(
                 "SELECT 1 FROM {} WHERE platform = ?1 AND user_id = ?2",
@@ -120,99 +365,509 @@ impl<'a> EntryRepository<'a> {
             if self
                 .db
                 .connection
-                .query_row(&query, [selector.platform, update.user_id], |_| Ok(()))
+                .query_row(&query, [&identity.platform, &identity.user_id], |_| Ok(()))
                 .optional()?
                 .is_some()
             {
                 return Err(StorageError::Conflict);
             }
         }
+        let current_query = format!(
+            "SELECT id, password, nonce, salt, key_scheme, details, kind, revision FROM {} WHERE platform = ?1 AND user_id = ?2 AND {LIVE}",
+            self.table.table_name
+        );
+        let (
+            id,
+            current_ciphertext,
+            current_nonce,
+            current_salt,
+            current_scheme,
+            current_details,
+            kind,
+            revision,
+        ): (
+            i64,
+            String,
+            String,
+            String,
+            KeyScheme,
+            Option<String>,
+            EntryKind,
+            i64,
+        ) = self
+            .db
+            .connection
+            .query_row(&current_query, [&platform, &user_id], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                ))
+            })
+            .optional()?
+            .ok_or(StorageError::NotFound)?;
+        let expected = update.expected_revision.unwrap_or(revision);
+        if expected != revision {
+            return Err(StorageError::RevisionMismatch {
+                expected,
+                actual: revision,
+            });
+        }
+        check_secret(kind, update.secret)?;
+        let current_secret = decrypt_entry(
+            key,
+            &current_ciphertext,
+            &current_nonce,
+            &current_salt,
+            current_scheme,
+        )?;
+        let details = match (update.notes, update.fields) {
+            (None, None) => current_details,
+            (Some(notes), Some(fields)) => details::seal(key, notes, fields)?,
+            (notes, fields) => {
+                let current = details::open(key, current_details.as_deref())?;
+                details::seal(
+                    key,
+                    notes.unwrap_or(&current.notes),
+                    fields.unwrap_or(&current.fields),
+                )?
+            }
+        };
+        let urls = update
+            .urls
+            .map(|urls| self.stored_urls(cipher.as_ref(), urls))
+            .transpose()?;
         let (ciphertext, nonce, salt) = encrypt_entry(key, update.secret)?;
+        let now = Utc::now().timestamp();
+        let transaction = Savepoint::new(&self.db.connection)?;
+        if current_secret.expose() != update.secret {
+            history::record(
+                &transaction,
+                key,
+                &self.table.table_name,
+                id,
+                current_secret.expose(),
+                now,
+            )?;
+        }
         let query = format!(
-            "UPDATE {} SET user_id = ?1, password = ?2, nonce = ?3, salt = ?4, updated_at = ?5 WHERE platform = ?6 AND user_id = ?7",
+            "UPDATE {} SET user_id = ?1, metadata = ?2, password = ?3, nonce = ?4, salt = ?5, key_scheme = ?6, details = ?7, updated_at = ?8, urls = CASE WHEN ?11 THEN ?12 ELSE urls END WHERE id = ?9 AND revision = ?10",
             self.table.table_name
         );
-        let affected = self.db.connection.execute(
+        let affected = transaction.execute(
             &query,
             params![
-                update.user_id,
+                identity.user_id,
+                identity.metadata,
                 ciphertext,
                 nonce,
                 salt,
-                Utc::now().timestamp(),
-                selector.platform,
-                selector.user_id
+                KeyScheme::CURRENT,
+                details,
+                now,
+                id,
+                revision,
+                urls.is_some(),
+                urls.flatten()
             ],
         )?;
-        exactly_one(affected)
+        if affected == 0 {
+            // Another connection wrote the entry after it was read above.
+            return Err(StorageError::RevisionMismatch {
+                expected,
+                actual: transaction.query_row(
+                    &format!(
+                        "SELECT revision FROM {} WHERE id = ?1",
+                        self.table.table_name
+                    ),
+                    [id],
+                    |row| row.get(0),
+                )?,
+            });
+        }
+        exactly_one(affected)?;
+        transaction.commit()?;
+        Ok(())
     }
 
+    /// Previous secrets of an entry, newest first.
+    pub fn history(
+        &self,
+        key: &SecretKey,
+        selector: EntrySelector<'_>,
+    ) -> Result<Vec<HistoryEntry>, StorageError> {
+        let cipher = self.cipher_for(key)?;
+        let id = self.entry_id(cipher.as_ref(), selector)?;
+        history::list(&self.db.connection, key, &self.table.table_name, id)
+    }
+
+    /// Makes a previous secret current again. The replaced secret joins the history, so a
+    /// restore can itself be undone.
+    pub fn restore_history(
+        &self,
+        key: &SecretKey,
+        selector: EntrySelector<'_>,
+        history_id: i64,
+    ) -> Result<(), StorageError> {
+        let cipher = self.cipher_for(key)?;
+        let id = self.entry_id(cipher.as_ref(), selector)?;
+        let secret = history::open(
+            &self.db.connection,
+            key,
+            &self.table.table_name,
+            id,
+            history_id,
+        )?;
+        self.update(
+            key,
+            selector,
+            EntryUpdate::new(selector.user_id, secret.expose()),
+        )
+    }
+
+    /// Moves an entry to the trash. Its history and tags stay with it until it is purged.
     pub fn remove(&self, selector: EntrySelector<'_>) -> Result<(), StorageError> {
+        let id = self.entry_id(self.cipher.as_ref(), selector)?;
         let query = format!(
-            "DELETE FROM {} WHERE platform = ?1 AND user_id = ?2",
+            "UPDATE {} SET deleted_at = ?1 WHERE id = ?2",
             self.table.table_name
         );
         exactly_one(
             self.db
                 .connection
-                .execute(&query, [selector.platform, selector.user_id])?,
+                .execute(&query, params![Utc::now().timestamp(), id])?,
         )
     }
 
+    /// Moves an entry into the vault of `target`, which must be opened on the same database,
+    /// with its history, tags, folder and URLs. Metadata is stored the way the target vault
+    /// stores it; the pin is dropped when ten entries there are already pinned. Like
+    /// [`Self::add`], it fails with [`StorageError::Conflict`] when the target vault holds the
+    /// identity, even in its trash.
+    pub fn move_to(
+        &self,
+        key: &SecretKey,
+        selector: EntrySelector<'_>,
+        target: &EntryRepository<'_>,
+    ) -> Result<(), StorageError> {
+        let (source_table, target_table) = (&self.table.table_name, &target.table.table_name);
+        if source_table == target_table {
+            return Ok(());
+        }
+        let source_cipher = self.cipher_for(key)?;
+        let target_cipher = target.cipher_for(key)?;
+        let id = self.entry_id(source_cipher.as_ref(), selector)?;
+        let (folder, urls): (Option<String>, Option<String>) = self.db.connection.query_row(
+            &format!("SELECT folder, urls FROM {source_table} WHERE id = ?1"),
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let folder = self
+            .table
+            .read_value(source_cipher.as_ref(), FOLDER_COLUMN, folder)?;
+        let urls = self
+            .table
+            .read_value(source_cipher.as_ref(), URLS_COLUMN, urls)?;
+        let tags = self
+            .table
+            .read_tags_for(&self.db.connection, source_cipher.as_ref(), id)?;
+        let target_cipher = target_cipher.as_ref();
+        let identity =
+            target
+                .table
+                .stored_identity(target_cipher, selector.platform, selector.user_id)?;
+        let folder = target
+            .table
+            .stored_value(target_cipher, FOLDER_COLUMN, folder)?;
+        let urls = target
+            .table
+            .stored_value(target_cipher, URLS_COLUMN, urls)?;
+
+        let transaction = Savepoint::new(&self.db.connection)?;
+        transaction
+            .execute(
+                &format!(
+                    "INSERT INTO {target_table} (platform, user_id, metadata, password, nonce, salt, key_scheme, details, totp, folder, urls, kind, revision, pinned, created_at, updated_at)
+                     SELECT ?1, ?2, ?3, password, nonce, salt, key_scheme, details, totp, ?4, ?5, kind,
+                        revision + 1,
+                        pinned AND (SELECT COUNT(*) FROM {target_table} WHERE pinned = TRUE AND {LIVE}) < 10,
+                        created_at, updated_at
+                     FROM {source_table} WHERE id = ?6"
+                ),
+                params![
+                    identity.platform,
+                    identity.user_id,
+                    identity.metadata,
+                    folder,
+                    urls,
+                    id
+                ],
+            )
+            .map_err(map_insert_error)?;
+        let new_id = transaction.last_insert_rowid();
+        history::reassign(&transaction, (source_table, id), (target_table, new_id))?;
+        tags::forget(&transaction, source_table, id)?;
+        for tag in tags {
+            tags::add(
+                &transaction,
+                target_table,
+                new_id,
+                target.table.stored_tag(target_cipher, &tag)?,
+            )?;
+        }
+        transaction.execute(&format!("DELETE FROM {source_table} WHERE id = ?1"), [id])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Entries in the trash, most recently removed first.
+    pub fn trash(&self) -> Result<Vec<TrashedEntry>, StorageError> {
+        let mut entries: Vec<TrashedEntry> = self
+            .read_metadata(TRASHED)?
+            .into_iter()
+            .map(|(metadata, deleted_at)| TrashedEntry {
+                metadata,
+                deleted_at: deleted_at.unwrap_or_default(),
+            })
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+        Ok(entries)
+    }
+
+    /// Moves an entry out of the trash. Its pin is dropped when ten entries are already pinned.
+    pub fn restore_trashed(&self, selector: EntrySelector<'_>) -> Result<(), StorageError> {
+        let id = self.row_id(self.cipher.as_ref(), selector, TRASHED)?;
+        let table = &self.table.table_name;
+        let query = format!(
+            "UPDATE {table} SET deleted_at = NULL,
+                pinned = pinned AND (SELECT COUNT(*) FROM {table} WHERE pinned = TRUE AND {LIVE}) < 10
+             WHERE id = ?1"
+        );
+        exactly_one(self.db.connection.execute(&query, [id])?)
+    }
+
+    /// Permanently deletes an entry in the trash, with its history and tags.
+    pub fn purge_trashed(&self, selector: EntrySelector<'_>) -> Result<(), StorageError> {
+        let id = self.row_id(self.cipher.as_ref(), selector, TRASHED)?;
+        let transaction = Savepoint::new(&self.db.connection)?;
+        trash::purge(&transaction, &self.table.table_name, id)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Permanently deletes every entry in the trash and returns how many there were.
+    pub fn empty_trash(&self) -> Result<usize, StorageError> {
+        let transaction = Savepoint::new(&self.db.connection)?;
+        let purged = trash::purge_before(&transaction, &self.table.table_name, i64::MAX)?;
+        transaction.commit()?;
+        Ok(purged)
+    }
+
+    /// Purges entries that have been in the trash longer than the configured retention.
+    fn purge_expired_trash(&self) -> Result<usize, StorageError> {
+        let Some(cutoff) = trash::retention_cutoff(&self.db.connection, Utc::now().timestamp())?
+        else {
+            return Ok(0);
+        };
+        let transaction = Savepoint::new(&self.db.connection)?;
+        let purged = trash::purge_before(&transaction, &self.table.table_name, cutoff)?;
+        transaction.commit()?;
+        Ok(purged)
+    }
+
+    /// Whether an entry with this identity exists.
+    pub fn contains(&self, selector: EntrySelector<'_>) -> Result<bool, StorageError> {
+        let (platform, user_id) = self.table.stored_selector(
+            self.cipher.as_ref(),
+            selector.platform,
+            selector.user_id,
+        )?;
+        let query = format!(
+            "SELECT EXISTS(SELECT 1 FROM {} WHERE platform = ?1 AND user_id = ?2 AND {LIVE})",
+            self.table.table_name
+        );
+        self.db
+            .connection
+            .query_row(&query, [platform, user_id], |row| row.get(0))
+            .map_err(Into::into)
+    }
+
     pub fn get(
         &self,
         key: &SecretKey,
         selector: EntrySelector<'_>,
     ) -> Result<DecryptedEntry, StorageError> {
+        let cipher = self.cipher_for(key)?;
+        let (platform, user_id) =
+            self.table
+                .stored_selector(cipher.as_ref(), selector.platform, selector.user_id)?;
         let query = format!(
-            "SELECT id, platform, user_id, password, nonce, salt, pinned, created_at, updated_at FROM {} WHERE platform = ?1 AND user_id = ?2",
+            "SELECT id, platform, user_id, password, nonce, salt, key_scheme, pinned, created_at, updated_at, metadata, details, totp, folder, urls, kind, revision FROM {} WHERE platform = ?1 AND user_id = ?2 AND {LIVE}",
             self.table.table_name
         );
-        let (metadata, ciphertext, nonce, salt): (EntryMetadata, String, String, String) = self
+        let row = self
             .db
             .connection
-            .query_row(&query, [selector.platform, selector.user_id], |row| {
-                Ok((
-                    EntryMetadata {
+            .query_row(&query, [platform, user_id], |row| {
+                Ok(StoredEntry {
+                    metadata: EntryMetadata {
                         id: row.get(0)?,
                         platform: row.get(1)?,
                         user_id: row.get(2)?,
-                        pinned: row.get(6)?,
-                        created_at: row.get(7).unwrap_or(0),
-                        updated_at: row.get(8).unwrap_or(0),
+                        pinned: row.get(7)?,
+                        created_at: row.get(8).unwrap_or(0),
+                        updated_at: row.get(9).unwrap_or(0),
+                        folder: row.get(13)?,
+                        tags: Vec::new(),
+                        urls: Vec::new(),
+                        kind: row.get(15)?,
+                        revision: row.get(16)?,
                     },
-                    row.get(3)?,
-                    row.get(4)?,
-                    row.get(5)?,
-                ))
+                    urls: row.get(14)?,
+                    sealed_metadata: row.get(10)?,
+                    ciphertext: row.get(3)?,
+                    nonce: row.get(4)?,
+                    salt: row.get(5)?,
+                    scheme: row.get(6)?,
+                    details: row.get(11)?,
+                    totp: row.get(12)?,
+                })
             })?;
-        let entry_key = derive_entry_key(key, salt.as_bytes())?;
-        let nonce = BASE64.decode(nonce).map_err(CryptoError::InvalidEncoding)?;
-        let bytes = BASE64
-            .decode(ciphertext)
-            .map_err(CryptoError::InvalidEncoding)?;
-        let ciphertext = Ciphertext::try_from_parts(&nonce, bytes)?;
-        let secret = decrypt(&entry_key, &ciphertext)?;
-        Ok(DecryptedEntry { metadata, secret })
+        let scheme = row.scheme;
+        let mut metadata =
+            self.open_metadata(cipher.as_ref(), row.metadata, row.sealed_metadata)?;
+        metadata.urls = self.read_urls(cipher.as_ref(), row.urls)?;
+        if let Some(id) = metadata.id {
+            metadata.tags = self
+                .table
+                .read_tags_for(&self.db.connection, cipher.as_ref(), id)?;
+        }
+        let secret = decrypt_entry(key, &row.ciphertext, &row.nonce, &row.salt, scheme)?;
+        let details = details::open(key, row.details.as_deref())?;
+        let totp = details::open_totp(key, row.totp.as_deref())?;
+        if scheme != KeyScheme::CURRENT {
+            // Best effort: a read must still succeed when the row cannot be rewritten, e.g. on
+            // a read-only database. The row is upgraded on a later read or by
+            // `Database::upgrade_entry_keys`.
+            let _ = self.rewrite_secret(key, metadata.id, secret.expose());
+        }
+        Ok(DecryptedEntry {
+            metadata,
+            secret,
+            details,
+            totp,
+        })
+    }
+
+    /// Attaches a TOTP secret to an entry, or removes it with `None`.
+    pub fn set_totp(
+        &self,
+        key: &SecretKey,
+        selector: EntrySelector<'_>,
+        totp: Option<&TotpSecret>,
+    ) -> Result<(), StorageError> {
+        let cipher = self.cipher_for(key)?;
+        let (platform, user_id) =
+            self.table
+                .stored_selector(cipher.as_ref(), selector.platform, selector.user_id)?;
+        let sealed = totp.map(|totp| details::seal_totp(key, totp)).transpose()?;
+        let query = format!(
+            "UPDATE {} SET totp = ?1, updated_at = ?2 WHERE platform = ?3 AND user_id = ?4 AND {LIVE}",
+            self.table.table_name
+        );
+        exactly_one(self.db.connection.execute(
+            &query,
+            params![sealed, Utc::now().timestamp(), platform, user_id],
+        )?)
+    }
+
+    /// Re-encrypts one row under the current key scheme without touching its timestamps or its
+    /// revision.
+    fn rewrite_secret(
+        &self,
+        key: &SecretKey,
+        id: Option<i64>,
+        secret: &[u8],
+    ) -> Result<(), StorageError> {
+        let (ciphertext, nonce, salt) = encrypt_entry(key, secret)?;
+        let query = format!(
+            "UPDATE {} SET password = ?1, nonce = ?2, salt = ?3, key_scheme = ?4 WHERE id = ?5",
+            self.table.table_name
+        );
+        let affected = self.db.connection.execute(
+            &query,
+            params![ciphertext, nonce, salt, KeyScheme::CURRENT, id],
+        )?;
+        exactly_one(affected)
     }
 
+    /// Entries outside the trash, pinned first.
     pub fn list_metadata(&self) -> Result<Vec<EntryMetadata>, StorageError> {
+        Ok(self
+            .read_metadata(LIVE)?
+            .into_iter()
+            .map(|(metadata, _)| metadata)
+            .collect())
+    }
+
+    /// Metadata and `deleted_at` of the rows matching `filter`, pinned first.
+    fn read_metadata(
+        &self,
+        filter: &str,
+    ) -> Result<Vec<(EntryMetadata, Option<i64>)>, StorageError> {
+        if self.table.encrypted_metadata && self.cipher.is_none() {
+            return Err(StorageError::MetadataLocked);
+        }
         let query = format!(
-            "SELECT id, platform, user_id, pinned, created_at, updated_at FROM {} ORDER BY pinned DESC, platform ASC",
+            "SELECT id, platform, user_id, pinned, created_at, updated_at, metadata, folder, urls, deleted_at, kind, revision FROM {} WHERE {filter} ORDER BY pinned DESC, platform ASC",
             self.table.table_name
         );
         let mut statement = self.db.connection.prepare(&query)?;
         let rows = statement.query_map([], |row| {
-            Ok(EntryMetadata {
-                id: row.get(0)?,
-                platform: row.get(1)?,
-                user_id: row.get(2)?,
-                pinned: row.get(3)?,
-                created_at: row.get(4).unwrap_or(0),
-                updated_at: row.get(5).unwrap_or(0),
-            })
+            Ok((
+                EntryMetadata {
+                    id: row.get(0)?,
+                    platform: row.get(1)?,
+                    user_id: row.get(2)?,
+                    pinned: row.get(3)?,
+                    created_at: row.get(4).unwrap_or(0),
+                    updated_at: row.get(5).unwrap_or(0),
+                    folder: row.get(7)?,
+                    tags: Vec::new(),
+                    urls: Vec::new(),
+                    kind: row.get(10)?,
+                    revision: row.get(11)?,
+                },
+                row.get::<_, Option<String>>(6)?,
+                row.get::<_, Option<String>>(8)?,
+                row.get::<_, Option<i64>>(9)?,
+            ))
         })?;
-        rows.collect::<Result<Vec<_>, _>>().map_err(Into::into)
+        let mut tags = self
+            .table
+            .read_tags(&self.db.connection, self.cipher.as_ref())?;
+        let mut entries = Vec::new();
+        for row in rows {
+            let (metadata, sealed, urls, deleted_at) = row?;
+            let mut metadata = self.open_metadata(self.cipher.as_ref(), metadata, sealed)?;
+            metadata.urls = self.read_urls(self.cipher.as_ref(), urls)?;
+            if let Some(id) = metadata.id {
+                metadata.tags = tags.remove(&id).unwrap_or_default();
+            }
+            entries.push((metadata, deleted_at));
+        }
+        if self.table.encrypted_metadata {
+            // Blind indexes carry no order; sort on the decrypted values instead.
+            entries.sort_by(|(a, _), (b, _)| {
+                b.pinned.cmp(&a.pinned).then(a.platform.cmp(&b.platform))
+            });
+        }
+        Ok(entries)
     }
 
     pub fn set_pinned(
@@ -220,18 +875,19 @@ impl<'a> EntryRepository<'a> {
         selector: EntrySelector<'_>,
         pinned: bool,
     ) -> Result<(), StorageError> {
+        let (platform, user_id) = self.table.stored_selector(
+            self.cipher.as_ref(),
+            selector.platform,
+            selector.user_id,
+        )?;
         let current_query = format!(
-            "SELECT pinned FROM {} WHERE platform = ?1 AND user_id = ?2",
+            "SELECT pinned FROM {} WHERE platform = ?1 AND user_id = ?2 AND {LIVE}",
             self.table.table_name
         );
         let current: Option<bool> = self
             .db
             .connection
-            .query_row(
-                &current_query,
-                [selector.platform, selector.user_id],
-                |row| row.get(0),
-            )
+            .query_row(&current_query, [&platform, &user_id], |row| row.get(0))
             .optional()?;
         let current = current.ok_or(StorageError::NotFound)?;
         if current == pinned {
@@ -239,7 +895,7 @@ impl<'a> EntryRepository<'a> {
         }
         if pinned && !current {
             let query = format!(
-                "SELECT COUNT(*) FROM {} WHERE pinned = TRUE",
+                "SELECT COUNT(*) FROM {} WHERE pinned = TRUE AND {LIVE}",
                 self.table.table_name
             );
             let count: i64 = self.db.connection.query_row(&query, [], |row| row.get(0))?;
@@ -248,31 +904,283 @@ impl<'a> EntryRepository<'a> {
             }
         }
         let query = format!(
-            "UPDATE {} SET pinned = ?1 WHERE platform = ?2 AND user_id = ?3",
+            "UPDATE {} SET pinned = ?1 WHERE platform = ?2 AND user_id = ?3 AND {LIVE}",
+            self.table.table_name
+        );
+        exactly_one(
+            self.db
+                .connection
+                .execute(&query, params![pinned, platform, user_id])?,
+        )
+    }
+
+    /// Entries carrying `tag`, in list order.
+    pub fn list_tagged(&self, tag: &str) -> Result<Vec<EntryMetadata>, StorageError> {
+        let mut entries = self.list_metadata()?;
+        entries.retain(|entry| entry.tags.iter().any(|entry_tag| entry_tag == tag));
+        Ok(entries)
+    }
+
+    /// Every tag used by entries outside the trash, sorted.
+    pub fn tags(&self) -> Result<Vec<String>, StorageError> {
+        let mut tags: Vec<String> = self
+            .list_metadata()?
+            .into_iter()
+            .flat_map(|entry| entry.tags)
+            .collect();
+        tags.sort();
+        tags.dedup();
+        Ok(tags)
+    }
+
+    /// Tags an entry. Surrounding whitespace is trimmed, and adding a tag the entry already
+    /// has, or an empty one, does nothing.
+    pub fn add_tag(&self, selector: EntrySelector<'_>, tag: &str) -> Result<(), StorageError> {
+        let id = self.entry_id(self.cipher.as_ref(), selector)?;
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Ok(());
+        }
+        let stored = self.table.stored_tag(self.cipher.as_ref(), tag)?;
+        tags::add(&self.db.connection, &self.table.table_name, id, stored)?;
+        Ok(())
+    }
+
+    pub fn remove_tag(&self, selector: EntrySelector<'_>, tag: &str) -> Result<(), StorageError> {
+        let id = self.entry_id(self.cipher.as_ref(), selector)?;
+        let (tag, _) = self.table.stored_tag(self.cipher.as_ref(), tag.trim())?;
+        tags::remove(&self.db.connection, &self.table.table_name, id, &tag)?;
+        Ok(())
+    }
+
+    /// Moves an entry into a `/`-separated folder path, creating it implicitly, or back to the
+    /// top level with `None`.
+    pub fn set_folder(
+        &self,
+        selector: EntrySelector<'_>,
+        folder: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let id = self.entry_id(self.cipher.as_ref(), selector)?;
+        let folder = self.table.stored_folder(self.cipher.as_ref(), folder)?;
+        let query = format!(
+            "UPDATE {} SET folder = ?1, updated_at = ?2 WHERE id = ?3",
+            self.table.table_name
+        );
+        exactly_one(
+            self.db
+                .connection
+                .execute(&query, params![folder, Utc::now().timestamp(), id])?,
+        )
+    }
+
+    /// Replaces the login URLs of an entry; an empty slice removes them.
+    pub fn set_urls(
+        &self,
+        selector: EntrySelector<'_>,
+        urls: &[EntryUrl],
+    ) -> Result<(), StorageError> {
+        let id = self.entry_id(self.cipher.as_ref(), selector)?;
+        let urls = self.stored_urls(self.cipher.as_ref(), urls)?;
+        let query = format!(
+            "UPDATE {} SET urls = ?1, updated_at = ?2 WHERE id = ?3",
             self.table.table_name
         );
         exactly_one(
             self.db
                 .connection
-                .execute(&query, params![pinned, selector.platform, selector.user_id])?,
+                .execute(&query, params![urls, Utc::now().timestamp(), id])?,
         )
     }
+
+    /// Entries with a URL matching `origin`, best match first. Equal matches keep list order,
+    /// so pinned entries come first.
+    pub fn match_origin(
+        &self,
+        origin: &str,
+    ) -> Result<Vec<(EntryMetadata, MatchQuality)>, StorageError> {
+        let mut matches: Vec<(EntryMetadata, MatchQuality)> = self
+            .list_metadata()?
+            .into_iter()
+            .filter_map(|entry| {
+                let quality = domain::best_match(&entry.urls, origin)?;
+                Some((entry, quality))
+            })
+            .collect();
+        matches.sort_by_key(|(_, quality)| std::cmp::Reverse(*quality));
+        Ok(matches)
+    }
+
+    /// Entries matching `query` by platform, user ID, tags, URLs and, given the key, notes;
+    /// best match first. Equal matches keep list order. An empty query matches every entry.
+    pub fn search(
+        &self,
+        key: Option<&SecretKey>,
+        query: &str,
+    ) -> Result<Vec<SearchHit>, StorageError> {
+        let query = Query::parse(query);
+        // Encrypted metadata is not indexed, so every entry is scored on its decrypted fields.
+        let candidates = if self.table.encrypted_metadata {
+            None
+        } else {
+            search::candidates(&self.db.connection, &self.table.table_name, &query)?
+        };
+        let details = match key {
+            Some(key) => self.read_details(key)?,
+            None => HashMap::new(),
+        };
+        let mut hits = Vec::new();
+        for entry in self.list_metadata()? {
+            let notes = entry
+                .id
+                .and_then(|id| details.get(&id))
+                .map_or("", |details| details.notes.as_str());
+            let indexed = match (&candidates, entry.id) {
+                (Some(ids), Some(id)) => ids.contains(&id),
+                _ => true,
+            };
+            let mut fields = vec![(SearchField::Notes, notes)];
+            // Entries the index ruled out share no trigram with the query outside their notes.
+            if indexed {
+                fields.push((SearchField::Platform, &entry.platform));
+                fields.push((SearchField::UserId, &entry.user_id));
+                fields.extend(
+                    entry
+                        .tags
+                        .iter()
+                        .map(|tag| (SearchField::Tag, tag.as_str())),
+                );
+                fields.extend(
+                    entry
+                        .urls
+                        .iter()
+                        .map(|url| (SearchField::Url, url.url.as_str())),
+                );
+            }
+            if let Some(score) = query.score(&fields) {
+                hits.push(SearchHit { entry, score });
+            }
+        }
+        hits.sort_by_key(|hit| std::cmp::Reverse(hit.score));
+        Ok(hits)
+    }
+
+    /// Decrypted notes and fields of the entries outside the trash that have any, by row id.
+    fn read_details(&self, key: &SecretKey) -> Result<HashMap<i64, EntryDetails>, StorageError> {
+        let query = format!(
+            "SELECT id, details FROM {} WHERE details IS NOT NULL AND {LIVE}",
+            self.table.table_name
+        );
+        let mut statement = self.db.connection.prepare(&query)?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut details = HashMap::new();
+        for row in rows {
+            let (id, sealed) = row?;
+            details.insert(id, details::open(key, Some(&sealed))?);
+        }
+        Ok(details)
+    }
+
+    fn stored_urls(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        urls: &[EntryUrl],
+    ) -> Result<Option<String>, StorageError> {
+        let json =
+            (!urls.is_empty()).then(|| serde_json::to_string(urls).expect("URLs always serialize"));
+        self.table.stored_value(cipher, URLS_COLUMN, json)
+    }
+
+    fn read_urls(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        stored: Option<String>,
+    ) -> Result<Vec<EntryUrl>, StorageError> {
+        let Some(json) = self.table.read_value(cipher, URLS_COLUMN, stored)? else {
+            return Ok(Vec::new());
+        };
+        serde_json::from_str(&json).map_err(|_| CryptoError::AuthenticationFailed.into())
+    }
+
+    fn entry_id(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        selector: EntrySelector<'_>,
+    ) -> Result<i64, StorageError> {
+        self.row_id(cipher, selector, LIVE)
+    }
+
+    fn row_id(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        selector: EntrySelector<'_>,
+        filter: &str,
+    ) -> Result<i64, StorageError> {
+        let (platform, user_id) =
+            self.table
+                .stored_selector(cipher, selector.platform, selector.user_id)?;
+        let query = format!(
+            "SELECT id FROM {} WHERE platform = ?1 AND user_id = ?2 AND {filter}",
+            self.table.table_name
+        );
+        self.db
+            .connection
+            .query_row(&query, [platform, user_id], |row| row.get(0))
+            .optional()?
+            .ok_or(StorageError::NotFound)
+    }
+
+    /// Metadata cipher for operations that receive the MEK, when the table needs one.
+    fn cipher_for(&self, key: &SecretKey) -> Result<Option<MetadataCipher>, StorageError> {
+        self.table
+            .encrypted_metadata
+            .then(|| MetadataCipher::new(key))
+            .transpose()
+    }
+
+    fn open_metadata(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        mut metadata: EntryMetadata,
+        sealed: Option<String>,
+    ) -> Result<EntryMetadata, StorageError> {
+        let (platform, user_id) =
+            self.table
+                .read_identity(cipher, metadata.platform, metadata.user_id, sealed)?;
+        metadata.platform = platform;
+        metadata.user_id = user_id;
+        metadata.folder = self
+            .table
+            .read_value(cipher, FOLDER_COLUMN, metadata.folder)?;
+        Ok(metadata)
+    }
 }
 
-pub(super) fn derive_entry_key(key: &SecretKey, salt: &[u8]) -> Result<SecretKey, StorageError> {
+pub(super) fn derive_entry_key(
+    key: &SecretKey,
+    salt: &[u8],
+    scheme: KeyScheme,
+) -> Result<SecretKey, StorageError> {
     let mut bytes = Zeroizing::new([0_u8; 32]);
-    Argon2::default()
-        .hash_password_into(key.as_bytes(), salt, bytes.as_mut())
-        .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?;
+    match scheme {
+        KeyScheme::Argon2 => Argon2::default()
+            .hash_password_into(key.as_bytes(), salt, bytes.as_mut())
+            .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?,
+        KeyScheme::Hkdf => Hkdf::<Sha256>::new(Some(salt), key.as_bytes())
+            .expand(HKDF_ENTRY_INFO, bytes.as_mut())
+            .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?,
+    }
     Ok(SecretKey::from_bytes(*bytes))
 }
 
+/// Encrypts `secret` under a fresh salt with [`KeyScheme::CURRENT`].
 pub(super) fn encrypt_entry(
     key: &SecretKey,
     secret: &[u8],
 ) -> Result<(String, String, String), StorageError> {
     let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
-    let entry_key = derive_entry_key(key, salt.as_ref().as_bytes())?;
+    let entry_key = derive_entry_key(key, salt.as_ref().as_bytes(), KeyScheme::CURRENT)?;
     let ciphertext = encrypt(&entry_key, secret)?;
     Ok((
         BASE64.encode(ciphertext.bytes()),
@@ -286,8 +1194,9 @@ pub(super) fn decrypt_entry(
     ciphertext: &str,
     nonce: &str,
     salt: &str,
+    scheme: KeyScheme,
 ) -> Result<SecretBytes, StorageError> {
-    let entry_key = derive_entry_key(key, salt.as_bytes())?;
+    let entry_key = derive_entry_key(key, salt.as_bytes(), scheme)?;
     let nonce = BASE64.decode(nonce).map_err(CryptoError::InvalidEncoding)?;
     let bytes = BASE64
         .decode(ciphertext)
@@ -296,6 +1205,17 @@ pub(super) fn decrypt_entry(
     decrypt(&entry_key, &ciphertext).map_err(Into::into)
 }
 
+/// Rejects secrets that do not hold a [`TypedSecret`] of the entry's kind. Login secrets are
+/// arbitrary bytes.
+pub(super) fn check_secret(kind: EntryKind, secret: &[u8]) -> Result<(), StorageError> {
+    match kind {
+        EntryKind::Login => Ok(()),
+        kind => TypedSecret::from_bytes(kind, secret)
+            .map(|_| ())
+            .map_err(|_| StorageError::InvalidSecret(kind)),
+    }
+}
+
 fn exactly_one(affected: usize) -> Result<(), StorageError> {
     if affected == 1 {
         Ok(())
@@ -315,7 +1235,7 @@ fn map_insert_error(error: rusqlite::Error) -> StorageError {
     }
 }
 
-fn map_database_error(error: crate::error::DatabaseError) -> StorageError {
+pub(super) fn map_database_error(error: crate::error::DatabaseError) -> StorageError {
     match error {
         crate::error::DatabaseError::Path => StorageError::Schema(
             rusqlite::Error::InvalidParameterName("database path".to_string()),
@@ -327,3 +1247,867 @@ fn map_database_error(error: crate::error::DatabaseError) -> StorageError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::MatchMode;
+    use crate::kind::Card;
+    use rusqlite::Connection;
+    use std::time::Instant;
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    /// Inserts a row the way 1.4 wrote it: an Argon2 entry key and `key_scheme = 1`.
+    fn insert_legacy(
+        repository: &EntryRepository<'_>,
+        key: &SecretKey,
+        platform: &str,
+        secret: &[u8],
+    ) {
+        let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
+        let entry_key = derive_entry_key(key, salt.as_ref().as_bytes(), KeyScheme::Argon2).unwrap();
+        let ciphertext = encrypt(&entry_key, secret).unwrap();
+        repository
+            .db
+            .connection
+            .execute(
+                &format!(
+                    "INSERT INTO {} (platform, user_id, password, nonce, salt, key_scheme) VALUES (?1, 'user', ?2, ?3, ?4, 1)",
+                    repository.table.table_name
+                ),
+                params![
+                    platform,
+                    BASE64.encode(ciphertext.bytes()),
+                    BASE64.encode(ciphertext.nonce()),
+                    salt.to_string()
+                ],
+            )
+            .unwrap();
+    }
+
+    fn scheme_of(repository: &EntryRepository<'_>, platform: &str) -> KeyScheme {
+        repository
+            .db
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT key_scheme FROM {} WHERE platform = ?1",
+                    repository.table.table_name
+                ),
+                [platform],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn new_entries_use_hkdf_entry_keys() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+
+        repository
+            .add(&key, NewEntry::new("github", "user", b"secret"))
+            .unwrap();
+
+        assert_eq!(scheme_of(&repository, "github"), KeyScheme::Hkdf);
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "user"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"secret");
+    }
+
+    #[test]
+    fn notes_and_fields_are_stored_encrypted_and_kept_by_updates() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let fields = vec![
+            CustomField::new("Account number", "12345678", false),
+            CustomField::new("First pet", "Rex", true),
+        ];
+        repository
+            .add(
+                &key,
+                NewEntry::new("bank", "user", b"secret")
+                    .with_notes("Call the branch first")
+                    .with_fields(&fields),
+            )
+            .unwrap();
+        let stored: String = db
+            .connection
+            .query_row("SELECT details FROM main", [], |row| row.get(0))
+            .unwrap();
+        assert!(!stored.contains("Rex"));
+
+        repository
+            .update(
+                &key,
+                EntrySelector::new("bank", "user"),
+                EntryUpdate::new("user", b"changed"),
+            )
+            .unwrap();
+        let entry = repository
+            .get(&key, EntrySelector::new("bank", "user"))
+            .unwrap();
+        assert_eq!(entry.details.notes, "Call the branch first");
+        assert_eq!(entry.details.fields, fields);
+
+        repository
+            .update(
+                &key,
+                EntrySelector::new("bank", "user"),
+                EntryUpdate::new("user", b"changed").with_fields(&fields[..1]),
+            )
+            .unwrap();
+        let entry = repository
+            .get(&key, EntrySelector::new("bank", "user"))
+            .unwrap();
+        assert_eq!(entry.details.notes, "Call the branch first");
+        assert_eq!(entry.details.fields, fields[..1]);
+    }
+
+    #[test]
+    fn totp_secrets_are_attached_encrypted_and_removed() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let totp = TotpSecret::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        repository
+            .add(&key, NewEntry::new("github", "user", b"secret"))
+            .unwrap();
+
+        repository
+            .set_totp(&key, EntrySelector::new("github", "user"), Some(&totp))
+            .unwrap();
+
+        let stored: String = db
+            .connection
+            .query_row("SELECT totp FROM main", [], |row| row.get(0))
+            .unwrap();
+        assert!(!stored.contains("GEZDGNBV"));
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "user"))
+            .unwrap();
+        assert_eq!(entry.totp.unwrap().code_at(59), "287082");
+
+        repository
+            .set_totp(&key, EntrySelector::new("github", "user"), None)
+            .unwrap();
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "user"))
+            .unwrap();
+        assert!(entry.totp.is_none());
+        assert!(matches!(
+            repository.set_totp(&key, EntrySelector::new("gitlab", "user"), Some(&totp)),
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn updates_keep_a_bounded_history_that_can_be_restored() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let selector = EntrySelector::new("github", "user");
+        repository
+            .add(&key, NewEntry::new("github", "user", b"one"))
+            .unwrap();
+        db.set_history_depth(2).unwrap();
+
+        for secret in [b"two", b"two", b"tri", b"for"] {
+            repository
+                .update(&key, selector, EntryUpdate::new("user", secret))
+                .unwrap();
+        }
+
+        let history = repository.history(&key, selector).unwrap();
+        let secrets: Vec<&[u8]> = history.iter().map(|entry| entry.secret.expose()).collect();
+        assert_eq!(secrets, vec![b"tri".as_slice(), b"two".as_slice()]);
+        let stored: String = db
+            .connection
+            .query_row("SELECT secret FROM rvault_history LIMIT 1", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(!stored.contains("tri"));
+
+        repository
+            .restore_history(&key, selector, history[1].id)
+            .unwrap();
+        let entry = repository.get(&key, selector).unwrap();
+        assert_eq!(entry.secret.expose(), b"two");
+        assert_eq!(
+            repository.history(&key, selector).unwrap()[0]
+                .secret
+                .expose(),
+            b"for"
+        );
+
+        repository.remove(selector).unwrap();
+        let history_rows = || -> i64 {
+            db.connection
+                .query_row("SELECT COUNT(*) FROM rvault_history", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(history_rows(), 2);
+        repository.purge_trashed(selector).unwrap();
+        assert_eq!(history_rows(), 0);
+    }
+
+    #[test]
+    fn removed_entries_wait_in_the_trash_until_restored_or_purged() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        for platform in ["github", "gitlab", "bank"] {
+            repository
+                .add(&key, NewEntry::new(platform, "user", b"secret"))
+                .unwrap();
+        }
+        let github = EntrySelector::new("github", "user");
+        let gitlab = EntrySelector::new("gitlab", "user");
+        repository.add_tag(github, "work").unwrap();
+        repository.set_pinned(github, true).unwrap();
+
+        repository.remove(github).unwrap();
+        repository.remove(gitlab).unwrap();
+        assert_eq!(repository.list_metadata().unwrap().len(), 1);
+        assert!(!repository.contains(github).unwrap());
+        assert!(matches!(
+            repository.get(&key, github),
+            Err(StorageError::NotFound)
+        ));
+        assert!(repository.tags().unwrap().is_empty());
+        let trash = repository.trash().unwrap();
+        assert_eq!(trash.len(), 2);
+        assert_eq!(trash[0].metadata.tags, vec!["work"]);
+
+        repository.restore_trashed(github).unwrap();
+        let entry = repository.get(&key, github).unwrap();
+        assert!(entry.metadata.pinned);
+        assert_eq!(entry.metadata.tags, vec!["work"]);
+        assert!(matches!(
+            repository.restore_trashed(github),
+            Err(StorageError::NotFound)
+        ));
+
+        // A trashed entry keeps its identity until it is restored or purged.
+        assert!(matches!(
+            repository.add(&key, NewEntry::new("gitlab", "user", b"fresh")),
+            Err(StorageError::Conflict)
+        ));
+        repository
+            .add(&key, NewEntry::new("gitlab", "other", b"secret"))
+            .unwrap();
+        assert!(matches!(
+            repository.update(
+                &key,
+                EntrySelector::new("gitlab", "other"),
+                EntryUpdate::new("user", b"secret")
+            ),
+            Err(StorageError::Conflict)
+        ));
+        let work = EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        work.add(&key, NewEntry::new("gitlab", "user", b"moved"))
+            .unwrap();
+        assert!(matches!(
+            work.move_to(&key, gitlab, &repository),
+            Err(StorageError::Conflict)
+        ));
+        assert!(work.contains(gitlab).unwrap());
+        assert_eq!(repository.trash().unwrap().len(), 1);
+
+        repository.purge_trashed(gitlab).unwrap();
+        repository
+            .add(&key, NewEntry::new("gitlab", "user", b"fresh"))
+            .unwrap();
+        assert_eq!(
+            repository.get(&key, gitlab).unwrap().secret.expose(),
+            b"fresh"
+        );
+
+        repository.remove(gitlab).unwrap();
+        assert_eq!(repository.empty_trash().unwrap(), 1);
+        assert!(repository.trash().unwrap().is_empty());
+    }
+
+    #[test]
+    fn trashed_entries_are_purged_after_the_retention() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        for platform in ["old", "recent"] {
+            repository
+                .add(&key, NewEntry::new(platform, "user", b"secret"))
+                .unwrap();
+            repository
+                .remove(EntrySelector::new(platform, "user"))
+                .unwrap();
+        }
+        let ten_days_ago = Utc::now().timestamp() - 10 * 24 * 60 * 60;
+        db.connection
+            .execute(
+                "UPDATE main SET deleted_at = ?1 WHERE platform = 'old'",
+                [ten_days_ago],
+            )
+            .unwrap();
+        assert_eq!(db.trash_retention_days().unwrap(), 30);
+
+        db.set_trash_retention_days(0).unwrap();
+        let repository = EntryRepository::new(&db, None).unwrap();
+        assert_eq!(repository.trash().unwrap().len(), 2);
+
+        db.set_trash_retention_days(7).unwrap();
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let trash = repository.trash().unwrap();
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].metadata.platform, "recent");
+    }
+
+    #[test]
+    fn tags_and_folders_filter_entries_and_are_dropped_with_them() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        for platform in ["github", "gitlab", "bank"] {
+            repository
+                .add(&key, NewEntry::new(platform, "user", b"secret"))
+                .unwrap();
+        }
+        let github = EntrySelector::new("github", "user");
+        repository.add_tag(github, "work").unwrap();
+        repository.add_tag(github, " dev ").unwrap();
+        repository.add_tag(github, "work").unwrap();
+        repository
+            .add_tag(EntrySelector::new("gitlab", "user"), "work")
+            .unwrap();
+        repository.set_folder(github, Some("/Work//Code/")).unwrap();
+
+        let tagged = repository.list_tagged("work").unwrap();
+        assert_eq!(tagged.len(), 2);
+        assert_eq!(repository.tags().unwrap(), vec!["dev", "work"]);
+        let entry = repository.get(&key, github).unwrap();
+        assert_eq!(entry.metadata.tags, vec!["dev", "work"]);
+        assert_eq!(entry.metadata.folder.as_deref(), Some("Work/Code"));
+        assert!(entry.metadata.in_folder("Work"));
+        assert!(!entry.metadata.in_folder("Code"));
+
+        repository.remove_tag(github, "dev").unwrap();
+        repository.set_folder(github, None).unwrap();
+        let entry = repository.get(&key, github).unwrap();
+        assert_eq!(entry.metadata.tags, vec!["work"]);
+        assert_eq!(entry.metadata.folder, None);
+        assert!(matches!(
+            repository.add_tag(EntrySelector::new("missing", "user"), "work"),
+            Err(StorageError::NotFound)
+        ));
+
+        repository.remove(github).unwrap();
+        assert_eq!(repository.list_tagged("work").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn encrypted_metadata_hides_tags_and_folders() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let new_key = SecretKey::from_bytes([8; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let selector = EntrySelector::new("github", "user");
+        repository
+            .add(&key, NewEntry::new("github", "user", b"secret"))
+            .unwrap();
+        repository.add_tag(selector, "finance").unwrap();
+        repository.set_folder(selector, Some("Banking")).unwrap();
+
+        db.encrypt_metadata(&key, None).unwrap();
+        db.reencrypt_entries(&key, &new_key, "fingerprint", || Ok(()))
+            .unwrap();
+
+        let stored: String = db
+            .connection
+            .query_row("SELECT tag || sealed FROM rvault_tags", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(!stored.contains("finance"));
+        let folder: String = db
+            .connection
+            .query_row("SELECT folder FROM main", [], |row| row.get(0))
+            .unwrap();
+        assert!(!folder.contains("Banking"));
+        let repository = EntryRepository::unlocked(&db, None, &new_key).unwrap();
+        let tagged = repository.list_tagged("finance").unwrap();
+        assert_eq!(tagged[0].folder.as_deref(), Some("Banking"));
+        repository.remove_tag(selector, "finance").unwrap();
+        assert!(repository.tags().unwrap().is_empty());
+    }
+
+    #[test]
+    fn entries_move_between_plaintext_and_encrypted_vaults_with_their_history() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let selector = EntrySelector::new("github", "user");
+        let personal = EntryRepository::new(&db, None).unwrap();
+        personal
+            .add(&key, NewEntry::new("github", "user", b"old"))
+            .unwrap();
+        personal
+            .update(&key, selector, EntryUpdate::new("user", b"new"))
+            .unwrap();
+        personal.add_tag(selector, "code").unwrap();
+        personal.set_folder(selector, Some("Dev")).unwrap();
+        let urls = [EntryUrl::new("github.com", MatchMode::Host).unwrap()];
+        personal.set_urls(selector, &urls).unwrap();
+        personal.set_pinned(selector, true).unwrap();
+        EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        db.encrypt_metadata(&key, Some("work".to_string())).unwrap();
+        let work = EntryRepository::unlocked(&db, Some("work".to_string()), &key).unwrap();
+
+        personal.move_to(&key, selector, &work).unwrap();
+        assert!(!personal.contains(selector).unwrap());
+        assert!(personal.trash().unwrap().is_empty());
+        let moved = work.get(&key, selector).unwrap();
+        assert_eq!(moved.secret.expose(), b"new");
+        assert_eq!(moved.metadata.tags, vec!["code"]);
+        assert_eq!(moved.metadata.folder.as_deref(), Some("Dev"));
+        assert_eq!(moved.metadata.urls, urls);
+        assert!(moved.metadata.pinned);
+        assert_eq!(work.history(&key, selector).unwrap().len(), 1);
+        let platform: String = db
+            .connection
+            .query_row(
+                &format!("SELECT platform FROM {}", work.table.table_name),
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_ne!(platform, "github");
+
+        personal
+            .add(&key, NewEntry::new("github", "user", b"other"))
+            .unwrap();
+        assert!(matches!(
+            personal.move_to(&key, selector, &work),
+            Err(StorageError::Conflict)
+        ));
+        work.move_to(&key, EntrySelector::new("gitlab", "user"), &personal)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn origins_match_entry_urls_best_first_even_with_encrypted_metadata() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let base = [EntryUrl::new("example.co.uk", MatchMode::BaseDomain).unwrap()];
+        let host = [EntryUrl::new("https://login.example.co.uk/", MatchMode::Host).unwrap()];
+        repository
+            .add(
+                &key,
+                NewEntry::new("main", "user", b"secret").with_urls(&base),
+            )
+            .unwrap();
+        repository
+            .add(
+                &key,
+                NewEntry::new("sso", "user", b"secret").with_urls(&host),
+            )
+            .unwrap();
+        repository
+            .add(&key, NewEntry::new("other", "user", b"secret"))
+            .unwrap();
+
+        db.encrypt_metadata(&key, None).unwrap();
+        let stored: String = db
+            .connection
+            .query_row(
+                "SELECT urls FROM main WHERE urls IS NOT NULL LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!stored.contains("example"));
+
+        let repository = EntryRepository::unlocked(&db, None, &key).unwrap();
+        let matches = repository
+            .match_origin("https://login.example.co.uk")
+            .unwrap();
+        let ranked: Vec<_> = matches
+            .iter()
+            .map(|(entry, quality)| (entry.platform.as_str(), *quality))
+            .collect();
+        assert_eq!(
+            ranked,
+            [
+                ("sso", MatchQuality::Host),
+                ("main", MatchQuality::BaseDomain)
+            ]
+        );
+        assert_eq!(matches[1].0.urls, base);
+        assert!(
+            repository
+                .match_origin("https://example.org")
+                .unwrap()
+                .is_empty()
+        );
+
+        let selector = EntrySelector::new("main", "user");
+        repository.set_urls(selector, &[]).unwrap();
+        assert!(
+            repository
+                .get(&key, selector)
+                .unwrap()
+                .metadata
+                .urls
+                .is_empty()
+        );
+        assert_eq!(
+            repository.match_origin("www.example.co.uk").unwrap().len(),
+            0
+        );
+    }
+
+    #[test]
+    fn search_ranks_fuzzy_matches_with_or_without_the_index() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let urls = [EntryUrl::new("cloudflare.com", MatchMode::BaseDomain).unwrap()];
+        repository
+            .add(
+                &key,
+                NewEntry::new("Cloudflare", "ops@example.com", b"secret").with_urls(&urls),
+            )
+            .unwrap();
+        repository
+            .add(
+                &key,
+                NewEntry::new("bank", "alice", b"secret").with_notes("Branch in Cloudflare street"),
+            )
+            .unwrap();
+        repository
+            .add(&key, NewEntry::new("github", "alice", b"secret"))
+            .unwrap();
+        repository
+            .add_tag(EntrySelector::new("github", "alice"), "cloudwork")
+            .unwrap();
+        let ranked = |repository: &EntryRepository<'_>, key: Option<&SecretKey>, query: &str| {
+            repository
+                .search(key, query)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.entry.platform)
+                .collect::<Vec<_>>()
+        };
+
+        let assert_ranking = |repository: &EntryRepository<'_>| {
+            assert_eq!(
+                ranked(repository, Some(&key), "cloudflare"),
+                ["Cloudflare", "bank"]
+            );
+            assert_eq!(ranked(repository, None, "cloudflare"), ["Cloudflare"]);
+            assert_eq!(
+                ranked(repository, Some(&key), "cludflare"),
+                ["Cloudflare", "bank"]
+            );
+            assert_eq!(
+                ranked(repository, Some(&key), "cloud alice"),
+                ["github", "bank"]
+            );
+            assert_eq!(ranked(repository, None, "exampel"), ["Cloudflare"]);
+            assert_eq!(ranked(repository, None, "").len(), 3);
+        };
+
+        assert_ranking(&repository);
+        db.encrypt_metadata(&key, None).unwrap();
+        assert_ranking(&EntryRepository::unlocked(&db, None, &key).unwrap());
+        let indexed: i64 = db
+            .connection
+            .query_row("SELECT COUNT(*) FROM rvault_search_main", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(indexed, 0);
+    }
+
+    #[test]
+    fn search_index_follows_entry_and_tag_changes() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let selector = EntrySelector::new("github", "user");
+        repository
+            .add(&key, NewEntry::new("github", "user", b"secret"))
+            .unwrap();
+        let found = |query: &str| repository.search(None, query).unwrap().len();
+
+        repository.add_tag(selector, "opensource").unwrap();
+        assert_eq!(found("opensource"), 1);
+        repository.remove_tag(selector, "opensource").unwrap();
+        assert_eq!(found("opensource"), 0);
+        repository
+            .update(&key, selector, EntryUpdate::new("maintainer", b"secret"))
+            .unwrap();
+        assert_eq!(found("maintainer"), 1);
+        repository
+            .remove(EntrySelector::new("github", "maintainer"))
+            .unwrap();
+        assert_eq!(found("maintainer"), 0);
+        repository
+            .restore_trashed(EntrySelector::new("github", "maintainer"))
+            .unwrap();
+        assert_eq!(found("maintainer"), 1);
+    }
+
+    #[test]
+    fn typed_entries_keep_their_kind_and_reject_mismatched_secrets() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let selector = EntrySelector::new("visa", "personal");
+        let card = TypedSecret::Card(
+            Card::new(
+                "Jane Doe",
+                "4111111111111111",
+                "09/27".parse().unwrap(),
+                "123",
+            )
+            .unwrap(),
+        );
+        repository
+            .add(
+                &key,
+                NewEntry::new("visa", "personal", card.to_bytes().expose())
+                    .with_kind(EntryKind::Card),
+            )
+            .unwrap();
+
+        let entry = repository.get(&key, selector).unwrap();
+        assert_eq!(entry.metadata.kind, EntryKind::Card);
+        assert_eq!(entry.typed_secret().unwrap(), Some(card));
+        assert_eq!(repository.list_metadata().unwrap()[0].kind, EntryKind::Card);
+        assert!(matches!(
+            repository.update(&key, selector, EntryUpdate::new("personal", b"hunter2")),
+            Err(StorageError::InvalidSecret(EntryKind::Card))
+        ));
+        assert!(matches!(
+            repository.add(
+                &key,
+                NewEntry::new("ci", "deploy", b"not json").with_kind(EntryKind::ApiKey)
+            ),
+            Err(StorageError::InvalidSecret(EntryKind::ApiKey))
+        ));
+        repository
+            .add(&key, NewEntry::new("github", "user", b"hunter2"))
+            .unwrap();
+        let login = repository
+            .get(&key, EntrySelector::new("github", "user"))
+            .unwrap();
+        assert_eq!(login.metadata.kind, EntryKind::Login);
+        assert_eq!(login.typed_secret().unwrap(), None);
+    }
+
+    #[test]
+    fn revisions_count_every_write_and_guard_updates() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        let selector = EntrySelector::new("github", "alice");
+        repository
+            .add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        let revision = || repository.get(&key, selector).unwrap().metadata.revision;
+        assert_eq!(revision(), 1);
+
+        repository
+            .update(&key, selector, EntryUpdate::new("alice", b"two"))
+            .unwrap();
+        repository.set_pinned(selector, true).unwrap();
+        repository.add_tag(selector, "work").unwrap();
+        repository.add_tag(selector, "work").unwrap();
+        repository.remove_tag(selector, "work").unwrap();
+        repository.set_folder(selector, Some("Code")).unwrap();
+        assert_eq!(revision(), 6);
+
+        let urls = [EntryUrl::new("github.com", MatchMode::Host).unwrap()];
+        let stale = repository.update(
+            &key,
+            selector,
+            EntryUpdate::new("alice", b"three")
+                .with_urls(&urls)
+                .with_expected_revision(5),
+        );
+        assert!(matches!(
+            stale,
+            Err(StorageError::RevisionMismatch {
+                expected: 5,
+                actual: 6
+            })
+        ));
+        let entry = repository.get(&key, selector).unwrap();
+        assert_eq!(entry.secret.expose(), b"two");
+        assert!(entry.metadata.urls.is_empty());
+        repository
+            .update(
+                &key,
+                selector,
+                EntryUpdate::new("alice", b"three")
+                    .with_urls(&urls)
+                    .with_expected_revision(6),
+            )
+            .unwrap();
+        assert_eq!(revision(), 7);
+        assert_eq!(repository.get(&key, selector).unwrap().metadata.urls, urls);
+        repository
+            .update(&key, selector, EntryUpdate::new("alice", b"three"))
+            .unwrap();
+        assert_eq!(repository.get(&key, selector).unwrap().metadata.urls, urls);
+        assert_eq!(revision(), 8);
+
+        repository.remove(selector).unwrap();
+        repository.restore_trashed(selector).unwrap();
+        assert_eq!(revision(), 10);
+    }
+
+    #[test]
+    fn entry_key_schemes_derive_different_keys() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let salt = b"0123456789abcdef";
+
+        let argon2 = derive_entry_key(&key, salt, KeyScheme::Argon2).unwrap();
+        let hkdf = derive_entry_key(&key, salt, KeyScheme::Hkdf).unwrap();
+
+        assert_ne!(argon2.as_bytes(), hkdf.as_bytes());
+        assert_eq!(
+            hkdf.as_bytes(),
+            derive_entry_key(&key, salt, KeyScheme::Hkdf)
+                .unwrap()
+                .as_bytes()
+        );
+    }
+
+    #[test]
+    fn legacy_entries_are_upgraded_when_read() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let repository = EntryRepository::new(&db, None).unwrap();
+        insert_legacy(&repository, &key, "github", b"secret");
+        let revision = repository.list_metadata().unwrap()[0].revision;
+
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "user"))
+            .unwrap();
+
+        assert_eq!(entry.secret.expose(), b"secret");
+        assert_eq!(scheme_of(&repository, "github"), KeyScheme::Hkdf);
+        let entry = repository
+            .get(&key, EntrySelector::new("github", "user"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"secret");
+        // The upgrade is not an edit: an update expecting the listed revision still applies.
+        assert_eq!(entry.metadata.revision, revision);
+        repository
+            .update(
+                &key,
+                EntrySelector::new("github", "user"),
+                EntryUpdate::new("user", b"changed").with_expected_revision(revision),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn bulk_upgrade_converts_every_legacy_entry() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let main = EntryRepository::new(&db, None).unwrap();
+        let work = EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        insert_legacy(&main, &key, "github", b"one");
+        insert_legacy(&work, &key, "gitlab", b"two");
+        work.add(&key, NewEntry::new("current", "user", b"three"))
+            .unwrap();
+        assert_eq!(db.legacy_entry_count().unwrap(), 2);
+
+        main.add_tag(EntrySelector::new("github", "user"), "work")
+            .unwrap();
+        let revision = main.list_metadata().unwrap()[0].revision;
+
+        assert_eq!(db.upgrade_entry_keys(&key).unwrap(), 2);
+
+        assert_eq!(main.list_metadata().unwrap()[0].revision, revision);
+        assert_eq!(db.legacy_entry_count().unwrap(), 0);
+        assert_eq!(scheme_of(&main, "github"), KeyScheme::Hkdf);
+        let entry = work
+            .get(&key, EntrySelector::new("gitlab", "user"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"two");
+    }
+
+    /// List-and-decrypt of 5,000 entries under each key scheme. Run with
+    /// `cargo test --release -p rvault-core entry_key_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn entry_key_benchmark() {
+        const ENTRIES: usize = 5_000;
+        let key = SecretKey::from_bytes([7; 32]);
+        for scheme in [KeyScheme::Argon2, KeyScheme::Hkdf] {
+            let db = memory_db();
+            let repository = EntryRepository::new(&db, None).unwrap();
+            // Rows share one ciphertext so setup does not pay the Argon2 cost being measured.
+            let salt = SaltString::generate(&mut argon2::password_hash::rand_core::OsRng);
+            let entry_key = derive_entry_key(&key, salt.as_ref().as_bytes(), scheme).unwrap();
+            let ciphertext = encrypt(&entry_key, b"secret").unwrap();
+            let transaction = db.connection.unchecked_transaction().unwrap();
+            for index in 0..ENTRIES {
+                transaction
+                    .execute(
+                        "INSERT INTO main (platform, user_id, password, nonce, salt, key_scheme) VALUES (?1, 'user', ?2, ?3, ?4, ?5)",
+                        params![
+                            format!("site-{index}"),
+                            BASE64.encode(ciphertext.bytes()),
+                            BASE64.encode(ciphertext.nonce()),
+                            salt.to_string(),
+                            scheme
+                        ],
+                    )
+                    .unwrap();
+            }
+            transaction.commit().unwrap();
+
+            let started = Instant::now();
+            let metadata = repository.list_metadata().unwrap();
+            // Decrypt directly: lazy upgrades in `get` would turn the legacy run into a write benchmark.
+            let mut statement = db
+                .connection
+                .prepare("SELECT password, nonce, salt, key_scheme FROM main")
+                .unwrap();
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get(3)?,
+                    ))
+                })
+                .unwrap();
+            for row in rows {
+                let (ciphertext, nonce, salt, scheme) = row.unwrap();
+                decrypt_entry(&key, &ciphertext, &nonce, &salt, scheme).unwrap();
+            }
+            let elapsed = started.elapsed();
+
+            assert_eq!(metadata.len(), ENTRIES);
+            println!(
+                "{scheme:?}: listed and decrypted {ENTRIES} entries in {:.2?} ({:.1?} per entry)",
+                elapsed,
+                elapsed / ENTRIES as u32
+            );
+        }
+    }
+}
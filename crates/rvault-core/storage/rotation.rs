@@ -0,0 +1,357 @@
+use rusqlite::{OptionalExtension, Transaction, params};
+
+use super::metadata::{self, MetadataCipher};
+use super::migration::{self, META_TABLE};
+use super::repository::{KeyScheme, decrypt_entry, encrypt_entry, map_database_error};
+use super::{Database, StorageError, Table};
+use super::{audit, details, history, sharing, sync, tags};
+use crate::crypto::CryptoError;
+use crate::secret::SecretKey;
+
+const KEY_FINGERPRINT: &str = "key_fingerprint";
+
+impl Database {
+    /// Names of every table that stores vault entries, in creation order.
+    pub fn vault_tables(&self) -> Result<Vec<String>, StorageError> {
+        // Opening the default table runs pending schema migrations for every entry table.
+        Table::new(self, None).map_err(map_database_error)?;
+        migration::entry_tables(&self.connection).map_err(Into::into)
+    }
+
+    /// Fingerprint of the key the entries were last re-encrypted with, if any.
+    pub fn key_fingerprint(&self) -> Result<Option<String>, StorageError> {
+        if !migration::meta_table_exists(&self.connection)? {
+            return Ok(None);
+        }
+        self.connection
+            .query_row(
+                &format!("SELECT value FROM {META_TABLE} WHERE name = ?1"),
+                [KEY_FINGERPRINT],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Re-encrypts every entry in every vault table, all password history and the audit log from
+    /// `old_key` to `new_key` in one transaction and records `fingerprint` for the new key. An
+    /// audit log that fails verification stops the rotation, and so does a sync folder or git
+    /// remote: the other devices would keep the old key, so [`Database::stop_sync`] has to come
+    /// first.
+    ///
+    /// `before_commit` runs after all rows are rewritten but before the commit, so callers can
+    /// stage files that must only take effect once the database has switched keys. If anything
+    /// fails, including `before_commit`, no row is changed. Returns the number of entries.
+    pub fn reencrypt_entries(
+        &self,
+        old_key: &SecretKey,
+        new_key: &SecretKey,
+        fingerprint: &str,
+        before_commit: impl FnOnce() -> std::io::Result<()>,
+    ) -> Result<usize, StorageError> {
+        if self.sync_folder()?.is_some() || self.git_sync_remote()?.is_some() {
+            return Err(StorageError::SyncConfigured);
+        }
+        let tables = self.vault_tables()?;
+        let transaction = self.connection.unchecked_transaction()?;
+        let mut count = 0;
+        for table in &tables {
+            count += reencrypt_table(&transaction, table, old_key, new_key, false)?;
+        }
+        history::reseal_all(&transaction, old_key, new_key)?;
+        audit::reseal_all(&transaction, old_key, new_key)?;
+        transaction.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {META_TABLE} (name TEXT PRIMARY KEY, value TEXT NOT NULL)"
+            ),
+            [],
+        )?;
+        transaction.execute(
+            &format!("INSERT OR REPLACE INTO {META_TABLE} (name, value) VALUES (?1, ?2)"),
+            [KEY_FINGERPRINT, fingerprint],
+        )?;
+        sharing::reseal_all(&transaction, old_key, new_key)?;
+        sync::rekey_states(&transaction, old_key, new_key)?;
+        before_commit()?;
+        transaction.commit()?;
+        Ok(count)
+    }
+
+    /// Number of entries whose key is still derived with a superseded scheme.
+    pub fn legacy_entry_count(&self) -> Result<usize, StorageError> {
+        let mut count = 0;
+        for table in self.vault_tables()? {
+            let rows: i64 = self.connection.query_row(
+                &format!(
+                    "SELECT COUNT(*) FROM {table} WHERE key_scheme != {}",
+                    KeyScheme::CURRENT as i64
+                ),
+                [],
+                |row| row.get(0),
+            )?;
+            count += rows as usize;
+        }
+        Ok(count)
+    }
+
+    /// Re-encrypts every entry still using a superseded key scheme, in one transaction.
+    ///
+    /// Entries are upgraded lazily when read; this converts the rest at once. Returns the
+    /// number of entries upgraded.
+    pub fn upgrade_entry_keys(&self, key: &SecretKey) -> Result<usize, StorageError> {
+        let tables = self.vault_tables()?;
+        let transaction = self.connection.unchecked_transaction()?;
+        let mut count = 0;
+        for table in &tables {
+            count += reencrypt_table(&transaction, table, key, key, true)?;
+        }
+        transaction.commit()?;
+        Ok(count)
+    }
+}
+
+/// Re-encrypts a table's secrets, details and TOTP secrets, and for encrypted-metadata tables
+/// its sealed identities, tags, folders and URLs, from `old_key` to `new_key`. With
+/// `legacy_only`, only rows on a superseded key scheme are touched and the keys must be equal.
+/// Entry revisions stay as they are.
+fn reencrypt_table(
+    transaction: &Transaction<'_>,
+    table: &str,
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+    legacy_only: bool,
+) -> Result<usize, StorageError> {
+    let filter = if legacy_only {
+        format!(" WHERE key_scheme != {}", KeyScheme::CURRENT as i64)
+    } else {
+        String::new()
+    };
+    let reseal = !legacy_only && metadata::is_encrypted(transaction, table)?;
+    let ciphers = if reseal {
+        Some((MetadataCipher::new(old_key)?, MetadataCipher::new(new_key)?))
+    } else {
+        None
+    };
+    let rows: Vec<ReencryptRow> = {
+        let mut statement = transaction.prepare(&format!(
+            "SELECT id, password, nonce, salt, key_scheme, platform, user_id, metadata, details, totp FROM {table}{filter}"
+        ))?;
+        statement
+            .query_map([], |row| {
+                Ok(ReencryptRow {
+                    id: row.get(0)?,
+                    ciphertext: row.get(1)?,
+                    nonce: row.get(2)?,
+                    salt: row.get(3)?,
+                    scheme: row.get(4)?,
+                    platform: row.get(5)?,
+                    user_id: row.get(6)?,
+                    metadata: row.get(7)?,
+                    details: row.get(8)?,
+                    totp: row.get(9)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?
+    };
+    let update = format!(
+        "UPDATE {table} SET password = ?1, nonce = ?2, salt = ?3, key_scheme = ?4, platform = ?5, user_id = ?6, metadata = ?7, details = ?8, totp = ?9 WHERE id = ?10"
+    );
+    for row in &rows {
+        let secret = decrypt_entry(old_key, &row.ciphertext, &row.nonce, &row.salt, row.scheme)?;
+        let (ciphertext, nonce, salt) = encrypt_entry(new_key, secret.expose())?;
+        // Details and TOTP secrets are always written with the current scheme, so an upgrade
+        // leaves them alone.
+        let (details, totp) = if legacy_only {
+            (row.details.clone(), row.totp.clone())
+        } else {
+            (
+                details::reseal(old_key, new_key, row.details.as_deref())?,
+                details::reseal(old_key, new_key, row.totp.as_deref())?,
+            )
+        };
+        let (platform, user_id, metadata) = match &ciphers {
+            Some((old, new)) => {
+                let sealed = row
+                    .metadata
+                    .as_deref()
+                    .ok_or(CryptoError::AuthenticationFailed)?;
+                let (platform, user_id) = old.open(&row.platform, &row.user_id, sealed)?;
+                let stored = new.seal(&platform, &user_id)?;
+                (stored.platform, stored.user_id, stored.metadata)
+            }
+            None => (
+                row.platform.clone(),
+                row.user_id.clone(),
+                row.metadata.clone(),
+            ),
+        };
+        transaction.execute(
+            &update,
+            params![
+                ciphertext,
+                nonce,
+                salt,
+                KeyScheme::CURRENT,
+                platform,
+                user_id,
+                metadata,
+                details,
+                totp,
+                row.id
+            ],
+        )?;
+    }
+    if let Some((old, new)) = &ciphers {
+        tags::reseal_table(transaction, table, Some(old), new)?;
+        metadata::reseal_columns(transaction, table, Some(old), new)?;
+    }
+    Ok(rows.len())
+}
+
+struct ReencryptRow {
+    id: i64,
+    ciphertext: String,
+    nonce: String,
+    salt: String,
+    scheme: KeyScheme,
+    platform: String,
+    user_id: String,
+    metadata: Option<String>,
+    details: Option<String>,
+    totp: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{EntryRepository, EntrySelector, EntryUpdate, NewEntry};
+    use rusqlite::Connection;
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    #[test]
+    fn reencrypts_every_vault_table_and_records_the_fingerprint() {
+        let db = memory_db();
+        let old_key = SecretKey::from_bytes([1; 32]);
+        let new_key = SecretKey::from_bytes([2; 32]);
+        let main = EntryRepository::new(&db, None).unwrap();
+        main.add(&old_key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        main.update(
+            &old_key,
+            EntrySelector::new("github", "alice"),
+            EntryUpdate::new("alice", b"one"),
+        )
+        .unwrap();
+        let work = EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        work.add(&old_key, NewEntry::new("gitlab", "bob", b"old"))
+            .unwrap();
+        work.update(
+            &old_key,
+            EntrySelector::new("gitlab", "bob"),
+            EntryUpdate::new("bob", b"two"),
+        )
+        .unwrap();
+
+        let count = db
+            .reencrypt_entries(&old_key, &new_key, "fingerprint", || Ok(()))
+            .unwrap();
+
+        assert_eq!(count, 2);
+        let tables = db.vault_tables().unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables[0], "main");
+        assert_eq!(
+            db.key_fingerprint().unwrap().as_deref(),
+            Some("fingerprint")
+        );
+        let entry = main
+            .get(&new_key, EntrySelector::new("github", "alice"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"one");
+        let entry = work
+            .get(&new_key, EntrySelector::new("gitlab", "bob"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"two");
+        let history = work
+            .history(&new_key, EntrySelector::new("gitlab", "bob"))
+            .unwrap();
+        assert_eq!(history[0].secret.expose(), b"old");
+        assert!(
+            main.get(&old_key, EntrySelector::new("github", "alice"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rotation_reseals_encrypted_metadata_under_the_new_key() {
+        let db = memory_db();
+        let old_key = SecretKey::from_bytes([1; 32]);
+        let new_key = SecretKey::from_bytes([2; 32]);
+        db.encrypt_metadata(&old_key, None).unwrap();
+        let selector = EntrySelector::new("github", "alice");
+        let repository = EntryRepository::unlocked(&db, None, &old_key).unwrap();
+        repository
+            .add(&old_key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        repository.add_tag(selector, "code").unwrap();
+        repository.set_folder(selector, Some("Dev")).unwrap();
+        let revision = repository.list_metadata().unwrap()[0].revision;
+
+        db.reencrypt_entries(&old_key, &new_key, "fingerprint", || Ok(()))
+            .unwrap();
+
+        let repository = EntryRepository::unlocked(&db, None, &new_key).unwrap();
+        let listed = repository.list_metadata().unwrap();
+        assert_eq!(listed[0].platform, "github");
+        assert_eq!(listed[0].tags, vec!["code"]);
+        assert_eq!(listed[0].folder.as_deref(), Some("Dev"));
+        // Re-encryption is not an edit, so a stale-edit check still passes afterwards.
+        assert_eq!(listed[0].revision, revision);
+        let entry = repository
+            .get(&new_key, EntrySelector::new("github", "alice"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"one");
+        assert!(
+            EntryRepository::unlocked(&db, None, &old_key)
+                .unwrap()
+                .list_metadata()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn failed_rotation_leaves_entries_under_the_old_key() {
+        let db = memory_db();
+        let old_key = SecretKey::from_bytes([1; 32]);
+        let new_key = SecretKey::from_bytes([2; 32]);
+        let main = EntryRepository::new(&db, None).unwrap();
+        main.add(&old_key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+
+        let result = db.reencrypt_entries(&old_key, &new_key, "fingerprint", || {
+            Err(std::io::Error::other("staging failed"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(db.key_fingerprint().unwrap(), None);
+        let entry = main
+            .get(&old_key, EntrySelector::new("github", "alice"))
+            .unwrap();
+        assert_eq!(entry.secret.expose(), b"one");
+
+        let wrong_key = SecretKey::from_bytes([3; 32]);
+        assert!(
+            db.reencrypt_entries(&wrong_key, &new_key, "fingerprint", || Ok(()))
+                .is_err()
+        );
+        assert!(
+            main.get(&old_key, EntrySelector::new("github", "alice"))
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! The search index.
+//!
+//! Every vault table has an FTS5 table with the trigram tokenizer over the platform, user ID,
+//! tags and login URLs of its live entries, keyed by entry id. Triggers on the entry table and
+//! on the tags table keep it current, and only index rows whose metadata is plaintext, so
+//! vaults with encrypted metadata leave their index empty. Notes are always encrypted and never
+//! indexed.
+//!
+//! The index only narrows down which entries' metadata can match a query; ranking is done by
+//! [`crate::fuzzy`] on the decrypted entries.
+
+use std::collections::HashSet;
+
+use rusqlite::Connection;
+
+use super::migration::TAGS_TABLE;
+use super::repository::EntryMetadata;
+use super::{Database, EntryRepository, StorageError};
+use crate::fuzzy::{self, Query};
+use crate::secret::SecretKey;
+
+/// An entry matching a search, with its score from [`Query::score`].
+pub struct SearchHit {
+    pub entry: EntryMetadata,
+    pub score: u32,
+}
+
+impl Database {
+    /// Searches every vault, best match first. Equal matches keep vault order.
+    pub fn search_vaults(
+        &self,
+        key: &SecretKey,
+        query: &str,
+    ) -> Result<Vec<(String, SearchHit)>, StorageError> {
+        let mut hits = Vec::new();
+        for vault in self.vaults()? {
+            let repository = EntryRepository::unlocked(self, Some(vault.name.clone()), key)?;
+            hits.extend(
+                repository
+                    .search(Some(key), query)?
+                    .into_iter()
+                    .map(|hit| (vault.name.clone(), hit)),
+            );
+        }
+        hits.sort_by_key(|(_, hit)| std::cmp::Reverse(hit.score));
+        Ok(hits)
+    }
+}
+
+fn index_name(table_name: &str) -> String {
+    format!("rvault_search_{table_name}")
+}
+
+/// Creates the search index of an entry table with its triggers and fills it.
+pub(super) fn create_index(connection: &Connection, table_name: &str) -> rusqlite::Result<()> {
+    let index = index_name(table_name);
+    let refresh = |id: &str| {
+        format!(
+            "DELETE FROM {index} WHERE rowid = {id};
+            INSERT INTO {index} (rowid, platform, user_id, tags, urls)
+            SELECT id, platform, user_id,
+                (SELECT group_concat(tag, ' ') FROM {TAGS_TABLE}
+                 WHERE entry_table = '{table_name}' AND entry_id = entry.id),
+                (SELECT group_concat(json_extract(value, '$.url'), ' ')
+                 FROM json_each(CASE WHEN json_valid(entry.urls) THEN entry.urls END))
+            FROM {table_name} AS entry
+            WHERE id = {id} AND metadata IS NULL AND deleted_at IS NULL;"
+        )
+    };
+    let retag = |id: &str| {
+        format!(
+            "UPDATE {index} SET tags = (
+                SELECT group_concat(tag, ' ') FROM {TAGS_TABLE}
+                WHERE entry_table = '{table_name}' AND entry_id = {id}
+            ) WHERE rowid = {id};"
+        )
+    };
+    connection.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS {index}
+            USING fts5(platform, user_id, tags, urls, tokenize = 'trigram');
+        CREATE TRIGGER IF NOT EXISTS {index}_insert AFTER INSERT ON {table_name} BEGIN
+            {insert}
+        END;
+        CREATE TRIGGER IF NOT EXISTS {index}_update AFTER UPDATE ON {table_name} BEGIN
+            DELETE FROM {index} WHERE rowid = OLD.id;
+            {update}
+        END;
+        CREATE TRIGGER IF NOT EXISTS {index}_delete AFTER DELETE ON {table_name} BEGIN
+            DELETE FROM {index} WHERE rowid = OLD.id;
+        END;
+        CREATE TRIGGER IF NOT EXISTS {index}_tag_insert AFTER INSERT ON {TAGS_TABLE}
+        WHEN NEW.entry_table = '{table_name}' BEGIN
+            {tag_insert}
+        END;
+        CREATE TRIGGER IF NOT EXISTS {index}_tag_update AFTER UPDATE ON {TAGS_TABLE}
+        WHEN NEW.entry_table = '{table_name}' BEGIN
+            {tag_update}
+        END;
+        CREATE TRIGGER IF NOT EXISTS {index}_tag_delete AFTER DELETE ON {TAGS_TABLE}
+        WHEN OLD.entry_table = '{table_name}' BEGIN
+            {tag_delete}
+        END;
+        DELETE FROM {index};",
+        insert = refresh("NEW.id"),
+        update = refresh("NEW.id"),
+        tag_insert = retag("NEW.entry_id"),
+        tag_update = retag("NEW.entry_id"),
+        tag_delete = retag("OLD.entry_id"),
+    ))?;
+    connection.execute(
+        &format!(
+            "INSERT INTO {index} (rowid, platform, user_id, tags, urls)
+             SELECT id, platform, user_id,
+                (SELECT group_concat(tag, ' ') FROM {TAGS_TABLE}
+                 WHERE entry_table = ?1 AND entry_id = entry.id),
+                (SELECT group_concat(json_extract(value, '$.url'), ' ')
+                 FROM json_each(CASE WHEN json_valid(entry.urls) THEN entry.urls END))
+             FROM {table_name} AS entry
+             WHERE metadata IS NULL AND deleted_at IS NULL"
+        ),
+        [table_name],
+    )?;
+    Ok(())
+}
+
+/// Drops the search index of an entry table and the triggers on the tags table. The triggers on
+/// the entry table go with it.
+pub(super) fn drop_index(connection: &Connection, table_name: &str) -> rusqlite::Result<()> {
+    let index = index_name(table_name);
+    connection.execute_batch(&format!(
+        "DROP TRIGGER IF EXISTS {index}_tag_insert;
+        DROP TRIGGER IF EXISTS {index}_tag_update;
+        DROP TRIGGER IF EXISTS {index}_tag_delete;
+        DROP TABLE IF EXISTS {index};"
+    ))
+}
+
+/// Builds the search index of an entry table anew, so tokens of overwritten metadata do not
+/// linger in the old index's segments.
+pub(super) fn rebuild_index(connection: &Connection, table_name: &str) -> rusqlite::Result<()> {
+    drop_index(connection, table_name)?;
+    create_index(connection, table_name)
+}
+
+/// Ids of the entries whose indexed metadata shares a trigram with a query term, or `None` when
+/// the index cannot rule entries out: for short terms or terms whose typos could hide every
+/// trigram.
+pub(super) fn candidates(
+    connection: &Connection,
+    table_name: &str,
+    query: &Query,
+) -> rusqlite::Result<Option<HashSet<i64>>> {
+    if query.is_empty()
+        || !query
+            .terms()
+            .iter()
+            .all(|term| fuzzy::found_by_trigrams(term))
+    {
+        return Ok(None);
+    }
+    let trigrams: Vec<String> = query
+        .terms()
+        .iter()
+        .flat_map(|term| {
+            let chars: Vec<char> = term.chars().collect();
+            chars
+                .windows(3)
+                .map(|trigram| {
+                    let trigram: String = trigram.iter().collect();
+                    format!("\"{}\"", trigram.replace('"', "\"\""))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let index = index_name(table_name);
+    let mut statement =
+        connection.prepare(&format!("SELECT rowid FROM {index} WHERE {index} MATCH ?1"))?;
+    let ids = statement
+        .query_map([trigrams.join(" OR ")], |row| row.get(0))?
+        .collect::<rusqlite::Result<HashSet<i64>>>()?;
+    Ok(Some(ids))
+}
@@ -0,0 +1,766 @@
+//! Shared vaults.
+//!
+//! A shared vault syncs between its members through a folder of its own, kept in step by a
+//! tool such as Syncthing like the sync folder. The folder holds `members.json` and a
+//! directory per epoch of the vault key, with a log for every device of every member in the
+//! format of [`super::sync`], sealed with the vault key instead of a MEK.
+//!
+//! `members.json` lists the members with the vault key wrapped to each of their identities by
+//! [`portable_export::wrap_vault_key`]. Only the owner, who shared the vault first, writes it,
+//! and tags it for every member with [`portable_export::member_tag`], so members can tell it
+//! comes from the owner and a removed member cannot rewrite it.
+//!
+//! On every device the vault itself is an ordinary vault under the member's own MEK, so the
+//! audit log, key rotation and everything else bound to the MEK never leave the device. The
+//! folder, the vault key and the members are kept in the settings table, sealed with the MEK.
+//!
+//! Removing a member starts a new epoch: a new vault key, wrapped to the remaining members, and
+//! a new directory the owner fills with every entry of the vault. Older directories are
+//! deleted. Members who sync next unwrap the new key and log their entries again, so changes
+//! they had not synced yet are kept; the newest change of every entry wins as usual.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::Utc;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::migration::META_TABLE;
+use super::sync::{Channel, SyncReport, read_meta, write_meta};
+use super::vaults::{self, DEFAULT_VAULT};
+use super::{Database, EntryRepository, StorageError, details};
+use crate::crypto::CryptoError;
+use crate::identity::{IdentityKeypair, parse_public_code, public_code_from_key};
+use crate::portable_export;
+use crate::secret::SecretKey;
+
+const SHARE_PREFIX: &str = "share.";
+const STATE_PREFIX: &str = "share_sync.";
+const DEVICE_PREFIX: &str = "share_device.";
+const MEMBERS_FILE: &str = "members.json";
+
+/// A member of a shared vault.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VaultMember {
+    /// The `rvault1-` public code the vault key is wrapped to.
+    pub public_code: String,
+    pub added_at: i64,
+}
+
+/// What a member keeps about a shared vault.
+#[derive(Serialize, Deserialize)]
+struct Share {
+    folder: PathBuf,
+    /// Public code of the member who writes the members file.
+    owner: String,
+    epoch: u64,
+    /// The vault key of `epoch`, base64.
+    key: String,
+    members: Vec<VaultMember>,
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+/// `members.json`. The roster is kept as the owner wrote it, so the tags check the same bytes.
+#[derive(Serialize, Deserialize)]
+struct MembersFile {
+    roster: String,
+    /// The tag of the roster for each member, by public code, base64.
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Roster {
+    owner: String,
+    epoch: u64,
+    members: Vec<RosterMember>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RosterMember {
+    public_code: String,
+    added_at: i64,
+    /// The vault key wrapped to this member, base64.
+    wrapped_key: String,
+}
+
+impl Database {
+    /// Members of `vault` in the order they were added, so the owner comes first; empty for a
+    /// vault that is not shared.
+    pub fn vault_members(
+        &self,
+        vault: Option<&str>,
+        key: &SecretKey,
+    ) -> Result<Vec<VaultMember>, StorageError> {
+        let table = self.vault_table(vault.unwrap_or(DEFAULT_VAULT))?;
+        Ok(read_share(&self.connection, &table, key)?
+            .map(|share| share.members.clone())
+            .unwrap_or_default())
+    }
+
+    /// Adds `public_code` to the members of `vault`, wrapping the vault key to it.
+    ///
+    /// The first member added shares the vault through `folder`, which must not hold a shared
+    /// vault yet: the vault gets a vault key, the owner of `identity` becomes its owner and
+    /// first member, and every entry is sent to the folder. Only the owner adds members.
+    pub fn add_vault_member(
+        &self,
+        vault: Option<&str>,
+        key: &SecretKey,
+        identity: &IdentityKeypair,
+        public_code: &str,
+        folder: Option<&Path>,
+    ) -> Result<(), StorageError> {
+        let table = self.vault_table(vault.unwrap_or(DEFAULT_VAULT))?;
+        let code = canonical_code(public_code)?;
+        let own = public_code_from_key(&identity.public_key);
+        let mut share = match read_share(&self.connection, &table, key)? {
+            Some(share) => {
+                if folder.is_some_and(|folder| folder != share.folder) {
+                    return Err(StorageError::Sharing(format!(
+                        "the vault is already shared through {}",
+                        share.folder.display()
+                    )));
+                }
+                share
+            }
+            None => {
+                let folder = folder.ok_or_else(|| {
+                    StorageError::Sharing("choose a folder to share the vault through".to_string())
+                })?;
+                if folder.join(MEMBERS_FILE).exists() {
+                    return Err(StorageError::Sharing(format!(
+                        "{} already holds a shared vault",
+                        folder.display()
+                    )));
+                }
+                Share {
+                    folder: folder.to_path_buf(),
+                    owner: own.clone(),
+                    epoch: 1,
+                    key: new_vault_key(),
+                    members: vec![VaultMember {
+                        public_code: own.clone(),
+                        added_at: Utc::now().timestamp(),
+                    }],
+                }
+            }
+        };
+        if share.owner != own {
+            return Err(StorageError::Sharing(format!(
+                "only the owner {} changes the members",
+                share.owner
+            )));
+        }
+        if share
+            .members
+            .iter()
+            .any(|member| member.public_code == code)
+        {
+            return Err(StorageError::Sharing(format!("{code} is already a member")));
+        }
+        share.members.push(VaultMember {
+            public_code: code,
+            added_at: Utc::now().timestamp(),
+        });
+        write_members_file(&share, identity)?;
+        write_share(&self.connection, &table, key, &share)?;
+        self.sync_shared_vault(vault, key, identity)?;
+        Ok(())
+    }
+
+    /// Joins the vault shared through `folder` as a new vault called `name` and fetches its
+    /// entries. The members file must carry a tag for `identity`; its owner is trusted from now
+    /// on, so compare their public code with `vault_members` before relying on the vault.
+    pub fn join_vault(
+        &self,
+        name: &str,
+        key: &SecretKey,
+        identity: &IdentityKeypair,
+        folder: &Path,
+    ) -> Result<SyncReport, StorageError> {
+        self.vault_tables()?;
+        vaults::ensure_free(&self.connection, name)?;
+        let roster = read_roster(folder, identity, None)?;
+        let share = Share {
+            folder: folder.to_path_buf(),
+            owner: roster.owner.clone(),
+            epoch: roster.epoch,
+            key: BASE64.encode(unwrap_key(&roster, identity)?.as_bytes()),
+            members: members(&roster),
+        };
+        EntryRepository::unlocked(self, Some(name.to_string()), key)?;
+        let table = self.vault_table(name)?;
+        write_share(&self.connection, &table, key, &share)?;
+        self.sync_shared_vault(Some(name), key, identity)
+    }
+
+    /// Removes `public_code` from `vault` and starts a new epoch, so the vault key the removed
+    /// member holds opens nothing written from now on: a new vault key is wrapped to the
+    /// remaining members and every entry is sent again under it. Only the owner removes
+    /// members, and never themselves. Returns the number of entries sent.
+    pub fn remove_vault_member(
+        &self,
+        vault: Option<&str>,
+        key: &SecretKey,
+        identity: &IdentityKeypair,
+        public_code: &str,
+    ) -> Result<usize, StorageError> {
+        let table = self.vault_table(vault.unwrap_or(DEFAULT_VAULT))?;
+        let code = canonical_code(public_code)?;
+        let own = public_code_from_key(&identity.public_key);
+        let share = read_share(&self.connection, &table, key)?.ok_or_else(not_shared)?;
+        if share.owner != own {
+            return Err(StorageError::Sharing(format!(
+                "only the owner {} changes the members",
+                share.owner
+            )));
+        }
+        if code == own {
+            return Err(StorageError::Sharing(
+                "the owner cannot leave the vault; delete it instead".to_string(),
+            ));
+        }
+        if !share
+            .members
+            .iter()
+            .any(|member| member.public_code == code)
+        {
+            return Err(StorageError::NotFound);
+        }
+        // Changes logged under the old key are taken in first.
+        self.sync_shared_vault(vault, key, identity)?;
+        let mut share = read_share(&self.connection, &table, key)?.ok_or_else(not_shared)?;
+        share.members.retain(|member| member.public_code != code);
+        share.epoch += 1;
+        share.key = new_vault_key();
+        write_members_file(&share, identity)?;
+        write_share(&self.connection, &table, key, &share)?;
+        forget_sync_state(&self.connection, &table)?;
+        let report = self.sync_shared_vault(vault, key, identity)?;
+        for epoch in 1..share.epoch {
+            match fs::remove_dir_all(epoch_folder(&share.folder, epoch)) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(error.into());
+                }
+                _ => {}
+            }
+        }
+        Ok(report.sent)
+    }
+
+    /// Syncs `vault` through its folder. A members file of a later epoch brings a new vault
+    /// key; the sync then starts over in the new epoch's directory. Fails when `identity` was
+    /// removed from the vault or the members file does not come from the owner.
+    pub fn sync_shared_vault(
+        &self,
+        vault: Option<&str>,
+        key: &SecretKey,
+        identity: &IdentityKeypair,
+    ) -> Result<SyncReport, StorageError> {
+        let name = vault.unwrap_or(DEFAULT_VAULT);
+        let table = self.vault_table(name)?;
+        let mut share = read_share(&self.connection, &table, key)?.ok_or_else(not_shared)?;
+        let roster = read_roster(&share.folder, identity, Some(&share.owner))?;
+        if roster.epoch < share.epoch {
+            return Err(StorageError::Sharing(
+                "the members file is older than this copy of the vault".to_string(),
+            ));
+        }
+        if roster.epoch > share.epoch {
+            share.key = BASE64.encode(unwrap_key(&roster, identity)?.as_bytes());
+            share.epoch = roster.epoch;
+            forget_sync_state(&self.connection, &table)?;
+        }
+        share.members = members(&roster);
+        write_share(&self.connection, &table, key, &share)?;
+        let vault_key = vault_key(&share)?;
+        let channel = Channel {
+            folder: epoch_folder(&share.folder, share.epoch),
+            log_key: &vault_key,
+            device_setting: format!("{DEVICE_PREFIX}{table}"),
+            state_setting: format!("{STATE_PREFIX}{table}"),
+            shared_vault: Some(name.to_string()),
+        };
+        self.sync_channel(key, &channel)
+    }
+}
+
+/// Whether `table` is a shared vault on this device.
+pub(super) fn is_shared(connection: &Connection, table: &str) -> Result<bool, StorageError> {
+    Ok(read_meta(connection, &format!("{SHARE_PREFIX}{table}"))?.is_some())
+}
+
+/// Drops what this device keeps about sharing `table`, which is being deleted.
+pub(super) fn forget(connection: &Connection, table: &str) -> rusqlite::Result<()> {
+    for prefix in [SHARE_PREFIX, STATE_PREFIX, DEVICE_PREFIX] {
+        connection.execute(
+            &format!("DELETE FROM {META_TABLE} WHERE name = ?1"),
+            [format!("{prefix}{table}")],
+        )?;
+    }
+    Ok(())
+}
+
+/// Moves the sealed settings of every shared vault from the MEK `old_key` to `new_key`. The
+/// vault keys themselves stay as they are.
+pub(super) fn reseal_all(
+    connection: &Connection,
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+) -> Result<(), StorageError> {
+    let sealed: Vec<(String, String)> = {
+        let mut statement = connection.prepare(&format!(
+            "SELECT name, value FROM {META_TABLE}
+             WHERE substr(name, 1, length(?1)) = ?1 OR substr(name, 1, length(?2)) = ?2"
+        ))?;
+        statement
+            .query_map([SHARE_PREFIX, STATE_PREFIX], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<_, _>>()?
+    };
+    for (name, value) in sealed {
+        let plaintext = details::open_bytes(old_key, &value)?;
+        write_meta(
+            connection,
+            &name,
+            &details::seal_bytes(new_key, plaintext.expose())?,
+        )?;
+    }
+    Ok(())
+}
+
+fn read_share(
+    connection: &Connection,
+    table: &str,
+    key: &SecretKey,
+) -> Result<Option<Share>, StorageError> {
+    let Some(sealed) = read_meta(connection, &format!("{SHARE_PREFIX}{table}"))? else {
+        return Ok(None);
+    };
+    let plaintext = details::open_bytes(key, &sealed)?;
+    serde_json::from_slice(plaintext.expose())
+        .map(Some)
+        .map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+fn write_share(
+    connection: &Connection,
+    table: &str,
+    key: &SecretKey,
+    share: &Share,
+) -> Result<(), StorageError> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(share).expect("shares always serialize"));
+    write_meta(
+        connection,
+        &format!("{SHARE_PREFIX}{table}"),
+        &details::seal_bytes(key, &plaintext)?,
+    )
+}
+
+/// Makes the next sync of `table` log every entry again, as in a new epoch.
+fn forget_sync_state(connection: &Connection, table: &str) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!("DELETE FROM {META_TABLE} WHERE name = ?1"),
+        [format!("{STATE_PREFIX}{table}")],
+    )?;
+    Ok(())
+}
+
+/// Wraps the vault key of `share` to every member and writes the members file, tagged for each
+/// of them by the owner `identity`.
+fn write_members_file(share: &Share, identity: &IdentityKeypair) -> Result<(), StorageError> {
+    let vault_key = vault_key(share)?;
+    let members = share
+        .members
+        .iter()
+        .map(|member| {
+            let wrapped =
+                portable_export::wrap_vault_key(&member.public_code, vault_key.as_bytes())
+                    .map_err(StorageError::Sharing)?;
+            Ok(RosterMember {
+                public_code: member.public_code.clone(),
+                added_at: member.added_at,
+                wrapped_key: BASE64.encode(wrapped),
+            })
+        })
+        .collect::<Result<_, StorageError>>()?;
+    let roster = Roster {
+        owner: share.owner.clone(),
+        epoch: share.epoch,
+        members,
+    };
+    let roster = serde_json::to_string(&roster).expect("rosters always serialize");
+    let mut tags = BTreeMap::new();
+    for member in &share.members {
+        let tag = portable_export::member_tag(identity, &member.public_code, roster.as_bytes())
+            .map_err(StorageError::Sharing)?;
+        tags.insert(member.public_code.clone(), BASE64.encode(tag));
+    }
+    let file = serde_json::to_vec_pretty(&MembersFile { roster, tags })
+        .expect("members files always serialize");
+    fs::create_dir_all(&share.folder)?;
+    // Written aside and renamed, so the folder never holds half a members file.
+    let partial = share.folder.join(format!("{MEMBERS_FILE}.partial"));
+    fs::write(&partial, file)?;
+    fs::rename(partial, share.folder.join(MEMBERS_FILE))?;
+    Ok(())
+}
+
+/// Reads the members file in `folder` and checks that `owner`, or the owner it names when
+/// joining, tagged it for `identity`.
+fn read_roster(
+    folder: &Path,
+    identity: &IdentityKeypair,
+    owner: Option<&str>,
+) -> Result<Roster, StorageError> {
+    let invalid = || StorageError::Sharing("invalid members file".to_string());
+    let file: MembersFile =
+        serde_json::from_slice(&fs::read(folder.join(MEMBERS_FILE))?).map_err(|_| invalid())?;
+    let roster: Roster = serde_json::from_str(&file.roster).map_err(|_| invalid())?;
+    let own = public_code_from_key(&identity.public_key);
+    let not_member = || StorageError::Sharing(format!("{own} is not a member of the vault"));
+    let tag = file.tags.get(&own).ok_or_else(not_member)?;
+    let tag = BASE64.decode(tag).map_err(|_| invalid())?;
+    let owner = owner.unwrap_or(&roster.owner);
+    if roster.owner != owner
+        || portable_export::verify_member_tag(identity, owner, file.roster.as_bytes(), &tag)
+            .is_err()
+    {
+        return Err(StorageError::Sharing(format!(
+            "the members file was not written by the owner {owner}"
+        )));
+    }
+    if !roster
+        .members
+        .iter()
+        .any(|member| member.public_code == own)
+    {
+        return Err(not_member());
+    }
+    Ok(roster)
+}
+
+/// The vault key `roster` wraps to `identity`.
+fn unwrap_key(roster: &Roster, identity: &IdentityKeypair) -> Result<SecretKey, StorageError> {
+    let own = public_code_from_key(&identity.public_key);
+    let member = roster
+        .members
+        .iter()
+        .find(|member| member.public_code == own)
+        .ok_or_else(|| StorageError::Sharing(format!("{own} is not a member of the vault")))?;
+    let wrapped = BASE64
+        .decode(&member.wrapped_key)
+        .map_err(|_| StorageError::Sharing("invalid wrapped vault key".to_string()))?;
+    portable_export::unwrap_vault_key(identity, &wrapped)
+        .map(SecretKey::from_bytes)
+        .map_err(StorageError::Sharing)
+}
+
+fn members(roster: &Roster) -> Vec<VaultMember> {
+    roster
+        .members
+        .iter()
+        .map(|member| VaultMember {
+            public_code: member.public_code.clone(),
+            added_at: member.added_at,
+        })
+        .collect()
+}
+
+fn vault_key(share: &Share) -> Result<SecretKey, StorageError> {
+    let bytes = Zeroizing::new(
+        BASE64
+            .decode(&share.key)
+            .map_err(|_| CryptoError::AuthenticationFailed)?,
+    );
+    <[u8; 32]>::try_from(bytes.as_slice())
+        .map(SecretKey::from_bytes)
+        .map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+fn new_vault_key() -> String {
+    BASE64.encode(SecretKey::from_bytes(rand::random()).as_bytes())
+}
+
+fn epoch_folder(folder: &Path, epoch: u64) -> PathBuf {
+    folder.join(format!("epoch-{epoch}"))
+}
+
+fn not_shared() -> StorageError {
+    StorageError::Sharing("the vault is not shared".to_string())
+}
+
+/// `public_code` in the form [`public_code_from_key`] writes, so one identity is one member.
+fn canonical_code(public_code: &str) -> Result<String, StorageError> {
+    parse_public_code(public_code.trim())
+        .map(|public_key| public_code_from_key(&public_key))
+        .map_err(StorageError::Sharing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{generate_identity_bytes, load_identity_from_bytes};
+    use crate::storage::{EntrySelector, EntryUpdate, NewEntry};
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn identity(seed: u8) -> IdentityKeypair {
+        let key = [seed; 32];
+        load_identity_from_bytes(&key, &generate_identity_bytes(&key).unwrap()).unwrap()
+    }
+
+    fn code(identity: &IdentityKeypair) -> String {
+        public_code_from_key(&identity.public_key)
+    }
+
+    fn temp_folder() -> PathBuf {
+        std::env::temp_dir().join(format!("rvault-share-test-{}", rand::random::<u64>()))
+    }
+
+    fn secret(db: &Database, key: &SecretKey, vault: &str) -> Option<Vec<u8>> {
+        match EntryRepository::unlocked(db, Some(vault.to_string()), key)
+            .unwrap()
+            .get(key, EntrySelector::new("github", "deploy"))
+        {
+            Ok(entry) => Some(entry.secret.expose().to_vec()),
+            Err(StorageError::NotFound) => None,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[test]
+    fn members_exchange_a_shared_vault_through_its_folder() {
+        let folder = temp_folder();
+        let (owner_db, teammate_db) = (memory_db(), memory_db());
+        let (key, teammate_key) = (
+            SecretKey::from_bytes([1; 32]),
+            SecretKey::from_bytes([5; 32]),
+        );
+        let (owner, teammate) = (identity(2), identity(3));
+        EntryRepository::unlocked(&owner_db, Some("Team".to_string()), &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "deploy", b"one"))
+            .unwrap();
+        EntryRepository::unlocked(&owner_db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("bank", "alice", b"private"))
+            .unwrap();
+
+        owner_db
+            .add_vault_member(Some("Team"), &key, &owner, &code(&teammate), Some(&folder))
+            .unwrap();
+        let report = teammate_db
+            .join_vault("Work", &teammate_key, &teammate, &folder)
+            .unwrap();
+
+        assert_eq!(report.applied, 1);
+        assert_eq!(secret(&teammate_db, &teammate_key, "Work").unwrap(), b"one");
+        let names: Vec<(String, bool)> = teammate_db
+            .vaults()
+            .unwrap()
+            .into_iter()
+            .map(|vault| (vault.name, vault.shared))
+            .collect();
+        assert_eq!(
+            names,
+            [("main".to_string(), false), ("Work".to_string(), true)]
+        );
+        let members = teammate_db
+            .vault_members(Some("Work"), &teammate_key)
+            .unwrap();
+        let codes: Vec<&str> = members.iter().map(|m| m.public_code.as_str()).collect();
+        assert_eq!(codes, [code(&owner), code(&teammate)]);
+        let members_file = fs::read_to_string(folder.join(MEMBERS_FILE)).unwrap();
+        assert!(!members_file.contains("github"));
+
+        // Each member keeps the vault under their own MEK, which rotates on its own.
+        let rotated = SecretKey::from_bytes([6; 32]);
+        teammate_db
+            .reencrypt_entries(&teammate_key, &rotated, "fingerprint", || Ok(()))
+            .unwrap();
+        EntryRepository::unlocked(&teammate_db, Some("Work".to_string()), &rotated)
+            .unwrap()
+            .update(
+                &rotated,
+                EntrySelector::new("github", "deploy"),
+                EntryUpdate::new("deploy", b"two"),
+            )
+            .unwrap();
+        assert_eq!(
+            teammate_db
+                .sync_shared_vault(Some("Work"), &rotated, &teammate)
+                .unwrap()
+                .sent,
+            1
+        );
+        let report = owner_db
+            .sync_shared_vault(Some("Team"), &key, &owner)
+            .unwrap();
+        assert_eq!((report.sent, report.applied), (0, 1));
+        assert_eq!(secret(&owner_db, &key, "Team").unwrap(), b"two");
+        assert!(matches!(
+            teammate_db.add_vault_member(
+                Some("Work"),
+                &rotated,
+                &teammate,
+                &code(&identity(7)),
+                None
+            ),
+            Err(StorageError::Sharing(_))
+        ));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn removing_a_member_starts_a_new_epoch() {
+        let folder = temp_folder();
+        let (owner_db, teammate_db, contractor_db) = (memory_db(), memory_db(), memory_db());
+        let key = SecretKey::from_bytes([1; 32]);
+        let (owner, teammate, contractor) = (identity(2), identity(3), identity(4));
+        EntryRepository::unlocked(&owner_db, Some("Team".to_string()), &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "deploy", b"one"))
+            .unwrap();
+        for member in [&teammate, &contractor] {
+            owner_db
+                .add_vault_member(Some("Team"), &key, &owner, &code(member), Some(&folder))
+                .unwrap();
+        }
+        teammate_db
+            .join_vault("Team", &key, &teammate, &folder)
+            .unwrap();
+        contractor_db
+            .join_vault("Team", &key, &contractor, &folder)
+            .unwrap();
+        // A change the teammate has not synced yet survives the new epoch.
+        EntryRepository::unlocked(&teammate_db, Some("Team".to_string()), &key)
+            .unwrap()
+            .add(&key, NewEntry::new("gitlab", "deploy", b"unsynced"))
+            .unwrap();
+
+        let sent = owner_db
+            .remove_vault_member(Some("Team"), &key, &owner, &code(&contractor))
+            .unwrap();
+
+        assert_eq!(sent, 1);
+        assert!(!epoch_folder(&folder, 1).exists());
+        assert!(matches!(
+            contractor_db.sync_shared_vault(Some("Team"), &key, &contractor),
+            Err(StorageError::Sharing(_))
+        ));
+        let report = teammate_db
+            .sync_shared_vault(Some("Team"), &key, &teammate)
+            .unwrap();
+        assert_eq!((report.sent, report.received), (2, 1));
+        owner_db
+            .sync_shared_vault(Some("Team"), &key, &owner)
+            .unwrap();
+        let gitlab = EntryRepository::unlocked(&owner_db, Some("Team".to_string()), &key)
+            .unwrap()
+            .get(&key, EntrySelector::new("gitlab", "deploy"))
+            .unwrap();
+        assert_eq!(gitlab.secret.expose(), b"unsynced");
+        assert_eq!(owner_db.vault_members(Some("Team"), &key).unwrap().len(), 2);
+        assert!(matches!(
+            owner_db.remove_vault_member(Some("Team"), &key, &owner, &code(&owner)),
+            Err(StorageError::Sharing(_))
+        ));
+
+        // A removed member cannot hand themselves the new key by rewriting the members file.
+        let stale = read_share(&contractor_db.connection, &table(&contractor_db), &key)
+            .unwrap()
+            .unwrap();
+        let forged = Share {
+            folder: folder.clone(),
+            owner: code(&contractor),
+            epoch: 3,
+            key: stale.key.clone(),
+            members: vec![
+                VaultMember {
+                    public_code: code(&contractor),
+                    added_at: 0,
+                },
+                VaultMember {
+                    public_code: code(&teammate),
+                    added_at: 0,
+                },
+            ],
+        };
+        write_members_file(&forged, &contractor).unwrap();
+        assert!(matches!(
+            teammate_db.sync_shared_vault(Some("Team"), &key, &teammate),
+            Err(StorageError::Sharing(_))
+        ));
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    fn table(db: &Database) -> String {
+        db.vault_table("Team").unwrap()
+    }
+
+    #[test]
+    fn search_and_sync_still_work_after_a_member_is_removed() {
+        let (share_folder, sync_folder) = (temp_folder(), temp_folder());
+        let (owner_db, laptop_db) = (memory_db(), memory_db());
+        let key = SecretKey::from_bytes([1; 32]);
+        let (owner, teammate) = (identity(2), identity(3));
+        EntryRepository::unlocked(&owner_db, Some("Team".to_string()), &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "deploy", b"one"))
+            .unwrap();
+        EntryRepository::unlocked(&owner_db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("gitlab", "alice", b"private"))
+            .unwrap();
+        owner_db
+            .add_vault_member(
+                Some("Team"),
+                &key,
+                &owner,
+                &code(&teammate),
+                Some(&share_folder),
+            )
+            .unwrap();
+        owner_db
+            .remove_vault_member(Some("Team"), &key, &owner, &code(&teammate))
+            .unwrap();
+
+        let hits = owner_db.search_vaults(&key, "git").unwrap();
+        let mut vaults: Vec<&str> = hits.iter().map(|(vault, _)| vault.as_str()).collect();
+        vaults.sort();
+        assert_eq!(vaults, ["Team", "main"]);
+
+        // The sync folder carries the vaults that are not shared.
+        for db in [&owner_db, &laptop_db] {
+            db.set_sync_folder(&sync_folder).unwrap();
+        }
+        assert_eq!(owner_db.sync_now(&key).unwrap().sent, 1);
+        assert_eq!(owner_db.sync_status(&key).unwrap().pending, 0);
+        assert_eq!(laptop_db.sync_now(&key).unwrap().applied, 1);
+        let names: Vec<String> = laptop_db
+            .vaults()
+            .unwrap()
+            .into_iter()
+            .map(|vault| vault.name)
+            .collect();
+        assert_eq!(names, ["main"]);
+        fs::remove_dir_all(share_folder).unwrap();
+        fs::remove_dir_all(sync_folder).unwrap();
+    }
+}
@@ -0,0 +1,971 @@
+//! Syncing databases through a shared folder.
+//!
+//! Every database taking part gets a random device ID and appends the changes made to its
+//! entries to `<device ID>.log` in the sync folder, one operation per line, sealed with the MEK.
+//! The folder is expected to be kept in step by another tool such as Syncthing, so it never
+//! sees a secret, an identity or a vault name, and each log only ever has one writer.
+//!
+//! An operation carries the whole entry, or records that it was removed, together with a
+//! clock: the time of the change and the device that made it. Replaying the logs of the other
+//! devices keeps, for each entry, the operation with the highest clock, comparing devices by
+//! ID when the times are equal. Every device therefore ends up with the same entry whatever
+//! order it reads the logs in. The secret an operation replaces joins the entry's history, as
+//! in a merge, and a removal moves the entry to the trash.
+//!
+//! Which entries changed since the last sync is told by their revision. The sync state, sealed
+//! with the MEK like the logs, keeps the revision and clock of every entry it has seen.
+//!
+//! Every device syncing through a folder or a git repository needs the same MEK, so the MEK is
+//! only rotated once syncing stopped. The sync state then moves to the new key with the
+//! entries; the git sync state is dropped, because the names and seals of the entry files
+//! derive from the MEK, and the next git sync pushes every entry again.
+//!
+//! Shared vaults stay out of the sync folder. Each syncs the same way through a folder of its
+//! own, with the logs sealed with its vault key; see [`super::sharing`].
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::batch::Savepoint;
+use super::details::{self, CustomField, EntryDetails};
+use super::merge::{self, MergeEntry, MergeOutcome};
+use super::migration::META_TABLE;
+use super::repository::{DecryptedEntry, EntryMetadata, EntrySelector};
+use super::{Database, EntryRepository, StorageError, vaults};
+use crate::crypto::CryptoError;
+use crate::domain::EntryUrl;
+use crate::kind::EntryKind;
+use crate::secret::{SecretBytes, SecretKey};
+use crate::totp::TotpSecret;
+
+mod git;
+
+pub use git::GitSyncReport;
+
+const DEVICE_ID: &str = "sync_device_id";
+const SYNC_FOLDER: &str = "sync_folder";
+const SYNC_STATE: &str = "sync_state";
+const LOG_EXTENSION: &str = "log";
+
+/// What [`Database::sync_now`] did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Local changes appended to this device's log.
+    pub sent: usize,
+    /// Operations read from the logs of other devices.
+    pub received: usize,
+    /// Entries those operations changed; the others were older than the local entry.
+    pub applied: usize,
+}
+
+/// How far this database is in step with the sync folder.
+pub struct SyncStatus {
+    pub device_id: String,
+    pub folder: Option<PathBuf>,
+    /// Local changes not yet in this device's log.
+    pub pending: usize,
+    /// The logs of the other devices in the folder, by device ID.
+    pub devices: Vec<DeviceLog>,
+}
+
+/// The log of another device.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeviceLog {
+    pub device_id: String,
+    /// Operations in the log.
+    pub operations: u64,
+    /// Operations of the log this database has replayed.
+    pub replayed: u64,
+}
+
+/// Where a sync exchanges operations and which vaults it carries.
+pub(super) struct Channel<'a> {
+    /// Folder holding the device logs.
+    pub(super) folder: PathBuf,
+    /// Seals the lines of the logs.
+    pub(super) log_key: &'a SecretKey,
+    /// Settings holding this device's ID and the sync state.
+    pub(super) device_setting: String,
+    pub(super) state_setting: String,
+    /// The shared vault the folder carries, logged without its local name. `None` carries
+    /// every vault that is not shared.
+    pub(super) shared_vault: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SyncState {
+    /// Operations in this device's log.
+    logged: u64,
+    /// Operations replayed from the log of each other device.
+    replayed: BTreeMap<String, u64>,
+    entries: Vec<Tracked>,
+}
+
+/// The last operation logged or replayed for one entry.
+#[derive(Serialize, Deserialize)]
+struct Tracked {
+    vault: String,
+    platform: String,
+    user_id: String,
+    /// Row and revision of the entry the operation left behind; `None` once it was removed.
+    row: Option<(i64, i64)>,
+    at: i64,
+    device: String,
+}
+
+impl Tracked {
+    fn is(&self, vault: &str, platform: &str, user_id: &str) -> bool {
+        self.vault == vault && self.platform == platform && self.user_id == user_id
+    }
+}
+
+/// One line of a device log.
+#[derive(Serialize, Deserialize)]
+struct SyncOp {
+    seq: u64,
+    device: String,
+    at: i64,
+    vault: String,
+    platform: String,
+    user_id: String,
+    /// The entry after the change, or `None` when it was removed.
+    entry: Option<Snapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    kind: EntryKind,
+    secret: String,
+    notes: String,
+    fields: Vec<CustomField>,
+    totp: Option<String>,
+    urls: Vec<EntryUrl>,
+    folder: Option<String>,
+    tags: Vec<String>,
+    pinned: bool,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+        self.notes.zeroize();
+        self.totp.zeroize();
+    }
+}
+
+impl Database {
+    /// This database's device ID, chosen the first time it is asked for.
+    pub fn sync_device_id(&self) -> Result<String, StorageError> {
+        // Opening the default table creates the settings table on older files.
+        self.vault_tables()?;
+        self.device_id(DEVICE_ID)
+    }
+
+    fn device_id(&self, setting: &str) -> Result<String, StorageError> {
+        if let Some(device_id) = read_meta(&self.connection, setting)? {
+            return Ok(device_id);
+        }
+        let device_id = new_device_id();
+        write_meta(&self.connection, setting, &device_id)?;
+        Ok(device_id)
+    }
+
+    /// The folder [`Database::sync_now`] syncs through, once one was set.
+    pub fn sync_folder(&self) -> Result<Option<PathBuf>, StorageError> {
+        self.vault_tables()?;
+        Ok(read_meta(&self.connection, SYNC_FOLDER)?.map(PathBuf::from))
+    }
+
+    /// Changes the sync folder. Logs already replayed from the previous folder are not replayed
+    /// again when the same devices write to the new one.
+    pub fn set_sync_folder(&self, folder: &Path) -> Result<(), StorageError> {
+        let folder = folder.to_str().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the sync folder path is not valid UTF-8",
+            )
+        })?;
+        self.vault_tables()?;
+        write_meta(&self.connection, SYNC_FOLDER, folder)
+    }
+
+    /// Stops syncing through the sync folder and the git repository. The sync state stays, so
+    /// setting a folder again only sends the changes made since.
+    pub fn stop_sync(&self) -> Result<(), StorageError> {
+        self.vault_tables()?;
+        for setting in [SYNC_FOLDER, git::GIT_REMOTE] {
+            self.connection.execute(
+                &format!("DELETE FROM {META_TABLE} WHERE name = ?1"),
+                [setting],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Compares the entries with the sync state and counts the operations in the logs of the
+    /// other devices.
+    pub fn sync_status(&self, key: &SecretKey) -> Result<SyncStatus, StorageError> {
+        let device_id = self.sync_device_id()?;
+        let folder = self.sync_folder()?;
+        let state = read_state(&self.connection, key, SYNC_STATE)?;
+        let repositories = self.sync_repositories(key)?;
+        let pending = local_changes(&state, &live_entries(&repositories)?).len();
+        let mut devices = Vec::new();
+        if let Some(folder) = folder.as_ref().filter(|folder| folder.is_dir()) {
+            for (other, path) in device_logs(folder)? {
+                if other == device_id {
+                    continue;
+                }
+                devices.push(DeviceLog {
+                    operations: complete_lines(&fs::read_to_string(path)?).count() as u64,
+                    replayed: state.replayed.get(&other).copied().unwrap_or(0),
+                    device_id: other,
+                });
+            }
+        }
+        Ok(SyncStatus {
+            device_id,
+            folder,
+            pending,
+            devices,
+        })
+    }
+
+    /// Appends the local changes since the last sync to this device's log, then replays the
+    /// operations the other devices logged since. Fails with [`StorageError::NotFound`] when
+    /// no sync folder is set.
+    pub fn sync_now(&self, key: &SecretKey) -> Result<SyncReport, StorageError> {
+        let folder = self.sync_folder()?.ok_or(StorageError::NotFound)?;
+        let channel = Channel {
+            folder,
+            log_key: key,
+            device_setting: DEVICE_ID.to_string(),
+            state_setting: SYNC_STATE.to_string(),
+            shared_vault: None,
+        };
+        self.sync_channel(key, &channel)
+    }
+
+    /// Logs the local changes of the vaults `channel` carries and replays those of the other
+    /// devices. Entries are read and written with the MEK `key`.
+    pub(super) fn sync_channel(
+        &self,
+        key: &SecretKey,
+        channel: &Channel<'_>,
+    ) -> Result<SyncReport, StorageError> {
+        let folder = &channel.folder;
+        fs::create_dir_all(folder)?;
+        let mut device_id = self.device_id(&channel.device_setting)?;
+        let mut state = read_state(&self.connection, key, &channel.state_setting)?;
+        // A log longer than the state knows was written by a copy of this database, which
+        // kept the device ID; this copy continues under a new one.
+        let logged = match fs::read_to_string(log_path(folder, &device_id)) {
+            Ok(log) => complete_lines(&log).count() as u64,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(error) => return Err(error.into()),
+        };
+        if logged != state.logged {
+            device_id = new_device_id();
+            write_meta(&self.connection, &channel.device_setting, &device_id)?;
+            state.logged = 0;
+        }
+        let own_log = log_path(folder, &device_id);
+
+        let mut report = SyncReport::default();
+        let mut repositories = self.channel_repositories(key, channel)?;
+        let live = live_entries(&repositories)?;
+        let mut lines = String::new();
+        for change in local_changes(&state, &live) {
+            let (vault, platform, user_id, metadata) = match change {
+                LocalChange::Put(vault, metadata) => {
+                    (vault, &metadata.platform, &metadata.user_id, Some(metadata))
+                }
+                LocalChange::Remove(tracked) => {
+                    let tracked = &state.entries[tracked];
+                    (&tracked.vault, &tracked.platform, &tracked.user_id, None)
+                }
+            };
+            let (vault, platform, user_id) = (vault.clone(), platform.clone(), user_id.clone());
+            let previous = state
+                .entries
+                .iter()
+                .position(|tracked| tracked.is(&vault, &platform, &user_id));
+            let not_before = previous.map_or(i64::MIN, |tracked| state.entries[tracked].at + 1);
+            let (at, entry, row) = match metadata {
+                Some(metadata) => {
+                    let repository = repository(&repositories, &vault)?;
+                    let entry = repository.get(key, EntrySelector::new(&platform, &user_id))?;
+                    let row = metadata.id.map(|id| (id, metadata.revision));
+                    (
+                        metadata.updated_at.max(not_before),
+                        Some(snapshot(&entry)),
+                        row,
+                    )
+                }
+                None => (Utc::now().timestamp().max(not_before), None, None),
+            };
+            state.logged += 1;
+            let op = SyncOp {
+                seq: state.logged,
+                device: device_id.clone(),
+                at,
+                vault,
+                platform,
+                user_id,
+                entry,
+            };
+            lines.push_str(&seal_op(channel.log_key, &op)?);
+            lines.push('\n');
+            track(&mut state, &op, row);
+            report.sent += 1;
+        }
+        if !lines.is_empty() {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&own_log)?
+                .write_all(lines.as_bytes())?;
+            write_state(&self.connection, key, &channel.state_setting, &state)?;
+        }
+
+        // Only the newest operation for each entry matters.
+        let mut newest: BTreeMap<(String, String, String), SyncOp> = BTreeMap::new();
+        for (other, path) in device_logs(folder)? {
+            if other == device_id {
+                continue;
+            }
+            let log = fs::read_to_string(path)?;
+            let replayed = state.replayed.get(&other).copied().unwrap_or(0);
+            let mut seq = replayed;
+            for line in complete_lines(&log).skip(replayed as usize) {
+                seq += 1;
+                let op = open_op(channel.log_key, line)?;
+                // Lines moved between logs or reordered within one are rejected.
+                if op.device != other || op.seq != seq {
+                    return Err(CryptoError::AuthenticationFailed.into());
+                }
+                report.received += 1;
+                let identity = (op.vault.clone(), op.platform.clone(), op.user_id.clone());
+                match newest.get(&identity) {
+                    Some(newer) if (newer.at, &newer.device) >= (op.at, &op.device) => {}
+                    _ => {
+                        newest.insert(identity, op);
+                    }
+                }
+            }
+            state.replayed.insert(other, seq);
+        }
+        let winners: Vec<SyncOp> = newest
+            .into_values()
+            .filter(|op| {
+                state
+                    .entries
+                    .iter()
+                    .find(|tracked| tracked.is(&op.vault, &op.platform, &op.user_id))
+                    .is_none_or(|tracked| (op.at, &op.device) > (tracked.at, &tracked.device))
+            })
+            .collect();
+
+        // Opening a vault may create it, which cannot happen inside the savepoint. A vault
+        // shared on this device only takes operations from its own folder.
+        for op in &winners {
+            let open = repositories.iter().any(|(vault, _)| *vault == op.vault);
+            if op.entry.is_some()
+                && !open
+                && channel.shared_vault.is_none()
+                && !vaults::is_shared(&self.connection, &op.vault)?
+            {
+                let repository = EntryRepository::unlocked(self, Some(op.vault.clone()), key)?;
+                repositories.push((op.vault.clone(), repository));
+            }
+        }
+        let transaction = Savepoint::new(&self.connection)?;
+        for op in &winners {
+            let row = match repository(&repositories, &op.vault) {
+                Ok(repository) => {
+                    let identity = (op.vault.as_str(), op.platform.as_str(), op.user_id.as_str());
+                    replay(repository, key, identity, op.entry.as_ref())?
+                }
+                // A removal from a vault this database does not have.
+                Err(_) => None,
+            };
+            track(&mut state, op, row);
+            report.applied += 1;
+        }
+        write_state(&self.connection, key, &channel.state_setting, &state)?;
+        transaction.commit()?;
+        Ok(report)
+    }
+
+    /// Repositories of the vaults `channel` carries, by the name the logs give them.
+    fn channel_repositories(
+        &self,
+        key: &SecretKey,
+        channel: &Channel<'_>,
+    ) -> Result<Vec<(String, EntryRepository<'_>)>, StorageError> {
+        match &channel.shared_vault {
+            Some(vault) => {
+                let repository = EntryRepository::unlocked(self, Some(vault.clone()), key)?;
+                Ok(vec![(String::new(), repository)])
+            }
+            None => self.sync_repositories(key),
+        }
+    }
+
+    /// Repositories of every vault that is not shared, by name.
+    fn sync_repositories(
+        &self,
+        key: &SecretKey,
+    ) -> Result<Vec<(String, EntryRepository<'_>)>, StorageError> {
+        self.vaults()?
+            .into_iter()
+            .filter(|vault| !vault.shared)
+            .map(|vault| {
+                let repository = EntryRepository::unlocked(self, Some(vault.name.clone()), key)?;
+                Ok((vault.name, repository))
+            })
+            .collect()
+    }
+}
+
+enum LocalChange<'a> {
+    Put(&'a String, &'a EntryMetadata),
+    /// Index of a tracked entry that is gone.
+    Remove(usize),
+}
+
+/// Live entries whose row or revision differ from the sync state, then tracked entries that
+/// are no longer live.
+fn local_changes<'a>(
+    state: &SyncState,
+    live: &'a [(String, EntryMetadata)],
+) -> Vec<LocalChange<'a>> {
+    let mut changes: Vec<LocalChange<'a>> = live
+        .iter()
+        .filter(|(vault, metadata)| {
+            let row = metadata.id.map(|id| (id, metadata.revision));
+            !state.entries.iter().any(|tracked| {
+                tracked.is(vault, &metadata.platform, &metadata.user_id) && tracked.row == row
+            })
+        })
+        .map(|(vault, metadata)| LocalChange::Put(vault, metadata))
+        .collect();
+    changes.extend(
+        state
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, tracked)| {
+                tracked.row.is_some()
+                    && !live.iter().any(|(vault, metadata)| {
+                        tracked.is(vault, &metadata.platform, &metadata.user_id)
+                    })
+            })
+            .map(|(index, _)| LocalChange::Remove(index)),
+    );
+    changes
+}
+
+fn live_entries(
+    repositories: &[(String, EntryRepository<'_>)],
+) -> Result<Vec<(String, EntryMetadata)>, StorageError> {
+    let mut live = Vec::new();
+    for (vault, repository) in repositories {
+        for metadata in repository.list_metadata()? {
+            live.push((vault.clone(), metadata));
+        }
+    }
+    Ok(live)
+}
+
+fn repository<'r, 'a>(
+    repositories: &'r [(String, EntryRepository<'a>)],
+    vault: &str,
+) -> Result<&'r EntryRepository<'a>, StorageError> {
+    repositories
+        .iter()
+        .find(|(name, _)| name == vault)
+        .map(|(_, repository)| repository)
+        .ok_or(StorageError::NotFound)
+}
+
+/// Records `op` as the last operation of its entry, which now has `row`.
+fn track(state: &mut SyncState, op: &SyncOp, row: Option<(i64, i64)>) {
+    let tracked = Tracked {
+        vault: op.vault.clone(),
+        platform: op.platform.clone(),
+        user_id: op.user_id.clone(),
+        row,
+        at: op.at,
+        device: op.device.clone(),
+    };
+    match state
+        .entries
+        .iter_mut()
+        .find(|tracked| tracked.is(&op.vault, &op.platform, &op.user_id))
+    {
+        Some(previous) => *previous = tracked,
+        None => state.entries.push(tracked),
+    }
+}
+
+/// Makes the entry with this identity hold `snapshot`, or moves it to the trash for `None`, and
+/// returns the row and revision it left behind.
+fn replay(
+    repository: &EntryRepository<'_>,
+    key: &SecretKey,
+    (vault, platform, user_id): (&str, &str, &str),
+    snapshot: Option<&Snapshot>,
+) -> Result<Option<(i64, i64)>, StorageError> {
+    let selector = EntrySelector::new(platform, user_id);
+    let mut local = match repository.get(key, selector) {
+        Ok(entry) => Some(entry),
+        Err(StorageError::NotFound) => None,
+        Err(error) => return Err(error),
+    };
+    let Some(snapshot) = snapshot else {
+        if local.is_some() {
+            repository.remove(selector)?;
+        }
+        return Ok(None);
+    };
+    let other = decrypted(platform, user_id, snapshot)?;
+    // Entries never change kind, so an entry of another kind with the same identity was
+    // removed and added again; the newer one replaces it outright.
+    let trashed = repository.trash()?.into_iter().find(|trashed| {
+        trashed.metadata.platform == platform && trashed.metadata.user_id == user_id
+    });
+    if local
+        .as_ref()
+        .is_some_and(|local| local.metadata.kind != snapshot.kind)
+    {
+        repository.remove(selector)?;
+        repository.purge_trashed(selector)?;
+        local = None;
+    } else if local.is_none()
+        && trashed.is_some_and(|trashed| trashed.metadata.kind != snapshot.kind)
+    {
+        repository.purge_trashed(selector)?;
+    }
+    let outcome = match &local {
+        None => MergeOutcome::Add,
+        Some(local) if merge::same_content(local, &other) => MergeOutcome::Unchanged,
+        Some(_) => MergeOutcome::TakeOther,
+    };
+    repository.merge(key, &MergeEntry::new(vault, local, other, outcome))?;
+    // Merging only ever adds tags, including those of a restored entry.
+    let mut metadata = repository.get(key, selector)?.metadata;
+    let removed: Vec<&String> = metadata
+        .tags
+        .iter()
+        .filter(|tag| !snapshot.tags.contains(tag))
+        .collect();
+    if !removed.is_empty() {
+        for tag in removed {
+            repository.remove_tag(selector, tag)?;
+        }
+        metadata = repository.get(key, selector)?.metadata;
+    }
+    Ok(metadata.id.map(|id| (id, metadata.revision)))
+}
+
+fn snapshot(entry: &DecryptedEntry) -> Snapshot {
+    let metadata = &entry.metadata;
+    Snapshot {
+        kind: metadata.kind,
+        secret: BASE64.encode(entry.secret.expose()),
+        notes: entry.details.notes.clone(),
+        fields: entry.details.fields.clone(),
+        totp: entry.totp.as_ref().map(TotpSecret::to_uri),
+        urls: metadata.urls.clone(),
+        folder: metadata.folder.clone(),
+        tags: metadata.tags.clone(),
+        pinned: metadata.pinned,
+        created_at: metadata.created_at,
+        updated_at: metadata.updated_at,
+    }
+}
+
+fn decrypted(
+    platform: &str,
+    user_id: &str,
+    snapshot: &Snapshot,
+) -> Result<DecryptedEntry, StorageError> {
+    let secret = BASE64
+        .decode(&snapshot.secret)
+        .map_err(|_| CryptoError::AuthenticationFailed)?;
+    let totp = snapshot
+        .totp
+        .as_deref()
+        .map(TotpSecret::parse)
+        .transpose()
+        .map_err(|_| CryptoError::AuthenticationFailed)?;
+    Ok(DecryptedEntry {
+        metadata: EntryMetadata {
+            id: None,
+            platform: platform.to_string(),
+            user_id: user_id.to_string(),
+            pinned: snapshot.pinned,
+            created_at: snapshot.created_at,
+            updated_at: snapshot.updated_at,
+            folder: snapshot.folder.clone(),
+            tags: snapshot.tags.clone(),
+            urls: snapshot.urls.clone(),
+            kind: snapshot.kind,
+            revision: 0,
+        },
+        secret: SecretBytes::new(secret),
+        details: EntryDetails {
+            notes: snapshot.notes.clone(),
+            fields: snapshot.fields.clone(),
+        },
+        totp,
+    })
+}
+
+fn seal_op(key: &SecretKey, op: &SyncOp) -> Result<String, StorageError> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(op).expect("operations always serialize"));
+    details::seal_bytes(key, &plaintext)
+}
+
+fn open_op(key: &SecretKey, line: &str) -> Result<SyncOp, StorageError> {
+    let plaintext = details::open_bytes(key, line)?;
+    serde_json::from_slice(plaintext.expose()).map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+/// Lines ending in a newline; a last line without one is still being written or synced.
+fn complete_lines(log: &str) -> impl Iterator<Item = &str> {
+    let end = log.rfind('\n').map_or(0, |end| end + 1);
+    log[..end].lines()
+}
+
+fn log_path(folder: &Path, device_id: &str) -> PathBuf {
+    folder.join(format!("{device_id}.{LOG_EXTENSION}"))
+}
+
+/// Device IDs and paths of the logs in `folder`, sorted by ID. Conflict copies and other files
+/// a sync tool leaves behind are skipped.
+fn device_logs(folder: &Path) -> Result<Vec<(String, PathBuf)>, StorageError> {
+    let mut logs = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        let device_id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(&format!(".{LOG_EXTENSION}")))
+            .filter(|id| is_device_id(id));
+        if let Some(device_id) = device_id {
+            logs.push((device_id.to_string(), path));
+        }
+    }
+    logs.sort();
+    Ok(logs)
+}
+
+fn new_device_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+fn is_device_id(id: &str) -> bool {
+    id.len() == 16
+        && id
+            .bytes()
+            .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+fn read_state(
+    connection: &Connection,
+    key: &SecretKey,
+    setting: &str,
+) -> Result<SyncState, StorageError> {
+    let Some(sealed) = read_meta(connection, setting)? else {
+        return Ok(SyncState::default());
+    };
+    let plaintext = details::open_bytes(key, &sealed)?;
+    serde_json::from_slice(plaintext.expose()).map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+fn write_state(
+    connection: &Connection,
+    key: &SecretKey,
+    setting: &str,
+    state: &SyncState,
+) -> Result<(), StorageError> {
+    let plaintext =
+        Zeroizing::new(serde_json::to_vec(state).expect("the sync state always serializes"));
+    write_meta(connection, setting, &details::seal_bytes(key, &plaintext)?)
+}
+
+/// Moves the sync state from the MEK `old_key` to `new_key` and drops the git sync state.
+pub(super) fn rekey_states(
+    connection: &Connection,
+    old_key: &SecretKey,
+    new_key: &SecretKey,
+) -> Result<(), StorageError> {
+    if read_meta(connection, SYNC_STATE)?.is_some() {
+        let state = read_state(connection, old_key, SYNC_STATE)?;
+        write_state(connection, new_key, SYNC_STATE, &state)?;
+    }
+    connection.execute(
+        &format!("DELETE FROM {META_TABLE} WHERE name = ?1"),
+        [git::GIT_STATE],
+    )?;
+    Ok(())
+}
+
+pub(super) fn read_meta(
+    connection: &Connection,
+    name: &str,
+) -> Result<Option<String>, StorageError> {
+    Ok(connection
+        .query_row(
+            &format!("SELECT value FROM {META_TABLE} WHERE name = ?1"),
+            [name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub(super) fn write_meta(
+    connection: &Connection,
+    name: &str,
+    value: &str,
+) -> Result<(), StorageError> {
+    connection.execute(
+        &format!("INSERT OR REPLACE INTO {META_TABLE} (name, value) VALUES (?1, ?2)"),
+        [name, value],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::repository::{EntryUpdate, NewEntry};
+
+    struct Device {
+        db: Database,
+        path: PathBuf,
+    }
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = fs::remove_file(format!("{}{suffix}", self.path.display()));
+            }
+        }
+    }
+
+    fn device(folder: &Path) -> Device {
+        let path = std::env::temp_dir().join(format!(
+            "rvault-sync-device-test-{}.db",
+            rand::random::<u64>()
+        ));
+        let db = Database::open(&path).unwrap();
+        db.set_sync_folder(folder).unwrap();
+        Device { db, path }
+    }
+
+    fn secret(db: &Database, key: &SecretKey, platform: &str) -> Option<Vec<u8>> {
+        match EntryRepository::unlocked(db, None, key)
+            .unwrap()
+            .get(key, EntrySelector::new(platform, "alice"))
+        {
+            Ok(entry) => Some(entry.secret.expose().to_vec()),
+            Err(StorageError::NotFound) => None,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    #[test]
+    fn devices_exchange_changes_through_the_folder() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let folder =
+            std::env::temp_dir().join(format!("rvault-sync-test-{}", rand::random::<u64>()));
+        let (laptop, desktop) = (device(&folder), device(&folder));
+        let ours = EntryRepository::unlocked(&laptop.db, None, &key).unwrap();
+        ours.add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        ours.add(&key, NewEntry::new("gitlab", "alice", b"gone"))
+            .unwrap();
+        ours.add_tag(EntrySelector::new("github", "alice"), "work")
+            .unwrap();
+        EntryRepository::unlocked(&laptop.db, Some("Travel".to_string()), &key)
+            .unwrap()
+            .add(&key, NewEntry::new("airline", "alice", b"miles"))
+            .unwrap();
+
+        let report = laptop.db.sync_now(&key).unwrap();
+        assert_eq!((report.sent, report.received), (3, 0));
+        let log = fs::read_to_string(log_path(&folder, &laptop.db.sync_device_id().unwrap()));
+        assert!(!log.unwrap().contains("github"));
+        let report = desktop.db.sync_now(&key).unwrap();
+        assert_eq!((report.sent, report.received, report.applied), (0, 3, 3));
+        assert_eq!(secret(&desktop.db, &key, "github").unwrap(), b"one");
+        let theirs = EntryRepository::unlocked(&desktop.db, None, &key).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        assert_eq!(theirs.get(&key, github).unwrap().metadata.tags, ["work"]);
+
+        // Replayed entries are not sent back.
+        assert_eq!(desktop.db.sync_status(&key).unwrap().pending, 0);
+        assert_eq!(desktop.db.sync_now(&key).unwrap(), SyncReport::default());
+
+        theirs
+            .update(&key, github, EntryUpdate::new("alice", b"two"))
+            .unwrap();
+        theirs.remove_tag(github, "work").unwrap();
+        theirs
+            .remove(EntrySelector::new("gitlab", "alice"))
+            .unwrap();
+        let status = desktop.db.sync_status(&key).unwrap();
+        assert_eq!(status.pending, 2);
+        assert_eq!(status.devices.len(), 1);
+        assert_eq!(status.devices[0].operations, status.devices[0].replayed);
+        assert_eq!(desktop.db.sync_now(&key).unwrap().sent, 2);
+        assert_eq!(
+            laptop.db.sync_status(&key).unwrap().devices[0].operations,
+            2
+        );
+
+        assert_eq!(laptop.db.sync_now(&key).unwrap().applied, 2);
+        assert_eq!(secret(&laptop.db, &key, "github").unwrap(), b"two");
+        assert_eq!(secret(&laptop.db, &key, "gitlab"), None);
+        let github_entry = ours.get(&key, github).unwrap();
+        assert!(github_entry.metadata.tags.is_empty());
+        assert_eq!(
+            ours.history(&key, github).unwrap()[0].secret.expose(),
+            b"one"
+        );
+        assert_eq!(laptop.db.sync_status(&key).unwrap().pending, 0);
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn concurrent_changes_resolve_the_same_way_on_every_device() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let folder =
+            std::env::temp_dir().join(format!("rvault-sync-test-{}", rand::random::<u64>()));
+        let (laptop, desktop) = (device(&folder), device(&folder));
+        EntryRepository::unlocked(&laptop.db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "alice", b"laptop"))
+            .unwrap();
+        EntryRepository::unlocked(&desktop.db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "alice", b"desktop"))
+            .unwrap();
+        // Both changes carry the same time, so the device IDs decide.
+        for device in [&laptop, &desktop] {
+            device
+                .db
+                .connection
+                .execute("UPDATE main SET updated_at = 100", [])
+                .unwrap();
+        }
+
+        laptop.db.sync_now(&key).unwrap();
+        desktop.db.sync_now(&key).unwrap();
+        laptop.db.sync_now(&key).unwrap();
+        let winner = if laptop.db.sync_device_id().unwrap() > desktop.db.sync_device_id().unwrap() {
+            b"laptop".as_slice()
+        } else {
+            b"desktop".as_slice()
+        };
+        assert_eq!(secret(&laptop.db, &key, "github").unwrap(), winner);
+        assert_eq!(secret(&desktop.db, &key, "github").unwrap(), winner);
+        for device in [&laptop, &desktop] {
+            assert_eq!(device.db.sync_status(&key).unwrap().pending, 0);
+        }
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn a_copied_database_continues_under_a_new_device_id() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let folder =
+            std::env::temp_dir().join(format!("rvault-sync-test-{}", rand::random::<u64>()));
+        let original = device(&folder);
+        EntryRepository::unlocked(&original.db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        original.db.sync_now(&key).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "rvault-sync-device-test-{}.db",
+            rand::random::<u64>()
+        ));
+        original
+            .db
+            .connection
+            .execute("VACUUM INTO ?1", [path.to_str().unwrap()])
+            .unwrap();
+        let copy = Device {
+            db: Database::open(&path).unwrap(),
+            path,
+        };
+        let device_id = original.db.sync_device_id().unwrap();
+        assert_eq!(copy.db.sync_device_id().unwrap(), device_id);
+
+        EntryRepository::unlocked(&original.db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("gitlab", "alice", b"two"))
+            .unwrap();
+        original.db.sync_now(&key).unwrap();
+        assert_eq!(copy.db.sync_now(&key).unwrap().applied, 1);
+        assert_ne!(copy.db.sync_device_id().unwrap(), device_id);
+        assert_eq!(secret(&copy.db, &key, "gitlab").unwrap(), b"two");
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn the_master_key_rotates_once_sync_stops_and_sync_carries_on() {
+        let (key, new_key) = (
+            SecretKey::from_bytes([7; 32]),
+            SecretKey::from_bytes([8; 32]),
+        );
+        let folder =
+            std::env::temp_dir().join(format!("rvault-sync-test-{}", rand::random::<u64>()));
+        let laptop = device(&folder);
+        EntryRepository::unlocked(&laptop.db, None, &key)
+            .unwrap()
+            .add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        laptop.db.sync_now(&key).unwrap();
+
+        assert!(matches!(
+            laptop
+                .db
+                .reencrypt_entries(&key, &new_key, "fingerprint", || Ok(())),
+            Err(StorageError::SyncConfigured)
+        ));
+        laptop.db.stop_sync().unwrap();
+        assert_eq!(laptop.db.sync_folder().unwrap(), None);
+        laptop
+            .db
+            .reencrypt_entries(&key, &new_key, "fingerprint", || Ok(()))
+            .unwrap();
+
+        let new_folder =
+            std::env::temp_dir().join(format!("rvault-sync-test-{}", rand::random::<u64>()));
+        laptop.db.set_sync_folder(&new_folder).unwrap();
+        assert_eq!(laptop.db.sync_status(&new_key).unwrap().pending, 0);
+        EntryRepository::unlocked(&laptop.db, None, &new_key)
+            .unwrap()
+            .add(&new_key, NewEntry::new("gitlab", "alice", b"two"))
+            .unwrap();
+        assert_eq!(laptop.db.sync_now(&new_key).unwrap().sent, 1);
+        fs::remove_dir_all(folder).unwrap();
+        fs::remove_dir_all(new_folder).unwrap();
+    }
+}
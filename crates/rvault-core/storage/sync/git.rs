@@ -0,0 +1,728 @@
+//! Syncing through a git repository.
+//!
+//! Every entry is one file, `<vault>/<entry>.entry`, named after keyed hashes of the vault name
+//! and of the entry's identity, so the repository never sees either. A file holds a format
+//! line and the entry sealed with the MEK on the next. Each sync rebases the local changes onto
+//! the remote branch in a checkout of its own and pushes them, which leaves the history of
+//! every entry on the remote.
+//!
+//! The commit a database last synced with is the common ancestor of both sides. An entry only
+//! the remote changed since is replayed like an operation of another device, and one only this
+//! database changed is written. When both changed it, the one updated last wins and the other
+//! secret joins the entry's history. A removal loses against a change on the other side.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use super::{
+    Snapshot, decrypted, live_entries, read_meta, replay, repository, snapshot, write_meta,
+};
+use crate::crypto::CryptoError;
+use crate::secret::SecretKey;
+use crate::storage::batch::Savepoint;
+use crate::storage::merge::{self, MergeEntry, MergeOutcome};
+use crate::storage::repository::{EntryMetadata, EntrySelector};
+use crate::storage::{Database, EntryRepository, StorageError};
+use crate::storage::{details, vaults};
+
+pub(super) const GIT_REMOTE: &str = "git_sync_remote";
+pub(super) const GIT_STATE: &str = "git_sync_state";
+const BRANCH: &str = "main";
+const FILE_HEADER: &str = "rvault-entry-v1";
+const FILE_EXTENSION: &str = ".entry";
+const PATH_KEY_INFO: &[u8] = b"rvault-git-sync-paths-v1";
+/// Syncs retried when another device pushed between fetching and pushing.
+const PUSH_ATTEMPTS: usize = 3;
+
+/// What [`Database::sync_git`] did.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct GitSyncReport {
+    /// Entries changed or removed from the remote.
+    pub pulled: usize,
+    /// Entry files written or deleted by the pushed commit.
+    pub pushed: usize,
+    /// Entries both sides changed since the last sync.
+    pub conflicts: usize,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct GitState {
+    /// Commit of the last sync.
+    commit: Option<String>,
+    /// Row and revision of the entry behind each file of that commit.
+    entries: BTreeMap<String, (i64, i64)>,
+}
+
+/// The sealed content of an entry file.
+#[derive(Serialize, Deserialize)]
+struct EntryFile {
+    vault: String,
+    platform: String,
+    user_id: String,
+    entry: Snapshot,
+}
+
+impl EntryFile {
+    fn identity(&self) -> (&str, &str, &str) {
+        (&self.vault, &self.platform, &self.user_id)
+    }
+}
+
+impl Database {
+    /// The repository [`Database::sync_git`] pushes to, once one was set.
+    pub fn git_sync_remote(&self) -> Result<Option<String>, StorageError> {
+        self.vault_tables()?;
+        read_meta(&self.connection, GIT_REMOTE)
+    }
+
+    /// Changes the repository to sync with, given as a path or any URL git understands.
+    pub fn set_git_sync_remote(&self, remote: &str) -> Result<(), StorageError> {
+        self.vault_tables()?;
+        write_meta(&self.connection, GIT_REMOTE, remote)
+    }
+
+    /// Pulls the entry files of the remote repository into the database, then commits the
+    /// local changes on top of them in `checkout` and pushes them. The checkout is created
+    /// when missing and must not be used for anything else. Fails with
+    /// [`StorageError::NotFound`] when no remote is set.
+    pub fn sync_git(
+        &self,
+        key: &SecretKey,
+        checkout: &Path,
+    ) -> Result<GitSyncReport, StorageError> {
+        let remote = self.git_sync_remote()?.ok_or(StorageError::NotFound)?;
+        let git = Git::open(checkout, &remote)?;
+        let paths = EntryPaths::new(key)?;
+        for _ in 1..PUSH_ATTEMPTS {
+            if let Some(report) = self.sync_git_once(key, &git, &paths)? {
+                return Ok(report);
+            }
+        }
+        self.sync_git_once(key, &git, &paths)?
+            .ok_or_else(|| StorageError::Git {
+                command: "push".to_string(),
+                message: "the remote kept changing while the vault was pushed".to_string(),
+            })
+    }
+
+    /// One sync; `None` when another device pushed first.
+    fn sync_git_once(
+        &self,
+        key: &SecretKey,
+        git: &Git<'_>,
+        paths: &EntryPaths,
+    ) -> Result<Option<GitSyncReport>, StorageError> {
+        // Pruning drops the branch of a previous remote when the new one is still empty.
+        git.run(&["fetch", "--quiet", "--prune", "origin"])?;
+        let head = git.remote_head()?;
+        git.start_from(head.as_deref())?;
+        let mut state = read_state(self, key)?;
+        let base = match &state.commit {
+            Some(commit) if git.has_commit(commit)? => git.tree(commit)?,
+            _ => BTreeMap::new(),
+        };
+        let remote = match &head {
+            Some(head) => git.tree(head)?,
+            None => BTreeMap::new(),
+        };
+        let mut repositories = self.sync_repositories(key)?;
+        let live: BTreeMap<String, (String, EntryMetadata)> = live_entries(&repositories)?
+            .into_iter()
+            .map(|(vault, metadata)| {
+                let path = paths.path(&vault, &metadata.platform, &metadata.user_id);
+                (path, (vault, metadata))
+            })
+            .collect();
+
+        let mut report = GitSyncReport::default();
+        let mut writes = Vec::new();
+        let mut deletes = Vec::new();
+        let mut replays: Vec<EntryFile> = Vec::new();
+        let mut removals = Vec::new();
+        let mut both = Vec::new();
+        let all: BTreeSet<&String> = base
+            .keys()
+            .chain(remote.keys())
+            .chain(live.keys())
+            .chain(state.entries.keys())
+            .collect();
+        for path in all {
+            let remote_blob = remote.get(path);
+            let local = live.get(path);
+            let row = local.and_then(|(_, metadata)| row(metadata));
+            let remote_changed = base.get(path) != remote_blob;
+            let local_changed = row != state.entries.get(path).copied();
+            match (remote_changed, local_changed, remote_blob, local) {
+                (false, false, _, _) | (_, _, None, None) => {}
+                (false, true, _, Some(_)) | (true, true, None, Some(_)) => {
+                    writes.push(path.clone())
+                }
+                (false, true, Some(_), None) => deletes.push(path.clone()),
+                (true, _, Some(blob), None) | (true, false, Some(blob), Some(_)) => {
+                    replays.push(read_file(git, key, paths, path, blob)?)
+                }
+                (true, false, None, Some((vault, metadata))) => {
+                    removals.push((vault, metadata));
+                }
+                (true, true, Some(blob), Some(_)) => {
+                    both.push((path.clone(), read_file(git, key, paths, path, blob)?))
+                }
+            }
+        }
+
+        // Opening a vault may create it, which cannot happen inside the savepoint. A vault
+        // shared on this device only takes changes from its own folder.
+        let mut kept = Vec::new();
+        for file in replays {
+            if !vaults::is_shared(&self.connection, &file.vault)? {
+                kept.push(file);
+            }
+        }
+        let replays = kept;
+        for file in &replays {
+            if repository(&repositories, &file.vault).is_err() {
+                let repository = EntryRepository::unlocked(self, Some(file.vault.clone()), key)?;
+                repositories.push((file.vault.clone(), repository));
+            }
+        }
+        let transaction = Savepoint::new(&self.connection)?;
+        for file in &replays {
+            let repository = repository(&repositories, &file.vault)?;
+            replay(repository, key, file.identity(), Some(&file.entry))?;
+            report.pulled += 1;
+        }
+        for (vault, metadata) in removals {
+            let identity = (
+                vault.as_str(),
+                metadata.platform.as_str(),
+                metadata.user_id.as_str(),
+            );
+            replay(repository(&repositories, vault)?, key, identity, None)?;
+            report.pulled += 1;
+        }
+        for (path, file) in both {
+            let repository = repository(&repositories, &file.vault)?;
+            let selector = EntrySelector::new(&file.platform, &file.user_id);
+            let local = repository.get(key, selector)?;
+            let other = decrypted(&file.platform, &file.user_id, &file.entry)?;
+            if merge::same_content(&local, &other) {
+                continue;
+            }
+            report.conflicts += 1;
+            if other.metadata.updated_at > local.metadata.updated_at {
+                replay(repository, key, file.identity(), Some(&file.entry))?;
+                report.pulled += 1;
+            } else {
+                let entry =
+                    MergeEntry::new(&file.vault, Some(local), other, MergeOutcome::KeepLocal);
+                repository.merge(key, &entry)?;
+                writes.push(path);
+            }
+        }
+        transaction.commit()?;
+
+        for path in &writes {
+            let (vault, metadata) = &live[path];
+            let entry = repository(&repositories, vault)?.get(
+                key,
+                EntrySelector::new(&metadata.platform, &metadata.user_id),
+            )?;
+            let file = EntryFile {
+                vault: vault.clone(),
+                platform: metadata.platform.clone(),
+                user_id: metadata.user_id.clone(),
+                entry: snapshot(&entry),
+            };
+            git.write(path, &seal_file(key, &file)?)?;
+        }
+        for path in &deletes {
+            git.delete(path)?;
+        }
+        report.pushed = writes.len() + deletes.len();
+        let mut commit = head.clone();
+        if report.pushed > 0 {
+            let message = format!(
+                "Sync {} entries from device {}",
+                report.pushed,
+                self.sync_device_id()?
+            );
+            commit = Some(git.commit(&message)?);
+            if !git.push()? {
+                git.run(&["fetch", "--quiet", "--prune", "origin"])?;
+                if git.remote_head()? != head {
+                    return Ok(None);
+                }
+                return Err(StorageError::Git {
+                    command: "push".to_string(),
+                    message: "the remote rejected the commit".to_string(),
+                });
+            }
+        }
+
+        state.commit = commit;
+        state.entries = live_entries(&repositories)?
+            .into_iter()
+            .filter_map(|(vault, metadata)| {
+                let path = paths.path(&vault, &metadata.platform, &metadata.user_id);
+                row(&metadata).map(|row| (path, row))
+            })
+            .collect();
+        write_state(self, key, &state)?;
+        Ok(Some(report))
+    }
+}
+
+fn row(metadata: &EntryMetadata) -> Option<(i64, i64)> {
+    metadata.id.map(|id| (id, metadata.revision))
+}
+
+/// File names derived from the MEK, the same on every device sharing it.
+struct EntryPaths {
+    key: SecretKey,
+}
+
+impl EntryPaths {
+    fn new(key: &SecretKey) -> Result<Self, StorageError> {
+        let mut path_key = Zeroizing::new([0_u8; 32]);
+        Hkdf::<Sha256>::new(None, key.as_bytes())
+            .expand(PATH_KEY_INFO, path_key.as_mut())
+            .map_err(|error| CryptoError::KeyDerivation(error.to_string()))?;
+        Ok(Self {
+            key: SecretKey::from_bytes(*path_key),
+        })
+    }
+
+    fn path(&self, vault: &str, platform: &str, user_id: &str) -> String {
+        let vault_hash = self.hash(&[vault]);
+        format!(
+            "{}/{}{FILE_EXTENSION}",
+            &vault_hash[..32],
+            self.hash(&[vault, platform, user_id])
+        )
+    }
+
+    fn hash(&self, parts: &[&str]) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.key.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        for part in parts {
+            mac.update(part.as_bytes());
+            mac.update(&[0]);
+        }
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+fn seal_file(key: &SecretKey, file: &EntryFile) -> Result<String, StorageError> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(file).expect("entry files always serialize"));
+    Ok(format!(
+        "{FILE_HEADER}\n{}\n",
+        details::seal_bytes(key, &plaintext)?
+    ))
+}
+
+/// Reads the entry file `blob` found at `path`. A file whose content belongs at another path
+/// was moved or copied there and is rejected.
+fn read_file(
+    git: &Git<'_>,
+    key: &SecretKey,
+    paths: &EntryPaths,
+    path: &str,
+    blob: &str,
+) -> Result<EntryFile, StorageError> {
+    let content = String::from_utf8(git.run(&["cat-file", "blob", blob])?)
+        .map_err(|_| CryptoError::AuthenticationFailed)?;
+    let mut lines = content.lines();
+    let (Some(FILE_HEADER), Some(sealed)) = (lines.next(), lines.next()) else {
+        return Err(CryptoError::AuthenticationFailed.into());
+    };
+    let plaintext = details::open_bytes(key, sealed)?;
+    let file: EntryFile = serde_json::from_slice(plaintext.expose())
+        .map_err(|_| CryptoError::AuthenticationFailed)?;
+    if paths.path(&file.vault, &file.platform, &file.user_id) != path {
+        return Err(CryptoError::AuthenticationFailed.into());
+    }
+    Ok(file)
+}
+
+fn read_state(db: &Database, key: &SecretKey) -> Result<GitState, StorageError> {
+    let Some(sealed) = read_meta(&db.connection, GIT_STATE)? else {
+        return Ok(GitState::default());
+    };
+    let plaintext = details::open_bytes(key, &sealed)?;
+    serde_json::from_slice(plaintext.expose()).map_err(|_| CryptoError::AuthenticationFailed.into())
+}
+
+fn write_state(db: &Database, key: &SecretKey, state: &GitState) -> Result<(), StorageError> {
+    let plaintext =
+        Zeroizing::new(serde_json::to_vec(state).expect("the git sync state always serializes"));
+    write_meta(
+        &db.connection,
+        GIT_STATE,
+        &details::seal_bytes(key, &plaintext)?,
+    )
+}
+
+/// The git command line, run in the sync checkout.
+struct Git<'a> {
+    checkout: &'a Path,
+}
+
+impl<'a> Git<'a> {
+    /// Opens the checkout, creating it with `remote` as `origin` when missing.
+    fn open(checkout: &'a Path, remote: &str) -> Result<Self, StorageError> {
+        let git = Self { checkout };
+        if checkout.join(".git").is_dir() {
+            git.run(&["remote", "set-url", "origin", remote])?;
+        } else {
+            fs::create_dir_all(checkout)?;
+            git.run(&["init", "--quiet"])?;
+            git.run(&["remote", "add", "origin", remote])?;
+        }
+        Ok(git)
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command
+            .arg("-C")
+            .arg(self.checkout)
+            .args(args)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .stdin(Stdio::null());
+        command
+    }
+
+    /// Runs git and returns its output.
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>, StorageError> {
+        let output = self.command(args).output()?;
+        if !output.status.success() {
+            return Err(StorageError::Git {
+                command: args
+                    .iter()
+                    .find(|arg| !arg.starts_with('-') && !arg.contains('='))
+                    .map_or_else(String::new, |command| command.to_string()),
+                message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    fn succeeds(&self, args: &[&str]) -> Result<bool, StorageError> {
+        Ok(self
+            .command(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?
+            .success())
+    }
+
+    fn remote_head(&self) -> Result<Option<String>, StorageError> {
+        let branch = format!("refs/remotes/origin/{BRANCH}");
+        if !self.succeeds(&["rev-parse", "--verify", "--quiet", &branch])? {
+            return Ok(None);
+        }
+        self.rev_parse(&branch).map(Some)
+    }
+
+    fn has_commit(&self, commit: &str) -> Result<bool, StorageError> {
+        self.succeeds(&["cat-file", "-e", &format!("{commit}^{{commit}}")])
+    }
+
+    fn rev_parse(&self, rev: &str) -> Result<String, StorageError> {
+        let output = self.run(&["rev-parse", rev])?;
+        Ok(String::from_utf8_lossy(&output).trim().to_string())
+    }
+
+    /// Resets the branch and the files of the checkout to `head`, or to nothing at all before
+    /// the first push.
+    fn start_from(&self, head: Option<&str>) -> Result<(), StorageError> {
+        match head {
+            Some(head) => {
+                self.run(&["checkout", "--quiet", "--force", "-B", BRANCH, head])?;
+            }
+            None => {
+                self.run(&["symbolic-ref", "HEAD", &format!("refs/heads/{BRANCH}")])?;
+                self.run(&["update-ref", "-d", &format!("refs/heads/{BRANCH}")])?;
+                self.run(&["read-tree", "--empty"])?;
+            }
+        }
+        self.run(&["clean", "--quiet", "--force", "-d", "-x"])?;
+        Ok(())
+    }
+
+    /// Entry files of `commit` by path, with their blob IDs.
+    fn tree(&self, commit: &str) -> Result<BTreeMap<String, String>, StorageError> {
+        let output = self.run(&["ls-tree", "-r", "-z", commit])?;
+        let mut files = BTreeMap::new();
+        for record in output.split(|byte| *byte == 0) {
+            let record = String::from_utf8_lossy(record);
+            let Some((info, path)) = record.split_once('\t') else {
+                continue;
+            };
+            let Some(blob) = info.split(' ').nth(2) else {
+                continue;
+            };
+            if path.ends_with(FILE_EXTENSION) {
+                files.insert(path.to_string(), blob.to_string());
+            }
+        }
+        Ok(files)
+    }
+
+    fn write(&self, path: &str, content: &str) -> Result<(), StorageError> {
+        let path = self.checkout.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let path = self.checkout.join(path);
+        match fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+        // Git does not keep empty folders; a vault folder left empty goes as well.
+        if let Some(parent) = path.parent() {
+            let _ = fs::remove_dir(parent);
+        }
+        Ok(())
+    }
+
+    /// Commits every change of the checkout and returns the new commit.
+    fn commit(&self, message: &str) -> Result<String, StorageError> {
+        self.run(&["add", "--all"])?;
+        self.run(&[
+            "-c",
+            "user.name=RVault",
+            "-c",
+            "user.email=rvault@localhost",
+            "-c",
+            "commit.gpgsign=false",
+            "commit",
+            "--quiet",
+            "--message",
+            message,
+        ])?;
+        self.rev_parse("HEAD")
+    }
+
+    /// Pushes the branch; `false` when the remote rejected it.
+    fn push(&self) -> Result<bool, StorageError> {
+        self.succeeds(&["push", "--quiet", "origin", BRANCH])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::repository::{EntryUpdate, NewEntry};
+    use rusqlite::Connection;
+    use std::path::PathBuf;
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rvault-git-{name}-test-{}", rand::random::<u64>()))
+    }
+
+    fn secret(db: &Database, key: &SecretKey, platform: &str) -> Option<Vec<u8>> {
+        match EntryRepository::unlocked(db, None, key)
+            .unwrap()
+            .get(key, EntrySelector::new(platform, "alice"))
+        {
+            Ok(entry) => Some(entry.secret.expose().to_vec()),
+            Err(StorageError::NotFound) => None,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    fn commits(remote: &Path) -> usize {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(remote)
+            .args(["rev-list", "--count", BRANCH])
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn git_failures_name_the_command() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let db = memory_db();
+        let checkout = temp_path("checkout");
+        assert!(matches!(
+            db.sync_git(&key, &checkout),
+            Err(StorageError::NotFound)
+        ));
+        let missing = temp_path("missing");
+        db.set_git_sync_remote(missing.to_str().unwrap()).unwrap();
+        match db.sync_git(&key, &checkout) {
+            Err(StorageError::Git { command, .. }) => assert_eq!(command, "fetch"),
+            other => panic!("unexpected result: {other:?}"),
+        }
+        fs::remove_dir_all(checkout).unwrap();
+    }
+
+    #[test]
+    fn devices_sync_entries_through_a_bare_repository() {
+        let key = SecretKey::from_bytes([7; 32]);
+        let remote = temp_path("remote");
+        let status = Command::new("git")
+            .args(["init", "--quiet", "--bare"])
+            .arg(&remote)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let (laptop, desktop) = (memory_db(), memory_db());
+        let checkouts = [temp_path("laptop"), temp_path("desktop")];
+        for db in [&laptop, &desktop] {
+            db.set_git_sync_remote(remote.to_str().unwrap()).unwrap();
+        }
+        let ours = EntryRepository::unlocked(&laptop, None, &key).unwrap();
+        ours.add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        ours.add(&key, NewEntry::new("gitlab", "alice", b"gone"))
+            .unwrap();
+
+        let report = laptop.sync_git(&key, &checkouts[0]).unwrap();
+        assert_eq!(report.pushed, 2);
+        let files = Git {
+            checkout: &checkouts[0],
+        }
+        .tree("HEAD")
+        .unwrap();
+        assert_eq!(files.len(), 2);
+        for path in files.keys() {
+            let content = fs::read_to_string(checkouts[0].join(path)).unwrap();
+            assert!(content.starts_with(FILE_HEADER));
+            assert!(!content.contains("github") && !path.contains("github"));
+        }
+        assert_eq!(
+            laptop.sync_git(&key, &checkouts[0]).unwrap(),
+            GitSyncReport::default()
+        );
+
+        assert_eq!(desktop.sync_git(&key, &checkouts[1]).unwrap().pulled, 2);
+        assert_eq!(secret(&desktop, &key, "github").unwrap(), b"one");
+        let theirs = EntryRepository::unlocked(&desktop, None, &key).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        theirs
+            .update(&key, github, EntryUpdate::new("alice", b"two"))
+            .unwrap();
+        ours.remove(EntrySelector::new("gitlab", "alice")).unwrap();
+        assert_eq!(desktop.sync_git(&key, &checkouts[1]).unwrap().pushed, 1);
+        let report = laptop.sync_git(&key, &checkouts[0]).unwrap();
+        assert_eq!((report.pulled, report.pushed), (1, 1));
+        assert_eq!(secret(&laptop, &key, "github").unwrap(), b"two");
+        desktop.sync_git(&key, &checkouts[1]).unwrap();
+        assert_eq!(secret(&desktop, &key, "gitlab"), None);
+
+        // Both change the same entry; the later change wins on both and keeps the other
+        // secret in the history.
+        ours.update(&key, github, EntryUpdate::new("alice", b"laptop"))
+            .unwrap();
+        theirs
+            .update(&key, github, EntryUpdate::new("alice", b"desktop"))
+            .unwrap();
+        laptop
+            .connection
+            .execute("UPDATE main SET updated_at = 100", [])
+            .unwrap();
+        desktop
+            .connection
+            .execute("UPDATE main SET updated_at = 200", [])
+            .unwrap();
+        laptop.sync_git(&key, &checkouts[0]).unwrap();
+        let report = desktop.sync_git(&key, &checkouts[1]).unwrap();
+        assert_eq!((report.conflicts, report.pushed), (1, 1));
+        laptop.sync_git(&key, &checkouts[0]).unwrap();
+        for db in [&laptop, &desktop] {
+            assert_eq!(secret(db, &key, "github").unwrap(), b"desktop");
+        }
+        let history = ours.history(&key, github).unwrap();
+        assert_eq!(history[0].secret.expose(), b"laptop");
+        assert_eq!(commits(&remote), 5);
+
+        for path in checkouts.iter().chain([&remote]) {
+            fs::remove_dir_all(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_rotated_key_pushes_every_entry_to_a_new_remote() {
+        let (key, new_key) = (
+            SecretKey::from_bytes([7; 32]),
+            SecretKey::from_bytes([8; 32]),
+        );
+        let remotes = [temp_path("remote"), temp_path("remote")];
+        for remote in &remotes {
+            let status = Command::new("git")
+                .args(["init", "--quiet", "--bare"])
+                .arg(remote)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        let (laptop, desktop) = (memory_db(), memory_db());
+        let checkouts = [temp_path("laptop"), temp_path("desktop")];
+        let ours = EntryRepository::unlocked(&laptop, None, &key).unwrap();
+        ours.add(&key, NewEntry::new("github", "alice", b"one"))
+            .unwrap();
+        ours.add(&key, NewEntry::new("gitlab", "alice", b"two"))
+            .unwrap();
+        laptop
+            .set_git_sync_remote(remotes[0].to_str().unwrap())
+            .unwrap();
+        assert_eq!(laptop.sync_git(&key, &checkouts[0]).unwrap().pushed, 2);
+
+        assert!(matches!(
+            laptop.reencrypt_entries(&key, &new_key, "fingerprint", || Ok(())),
+            Err(StorageError::SyncConfigured)
+        ));
+        laptop.stop_sync().unwrap();
+        assert_eq!(laptop.git_sync_remote().unwrap(), None);
+        laptop
+            .reencrypt_entries(&key, &new_key, "fingerprint", || Ok(()))
+            .unwrap();
+        assert_eq!(read_meta(&laptop.connection, GIT_STATE).unwrap(), None);
+
+        for db in [&laptop, &desktop] {
+            db.set_git_sync_remote(remotes[1].to_str().unwrap())
+                .unwrap();
+        }
+        // The checkout stays and follows the new remote.
+        assert_eq!(laptop.sync_git(&new_key, &checkouts[0]).unwrap().pushed, 2);
+        assert_eq!(commits(&remotes[1]), 1);
+        assert_eq!(desktop.sync_git(&new_key, &checkouts[1]).unwrap().pulled, 2);
+        assert_eq!(secret(&desktop, &new_key, "github").unwrap(), b"one");
+        for path in remotes.iter().chain(&checkouts) {
+            fs::remove_dir_all(path).unwrap();
+        }
+    }
+}
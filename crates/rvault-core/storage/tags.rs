@@ -0,0 +1,236 @@
+//! Entry tags and folders.
+//!
+//! Tags live in the database-wide tags table, one row per entry and tag. A folder is a
+//! `/`-separated path kept in the entry's `folder` column. In tables with encrypted metadata the
+//! `tag` column holds a blind index with the tag itself sealed next to it, and the folder column
+//! holds a sealed path, so neither can be read without the MEK.
+
+use std::collections::HashMap;
+
+use rusqlite::{Connection, Transaction, params};
+
+use super::metadata::MetadataCipher;
+use super::migration::TAGS_TABLE;
+use super::{StorageError, Table};
+use crate::crypto::CryptoError;
+
+pub(super) const FOLDER_COLUMN: &str = "folder";
+const TAG_FIELD: &str = "tag";
+
+/// Trims a folder path and drops empty segments, so `/Work//Servers/` becomes `Work/Servers`.
+/// Returns `None` for the top level.
+pub(super) fn normalize_folder(path: &str) -> Option<String> {
+    let segments: Vec<&str> = path
+        .split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    (!segments.is_empty()).then(|| segments.join("/"))
+}
+
+/// Whether `folder` is `parent` or nested inside it.
+pub(super) fn is_within(folder: Option<&str>, parent: &str) -> bool {
+    let Some(parent) = normalize_folder(parent) else {
+        return true;
+    };
+    folder.is_some_and(|folder| {
+        folder == parent
+            || folder
+                .strip_prefix(parent.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+impl Table {
+    /// Column value to write for a folder path.
+    pub(super) fn stored_folder(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        folder: Option<&str>,
+    ) -> Result<Option<String>, StorageError> {
+        self.stored_value(cipher, FOLDER_COLUMN, folder.and_then(normalize_folder))
+    }
+
+    /// `tag` and `sealed` column values for a tag.
+    pub(super) fn stored_tag(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        tag: &str,
+    ) -> Result<(String, Option<String>), StorageError> {
+        if !self.encrypted_metadata {
+            return Ok((tag.to_string(), None));
+        }
+        let cipher = cipher.ok_or(StorageError::MetadataLocked)?;
+        Ok((
+            cipher.tag_index(tag),
+            Some(cipher.seal_value(TAG_FIELD, tag)?),
+        ))
+    }
+
+    /// Tags of every entry in the table by row id, each list sorted.
+    pub(super) fn read_tags(
+        &self,
+        connection: &Connection,
+        cipher: Option<&MetadataCipher>,
+    ) -> Result<HashMap<i64, Vec<String>>, StorageError> {
+        let rows: Vec<(i64, String, Option<String>)> = {
+            let mut statement = connection.prepare(&format!(
+                "SELECT entry_id, tag, sealed FROM {TAGS_TABLE} WHERE entry_table = ?1"
+            ))?;
+            statement
+                .query_map([&self.table_name], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<Result<_, _>>()?
+        };
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for (id, tag, sealed) in rows {
+            tags.entry(id)
+                .or_default()
+                .push(self.open_tag(cipher, tag, sealed)?);
+        }
+        for list in tags.values_mut() {
+            list.sort();
+        }
+        Ok(tags)
+    }
+
+    /// Tags of one entry, sorted. Only that entry's rows are read and decrypted.
+    pub(super) fn read_tags_for(
+        &self,
+        connection: &Connection,
+        cipher: Option<&MetadataCipher>,
+        entry_id: i64,
+    ) -> Result<Vec<String>, StorageError> {
+        let rows: Vec<(String, Option<String>)> = {
+            let mut statement = connection.prepare(&format!(
+                "SELECT tag, sealed FROM {TAGS_TABLE} WHERE entry_table = ?1 AND entry_id = ?2"
+            ))?;
+            statement
+                .query_map(params![self.table_name, entry_id], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?
+                .collect::<Result<_, _>>()?
+        };
+        let mut tags = rows
+            .into_iter()
+            .map(|(tag, sealed)| self.open_tag(cipher, tag, sealed))
+            .collect::<Result<Vec<_>, _>>()?;
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// The tag stored in a `tag` and `sealed` column pair.
+    fn open_tag(
+        &self,
+        cipher: Option<&MetadataCipher>,
+        tag: String,
+        sealed: Option<String>,
+    ) -> Result<String, StorageError> {
+        if !self.encrypted_metadata {
+            return Ok(tag);
+        }
+        let sealed = sealed.ok_or(CryptoError::AuthenticationFailed)?;
+        cipher
+            .ok_or(StorageError::MetadataLocked)?
+            .open_value(TAG_FIELD, &sealed)
+    }
+}
+
+pub(super) fn add(
+    connection: &Connection,
+    table: &str,
+    entry_id: i64,
+    (tag, sealed): (String, Option<String>),
+) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!(
+            "INSERT OR IGNORE INTO {TAGS_TABLE} (entry_table, entry_id, tag, sealed) VALUES (?1, ?2, ?3, ?4)"
+        ),
+        params![table, entry_id, tag, sealed],
+    )?;
+    Ok(())
+}
+
+pub(super) fn remove(
+    connection: &Connection,
+    table: &str,
+    entry_id: i64,
+    tag: &str,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!("DELETE FROM {TAGS_TABLE} WHERE entry_table = ?1 AND entry_id = ?2 AND tag = ?3"),
+        params![table, entry_id, tag],
+    )?;
+    Ok(())
+}
+
+/// Drops every tag of an entry.
+pub(super) fn forget(connection: &Connection, table: &str, entry_id: i64) -> rusqlite::Result<()> {
+    connection.execute(
+        &format!("DELETE FROM {TAGS_TABLE} WHERE entry_table = ?1 AND entry_id = ?2"),
+        params![table, entry_id],
+    )?;
+    Ok(())
+}
+
+/// Rewrites a table's tags for `new` metadata keys in place, so entry revisions are kept. With
+/// `old` set to `None` the stored values are plaintext, as when a table is first converted to
+/// encrypted metadata.
+pub(super) fn reseal_table(
+    transaction: &Transaction<'_>,
+    table: &str,
+    old: Option<&MetadataCipher>,
+    new: &MetadataCipher,
+) -> Result<(), StorageError> {
+    let tags: Vec<(i64, String, Option<String>)> = {
+        let mut statement = transaction.prepare(&format!(
+            "SELECT entry_id, tag, sealed FROM {TAGS_TABLE} WHERE entry_table = ?1"
+        ))?;
+        statement
+            .query_map([table], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?
+    };
+    let update = format!(
+        "UPDATE {TAGS_TABLE} SET tag = ?1, sealed = ?2 WHERE entry_table = ?3 AND entry_id = ?4 AND tag = ?5"
+    );
+    for (id, stored, sealed) in tags {
+        let tag = match old {
+            Some(old) => {
+                let sealed = sealed.ok_or(CryptoError::AuthenticationFailed)?;
+                old.open_value(TAG_FIELD, &sealed)?
+            }
+            None => stored.clone(),
+        };
+        transaction.execute(
+            &update,
+            params![
+                new.tag_index(&tag),
+                new.seal_value(TAG_FIELD, &tag)?,
+                table,
+                id,
+                stored
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_paths_are_normalized_and_match_nested_folders() {
+        assert_eq!(
+            normalize_folder(" /Work//Servers/ ").as_deref(),
+            Some("Work/Servers")
+        );
+        assert_eq!(normalize_folder("//"), None);
+        assert!(is_within(Some("Work/Servers"), "Work"));
+        assert!(is_within(Some("Work"), "/Work/"));
+        assert!(!is_within(Some("Workshop"), "Work"));
+        assert!(!is_within(None, "Work"));
+        assert!(is_within(None, ""));
+    }
+}
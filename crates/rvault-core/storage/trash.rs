@@ -0,0 +1,109 @@
+//! Removed entries.
+//!
+//! Removing an entry only stamps its row with `deleted_at`, which hides it from every other
+//! repository operation. Trashed entries can be restored until they are purged, either
+//! explicitly or automatically once they are older than [`Database::trash_retention_days`].
+//! Their history and tags are kept until then.
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::migration::{self, META_TABLE};
+use super::repository::EntryMetadata;
+use super::{Database, StorageError, history, tags};
+
+/// Days a trashed entry is kept until [`Database::set_trash_retention_days`] changes it.
+pub const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
+const TRASH_RETENTION_DAYS: &str = "trash_retention_days";
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// An entry in the trash.
+pub struct TrashedEntry {
+    pub metadata: EntryMetadata,
+    /// Unix time at which the entry was removed.
+    pub deleted_at: i64,
+}
+
+impl Database {
+    /// Days after which trashed entries are purged; zero keeps them until purged by hand.
+    pub fn trash_retention_days(&self) -> Result<u32, StorageError> {
+        retention_days(&self.connection)
+    }
+
+    /// Changes how long trashed entries are kept. Entries past the new retention are purged the
+    /// next time their vault is opened.
+    pub fn set_trash_retention_days(&self, days: u32) -> Result<(), StorageError> {
+        // Opening the default table creates the settings table on older files.
+        self.vault_tables()?;
+        self.connection.execute(
+            &format!("INSERT OR REPLACE INTO {META_TABLE} (name, value) VALUES (?1, ?2)"),
+            [TRASH_RETENTION_DAYS.to_string(), days.to_string()],
+        )?;
+        Ok(())
+    }
+}
+
+fn retention_days(connection: &Connection) -> Result<u32, StorageError> {
+    if !migration::meta_table_exists(connection)? {
+        return Ok(DEFAULT_TRASH_RETENTION_DAYS);
+    }
+    let value: Option<String> = connection
+        .query_row(
+            &format!("SELECT value FROM {META_TABLE} WHERE name = ?1"),
+            [TRASH_RETENTION_DAYS],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS))
+}
+
+/// Cutoff for [`purge_before`] under the configured retention, or `None` when trashed entries
+/// are kept forever.
+pub(super) fn retention_cutoff(
+    connection: &Connection,
+    now: i64,
+) -> Result<Option<i64>, StorageError> {
+    let days = retention_days(connection)?;
+    Ok((days > 0).then(|| now - i64::from(days) * SECONDS_PER_DAY))
+}
+
+/// Permanently deletes one trashed entry with its history and tags.
+pub(super) fn purge(
+    connection: &Connection,
+    table: &str,
+    entry_id: i64,
+) -> Result<(), StorageError> {
+    history::forget(connection, table, entry_id)?;
+    tags::forget(connection, table, entry_id)?;
+    let affected = connection.execute(
+        &format!("DELETE FROM {table} WHERE id = ?1 AND deleted_at IS NOT NULL"),
+        [entry_id],
+    )?;
+    if affected == 1 {
+        Ok(())
+    } else {
+        Err(StorageError::NotFound)
+    }
+}
+
+/// Purges every entry of `table` trashed before `cutoff` and returns how many there were.
+pub(super) fn purge_before(
+    connection: &Connection,
+    table: &str,
+    cutoff: i64,
+) -> Result<usize, StorageError> {
+    let ids: Vec<i64> = {
+        let mut statement = connection.prepare(&format!(
+            "SELECT id FROM {table} WHERE deleted_at IS NOT NULL AND deleted_at < ?1"
+        ))?;
+        statement
+            .query_map(params![cutoff], |row| row.get(0))?
+            .collect::<Result<_, _>>()?
+    };
+    for &id in &ids {
+        purge(connection, table, id)?;
+    }
+    Ok(ids.len())
+}
@@ -0,0 +1,449 @@
+//! The vault registry.
+//!
+//! Every vault is an entry table, recorded in the registry with its display name the first time
+//! it is opened. Display names can hold any Unicode text, so new vaults get a random table name
+//! that never appears in SQL; vaults from before the registry keep their name as table name.
+//! History, tags and the metadata mode refer to a vault by table name, so renaming a vault only
+//! changes the registry.
+
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::migration::{self, HISTORY_TABLE, META_TABLE, TAGS_TABLE, VAULTS_TABLE};
+use super::{Database, StorageError, create_entry_table, metadata, search, sharing};
+
+/// Vault used when no vault name is given.
+pub const DEFAULT_VAULT: &str = "main";
+
+/// Longest vault name, in characters.
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Columns copied when a vault is duplicated. Row ids are kept so history and tags still match.
+const COPIED_COLUMNS: &str = "id, platform, user_id, password, nonce, salt, key_scheme, pinned, created_at, updated_at, metadata, details, totp, folder, urls, deleted_at, kind, revision";
+
+/// A vault and a summary of its entries.
+pub struct VaultInfo {
+    pub name: String,
+    /// Entries outside the trash.
+    pub entries: usize,
+    pub encrypted_metadata: bool,
+    /// Whether the vault is shared with other members through a folder of its own.
+    pub shared: bool,
+    pub created_at: i64,
+    /// Unix time of the latest change to an entry, or the creation time of an empty vault.
+    pub modified_at: i64,
+}
+
+/// A registry row: the display name of a vault and the table holding its entries.
+struct Registered {
+    name: String,
+    table_name: String,
+}
+
+impl Database {
+    /// Every registered vault, sorted by name.
+    pub fn vaults(&self) -> Result<Vec<VaultInfo>, StorageError> {
+        // Opening the default table creates the registry on older files.
+        self.vault_tables()?;
+        let registered: Vec<(String, String, i64)> = {
+            let mut statement = self.connection.prepare(&format!(
+                "SELECT name, table_name, created_at FROM {VAULTS_TABLE}"
+            ))?;
+            statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<Result<_, _>>()?
+        };
+        let mut vaults = registered
+            .into_iter()
+            .map(|(name, table_name, created_at)| {
+                let (entries, modified_at): (i64, i64) = self.connection.query_row(
+                    &format!(
+                        "SELECT COALESCE(SUM(deleted_at IS NULL), 0), MAX(
+                            COALESCE(MAX(created_at), 0),
+                            COALESCE(MAX(updated_at), 0),
+                            COALESCE(MAX(deleted_at), 0)
+                        ) FROM {table_name}"
+                    ),
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                Ok(VaultInfo {
+                    encrypted_metadata: metadata::is_encrypted(&self.connection, &table_name)?,
+                    shared: sharing::is_shared(&self.connection, &table_name)?,
+                    name,
+                    entries: entries as usize,
+                    created_at,
+                    modified_at: modified_at.max(created_at),
+                })
+            })
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        vaults.sort_by_key(|vault| vault.name.to_lowercase());
+        Ok(vaults)
+    }
+
+    /// Renames a vault. Its entries, history, tags and metadata mode stay where they are.
+    pub fn rename_vault(&self, name: &str, new_name: &str) -> Result<(), StorageError> {
+        let vault = self.registered_vault(name)?;
+        if vault.table_name == DEFAULT_VAULT {
+            return Err(StorageError::DefaultVault);
+        }
+        // Changing only the case of a name renames the vault to itself.
+        if same_name(&vault.name, new_name) {
+            check_name(new_name)?;
+        } else {
+            ensure_free(&self.connection, new_name)?;
+        }
+        self.connection.execute(
+            &format!("UPDATE {VAULTS_TABLE} SET name = ?1 WHERE table_name = ?2"),
+            [new_name, &vault.table_name],
+        )?;
+        Ok(())
+    }
+
+    /// Permanently deletes a vault with every entry in it, including its trash, history and tags.
+    pub fn delete_vault(&self, name: &str) -> Result<(), StorageError> {
+        let Registered { table_name, .. } = self.registered_vault(name)?;
+        if table_name == DEFAULT_VAULT {
+            return Err(StorageError::DefaultVault);
+        }
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute(&format!("DROP TABLE {table_name}"), [])?;
+        search::drop_index(&transaction, &table_name)?;
+        migration::drop_revision_triggers(&transaction, &table_name)?;
+        for table in [HISTORY_TABLE, TAGS_TABLE] {
+            transaction.execute(
+                &format!("DELETE FROM {table} WHERE entry_table = ?1"),
+                [&table_name],
+            )?;
+        }
+        transaction.execute(
+            &format!("DELETE FROM {VAULTS_TABLE} WHERE table_name = ?1"),
+            [&table_name],
+        )?;
+        transaction.execute(
+            &format!("DELETE FROM {META_TABLE} WHERE name = ?1"),
+            [metadata::mode_key(&table_name)],
+        )?;
+        sharing::forget(&transaction, &table_name)?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Copies the entries of a vault, outside its trash, into a new vault with their history
+    /// and tags. The copy stores metadata the same way as the original but is never shared.
+    /// Returns the number of copied entries.
+    pub fn copy_vault(&self, name: &str, new_name: &str) -> Result<usize, StorageError> {
+        let Registered { table_name, .. } = self.registered_vault(name)?;
+        let transaction = self.connection.unchecked_transaction()?;
+        ensure_free(&transaction, new_name)?;
+        let new_table = register(&transaction, new_name)?;
+        let copied = transaction.execute(
+            &format!(
+                "INSERT INTO {new_table} ({COPIED_COLUMNS})
+                 SELECT {COPIED_COLUMNS} FROM {table_name} WHERE deleted_at IS NULL"
+            ),
+            [],
+        )?;
+        transaction.execute(
+            &format!(
+                "INSERT INTO {HISTORY_TABLE} (entry_table, entry_id, secret, replaced_at)
+                 SELECT ?1, entry_id, secret, replaced_at FROM {HISTORY_TABLE}
+                 WHERE entry_table = ?2 AND entry_id IN (SELECT id FROM {new_table})
+                 ORDER BY id"
+            ),
+            [&new_table, &table_name],
+        )?;
+        transaction.execute(
+            &format!(
+                "INSERT INTO {TAGS_TABLE} (entry_table, entry_id, tag, sealed)
+                 SELECT ?1, entry_id, tag, sealed FROM {TAGS_TABLE}
+                 WHERE entry_table = ?2 AND entry_id IN (SELECT id FROM {new_table})"
+            ),
+            [&new_table, &table_name],
+        )?;
+        transaction.execute(
+            &format!(
+                "INSERT INTO {META_TABLE} (name, value)
+                 SELECT ?1, value FROM {META_TABLE} WHERE name = ?2"
+            ),
+            [
+                metadata::mode_key(&new_table),
+                metadata::mode_key(&table_name),
+            ],
+        )?;
+        transaction.commit()?;
+        Ok(copied)
+    }
+
+    /// Table of an existing vault.
+    pub(super) fn vault_table(&self, name: &str) -> Result<String, StorageError> {
+        Ok(self.registered_vault(name)?.table_name)
+    }
+
+    /// Registry row of an existing vault.
+    fn registered_vault(&self, name: &str) -> Result<Registered, StorageError> {
+        self.vault_tables()?;
+        find(&self.connection, name)?.ok_or(StorageError::NotFound)
+    }
+}
+
+/// Table of the vault called `name`, registering the vault with a new table if there is none.
+/// The default vault always lives in the table named after it.
+pub(super) fn open(connection: &Connection, name: &str) -> rusqlite::Result<String> {
+    // Looked up first so that opening a registered vault never writes.
+    if let Some(vault) = find(connection, name)? {
+        return Ok(vault.table_name);
+    }
+    let transaction = connection.unchecked_transaction()?;
+    let table_name = register(&transaction, name)?;
+    transaction.commit()?;
+    Ok(table_name)
+}
+
+/// Registers a vault called `name` and creates its entry table.
+fn register(connection: &Connection, name: &str) -> rusqlite::Result<String> {
+    let table_name = if same_name(name, DEFAULT_VAULT) {
+        DEFAULT_VAULT.to_string()
+    } else {
+        unused_table_name(connection)?
+    };
+    create_entry_table(connection, &table_name)?;
+    search::create_index(connection, &table_name)?;
+    migration::create_revision_triggers(connection, &table_name)?;
+    connection.execute(
+        &format!("INSERT INTO {VAULTS_TABLE} (name, table_name, created_at) VALUES (?1, ?2, ?3)"),
+        params![name, table_name, Utc::now().timestamp()],
+    )?;
+    Ok(table_name)
+}
+
+/// A random table name that no table or index uses yet.
+fn unused_table_name(connection: &Connection) -> rusqlite::Result<String> {
+    loop {
+        let candidate = format!("vault_{:016x}", rand::random::<u64>());
+        let taken = connection
+            .query_row(
+                "SELECT 1 FROM sqlite_master WHERE name = ?1 COLLATE NOCASE",
+                [&candidate],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !taken {
+            return Ok(candidate);
+        }
+    }
+}
+
+/// Whether a vault called `name`, ignoring case, is registered and shared.
+pub(super) fn is_shared(connection: &Connection, name: &str) -> Result<bool, StorageError> {
+    match find(connection, name)? {
+        Some(vault) => sharing::is_shared(connection, &vault.table_name),
+        None => Ok(false),
+    }
+}
+
+/// Whether a vault called `name`, ignoring case, is registered.
+pub(super) fn is_registered(connection: &Connection, name: &str) -> rusqlite::Result<bool> {
+    find(connection, name).map(|vault| vault.is_some())
+}
+
+/// The registered vault whose name matches `name`, ignoring case.
+fn find(connection: &Connection, name: &str) -> rusqlite::Result<Option<Registered>> {
+    let mut statement =
+        connection.prepare(&format!("SELECT name, table_name FROM {VAULTS_TABLE}"))?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let registered: String = row.get(0)?;
+        if same_name(&registered, name) {
+            return Ok(Some(Registered {
+                name: registered,
+                table_name: row.get(1)?,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Vault names are compared without regard to case, in any script.
+fn same_name(a: &str, b: &str) -> bool {
+    a == b || a.to_lowercase() == b.to_lowercase()
+}
+
+/// Whether `name` can name a vault: 1 to 64 characters without control characters or
+/// surrounding whitespace.
+pub(super) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.trim() == name
+        && name.chars().count() <= MAX_NAME_LENGTH
+        && !name.chars().any(char::is_control)
+}
+
+fn check_name(name: &str) -> Result<(), StorageError> {
+    if !is_valid_name(name) {
+        return Err(StorageError::InvalidVaultName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Fails unless `name` is a valid vault name that no vault uses yet.
+pub(super) fn ensure_free(connection: &Connection, name: &str) -> Result<(), StorageError> {
+    check_name(name)?;
+    if find(connection, name)?.is_some() {
+        return Err(StorageError::Conflict);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::SecretKey;
+    use crate::storage::{EntryRepository, EntrySelector, EntryUpdate, NewEntry};
+
+    fn memory_db() -> Database {
+        Database {
+            connection: Connection::open_in_memory().unwrap(),
+        }
+    }
+
+    fn names(db: &Database) -> Vec<String> {
+        db.vaults()
+            .unwrap()
+            .into_iter()
+            .map(|vault| vault.name)
+            .collect()
+    }
+
+    #[test]
+    fn vaults_are_listed_renamed_copied_and_deleted() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let work = EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        work.add(&key, NewEntry::new("github", "alice", b"old"))
+            .unwrap();
+        work.add(&key, NewEntry::new("gitlab", "alice", b"secret"))
+            .unwrap();
+        work.update(
+            &key,
+            EntrySelector::new("github", "alice"),
+            EntryUpdate::new("alice", b"new"),
+        )
+        .unwrap();
+        work.add_tag(EntrySelector::new("github", "alice"), "code")
+            .unwrap();
+        work.remove(EntrySelector::new("gitlab", "alice")).unwrap();
+
+        let vaults = db.vaults().unwrap();
+        assert_eq!(names(&db), vec!["main", "work"]);
+        assert_eq!(vaults[1].entries, 1);
+        assert!(vaults[1].modified_at >= vaults[1].created_at);
+
+        db.rename_vault("WORK", "job").unwrap();
+        assert_eq!(names(&db), vec!["job", "main"]);
+        let job = EntryRepository::new(&db, Some("job".to_string())).unwrap();
+        let github = EntrySelector::new("github", "alice");
+        assert_eq!(job.history(&key, github).unwrap().len(), 1);
+        assert_eq!(job.tags().unwrap(), vec!["code"]);
+        assert_eq!(job.trash().unwrap().len(), 1);
+
+        assert_eq!(db.copy_vault("job", "job_copy").unwrap(), 1);
+        let copy = EntryRepository::new(&db, Some("job_copy".to_string())).unwrap();
+        assert_eq!(copy.get(&key, github).unwrap().secret.expose(), b"new");
+        assert_eq!(copy.history(&key, github).unwrap().len(), 1);
+        assert_eq!(copy.tags().unwrap(), vec!["code"]);
+        assert!(copy.trash().unwrap().is_empty());
+
+        db.delete_vault("job").unwrap();
+        assert_eq!(names(&db), vec!["job_copy", "main"]);
+        let count: i64 = db
+            .connection
+            .query_row("SELECT COUNT(*) FROM rvault_history", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(copy.history(&key, github).unwrap().len(), 1);
+        let triggers: i64 = db
+            .connection
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'trigger' AND tbl_name = 'rvault_tags'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(triggers, 10);
+        let hits = db.search_vaults(&key, "code").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].0, "job_copy");
+    }
+
+    #[test]
+    fn vault_names_are_checked() {
+        let db = memory_db();
+        EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        EntryRepository::new(&db, Some("home".to_string())).unwrap();
+
+        assert!(matches!(
+            db.rename_vault("work", "Home"),
+            Err(StorageError::Conflict)
+        ));
+        assert!(matches!(
+            db.copy_vault("work", ""),
+            Err(StorageError::InvalidVaultName(_))
+        ));
+        assert!(matches!(
+            db.rename_vault("work", "my vault "),
+            Err(StorageError::InvalidVaultName(_))
+        ));
+        assert!(matches!(
+            db.rename_vault("missing", "other"),
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            db.delete_vault(DEFAULT_VAULT),
+            Err(StorageError::DefaultVault)
+        ));
+        assert!(matches!(
+            db.rename_vault(DEFAULT_VAULT, "other"),
+            Err(StorageError::DefaultVault)
+        ));
+        assert_eq!(names(&db), vec!["home", "main", "work"]);
+
+        db.rename_vault("work", "Client – ACME Prod").unwrap();
+        db.rename_vault("client – acme prod", "Client – ACME prod")
+            .unwrap();
+        db.copy_vault("home", "rvault_meta").unwrap();
+        assert_eq!(
+            names(&db),
+            vec!["Client – ACME prod", "home", "main", "rvault_meta"]
+        );
+        EntryRepository::new(&db, Some("Ärger Ünd Ösen".to_string())).unwrap();
+        assert!(matches!(
+            db.copy_vault("home", "ärger ünd ösen"),
+            Err(StorageError::Conflict)
+        ));
+    }
+
+    #[test]
+    fn encrypted_vaults_stay_encrypted_when_renamed_or_copied() {
+        let db = memory_db();
+        let key = SecretKey::from_bytes([7; 32]);
+        let work = EntryRepository::new(&db, Some("work".to_string())).unwrap();
+        work.add(&key, NewEntry::new("github", "alice", b"secret"))
+            .unwrap();
+        db.encrypt_metadata(&key, Some("work".to_string())).unwrap();
+
+        db.rename_vault("work", "job").unwrap();
+        db.copy_vault("job", "job_copy").unwrap();
+        for name in ["job", "job_copy"] {
+            let repository = EntryRepository::unlocked(&db, Some(name.to_string()), &key).unwrap();
+            assert!(repository.has_encrypted_metadata());
+            assert_eq!(repository.list_metadata().unwrap()[0].platform, "github");
+        }
+        assert!(
+            db.vaults()
+                .unwrap()
+                .iter()
+                .all(|vault| vault.encrypted_metadata == (vault.name != DEFAULT_VAULT))
+        );
+    }
+}
@@ -1,13 +1,20 @@
 use serde::{Deserialize, Serialize};
 
-use crate::keystore::keystore_path;
+use crate::keystore::{SlotKind, keystore_path};
+use crate::recovery::RecoveryKey;
+use crate::rotation::{RotationFiles, RotationReport, recover_interrupted_rotation};
 use crate::{
-    config::Config,
+    config::{Config, config_path},
     crypto::{generate_key, hash_data, verify_password},
     storage::{Database, Table},
 };
 use base64::engine::general_purpose::STANDARD as Base64;
 use base64::prelude::*;
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 const KEYRING_SERVICE: &str = "RVault";
 const KEYRING_ACCOUNT: &str = "encryption_key"; // stable account name
@@ -36,11 +43,79 @@ impl Vault {
         master_password: &str,
         stored_master_hash: &str,
     ) -> Result<[u8; 32], String> {
-        if !crate::crypto::verify_password(master_password.as_bytes(), stored_master_hash) {
+        let files = CredentialFiles::current()?;
+        recover_rotation()?;
+        // A password change interrupted after its commit point carries the new hash.
+        let recovered_hash = recover_password_change(&files)?;
+        let stored_master_hash = recovered_hash.as_deref().unwrap_or(stored_master_hash);
+        if crate::crypto::verify_password(master_password.as_bytes(), stored_master_hash) {
+            return crate::keystore::unlock_key_vault(master_password, &files.keystore);
+        }
+        // Like a LUKS passphrase prompt, the same input may be a recovery key for another slot.
+        if let Ok(recovery_key) = RecoveryKey::parse(master_password) {
+            return crate::keystore::load_key_with_slot(
+                SlotKind::Recovery,
+                recovery_key.as_bytes(),
+                &files.keystore,
+            );
+        }
+        Err("Invalid master password".into())
+    }
+
+    /// Unwraps the MEK through a keyfile slot.
+    pub fn get_encryption_key_from_keyfile(keyfile: &Path) -> Result<[u8; 32], String> {
+        let files = CredentialFiles::current()?;
+        recover_rotation()?;
+        recover_password_change(&files)?;
+        let secret = crate::keystore::keyfile_secret(keyfile)?;
+        crate::keystore::load_key_with_slot(SlotKind::Keyfile, secret.as_slice(), &files.keystore)
+    }
+
+    /// Sets a new master password after unwrapping the MEK with a recovery key.
+    ///
+    /// Used when the master password is forgotten; commits the same way as
+    /// [`Vault::change_master_password`].
+    pub fn recover_master_password(recovery_key: &str, new_password: &str) -> Result<(), String> {
+        let files = CredentialFiles::current()?;
+        recover_rotation()?;
+        recover_password_change(&files)?;
+        let config = Config::new().map_err(|e| e.to_string())?;
+        recover_master_password_at(&files, config, recovery_key, new_password)
+    }
+
+    /// Replaces the master password by re-wrapping the existing MEK.
+    ///
+    /// Entries stay readable because the MEK itself does not change. The keystore and the
+    /// configuration hash are committed together; an interrupted change is finished or discarded
+    /// on the next unlock so the two files never disagree.
+    pub fn change_master_password(old_password: &str, new_password: &str) -> Result<(), String> {
+        let files = CredentialFiles::current()?;
+        recover_rotation()?;
+        recover_password_change(&files)?;
+        let config = Config::new().map_err(|e| e.to_string())?;
+        change_master_password_at(&files, config, old_password, new_password)
+    }
+
+    /// Replaces the MEK with a fresh one and re-encrypts everything it protects.
+    ///
+    /// Every entry in every vault table and `identity.rvault` move to the new key inside one
+    /// SQLite transaction; the rewrapped keystore is swapped in only after it commits. Recovery
+    /// and keyfile slots wrap the old MEK and are dropped. The current session is ended first
+    /// so no other process keeps writing with the old key.
+    pub fn rotate_master_key(
+        master_password: &str,
+        stored_master_hash: &str,
+    ) -> Result<RotationReport, String> {
+        let files = CredentialFiles::current()?;
+        recover_rotation()?;
+        let recovered_hash = recover_password_change(&files)?;
+        let stored_master_hash = recovered_hash.as_deref().unwrap_or(stored_master_hash);
+        if !verify_password(master_password.as_bytes(), stored_master_hash) {
             return Err("Invalid master password".into());
         }
-        let path = keystore_path()?; // or reimplement helper here
-        crate::keystore::load_key_from_vault(master_password, &path)
+        let _ = crate::session::end_session();
+        let db = Database::new().map_err(|e| e.to_string())?;
+        crate::rotation::rotate_master_key_at(&db, &RotationFiles::current()?, master_password)
     }
     fn load_external_vault(vault: Vec<u8>) {}
     fn encrypt_vault() {}
@@ -50,3 +125,278 @@ impl Vault {
     #[deprecated(note = "no replacement exists; use portable_export with explicit entries")]
     pub fn export_partial_vault() {}
 }
+
+struct CredentialFiles {
+    keystore: PathBuf,
+    config: PathBuf,
+}
+
+impl CredentialFiles {
+    fn current() -> Result<Self, String> {
+        Ok(Self {
+            keystore: keystore_path()?,
+            config: config_path().map_err(|e| e.to_string())?,
+        })
+    }
+}
+
+fn change_master_password_at(
+    files: &CredentialFiles,
+    config: Config,
+    old_password: &str,
+    new_password: &str,
+) -> Result<(), String> {
+    let Some(stored_hash) = config.master_password_hash.as_deref() else {
+        return Err("RVault has not been set up.".into());
+    };
+    if !verify_password(old_password.as_bytes(), stored_hash) {
+        return Err("Invalid master password".into());
+    }
+    if new_password.is_empty() {
+        return Err("The new master password must not be empty.".into());
+    }
+    let keystore = crate::keystore::rewrap_key_vault(old_password, new_password, &files.keystore)?;
+    commit_new_password(files, config, &keystore, new_password)
+}
+
+fn recover_master_password_at(
+    files: &CredentialFiles,
+    config: Config,
+    recovery_key: &str,
+    new_password: &str,
+) -> Result<(), String> {
+    if config.master_password_hash.is_none() {
+        return Err("RVault has not been set up.".into());
+    }
+    let recovery_key = RecoveryKey::parse(recovery_key)?;
+    if new_password.is_empty() {
+        return Err("The new master password must not be empty.".into());
+    }
+    let keystore = crate::keystore::reset_password_with_slot(
+        SlotKind::Recovery,
+        recovery_key.as_bytes(),
+        new_password,
+        &files.keystore,
+    )?;
+    commit_new_password(files, config, &keystore, new_password)
+}
+
+fn commit_new_password(
+    files: &CredentialFiles,
+    mut config: Config,
+    keystore: &[u8],
+    new_password: &str,
+) -> Result<(), String> {
+    let hashed = hash_data(new_password.as_bytes()).map_err(|e| e.to_string())?;
+    config.master_password_hash = Some(hashed.hash);
+    let config = serde_json::to_vec(&config).map_err(|e| e.to_string())?;
+    commit_password_change(files, keystore, &config)
+}
+
+/// Stages both files, then renames the keystore (the commit point) followed by the config.
+fn commit_password_change(
+    files: &CredentialFiles,
+    keystore: &[u8],
+    config: &[u8],
+) -> Result<(), String> {
+    let keystore_pending = pending_path(&files.keystore);
+    let config_pending = pending_path(&files.config);
+    let staged = write_synced(&keystore_pending, keystore)
+        .and_then(|_| write_synced(&config_pending, config));
+    if let Err(error) = staged {
+        let _ = fs::remove_file(&keystore_pending);
+        let _ = fs::remove_file(&config_pending);
+        return Err(error);
+    }
+    if let Err(error) = fs::rename(&keystore_pending, &files.keystore) {
+        let _ = fs::remove_file(&keystore_pending);
+        let _ = fs::remove_file(&config_pending);
+        return Err(format!("replace keystore: {error}"));
+    }
+    fs::rename(&config_pending, &files.config).map_err(|e| {
+        format!("replace config: {e}; the change will be completed on the next unlock")
+    })
+}
+
+/// Finishes or discards a password change interrupted by a crash.
+///
+/// A staged keystore means the commit point was never reached, so both staged files are
+/// discarded. A staged config without a staged keystore means the keystore was already replaced,
+/// so the config is moved into place and its hash returned.
+fn recover_password_change(files: &CredentialFiles) -> Result<Option<String>, String> {
+    let keystore_pending = pending_path(&files.keystore);
+    let config_pending = pending_path(&files.config);
+    if keystore_pending.exists() {
+        fs::remove_file(&keystore_pending).map_err(|e| format!("discard staged keystore: {e}"))?;
+        if config_pending.exists() {
+            fs::remove_file(&config_pending).map_err(|e| format!("discard staged config: {e}"))?;
+        }
+        return Ok(None);
+    }
+    if !config_pending.exists() {
+        return Ok(None);
+    }
+    let bytes = fs::read(&config_pending).map_err(|e| format!("read staged config: {e}"))?;
+    let config: Config =
+        serde_json::from_slice(&bytes).map_err(|e| format!("parse staged config: {e}"))?;
+    fs::rename(&config_pending, &files.config).map_err(|e| format!("replace config: {e}"))?;
+    Ok(config.master_password_hash)
+}
+
+/// Finishes or discards an interrupted `rotate-key`. Every entry point runs it before
+/// [`recover_password_change`]: a staged rotation keystore replaces the current one, so a
+/// password change applied first would be lost with the keystore it rewrapped.
+fn recover_rotation() -> Result<(), String> {
+    recover_interrupted_rotation(&RotationFiles::current()?, || {
+        let db = Database::new().map_err(|e| e.to_string())?;
+        db.key_fingerprint().map_err(|e| e.to_string())
+    })
+}
+
+fn pending_path(path: &Path) -> PathBuf {
+    let mut pending = path.as_os_str().to_os_string();
+    pending.push(".pending");
+    PathBuf::from(pending)
+}
+
+pub(crate) fn write_synced(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("write {}: {e}", path.display()))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("write {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystore::{create_key_vault, load_key_from_vault};
+
+    fn temporary_files() -> CredentialFiles {
+        let root =
+            std::env::temp_dir().join(format!("rvault-passwd-test-{}", rand::random::<u64>()));
+        fs::create_dir_all(&root).unwrap();
+        CredentialFiles {
+            keystore: root.join("keystore.rvault"),
+            config: root.join("config.json"),
+        }
+    }
+
+    fn set_up(files: &CredentialFiles, password: &str) -> Config {
+        create_key_vault(password, &files.keystore).unwrap();
+        let config = Config {
+            master_password_hash: Some(hash_data(password.as_bytes()).unwrap().hash),
+            ..Default::default()
+        };
+        fs::write(&files.config, serde_json::to_vec(&config).unwrap()).unwrap();
+        config
+    }
+
+    fn stored_hash(files: &CredentialFiles) -> String {
+        let config: Config = serde_json::from_slice(&fs::read(&files.config).unwrap()).unwrap();
+        config.master_password_hash.unwrap()
+    }
+
+    #[test]
+    fn password_change_rewraps_the_same_mek_and_updates_the_hash() {
+        let files = temporary_files();
+        let config = set_up(&files, "old-password");
+        let mek = load_key_from_vault("old-password", &files.keystore).unwrap();
+
+        change_master_password_at(&files, config, "old-password", "new-password").unwrap();
+
+        assert_eq!(
+            load_key_from_vault("new-password", &files.keystore).unwrap(),
+            mek
+        );
+        assert!(verify_password(b"new-password", &stored_hash(&files)));
+        assert!(!verify_password(b"old-password", &stored_hash(&files)));
+        assert!(!pending_path(&files.keystore).exists());
+        assert!(!pending_path(&files.config).exists());
+        fs::remove_dir_all(files.keystore.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn password_change_rejects_the_wrong_current_password_without_writing() {
+        let files = temporary_files();
+        let config = set_up(&files, "old-password");
+        let keystore = fs::read(&files.keystore).unwrap();
+
+        assert!(change_master_password_at(&files, config, "wrong", "new-password").is_err());
+
+        assert_eq!(fs::read(&files.keystore).unwrap(), keystore);
+        assert!(verify_password(b"old-password", &stored_hash(&files)));
+        fs::remove_dir_all(files.keystore.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recovery_discards_a_change_interrupted_before_the_commit_point() {
+        let files = temporary_files();
+        set_up(&files, "old-password");
+        fs::write(pending_path(&files.keystore), b"staged keystore").unwrap();
+        fs::write(pending_path(&files.config), b"staged config").unwrap();
+
+        assert_eq!(recover_password_change(&files).unwrap(), None);
+
+        assert!(!pending_path(&files.keystore).exists());
+        assert!(!pending_path(&files.config).exists());
+        assert!(load_key_from_vault("old-password", &files.keystore).is_ok());
+        assert!(verify_password(b"old-password", &stored_hash(&files)));
+        fs::remove_dir_all(files.keystore.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recovery_completes_a_change_interrupted_after_the_commit_point() {
+        let files = temporary_files();
+        let mut config = set_up(&files, "old-password");
+        let keystore =
+            crate::keystore::rewrap_key_vault("old-password", "new-password", &files.keystore)
+                .unwrap();
+        config.master_password_hash = Some(hash_data(b"new-password").unwrap().hash);
+        fs::write(&files.keystore, keystore).unwrap();
+        fs::write(
+            pending_path(&files.config),
+            serde_json::to_vec(&config).unwrap(),
+        )
+        .unwrap();
+
+        let recovered = recover_password_change(&files).unwrap().unwrap();
+
+        assert!(verify_password(b"new-password", &recovered));
+        assert!(verify_password(b"new-password", &stored_hash(&files)));
+        assert!(!pending_path(&files.config).exists());
+        fs::remove_dir_all(files.keystore.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn recovery_key_sets_a_new_master_password() {
+        let files = temporary_files();
+        let config = set_up(&files, "forgotten");
+        let mek = load_key_from_vault("forgotten", &files.keystore).unwrap();
+        let recovery_key = RecoveryKey::generate();
+        crate::keystore::add_key_slot(
+            "forgotten",
+            SlotKind::Recovery,
+            recovery_key.as_bytes(),
+            &files.keystore,
+        )
+        .unwrap();
+
+        assert!(
+            recover_master_password_at(&files, saved_config(&files), "ABCDE", "new-password")
+                .is_err()
+        );
+        recover_master_password_at(&files, config, &recovery_key.display(), "new-password")
+            .unwrap();
+
+        assert_eq!(
+            load_key_from_vault("new-password", &files.keystore).unwrap(),
+            mek
+        );
+        assert!(verify_password(b"new-password", &stored_hash(&files)));
+        fs::remove_dir_all(files.keystore.parent().unwrap()).unwrap();
+    }
+
+    fn saved_config(files: &CredentialFiles) -> Config {
+        serde_json::from_slice(&fs::read(&files.config).unwrap()).unwrap()
+    }
+}
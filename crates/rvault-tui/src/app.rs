@@ -3,14 +3,17 @@ use crate::ui::Theme;
 use crossterm::event::{KeyCode, KeyEventKind};
 use ratatui::widgets::ListState;
 use rvault_core::{
-    backup, clipboard, config, crypto, identity,
+    SecretBytes, backup, clipboard, config, crypto, identity,
     keystore::{self, keystore_path},
+    kind::{ApiKey, Card, EntryKind, Identity, SecureNote, TypedSecret},
     portable_export,
     session::{self, SessionKey},
     storage::{
-        Database, EntryMetadata, EntryRepository, EntrySelector, EntryUpdate, NewEntry,
-        StorageError, Table,
+        AuditEvent, AuditOperation, AuditSource, BatchOp, CustomField, DEFAULT_VAULT, Database,
+        EntryDetails, EntryMetadata, EntryRepository, EntrySelector, EntryUpdate, HistoryEntry,
+        NewEntry, StorageError, TrashedEntry, VaultInfo,
     },
+    totp::TotpSecret,
     vault::Vault,
 };
 use std::io;
@@ -19,17 +22,22 @@ use std::time::{Duration, Instant};
 pub enum SetupStage {
     EnterPassword,
     ConfirmPassword,
+    Calibrating,
 }
 
 pub enum AddEntryStage {
     Platform,
     UserId,
     Password,
+    Notes,
+    Fields,
 }
 
 pub enum EditEntryStage {
     UserId,
     Password,
+    Notes,
+    Fields,
 }
 
 pub enum BackupCreateStage {
@@ -43,6 +51,12 @@ pub enum BackupRestoreStage {
     Confirm,
 }
 
+pub enum ChangePasswordStage {
+    Current,
+    New,
+    Confirm,
+}
+
 pub enum ExportEntryStage {
     Recipient,
     Path,
@@ -101,17 +115,37 @@ pub enum AppState {
         platform: String,
         user_id: String,
     },
+    EntryView {
+        platform: String,
+        user_id: String,
+        details: EntryDetails,
+        totp: Option<TotpSecret>, // Code is recomputed on every draw
+        typed: Option<TypedSecret>,
+    },
+    History {
+        platform: String,
+        user_id: String,
+        versions: Vec<HistoryEntry>, // Newest first
+        selected: usize,
+    },
     EditEntry {
         platform: String,         // Immutable
         original_user_id: String, // Target for update
+        kind: EntryKind,          // Immutable
+        revision: i64,            // Saving fails if the entry changed since
         user_id: InputState,
-        password: InputState,
+        password: InputState, // A typed secret's `name=value` pairs for other kinds
+        notes: InputState,
+        fields: InputState, // `name=value; *secret=value`
         stage: EditEntryStage,
     },
     AddEntry {
+        kind: EntryKind, // Tab cycles through the kinds
         platform: InputState,
         user_id: InputState,
-        password: InputState,
+        password: InputState, // A typed secret's `name=value` pairs for other kinds
+        notes: InputState,
+        fields: InputState,
         stage: AddEntryStage,
     },
     BackupCreate {
@@ -125,6 +159,12 @@ pub enum AppState {
         confirm: InputState,
         stage: BackupRestoreStage,
     },
+    ChangePassword {
+        current: InputState,
+        new_password: InputState,
+        confirm: InputState,
+        stage: ChangePasswordStage,
+    },
     ExportEntry {
         platform: String,
         user_id: String,
@@ -141,6 +181,19 @@ pub enum AppState {
     },
     ThemeSelection,
     SortSelection,
+    TagFilter {
+        tags: Vec<String>,
+        selected: usize, // 0 shows every entry, n shows tags[n - 1]
+    },
+    Trash {
+        entries: Vec<TrashedEntry>, // Most recently removed first
+        selected: usize,
+        confirm_purge: bool,
+    },
+    VaultSelection {
+        vaults: Vec<VaultInfo>, // Sorted by name
+        selected: usize,
+    },
 }
 
 pub struct App {
@@ -163,6 +216,16 @@ pub struct App {
     // Sorting
     pub sort_mode: SortMode,
 
+    // Only entries with this tag are listed
+    pub tag_filter: Option<String>,
+
+    // Vault shown in the main table; None is the default vault
+    pub vault: Option<String>,
+
+    // Search query of the filter bar, and whether keys currently edit it
+    pub filter: String,
+    pub filter_editing: bool,
+
     // Toast
     pub toast: Option<Toast>,
 }
@@ -216,6 +279,10 @@ impl App {
             themes,
             current_theme,
             sort_mode: SortMode::PlatformAsc,
+            tag_filter: None,
+            vault: None,
+            filter: String::new(),
+            filter_editing: false,
             toast: None,
         }
     }
@@ -233,6 +300,24 @@ impl App {
                 self.toast = None;
             }
         }
+
+        if let AppState::Setup {
+            password,
+            confirm,
+            stage: stage @ SetupStage::Calibrating,
+            error,
+        } = &mut self.state
+        {
+            match complete_setup(password) {
+                Ok(()) => self.state = AppState::Authentication(String::new()),
+                Err(e) => {
+                    *error = Some(e);
+                    password.clear();
+                    confirm.clear();
+                    *stage = SetupStage::EnterPassword;
+                }
+            }
+        }
     }
 
     pub fn next_tab(&mut self) {
@@ -256,8 +341,28 @@ impl App {
 
     pub fn refresh_vault_list(&mut self) {
         if let Ok(db) = Database::new() {
-            if let Ok(repository) = EntryRepository::new(&db, None) {
-                if let Ok(entries) = repository.list_metadata() {
+            if let Ok(repository) = open_repository(&db, self.vault.as_deref()) {
+                if !self.filter.is_empty() {
+                    // Search results stay in ranking order instead of the sort mode.
+                    let key = SessionKey::load().ok();
+                    if let Ok(hits) = repository.search(key.as_ref(), &self.filter) {
+                        self.items = hits
+                            .into_iter()
+                            .map(|hit| hit.entry)
+                            .filter(|entry| {
+                                self.tag_filter
+                                    .as_ref()
+                                    .is_none_or(|tag| entry.tags.contains(tag))
+                            })
+                            .collect();
+                    }
+                    return;
+                }
+                let entries = match &self.tag_filter {
+                    Some(tag) => repository.list_tagged(tag),
+                    None => repository.list_metadata(),
+                };
+                if let Ok(entries) = entries {
                     self.items = entries;
                     self.sort_items();
                 }
@@ -265,6 +370,13 @@ impl App {
         }
     }
 
+    /// Reloads the list after the filter changed and selects the best match.
+    fn refresh_filtered_list(&mut self) {
+        self.refresh_vault_list();
+        self.list_state
+            .select((!self.items.is_empty()).then_some(0));
+    }
+
     pub fn sort_items(&mut self) {
         // Separate pinned and unpinned
         let (pinned, mut unpinned): (Vec<_>, Vec<_>) = self.items.drain(..).partition(|e| e.pinned);
@@ -339,25 +451,35 @@ impl App {
             } => match stage {
                 SetupStage::EnterPassword => password.push_str(value),
                 SetupStage::ConfirmPassword => confirm.push_str(value),
+                SetupStage::Calibrating => {}
             },
             AppState::EditEntry {
                 user_id,
                 password,
+                notes,
+                fields,
                 stage,
                 ..
             } => match stage {
                 EditEntryStage::UserId => user_id.insert_str(value),
                 EditEntryStage::Password => password.insert_str(value),
+                EditEntryStage::Notes => notes.insert_str(value),
+                EditEntryStage::Fields => fields.insert_str(value),
             },
             AppState::AddEntry {
                 platform,
                 user_id,
                 password,
+                notes,
+                fields,
                 stage,
+                ..
             } => match stage {
                 AddEntryStage::Platform => platform.insert_str(value),
                 AddEntryStage::UserId => user_id.insert_str(value),
                 AddEntryStage::Password => password.insert_str(value),
+                AddEntryStage::Notes => notes.insert_str(value),
+                AddEntryStage::Fields => fields.insert_str(value),
             },
             AppState::BackupCreate {
                 path,
@@ -370,6 +492,13 @@ impl App {
                 confirm,
                 stage,
             } => active_backup_restore_input(path, password, confirm, stage).insert_str(value),
+            AppState::ChangePassword {
+                current,
+                new_password,
+                confirm,
+                stage,
+            } => active_change_password_input(current, new_password, confirm, stage)
+                .insert_str(value),
             AppState::ExportEntry {
                 recipient,
                 path,
@@ -377,12 +506,21 @@ impl App {
                 ..
             } => active_export_input(recipient, path, stage).insert_str(value),
             AppState::ImportExport { path } => path.insert_str(value),
+            AppState::MainTable if self.filter_editing => {
+                self.filter.push_str(value);
+                self.refresh_filtered_list();
+            }
             AppState::MainTable
             | AppState::Generator
             | AppState::RemoveConfirmation { .. }
+            | AppState::EntryView { .. }
+            | AppState::History { .. }
             | AppState::ImportExportConfirm { .. }
             | AppState::ThemeSelection
-            | AppState::SortSelection => {}
+            | AppState::SortSelection
+            | AppState::TagFilter { .. }
+            | AppState::Trash { .. }
+            | AppState::VaultSelection { .. } => {}
         }
     }
 
@@ -417,6 +555,7 @@ impl App {
                             Ok(key) => {
                                 if let Ok(token) = session::start_session(&key) {
                                     let _ = session::write_current(&token);
+                                    record_audit(None, AuditOperation::Unlock, None);
                                     transition_to_main = true;
                                 } else {
                                     self.auth_error = Some("Failed to start session".into());
@@ -438,13 +577,38 @@ impl App {
                 }
                 _ => {}
             },
+            AppState::MainTable if self.filter_editing => match key.code {
+                KeyCode::Enter | KeyCode::Down | KeyCode::Up => self.filter_editing = false,
+                KeyCode::Esc => {
+                    self.filter_editing = false;
+                    self.filter.clear();
+                    self.refresh_filtered_list();
+                }
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.refresh_filtered_list();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.refresh_filtered_list();
+                }
+                _ => {}
+            },
             AppState::MainTable => {
                 match key.code {
+                    KeyCode::Char('/') => self.filter_editing = true,
+                    KeyCode::Esc if !self.filter.is_empty() => {
+                        self.filter.clear();
+                        self.refresh_filtered_list();
+                    }
                     KeyCode::Char('a') => {
                         self.state = AppState::AddEntry {
+                            kind: EntryKind::Login,
                             platform: InputState::new(),
                             user_id: InputState::new(),
                             password: InputState::new(),
+                            notes: InputState::new(),
+                            fields: InputState::new(),
                             stage: AddEntryStage::Platform,
                         };
                     }
@@ -473,6 +637,15 @@ impl App {
                             stage: BackupRestoreStage::Path,
                         };
                     }
+                    KeyCode::Char('P') => {
+                        self.auth_error = None;
+                        self.state = AppState::ChangePassword {
+                            current: InputState::new(),
+                            new_password: InputState::new(),
+                            confirm: InputState::new(),
+                            stage: ChangePasswordStage::Current,
+                        };
+                    }
                     KeyCode::Char('x') => {
                         if let Some(i) = self.list_state.selected() {
                             if let Some(entry) = self.items.get(i) {
@@ -496,6 +669,7 @@ impl App {
                     }
                     KeyCode::Char('q') | KeyCode::Esc => return Ok(true),
                     KeyCode::Char('Q') => {
+                        record_audit(None, AuditOperation::Lock, None);
                         let _ = rvault_core::lock();
                         return Ok(true);
                     }
@@ -530,7 +704,9 @@ impl App {
                         if let Some(i) = self.list_state.selected() {
                             if let Some(entry) = self.items.get(i) {
                                 if let Ok(db) = Database::new() {
-                                    if let Ok(repository) = EntryRepository::new(&db, None) {
+                                    if let Ok(repository) =
+                                        open_repository(&db, self.vault.as_deref())
+                                    {
                                         match repository.set_pinned(
                                             EntrySelector::new(&entry.platform, &entry.user_id),
                                             !entry.pinned,
@@ -549,6 +725,54 @@ impl App {
                             }
                         }
                     }
+                    KeyCode::Char('v') => {
+                        let selected = self.list_state.selected();
+                        if let Some(entry) = selected.and_then(|i| self.items.get(i)) {
+                            match load_entry(self.vault.as_deref(), &entry.platform, &entry.user_id)
+                            {
+                                Ok(LoadedEntry {
+                                    details,
+                                    totp,
+                                    typed,
+                                    ..
+                                }) => {
+                                    record_audit(
+                                        self.vault.as_deref(),
+                                        AuditOperation::Get,
+                                        Some((&entry.platform, &entry.user_id)),
+                                    );
+                                    self.state = AppState::EntryView {
+                                        platform: entry.platform.clone(),
+                                        user_id: entry.user_id.clone(),
+                                        details,
+                                        totp,
+                                        typed,
+                                    };
+                                }
+                                Err(e) => self.show_toast(&format!("Failed to open entry: {e}")),
+                            }
+                        }
+                    }
+                    KeyCode::Char('h') => {
+                        let selected = self.list_state.selected();
+                        if let Some(entry) = selected.and_then(|i| self.items.get(i)) {
+                            match load_history(
+                                self.vault.as_deref(),
+                                &entry.platform,
+                                &entry.user_id,
+                            ) {
+                                Ok(versions) => {
+                                    self.state = AppState::History {
+                                        platform: entry.platform.clone(),
+                                        user_id: entry.user_id.clone(),
+                                        versions,
+                                        selected: 0,
+                                    };
+                                }
+                                Err(e) => self.show_toast(&format!("Failed to load history: {e}")),
+                            }
+                        }
+                    }
                     KeyCode::Char('d') => {
                         if let Some(i) = self.list_state.selected() {
                             if let Some(entry) = self.items.get(i) {
@@ -562,13 +786,45 @@ impl App {
                     KeyCode::Char('e') => {
                         if let Some(i) = self.list_state.selected() {
                             if let Some(entry) = self.items.get(i) {
-                                self.state = AppState::EditEntry {
-                                    platform: entry.platform.clone(),
-                                    original_user_id: entry.user_id.clone(),
-                                    user_id: InputState::with_value(entry.user_id.clone()),
-                                    password: InputState::new(), // Start empty for security, or fetch? Better empty to act as "change password"
-                                    stage: EditEntryStage::UserId,
-                                };
+                                // Notes and fields are prefilled, so they must decrypt first.
+                                match load_entry(
+                                    self.vault.as_deref(),
+                                    &entry.platform,
+                                    &entry.user_id,
+                                ) {
+                                    Ok(LoadedEntry {
+                                        details,
+                                        typed,
+                                        revision,
+                                        ..
+                                    }) => {
+                                        // The read may have upgraded the row, so the list's
+                                        // revision can be stale.
+                                        self.state = AppState::EditEntry {
+                                            platform: entry.platform.clone(),
+                                            original_user_id: entry.user_id.clone(),
+                                            kind: entry.kind,
+                                            revision,
+                                            user_id: InputState::with_value(entry.user_id.clone()),
+                                            // Passwords start empty; typed secrets are edited in place.
+                                            password: typed
+                                                .as_ref()
+                                                .map(format_typed_secret)
+                                                .map_or_else(
+                                                    InputState::new,
+                                                    InputState::with_value,
+                                                ),
+                                            notes: InputState::with_value(details.notes.clone()),
+                                            fields: InputState::with_value(format_fields(
+                                                &details.fields,
+                                            )),
+                                            stage: EditEntryStage::UserId,
+                                        };
+                                    }
+                                    Err(e) => {
+                                        self.show_toast(&format!("Failed to open entry: {e}"))
+                                    }
+                                }
                             }
                         }
                     }
@@ -576,18 +832,41 @@ impl App {
                         if let Some(i) = self.list_state.selected() {
                             if let Some(entry) = self.items.get(i) {
                                 if let Ok(db) = Database::new() {
-                                    if let Ok(repository) = EntryRepository::new(&db, None) {
+                                    if let Ok(repository) =
+                                        EntryRepository::new(&db, self.vault.clone())
+                                    {
                                         if let Ok(ek) = SessionKey::load() {
                                             if let Ok(entry) = repository.get(
                                                 &ek,
                                                 EntrySelector::new(&entry.platform, &entry.user_id),
                                             ) {
-                                                if let Ok(plaintext) =
-                                                    std::str::from_utf8(entry.secret.expose())
-                                                {
+                                                let typed = entry.typed_secret().ok().flatten();
+                                                let copied = match &typed {
+                                                    Some(secret) => Some(secret.primary_value()),
+                                                    None => {
+                                                        std::str::from_utf8(entry.secret.expose())
+                                                            .ok()
+                                                    }
+                                                };
+                                                if let Some(plaintext) = copied {
                                                     clipboard::copy_text(plaintext.to_string());
+                                                    record_audit_with(
+                                                        &ek,
+                                                        self.vault.as_deref(),
+                                                        AuditOperation::Get,
+                                                        Some((
+                                                            &entry.metadata.platform,
+                                                            &entry.metadata.user_id,
+                                                        )),
+                                                    );
                                                 }
-                                                self.show_toast("Password has been copied!");
+                                                self.show_toast(&format!(
+                                                    "{} has been copied!",
+                                                    typed.as_ref().map_or(
+                                                        "Password",
+                                                        TypedSecret::primary_label
+                                                    )
+                                                ));
                                             }
                                         }
                                     }
@@ -601,9 +880,139 @@ impl App {
                     KeyCode::Char('S') => {
                         self.state = AppState::SortSelection;
                     }
+                    KeyCode::Char('D') => match load_trash(self.vault.as_deref()) {
+                        Ok(entries) => {
+                            self.state = AppState::Trash {
+                                entries,
+                                selected: 0,
+                                confirm_purge: false,
+                            };
+                        }
+                        Err(e) => self.show_toast(&format!("Failed to load trash: {e}")),
+                    },
+                    KeyCode::Char('T') => match load_tags(self.vault.as_deref()) {
+                        Ok(tags) => {
+                            let selected = self
+                                .tag_filter
+                                .as_ref()
+                                .and_then(|tag| tags.iter().position(|t| t == tag))
+                                .map_or(0, |index| index + 1);
+                            self.state = AppState::TagFilter { tags, selected };
+                        }
+                        Err(e) => self.show_toast(&format!("Failed to load tags: {e}")),
+                    },
+                    KeyCode::Char('V') => match load_vaults() {
+                        Ok(vaults) => {
+                            let current = self.vault.as_deref().unwrap_or(DEFAULT_VAULT);
+                            let selected = vaults
+                                .iter()
+                                .position(|vault| vault.name.eq_ignore_ascii_case(current))
+                                .unwrap_or(0);
+                            self.state = AppState::VaultSelection { vaults, selected };
+                        }
+                        Err(e) => self.show_toast(&format!("Failed to load vaults: {e}")),
+                    },
                     _ => {}
                 }
             }
+            AppState::Trash {
+                entries,
+                selected,
+                confirm_purge,
+            } if *confirm_purge => match key.code {
+                KeyCode::Char('y') | KeyCode::Enter => {
+                    *confirm_purge = false;
+                    if let Some(entry) = entries.get(*selected) {
+                        let metadata = &entry.metadata;
+                        match purge_trashed(
+                            self.vault.as_deref(),
+                            &metadata.platform,
+                            &metadata.user_id,
+                        ) {
+                            Ok(()) => {
+                                entries.remove(*selected);
+                                *selected = (*selected).min(entries.len().saturating_sub(1));
+                                self.show_toast("Entry permanently deleted");
+                            }
+                            Err(e) => self.show_toast(&format!("Failed to delete: {e}")),
+                        }
+                    }
+                }
+                _ => *confirm_purge = false,
+            },
+            AppState::Trash {
+                entries,
+                selected,
+                confirm_purge,
+            } => match key.code {
+                KeyCode::Down | KeyCode::Char('j') if *selected + 1 < entries.len() => {
+                    *selected += 1;
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    *selected = selected.saturating_sub(1);
+                }
+                KeyCode::Enter | KeyCode::Char('r') => {
+                    if let Some(entry) = entries.get(*selected) {
+                        let metadata = &entry.metadata;
+                        match restore_trashed(
+                            self.vault.as_deref(),
+                            &metadata.platform,
+                            &metadata.user_id,
+                        ) {
+                            Ok(()) => {
+                                entries.remove(*selected);
+                                *selected = (*selected).min(entries.len().saturating_sub(1));
+                                self.refresh_vault_list();
+                                self.show_toast("Entry restored");
+                            }
+                            Err(e) => self.show_toast(&format!("Failed to restore: {e}")),
+                        }
+                    }
+                }
+                KeyCode::Char('x') if !entries.is_empty() => *confirm_purge = true,
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('D') => {
+                    transition_to_main = true;
+                }
+                _ => {}
+            },
+            AppState::TagFilter { tags, selected } => match key.code {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('T') => {
+                    self.state = AppState::MainTable;
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    *selected = (*selected + 1) % (tags.len() + 1);
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    *selected = selected.checked_sub(1).unwrap_or(tags.len());
+                }
+                KeyCode::Enter => {
+                    self.tag_filter = selected.checked_sub(1).map(|index| tags[index].clone());
+                    self.list_state.select(Some(0));
+                    transition_to_main = true;
+                }
+                _ => {}
+            },
+            AppState::VaultSelection { vaults, selected } => match key.code {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('V') => {
+                    self.state = AppState::MainTable;
+                }
+                KeyCode::Down | KeyCode::Char('j') if !vaults.is_empty() => {
+                    *selected = (*selected + 1) % vaults.len();
+                }
+                KeyCode::Up | KeyCode::Char('k') if !vaults.is_empty() => {
+                    *selected = selected.checked_sub(1).unwrap_or(vaults.len() - 1);
+                }
+                KeyCode::Enter => {
+                    if let Some(vault) = vaults.get(*selected) {
+                        self.vault = (vault.name != DEFAULT_VAULT).then(|| vault.name.clone());
+                        // Tags belong to a vault, so the filter does not carry over.
+                        self.tag_filter = None;
+                        self.list_state.select(Some(0));
+                    }
+                    transition_to_main = true;
+                }
+                _ => {}
+            },
             AppState::SortSelection => match key.code {
                 KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('S') => {
                     self.state = AppState::MainTable;
@@ -711,25 +1120,9 @@ impl App {
                             }
                             SetupStage::ConfirmPassword => {
                                 if password == confirm {
-                                    // Setup Logic
-                                    let mut config = config::Config::new().unwrap_or_default();
-                                    match crypto::hash_data(password.as_bytes()) {
-                                        Ok(hashed) => {
-                                            config.master_password_hash = Some(hashed.hash);
-                                            if config.save_config().is_ok() {
-                                                if let Ok(path) = keystore_path() {
-                                                    let _ =
-                                                        keystore::create_key_vault(password, &path);
-                                                }
-                                                transition_to_login = true;
-                                            } else {
-                                                *error = Some("Failed to save config".into());
-                                            }
-                                        }
-                                        Err(e) => {
-                                            *error = Some(format!("Hash error: {}", e));
-                                        }
-                                    }
+                                    // Setup runs from tick() so the calibration notice is drawn first.
+                                    *stage = SetupStage::Calibrating;
+                                    *error = None;
                                 } else {
                                     *error = Some("Passwords do not match".into());
                                     confirm.clear();
@@ -742,6 +1135,7 @@ impl App {
                                     *stage = SetupStage::EnterPassword;
                                 }
                             }
+                            SetupStage::Calibrating => {}
                         }
                     }
                     KeyCode::Backspace => match stage {
@@ -751,19 +1145,99 @@ impl App {
                         SetupStage::ConfirmPassword => {
                             confirm.pop();
                         }
+                        SetupStage::Calibrating => {}
                     },
                     KeyCode::Char(c) => match stage {
                         SetupStage::EnterPassword => password.push(c),
                         SetupStage::ConfirmPassword => confirm.push(c),
+                        SetupStage::Calibrating => {}
                     },
                     _ => {}
                 }
             }
+            AppState::EntryView {
+                platform,
+                user_id,
+                totp,
+                ..
+            } => match key.code {
+                KeyCode::Char('c') => {
+                    if let Some(totp) = totp {
+                        let code = totp.code_now();
+                        clipboard::copy_text(code.code);
+                        record_audit(
+                            self.vault.as_deref(),
+                            AuditOperation::Get,
+                            Some((platform, user_id)),
+                        );
+                        self.show_toast(&format!(
+                            "TOTP code copied, valid for {}s",
+                            code.remaining
+                        ));
+                    }
+                }
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('v') => {
+                    transition_to_main = true;
+                }
+                _ => {}
+            },
+            AppState::History {
+                platform,
+                user_id,
+                versions,
+                selected,
+            } => match key.code {
+                KeyCode::Down | KeyCode::Char('j') if *selected + 1 < versions.len() => {
+                    *selected += 1;
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    *selected = selected.saturating_sub(1);
+                }
+                KeyCode::Enter | KeyCode::Char('c') => {
+                    if let Some(version) = versions.get(*selected) {
+                        clipboard::copy_text(
+                            String::from_utf8_lossy(version.secret.expose()).into_owned(),
+                        );
+                        record_audit(
+                            self.vault.as_deref(),
+                            AuditOperation::Get,
+                            Some((platform, user_id)),
+                        );
+                        self.show_toast("Previous password copied");
+                    }
+                }
+                KeyCode::Char('r') => {
+                    if let Some(version) = versions.get(*selected) {
+                        match restore_history(self.vault.as_deref(), platform, user_id, version.id)
+                        {
+                            Ok(()) => {
+                                self.show_toast("Previous password restored");
+                                transition_to_main = true;
+                            }
+                            Err(e) => self.show_toast(&format!("Failed to restore: {e}")),
+                        }
+                    }
+                }
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('h') => {
+                    transition_to_main = true;
+                }
+                _ => {}
+            },
             AppState::RemoveConfirmation { platform, user_id } => match key.code {
                 KeyCode::Char('y') | KeyCode::Enter => {
                     if let Ok(db) = Database::new() {
-                        if let Ok(repository) = EntryRepository::new(&db, None) {
-                            let _ = repository.remove(EntrySelector::new(platform, user_id));
+                        let removed =
+                            open_repository(&db, self.vault.as_deref()).is_ok_and(|repository| {
+                                repository
+                                    .remove(EntrySelector::new(platform, user_id))
+                                    .is_ok()
+                            });
+                        if removed {
+                            record_audit(
+                                self.vault.as_deref(),
+                                AuditOperation::Remove,
+                                Some((platform, user_id)),
+                            );
                         }
                     }
                     transition_to_main = true;
@@ -776,8 +1250,12 @@ impl App {
             AppState::EditEntry {
                 platform,
                 original_user_id,
+                kind,
+                revision,
                 user_id,
                 password,
+                notes,
+                fields,
                 stage,
             } => match key.code {
                 KeyCode::Enter => match stage {
@@ -788,53 +1266,87 @@ impl App {
                     }
                     EditEntryStage::Password => {
                         if !password.value.is_empty() {
-                            entry_save_result = Some(update_entry(
-                                platform,
-                                original_user_id,
-                                &user_id.value,
-                                password.value.as_bytes(),
-                            ));
+                            *stage = EditEntryStage::Notes;
+                        }
+                    }
+                    EditEntryStage::Notes => *stage = EditEntryStage::Fields,
+                    EditEntryStage::Fields => {
+                        if !password.value.is_empty() {
+                            entry_save_result = Some(
+                                parse_fields(&fields.value)
+                                    .and_then(|custom_fields| {
+                                        Ok((custom_fields, entry_secret(*kind, &password.value)?))
+                                    })
+                                    .and_then(|(custom_fields, secret)| {
+                                        update_entry(
+                                            self.vault.as_deref(),
+                                            platform,
+                                            original_user_id,
+                                            EntryUpdate::new(&user_id.value, secret.expose())
+                                                .with_notes(&notes.value)
+                                                .with_fields(&custom_fields)
+                                                .with_expected_revision(*revision),
+                                        )
+                                    }),
+                            );
                         }
                     }
                 },
                 KeyCode::Left => match stage {
                     EditEntryStage::UserId => user_id.move_cursor_left(),
                     EditEntryStage::Password => password.move_cursor_left(),
+                    EditEntryStage::Notes => notes.move_cursor_left(),
+                    EditEntryStage::Fields => fields.move_cursor_left(),
                 },
                 KeyCode::Right => match stage {
                     EditEntryStage::UserId => user_id.move_cursor_right(),
                     EditEntryStage::Password => password.move_cursor_right(),
+                    EditEntryStage::Notes => notes.move_cursor_right(),
+                    EditEntryStage::Fields => fields.move_cursor_right(),
                 },
                 KeyCode::Char(c) => match stage {
                     EditEntryStage::UserId => user_id.insert_char(c),
                     EditEntryStage::Password => password.insert_char(c),
+                    EditEntryStage::Notes => notes.insert_char(c),
+                    EditEntryStage::Fields => fields.insert_char(c),
                 },
                 KeyCode::Backspace => match stage {
                     EditEntryStage::UserId => user_id.delete_char(),
                     EditEntryStage::Password => password.delete_char(),
+                    EditEntryStage::Notes => notes.delete_char(),
+                    EditEntryStage::Fields => fields.delete_char(),
+                },
+                KeyCode::Up => match stage {
+                    EditEntryStage::UserId => {}
+                    EditEntryStage::Password => *stage = EditEntryStage::UserId,
+                    EditEntryStage::Notes => *stage = EditEntryStage::Password,
+                    EditEntryStage::Fields => *stage = EditEntryStage::Notes,
+                },
+                KeyCode::Down => match stage {
+                    EditEntryStage::UserId => *stage = EditEntryStage::Password,
+                    EditEntryStage::Password => *stage = EditEntryStage::Notes,
+                    EditEntryStage::Notes => *stage = EditEntryStage::Fields,
+                    EditEntryStage::Fields => {}
                 },
-                KeyCode::Up => {
-                    if let EditEntryStage::Password = stage {
-                        *stage = EditEntryStage::UserId;
-                    }
-                }
-                KeyCode::Down => {
-                    if let EditEntryStage::UserId = stage {
-                        *stage = EditEntryStage::Password;
-                    }
-                }
                 KeyCode::Esc => {
                     transition_to_main = true;
                 }
                 _ => {}
             },
             AppState::AddEntry {
+                kind,
                 platform,
                 user_id,
                 password,
+                notes,
+                fields,
                 stage,
             } => match key.code {
                 KeyCode::Esc => transition_to_main = true,
+                KeyCode::Tab => {
+                    let next = EntryKind::ALL.iter().position(|k| k == kind).unwrap_or(0) + 1;
+                    *kind = EntryKind::ALL[next % EntryKind::ALL.len()];
+                }
                 KeyCode::Enter => match stage {
                     AddEntryStage::Platform => {
                         if !platform.value.is_empty() {
@@ -848,11 +1360,31 @@ impl App {
                     }
                     AddEntryStage::Password => {
                         if !password.value.is_empty() {
-                            entry_save_result = Some(save_entry(
-                                &platform.value,
-                                &user_id.value,
-                                password.value.as_bytes(),
-                            ));
+                            *stage = AddEntryStage::Notes;
+                        }
+                    }
+                    AddEntryStage::Notes => *stage = AddEntryStage::Fields,
+                    AddEntryStage::Fields => {
+                        if !password.value.is_empty() {
+                            entry_save_result = Some(
+                                parse_fields(&fields.value)
+                                    .and_then(|custom_fields| {
+                                        Ok((custom_fields, entry_secret(*kind, &password.value)?))
+                                    })
+                                    .and_then(|(custom_fields, secret)| {
+                                        save_entry(
+                                            self.vault.as_deref(),
+                                            NewEntry::new(
+                                                &platform.value,
+                                                &user_id.value,
+                                                secret.expose(),
+                                            )
+                                            .with_kind(*kind)
+                                            .with_notes(&notes.value)
+                                            .with_fields(&custom_fields),
+                                        )
+                                    }),
+                            );
                         }
                     }
                 },
@@ -860,31 +1392,43 @@ impl App {
                     AddEntryStage::Platform => platform.move_cursor_left(),
                     AddEntryStage::UserId => user_id.move_cursor_left(),
                     AddEntryStage::Password => password.move_cursor_left(),
+                    AddEntryStage::Notes => notes.move_cursor_left(),
+                    AddEntryStage::Fields => fields.move_cursor_left(),
                 },
                 KeyCode::Right => match stage {
                     AddEntryStage::Platform => platform.move_cursor_right(),
                     AddEntryStage::UserId => user_id.move_cursor_right(),
                     AddEntryStage::Password => password.move_cursor_right(),
+                    AddEntryStage::Notes => notes.move_cursor_right(),
+                    AddEntryStage::Fields => fields.move_cursor_right(),
                 },
                 KeyCode::Backspace => match stage {
                     AddEntryStage::Platform => platform.delete_char(),
                     AddEntryStage::UserId => user_id.delete_char(),
                     AddEntryStage::Password => password.delete_char(),
+                    AddEntryStage::Notes => notes.delete_char(),
+                    AddEntryStage::Fields => fields.delete_char(),
                 },
                 KeyCode::Up => match stage {
                     AddEntryStage::Platform => {}
                     AddEntryStage::UserId => *stage = AddEntryStage::Platform,
                     AddEntryStage::Password => *stage = AddEntryStage::UserId,
+                    AddEntryStage::Notes => *stage = AddEntryStage::Password,
+                    AddEntryStage::Fields => *stage = AddEntryStage::Notes,
                 },
                 KeyCode::Down => match stage {
                     AddEntryStage::Platform => *stage = AddEntryStage::UserId,
                     AddEntryStage::UserId => *stage = AddEntryStage::Password,
-                    AddEntryStage::Password => {}
+                    AddEntryStage::Password => *stage = AddEntryStage::Notes,
+                    AddEntryStage::Notes => *stage = AddEntryStage::Fields,
+                    AddEntryStage::Fields => {}
                 },
                 KeyCode::Char(c) => match stage {
                     AddEntryStage::Platform => platform.insert_char(c),
                     AddEntryStage::UserId => user_id.insert_char(c),
                     AddEntryStage::Password => password.insert_char(c),
+                    AddEntryStage::Notes => notes.insert_char(c),
+                    AddEntryStage::Fields => fields.insert_char(c),
                 },
                 _ => {}
             },
@@ -909,7 +1453,10 @@ impl App {
                                 )
                             });
                         match result {
-                            Ok(_) => self.show_toast("Backup written!"),
+                            Ok(_) => {
+                                record_audit(None, AuditOperation::BackupCreate, None);
+                                self.show_toast("Backup written!")
+                            }
                             Err(e) => self.auth_error = Some(e),
                         }
                         transition_to_main = true;
@@ -948,7 +1495,18 @@ impl App {
                                     &password.value,
                                     std::path::Path::new(&path.value),
                                 ) {
-                                    Ok(_) => self.show_toast("Backup restored. Restart RVault."),
+                                    Ok(_) => {
+                                        // The restored files may hold a different key.
+                                        if let Some(key) = master_key(&password.value) {
+                                            record_audit_with(
+                                                &key,
+                                                None,
+                                                AuditOperation::BackupRestore,
+                                                None,
+                                            );
+                                        }
+                                        self.show_toast("Backup restored. Restart RVault.")
+                                    }
                                     Err(e) => self.auth_error = Some(e),
                                 }
                                 transition_to_login = true;
@@ -984,6 +1542,67 @@ impl App {
                     _ => {}
                 }
             }
+            AppState::ChangePassword {
+                current,
+                new_password,
+                confirm,
+                stage,
+            } => match key.code {
+                KeyCode::Esc => {
+                    self.auth_error = None;
+                    transition_to_main = true;
+                }
+                KeyCode::Enter => match stage {
+                    ChangePasswordStage::Current => *stage = ChangePasswordStage::New,
+                    ChangePasswordStage::New => *stage = ChangePasswordStage::Confirm,
+                    ChangePasswordStage::Confirm => {
+                        if new_password.value != confirm.value {
+                            self.auth_error = Some("Passwords do not match.".into());
+                        } else {
+                            match Vault::change_master_password(&current.value, &new_password.value)
+                            {
+                                Ok(()) => {
+                                    self.auth_error = None;
+                                    self.show_toast("Master password changed!");
+                                    transition_to_main = true;
+                                }
+                                Err(e) => self.auth_error = Some(e),
+                            }
+                        }
+                    }
+                },
+                KeyCode::Up => {
+                    *stage = match stage {
+                        ChangePasswordStage::Current => ChangePasswordStage::Current,
+                        ChangePasswordStage::New => ChangePasswordStage::Current,
+                        ChangePasswordStage::Confirm => ChangePasswordStage::New,
+                    }
+                }
+                KeyCode::Down => {
+                    *stage = match stage {
+                        ChangePasswordStage::Current => ChangePasswordStage::New,
+                        ChangePasswordStage::New => ChangePasswordStage::Confirm,
+                        ChangePasswordStage::Confirm => ChangePasswordStage::Confirm,
+                    }
+                }
+                KeyCode::Left => {
+                    active_change_password_input(current, new_password, confirm, stage)
+                        .move_cursor_left()
+                }
+                KeyCode::Right => {
+                    active_change_password_input(current, new_password, confirm, stage)
+                        .move_cursor_right()
+                }
+                KeyCode::Backspace => {
+                    active_change_password_input(current, new_password, confirm, stage)
+                        .delete_char()
+                }
+                KeyCode::Char(c) => {
+                    active_change_password_input(current, new_password, confirm, stage)
+                        .insert_char(c)
+                }
+                _ => {}
+            },
             AppState::ExportEntry {
                 platform,
                 user_id,
@@ -995,7 +1614,13 @@ impl App {
                 KeyCode::Enter => match stage {
                     ExportEntryStage::Recipient => *stage = ExportEntryStage::Path,
                     ExportEntryStage::Path => {
-                        match export_one_entry(platform, user_id, &recipient.value, &path.value) {
+                        match export_one_entry(
+                            self.vault.as_deref(),
+                            platform,
+                            user_id,
+                            &recipient.value,
+                            &path.value,
+                        ) {
                             Ok(_) => self.show_toast("Export written!"),
                             Err(e) => self.auth_error = Some(e),
                         }
@@ -1012,27 +1637,36 @@ impl App {
             },
             AppState::ImportExport { path } => match key.code {
                 KeyCode::Esc => transition_to_main = true,
-                KeyCode::Enter => match preview_import_conflicts(&path.value) {
-                    Ok(0) => {
-                        match import_export_file(&path.value, false, false) {
-                            Ok((imported, skipped)) => {
-                                self.show_toast(&format!("Imported {imported}, skipped {skipped}"));
+                KeyCode::Enter => {
+                    match preview_import_conflicts(self.vault.as_deref(), &path.value) {
+                        Ok(0) => {
+                            match import_export_file(
+                                self.vault.as_deref(),
+                                &path.value,
+                                false,
+                                false,
+                            ) {
+                                Ok((imported, skipped)) => {
+                                    self.show_toast(&format!(
+                                        "Imported {imported}, skipped {skipped}"
+                                    ));
+                                }
+                                Err(e) => self.auth_error = Some(e),
                             }
-                            Err(e) => self.auth_error = Some(e),
+                            transition_to_main = true;
+                        }
+                        Ok(conflicts) => {
+                            self.state = AppState::ImportExportConfirm {
+                                path: path.value.clone(),
+                                conflicts,
+                            };
+                        }
+                        Err(e) => {
+                            self.auth_error = Some(e);
+                            transition_to_main = true;
                         }
-                        transition_to_main = true;
-                    }
-                    Ok(conflicts) => {
-                        self.state = AppState::ImportExportConfirm {
-                            path: path.value.clone(),
-                            conflicts,
-                        };
-                    }
-                    Err(e) => {
-                        self.auth_error = Some(e);
-                        transition_to_main = true;
                     }
-                },
+                }
                 KeyCode::Left => path.move_cursor_left(),
                 KeyCode::Right => path.move_cursor_right(),
                 KeyCode::Backspace => path.delete_char(),
@@ -1041,7 +1675,7 @@ impl App {
             },
             AppState::ImportExportConfirm { path, conflicts } => match key.code {
                 KeyCode::Char('y') | KeyCode::Enter => {
-                    match import_export_file(path, true, false) {
+                    match import_export_file(self.vault.as_deref(), path, true, false) {
                         Ok((imported, skipped)) => {
                             self.show_toast(&format!("Imported {imported}, skipped {skipped}"));
                         }
@@ -1050,7 +1684,7 @@ impl App {
                     transition_to_main = true;
                 }
                 KeyCode::Char('n') => {
-                    match import_export_file(path, false, true) {
+                    match import_export_file(self.vault.as_deref(), path, false, true) {
                         Ok((imported, skipped)) => {
                             self.show_toast(&format!("Imported {imported}, skipped {skipped}"));
                         }
@@ -1083,6 +1717,20 @@ impl App {
     }
 }
 
+/// Calibrates the keystore KDF for the configured unlock time, then writes config and keystore.
+fn complete_setup(password: &str) -> Result<(), String> {
+    let mut config = config::Config::new().unwrap_or_default();
+    let kdf_params = keystore::calibrate_kdf(Duration::from_millis(config.unlock_target_ms))?;
+    let hashed =
+        crypto::hash_data(password.as_bytes()).map_err(|e| format!("Hash error: {}", e))?;
+    config.master_password_hash = Some(hashed.hash);
+    config
+        .save_config()
+        .map_err(|_| "Failed to save config".to_string())?;
+    let path = keystore_path()?;
+    keystore::create_key_vault_with_params(password, &path, &kdf_params)
+}
+
 fn active_backup_create_input<'a>(
     path: &'a mut InputState,
     password: &'a mut InputState,
@@ -1122,6 +1770,19 @@ fn active_backup_restore_input<'a>(
     }
 }
 
+fn active_change_password_input<'a>(
+    current: &'a mut InputState,
+    new_password: &'a mut InputState,
+    confirm: &'a mut InputState,
+    stage: &ChangePasswordStage,
+) -> &'a mut InputState {
+    match stage {
+        ChangePasswordStage::Current => current,
+        ChangePasswordStage::New => new_password,
+        ChangePasswordStage::Confirm => confirm,
+    }
+}
+
 fn active_export_input<'a>(
     recipient: &'a mut InputState,
     path: &'a mut InputState,
@@ -1134,13 +1795,15 @@ fn active_export_input<'a>(
 }
 
 fn export_one_entry(
+    vault: Option<&str>,
     platform: &str,
     user_id: &str,
     recipient: &str,
     path: &str,
 ) -> Result<(), String> {
     let db = Database::new().map_err(|e| e.to_string())?;
-    let repository = EntryRepository::new(&db, None).map_err(|e| e.to_string())?;
+    let repository =
+        EntryRepository::new(&db, vault.map(str::to_owned)).map_err(|e| e.to_string())?;
     let key = SessionKey::load().map_err(|e| e.to_string())?;
     let decrypted = repository
         .get(&key, EntrySelector::new(platform, user_id))
@@ -1155,54 +1818,70 @@ fn export_one_entry(
         pinned: decrypted.metadata.pinned,
         created_at: decrypted.metadata.created_at,
         updated_at: decrypted.metadata.updated_at,
+        notes: decrypted.details.notes.clone(),
+        fields: decrypted.details.fields.clone(),
+        kind: decrypted.metadata.kind,
     };
     let bytes = portable_export::create_export_bytes(recipient, &[entry])?;
-    std::fs::write(path, bytes).map_err(|e| format!("write export: {e}"))
+    std::fs::write(path, bytes).map_err(|e| format!("write export: {e}"))?;
+    record_audit_with(
+        &key,
+        vault,
+        AuditOperation::Export,
+        Some((platform, user_id)),
+    );
+    Ok(())
 }
 
 #[allow(deprecated)] // 1.4 import boundary: preserves the existing conflict preview.
-fn preview_import_conflicts(path: &str) -> Result<usize, String> {
+fn preview_import_conflicts(vault: Option<&str>, path: &str) -> Result<usize, String> {
     let entries = decrypt_export_file(path)?;
     let db = Database::new().map_err(|e| e.to_string())?;
-    let table = Table::new(&db, None).map_err(|e| e.to_string())?;
+    let repository = open_repository(&db, vault).map_err(|e| e.to_string())?;
     entries.iter().try_fold(0, |count, entry| {
-        table
-            .entry_exists(&db, &entry.platform, &entry.user_id)
+        repository
+            .contains(EntrySelector::new(&entry.platform, &entry.user_id))
             .map(|exists| count + usize::from(exists))
             .map_err(|e| e.to_string())
     })
 }
 
-#[allow(deprecated)] // 1.4 import boundary: preserves imported timestamps and pin state.
+/// Imports the entries of an export file in one batch, so a failure imports none of them.
 fn import_export_file(
+    vault: Option<&str>,
     path: &str,
     overwrite_all: bool,
     skip_all: bool,
 ) -> Result<(usize, usize), String> {
     let entries = decrypt_export_file(path)?;
     let db = Database::new().map_err(|e| e.to_string())?;
-    let table = Table::new(&db, None).map_err(|e| e.to_string())?;
     let key = SessionKey::load().map_err(|e| e.to_string())?;
-    let mut imported = 0;
+    let repository = EntryRepository::unlocked(&db, vault.map(str::to_owned), &key)
+        .map_err(|e| e.to_string())?;
+    let mut selected = Vec::new();
     let mut skipped = 0;
-    for entry in entries {
-        let exists = table
-            .entry_exists(&db, &entry.platform, &entry.user_id)
+    for entry in &entries {
+        let exists = repository
+            .contains(EntrySelector::new(&entry.platform, &entry.user_id))
             .map_err(|e| e.to_string())?;
-        if exists && skip_all {
-            skipped += 1;
-            continue;
-        }
-        if exists && !overwrite_all {
+        if exists && (skip_all || !overwrite_all) {
             skipped += 1;
             continue;
         }
-        table
-            .import_entry_with_key_result(&db, key.as_bytes(), &entry)
-            .map_err(|e| e.to_string())?;
-        imported += 1;
+        selected.push(entry);
     }
-    Ok((imported, skipped))
+    let report = repository
+        .apply_batch(&key, selected.iter().map(|entry| BatchOp::Import(entry)))
+        .map_err(|e| e.to_string())?;
+    if let Some((index, error)) = report.first_failure() {
+        let entry = selected[index];
+        return Err(format!(
+            "{} / {}: {error}; nothing was imported",
+            entry.platform, entry.user_id
+        ));
+    }
+    record_audit_with(&key, vault, AuditOperation::Import, None);
+    Ok((selected.len(), skipped))
 }
 
 fn decrypt_export_file(path: &str) -> Result<Vec<portable_export::ExportEntry>, String> {
@@ -1244,46 +1923,346 @@ fn recover_duplicate_add(
 fn add_or_update_entry(
     repository: &EntryRepository<'_>,
     key: &rvault_core::SecretKey,
-    platform: &str,
-    user_id: &str,
-    password: &[u8],
+    entry: NewEntry<'_>,
 ) -> Result<(), StorageError> {
-    recover_duplicate_add(
-        repository.add(key, NewEntry::new(platform, user_id, password)),
-        || {
-            repository.update(
-                key,
-                EntrySelector::new(platform, user_id),
-                EntryUpdate::new(user_id, password),
-            )
-        },
-    )
+    // Every field is a borrow, so this copies them and `entry` stays usable.
+    let NewEntry {
+        platform,
+        user_id,
+        secret,
+        notes,
+        fields,
+        ..
+    } = entry;
+    recover_duplicate_add(repository.add(key, entry), || {
+        repository.update(
+            key,
+            EntrySelector::new(platform, user_id),
+            EntryUpdate::new(user_id, secret)
+                .with_notes(notes)
+                .with_fields(fields),
+        )
+    })
 }
 
-fn save_entry(platform: &str, user_id: &str, password: &[u8]) -> Result<(), String> {
+/// Opens the vault with the session key when there is one, so vaults with encrypted
+/// metadata can be listed and edited.
+fn open_repository<'a>(
+    db: &'a Database,
+    vault: Option<&str>,
+) -> Result<EntryRepository<'a>, StorageError> {
+    let vault = vault.map(str::to_owned);
+    match SessionKey::load() {
+        Ok(key) => EntryRepository::unlocked(db, vault, &key),
+        Err(_) => EntryRepository::new(db, vault),
+    }
+}
+
+fn save_entry(vault: Option<&str>, entry: NewEntry<'_>) -> Result<(), String> {
     let db = Database::new().map_err(|error| error.to_string())?;
-    let repository = EntryRepository::new(&db, None).map_err(|error| error.to_string())?;
+    let repository =
+        EntryRepository::new(&db, vault.map(str::to_owned)).map_err(|error| error.to_string())?;
     let key = SessionKey::load().map_err(|error| error.to_string())?;
-    add_or_update_entry(&repository, &key, platform, user_id, password)
-        .map_err(|error| error.to_string())
+    let (platform, user_id) = (entry.platform, entry.user_id);
+    add_or_update_entry(&repository, &key, entry).map_err(|error| error.to_string())?;
+    record_audit_with(&key, vault, AuditOperation::Add, Some((platform, user_id)));
+    Ok(())
 }
 
 fn update_entry(
+    vault: Option<&str>,
     platform: &str,
     original_user_id: &str,
+    update: EntryUpdate<'_>,
+) -> Result<(), String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository =
+        EntryRepository::new(&db, vault.map(str::to_owned)).map_err(|error| error.to_string())?;
+    let key = SessionKey::load().map_err(|error| error.to_string())?;
+    let user_id = update.user_id;
+    repository
+        .update(&key, EntrySelector::new(platform, original_user_id), update)
+        .map_err(|error| error.to_string())?;
+    record_audit_with(
+        &key,
+        vault,
+        AuditOperation::Update,
+        Some((platform, user_id)),
+    );
+    Ok(())
+}
+
+/// Decrypted parts of one entry, read for the entry view and the edit dialog.
+struct LoadedEntry {
+    details: EntryDetails,
+    totp: Option<TotpSecret>,
+    typed: Option<TypedSecret>,
+    revision: i64,
+}
+
+fn load_entry(vault: Option<&str>, platform: &str, user_id: &str) -> Result<LoadedEntry, String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository =
+        EntryRepository::new(&db, vault.map(str::to_owned)).map_err(|error| error.to_string())?;
+    let key = SessionKey::load().map_err(|error| error.to_string())?;
+    let entry = repository
+        .get(&key, EntrySelector::new(platform, user_id))
+        .map_err(|error| error.to_string())?;
+    let typed = entry.typed_secret().map_err(|error| error.to_string())?;
+    Ok(LoadedEntry {
+        revision: entry.metadata.revision,
+        details: entry.details,
+        totp: entry.totp,
+        typed,
+    })
+}
+
+fn load_vaults() -> Result<Vec<VaultInfo>, String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    db.vaults().map_err(|error| error.to_string())
+}
+
+fn load_tags(vault: Option<&str>) -> Result<Vec<String>, String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository = open_repository(&db, vault).map_err(|error| error.to_string())?;
+    repository.tags().map_err(|error| error.to_string())
+}
+
+fn load_trash(vault: Option<&str>) -> Result<Vec<TrashedEntry>, String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository = open_repository(&db, vault).map_err(|error| error.to_string())?;
+    repository.trash().map_err(|error| error.to_string())
+}
+
+fn restore_trashed(vault: Option<&str>, platform: &str, user_id: &str) -> Result<(), String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository = open_repository(&db, vault).map_err(|error| error.to_string())?;
+    repository
+        .restore_trashed(EntrySelector::new(platform, user_id))
+        .map_err(|error| error.to_string())
+}
+
+fn purge_trashed(vault: Option<&str>, platform: &str, user_id: &str) -> Result<(), String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository = open_repository(&db, vault).map_err(|error| error.to_string())?;
+    repository
+        .purge_trashed(EntrySelector::new(platform, user_id))
+        .map_err(|error| error.to_string())
+}
+
+fn load_history(
+    vault: Option<&str>,
+    platform: &str,
     user_id: &str,
-    password: &[u8],
+) -> Result<Vec<HistoryEntry>, String> {
+    let db = Database::new().map_err(|error| error.to_string())?;
+    let repository =
+        EntryRepository::new(&db, vault.map(str::to_owned)).map_err(|error| error.to_string())?;
+    let key = SessionKey::load().map_err(|error| error.to_string())?;
+    repository
+        .history(&key, EntrySelector::new(platform, user_id))
+        .map_err(|error| error.to_string())
+}
+
+fn restore_history(
+    vault: Option<&str>,
+    platform: &str,
+    user_id: &str,
+    history_id: i64,
 ) -> Result<(), String> {
     let db = Database::new().map_err(|error| error.to_string())?;
-    let repository = EntryRepository::new(&db, None).map_err(|error| error.to_string())?;
+    let repository =
+        EntryRepository::new(&db, vault.map(str::to_owned)).map_err(|error| error.to_string())?;
     let key = SessionKey::load().map_err(|error| error.to_string())?;
     repository
-        .update(
-            &key,
-            EntrySelector::new(platform, original_user_id),
-            EntryUpdate::new(user_id, password),
+        .restore_history(&key, EntrySelector::new(platform, user_id), history_id)
+        .map_err(|error| error.to_string())?;
+    record_audit_with(
+        &key,
+        vault,
+        AuditOperation::Update,
+        Some((platform, user_id)),
+    );
+    Ok(())
+}
+
+/// Records a TUI operation in the audit log with the session key, if there is one.
+fn record_audit(vault: Option<&str>, operation: AuditOperation, entry: Option<(&str, &str)>) {
+    if let Ok(key) = SessionKey::load() {
+        record_audit_with(&key, vault, operation, entry);
+    }
+}
+
+/// Records a TUI operation in the audit log. Failures are ignored so the log never gets in the
+/// way of the operation itself.
+fn record_audit_with(
+    key: &rvault_core::SecretKey,
+    vault: Option<&str>,
+    operation: AuditOperation,
+    entry: Option<(&str, &str)>,
+) {
+    let mut event = AuditEvent::new(operation, AuditSource::Tui).in_vault(vault);
+    if let Some((platform, user_id)) = entry {
+        event = event.for_entry(platform, user_id);
+    }
+    if let Ok(db) = Database::new() {
+        let _ = db.record_audit(key, &event);
+    }
+}
+
+/// Encryption key unlocked by `master_password` with the current configuration.
+fn master_key(master_password: &str) -> Option<rvault_core::SecretKey> {
+    let stored_hash = config::Config::new().ok()?.master_password_hash?;
+    Vault::get_encryption_key(master_password, &stored_hash)
+        .ok()
+        .map(rvault_core::SecretKey::from_bytes)
+}
+
+/// Parses the single-line fields input: `name=value` pairs separated by `;`, where a leading
+/// `*` marks the field secret.
+fn parse_fields(input: &str) -> Result<Vec<CustomField>, String> {
+    input
+        .split(';')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let (secret, field) = match field.strip_prefix('*') {
+                Some(field) => (true, field),
+                None => (false, field),
+            };
+            match field.split_once('=') {
+                Some((name, value)) if !name.trim().is_empty() => {
+                    Ok(CustomField::new(name.trim(), value.trim(), secret))
+                }
+                _ => Err(format!("field '{field}' must use name=value")),
+            }
+        })
+        .collect()
+}
+
+fn format_fields(fields: &[CustomField]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            let marker = if field.secret { "*" } else { "" };
+            format!("{marker}{}={}", field.name, field.value)
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The secret stored for a `kind` entry from the secret input: the password of a login, the
+/// text of a note, or `name=value` pairs separated by `;` for the other kinds.
+fn entry_secret(kind: EntryKind, input: &str) -> Result<SecretBytes, String> {
+    match kind {
+        EntryKind::Login => Ok(SecretBytes::new(input.as_bytes().to_vec())),
+        kind => parse_typed_secret(kind, input).map(|secret| secret.to_bytes()),
+    }
+}
+
+fn parse_typed_secret(kind: EntryKind, input: &str) -> Result<TypedSecret, String> {
+    if kind == EntryKind::Note {
+        return SecureNote::new(input.trim()).map(TypedSecret::Note);
+    }
+    let mut values = std::collections::HashMap::new();
+    for pair in input
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let Some((name, value)) = pair.split_once('=') else {
+            return Err(format!("'{pair}' must use name=value"));
+        };
+        let name = name.trim();
+        if !typed_secret_names(kind).contains(&name) {
+            return Err(format!("a {kind} has no '{name}'"));
+        }
+        values.insert(name, value.trim().to_string());
+    }
+    let mut take = |name: &str| values.remove(name).unwrap_or_default();
+    match kind {
+        EntryKind::Card => Card::new(
+            &take("holder"),
+            &take("number"),
+            take("expiry").parse()?,
+            &take("cvv"),
         )
-        .map_err(|error| error.to_string())
+        .map(TypedSecret::Card),
+        EntryKind::Identity => {
+            let mut identity = Identity::default();
+            identity.full_name = take("name");
+            identity.email = take("email");
+            identity.phone = take("phone");
+            identity.address = take("address");
+            identity.city = take("city");
+            identity.postal_code = take("postal_code");
+            identity.country = take("country");
+            identity.validated().map(TypedSecret::Identity)
+        }
+        EntryKind::ApiKey => {
+            let expires = take("expires");
+            let expires_on = (!expires.is_empty())
+                .then(|| {
+                    chrono::NaiveDate::parse_from_str(&expires, "%Y-%m-%d")
+                        .map_err(|_| format!("'{expires}' is not a YYYY-MM-DD date"))
+                })
+                .transpose()?;
+            ApiKey::new(&take("token"), expires_on).map(TypedSecret::ApiKey)
+        }
+        EntryKind::Login | EntryKind::Note => unreachable!("handled above"),
+    }
+}
+
+/// Names accepted by [`parse_typed_secret`] for each kind, in the order they are formatted.
+fn typed_secret_names(kind: EntryKind) -> &'static [&'static str] {
+    match kind {
+        EntryKind::Login | EntryKind::Note => &[],
+        EntryKind::Card => &["number", "expiry", "cvv", "holder"],
+        EntryKind::Identity => &[
+            "name",
+            "email",
+            "phone",
+            "address",
+            "city",
+            "postal_code",
+            "country",
+        ],
+        EntryKind::ApiKey => &["token", "expires"],
+    }
+}
+
+fn format_typed_secret(secret: &TypedSecret) -> String {
+    let values: Vec<String> = match secret {
+        TypedSecret::Note(note) => return note.text.clone(),
+        TypedSecret::Card(card) => vec![
+            card.number.clone(),
+            card.expiry.to_string(),
+            card.cvv.clone(),
+            card.holder.clone(),
+        ],
+        TypedSecret::Identity(identity) => vec![
+            identity.full_name.clone(),
+            identity.email.clone(),
+            identity.phone.clone(),
+            identity.address.clone(),
+            identity.city.clone(),
+            identity.postal_code.clone(),
+            identity.country.clone(),
+        ],
+        TypedSecret::ApiKey(key) => vec![
+            key.token.clone(),
+            key.expires_on
+                .map(|date| date.to_string())
+                .unwrap_or_default(),
+        ],
+    };
+    typed_secret_names(secret.kind())
+        .iter()
+        .zip(values)
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[cfg(test)]
@@ -1295,9 +2274,12 @@ mod tests {
     fn uppercase_q_is_text_while_entering_a_secret() {
         let mut app = App::new();
         app.state = AppState::AddEntry {
+            kind: EntryKind::Login,
             platform: InputState::with_value("service".to_string()),
             user_id: InputState::with_value("account".to_string()),
             password: InputState::new(),
+            notes: InputState::new(),
+            fields: InputState::new(),
             stage: AddEntryStage::Password,
         };
 
@@ -1316,9 +2298,12 @@ mod tests {
     fn pasted_secret_is_inserted_atomically_including_uppercase_q() {
         let mut app = App::new();
         app.state = AppState::AddEntry {
+            kind: EntryKind::Login,
             platform: InputState::with_value("service".to_string()),
             user_id: InputState::with_value("account".to_string()),
             password: InputState::new(),
+            notes: InputState::new(),
+            fields: InputState::new(),
             stage: AddEntryStage::Password,
         };
 
@@ -1331,6 +2316,100 @@ mod tests {
         assert_eq!(password.cursor_position, "api-key-Qxy".len());
     }
 
+    #[test]
+    fn purging_from_the_trash_needs_confirmation() {
+        let mut app = App::new();
+        app.state = AppState::Trash {
+            entries: vec![TrashedEntry {
+                metadata: EntryMetadata {
+                    platform: "github".to_string(),
+                    user_id: "alice".to_string(),
+                    pinned: false,
+                    id: None,
+                    created_at: 0,
+                    updated_at: 0,
+                    folder: None,
+                    tags: Vec::new(),
+                    urls: Vec::new(),
+                    kind: EntryKind::Login,
+                    revision: 1,
+                },
+                deleted_at: 0,
+            }],
+            selected: 0,
+            confirm_purge: false,
+        };
+
+        app.on_key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE))
+            .expect("handle key");
+        assert!(matches!(
+            app.state,
+            AppState::Trash {
+                confirm_purge: true,
+                ..
+            }
+        ));
+
+        app.on_key(KeyEvent::new(KeyCode::Char('n'), KeyModifiers::NONE))
+            .expect("handle key");
+        let AppState::Trash {
+            entries,
+            confirm_purge,
+            ..
+        } = &app.state
+        else {
+            panic!("expected trash state");
+        };
+        assert!(!confirm_purge);
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn fields_input_round_trips_with_secret_markers() {
+        let fields = parse_fields("Account = 123; *Answer=Rex;; ").expect("valid fields");
+
+        assert_eq!(
+            fields,
+            vec![
+                CustomField::new("Account", "123", false),
+                CustomField::new("Answer", "Rex", true),
+            ]
+        );
+        assert_eq!(format_fields(&fields), "Account=123; *Answer=Rex");
+        assert!(parse_fields("missing value").is_err());
+    }
+
+    #[test]
+    fn typed_secret_input_round_trips_per_kind() {
+        let card = parse_typed_secret(
+            EntryKind::Card,
+            "number=4111 1111 1111 1111; expiry=09/27; holder=Jane Doe",
+        )
+        .expect("valid card");
+        assert_eq!(
+            format_typed_secret(&card),
+            "number=4111111111111111; expiry=09/2027; holder=Jane Doe"
+        );
+        let key = parse_typed_secret(EntryKind::ApiKey, "token=t0k3n; expires=2027-01-31")
+            .expect("valid API key");
+        assert_eq!(
+            parse_typed_secret(EntryKind::ApiKey, &format_typed_secret(&key)).unwrap(),
+            key
+        );
+        assert_eq!(
+            entry_secret(EntryKind::Note, "  door code 1234 ")
+                .map(|secret| TypedSecret::from_bytes(EntryKind::Note, secret.expose())),
+            Ok(Ok(TypedSecret::Note(
+                SecureNote::new("door code 1234").unwrap()
+            )))
+        );
+        assert!(
+            parse_typed_secret(EntryKind::Card, "number=4111111111111112; expiry=09/27").is_err()
+        );
+        assert!(parse_typed_secret(EntryKind::Identity, "token=t0k3n").is_err());
+        assert!(parse_typed_secret(EntryKind::ApiKey, "token=t; expires=soon").is_err());
+    }
+
     #[test]
     fn entry_save_error_is_shown_in_the_tui() {
         let mut app = App::new();
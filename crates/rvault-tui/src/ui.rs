@@ -1,6 +1,6 @@
 use crate::app::{
-    AddEntryStage, App, AppState, BackupCreateStage, BackupRestoreStage, EditEntryStage,
-    ExportEntryStage, SetupStage, SortMode,
+    AddEntryStage, App, AppState, BackupCreateStage, BackupRestoreStage, ChangePasswordStage,
+    EditEntryStage, ExportEntryStage, SetupStage, SortMode,
 }; // Added EditEntryStage
 use crate::input::InputState; // Import InputState
 use chrono::{DateTime, Local};
@@ -14,7 +14,11 @@ use ratatui::{
         Paragraph, Row, Table, TableState, Tabs,
     },
 };
-use rvault_core::storage::EntryMetadata;
+use rvault_core::kind::{EntryKind, TypedSecret};
+use rvault_core::storage::{
+    DEFAULT_VAULT, EntryDetails, EntryMetadata, HistoryEntry, TrashedEntry, VaultInfo,
+};
+use rvault_core::totp::TotpSecret;
 
 const PASSWORD_MASK_SYMBOL: &str = "•";
 
@@ -146,7 +150,18 @@ pub fn draw(f: &mut Frame, app: &mut App) {
     match &app.state {
         AppState::Authentication(input) => draw_auth(f, input, &app.auth_error, theme),
         AppState::MainTable => {
-            draw_main(f, &app.items, &mut app.list_state, &app.auth_error, theme)
+            draw_main(
+                f,
+                &app.items,
+                &mut app.list_state,
+                &app.auth_error,
+                app.vault.as_deref().unwrap_or(DEFAULT_VAULT),
+                app.tag_filter.as_deref(),
+                theme,
+            );
+            if app.filter_editing || !app.filter.is_empty() {
+                draw_filter_bar(f, &app.filter, app.filter_editing, theme);
+            }
         }
         AppState::Generator => draw_generator(f, app.gen_length, app.gen_special, theme),
         AppState::Setup {
@@ -158,27 +173,59 @@ pub fn draw(f: &mut Frame, app: &mut App) {
         AppState::RemoveConfirmation { platform, user_id } => {
             draw_remove_confirmation(f, platform, user_id, theme)
         }
+        AppState::EntryView {
+            platform,
+            user_id,
+            details,
+            totp,
+            typed,
+        } => draw_entry_view(
+            f,
+            platform,
+            user_id,
+            details,
+            totp.as_ref(),
+            typed.as_ref(),
+            theme,
+        ),
+        AppState::History {
+            platform,
+            user_id,
+            versions,
+            selected,
+        } => draw_history(f, platform, user_id, versions, *selected, theme),
         AppState::EditEntry {
             platform,
-            original_user_id,
+            kind,
             user_id,
             password,
+            notes,
+            fields,
             stage,
+            ..
         } => draw_edit_entry(
             f,
             platform,
-            original_user_id,
-            user_id,
-            password,
+            *kind,
+            [user_id, password, notes, fields],
             stage,
             theme,
         ),
         AppState::AddEntry {
+            kind,
             platform,
             user_id,
             password,
+            notes,
+            fields,
+            stage,
+        } => draw_add_entry(
+            f,
+            *kind,
+            [platform, user_id, password, notes, fields],
             stage,
-        } => draw_add_entry(f, platform, user_id, password, stage, theme),
+            theme,
+        ),
         AppState::BackupCreate {
             path,
             password,
@@ -190,6 +237,20 @@ pub fn draw(f: &mut Frame, app: &mut App) {
             confirm,
             stage,
         } => draw_backup_restore(f, path, password, confirm, stage, theme),
+        AppState::ChangePassword {
+            current,
+            new_password,
+            confirm,
+            stage,
+        } => draw_change_password(
+            f,
+            current,
+            new_password,
+            confirm,
+            stage,
+            &app.auth_error,
+            theme,
+        ),
         AppState::ExportEntry {
             platform,
             user_id,
@@ -203,6 +264,15 @@ pub fn draw(f: &mut Frame, app: &mut App) {
         }
         AppState::ThemeSelection => draw_theme_selection(f, &app.themes, theme),
         AppState::SortSelection => draw_sort_selection(f, &app.sort_mode, theme),
+        AppState::TagFilter { tags, selected } => draw_tag_filter(f, tags, *selected, theme),
+        AppState::Trash {
+            entries,
+            selected,
+            confirm_purge,
+        } => draw_trash(f, entries, *selected, *confirm_purge, theme),
+        AppState::VaultSelection { vaults, selected } => {
+            draw_vault_selection(f, vaults, *selected, theme)
+        }
     }
 
     if let Some(toast) = &app.toast {
@@ -210,6 +280,101 @@ pub fn draw(f: &mut Frame, app: &mut App) {
     }
 }
 
+fn draw_tag_filter(f: &mut Frame, tags: &[String], selected: usize, theme: &Theme) {
+    let area = centered_rect_fixed(40, 20, f.area());
+    draw_shadow(f, area);
+
+    let items: Vec<ListItem> = std::iter::once("All entries".to_string())
+        .chain(tags.iter().map(|tag| format!("#{tag}")))
+        .enumerate()
+        .map(|(index, label)| {
+            let style = if index == selected {
+                Style::default()
+                    .bg(theme.accent)
+                    .fg(theme.bg)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(theme.text)
+            };
+            ListItem::new(Line::from(label)).style(style)
+        })
+        .collect();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(area);
+
+    let list = List::new(items).block(
+        Block::default()
+            .title(" 🏷️  Filter by Tag ")
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(theme.accent))
+            .style(Style::default().bg(theme.surface).fg(theme.text)),
+    );
+
+    f.render_widget(Clear, area);
+    f.render_widget(list, chunks[0]);
+
+    let help_text = Paragraph::new("Press <Enter> to filter")
+        .style(Style::default().fg(theme.muted))
+        .alignment(ratatui::layout::Alignment::Center);
+
+    f.render_widget(help_text, chunks[1]);
+}
+
+fn draw_vault_selection(f: &mut Frame, vaults: &[VaultInfo], selected: usize, theme: &Theme) {
+    let area = centered_rect_fixed(50, 20, f.area());
+    draw_shadow(f, area);
+
+    let items: Vec<ListItem> = vaults
+        .iter()
+        .enumerate()
+        .map(|(index, vault)| {
+            let style = if index == selected {
+                Style::default()
+                    .bg(theme.accent)
+                    .fg(theme.bg)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(theme.text)
+            };
+            let lock = if vault.encrypted_metadata {
+                " 🔒"
+            } else {
+                ""
+            };
+            let team = if vault.shared { " 👥" } else { "" };
+            let label = format!("{}{lock}{team} ({} entries)", vault.name, vault.entries);
+            ListItem::new(Line::from(label)).style(style)
+        })
+        .collect();
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(area);
+
+    let list = List::new(items).block(
+        Block::default()
+            .title(" 🗄️  Switch Vault ")
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(theme.accent))
+            .style(Style::default().bg(theme.surface).fg(theme.text)),
+    );
+
+    f.render_widget(Clear, area);
+    f.render_widget(list, chunks[0]);
+
+    let help_text = Paragraph::new("Press <Enter> to open")
+        .style(Style::default().fg(theme.muted))
+        .alignment(ratatui::layout::Alignment::Center);
+
+    f.render_widget(help_text, chunks[1]);
+}
+
 fn draw_sort_selection(f: &mut Frame, current_mode: &SortMode, theme: &Theme) {
     let area = centered_rect_fixed(40, 20, f.area());
     draw_shadow(f, area);
@@ -383,11 +548,12 @@ fn draw_setup(
     let stage_text = match stage {
         SetupStage::EnterPassword => "Create Master Password",
         SetupStage::ConfirmPassword => "Confirm Master Password",
+        SetupStage::Calibrating => "Calibrating key derivation...",
     };
 
     let active_password = match stage {
         SetupStage::EnterPassword => password,
-        SetupStage::ConfirmPassword => confirm,
+        SetupStage::ConfirmPassword | SetupStage::Calibrating => confirm,
     };
 
     f.render_widget(Clear, area);
@@ -437,6 +603,8 @@ fn draw_main(
     items: &[EntryMetadata],
     list_state: &mut ListState,
     error: &Option<String>,
+    vault: &str,
+    tag_filter: Option<&str>,
     theme: &Theme,
 ) {
     let chunks = Layout::default()
@@ -515,7 +683,10 @@ fn draw_main(
         .border_type(BorderType::Rounded)
         .border_style(Style::default().fg(theme.muted))
         .title(Span::styled(
-            " Entries ",
+            match tag_filter {
+                Some(tag) => format!(" Entries in {vault} tagged #{tag} "),
+                None => format!(" Entries in {vault} "),
+            },
             Style::default().fg(theme.text).add_modifier(Modifier::BOLD),
         ));
     let entries_inner = entries_block.inner(chunks[1]);
@@ -558,11 +729,47 @@ fn draw_main(
     draw_help(
         f,
         chunks[2],
-        "Navigate: ↑/↓ | Copy: Enter | Pin: p | Add: a | Export: x | Import: m | Backup: b | Restore: r | Identity: i | Sort: S | Lock: Q | Quit: q",
+        "Navigate: ↑/↓ | Copy: Enter | Pin: p | Add: a | Export: x | Import: m | Backup: b | Restore: r | Identity: i | Sort: S | Search: / | Tags: T | Vaults: V | Trash: D | Passwd: P | Lock: Q | Quit: q",
         theme,
     );
 }
 
+/// Replaces the help line of the main table with the search query.
+fn draw_filter_bar(f: &mut Frame, filter: &str, editing: bool, theme: &Theme) {
+    let area = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(3),
+        ])
+        .split(f.area())[2];
+    let hint = if editing {
+        "  (Enter: keep | Esc: clear)"
+    } else {
+        "  (/: edit | Esc: clear)"
+    };
+    let bar = Paragraph::new(Line::from(vec![
+        Span::styled("/ ", Style::default().fg(theme.accent)),
+        Span::styled(filter, Style::default().fg(theme.text)),
+        Span::styled(hint, Style::default().fg(theme.muted)),
+    ]))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(if editing { theme.accent } else { theme.muted }))
+            .title(" Search ")
+            .style(Style::default().bg(theme.bg)),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(bar, area);
+    if editing {
+        let x = area.x + 3 + Span::raw(filter).width() as u16;
+        f.set_cursor_position((x.min(area.right().saturating_sub(2)), area.y + 1));
+    }
+}
+
 fn format_updated_at(updated_at: i64) -> String {
     if updated_at > 0 {
         let dt = DateTime::from_timestamp(updated_at, 0).unwrap_or_default();
@@ -710,7 +917,7 @@ fn draw_remove_confirmation(f: &mut Frame, platform: &str, user_id: &str, theme:
 
     let text = vec![
         Line::from(Span::styled(
-            "Are you sure you want to remove:",
+            "Move this entry to the trash?",
             Style::default().fg(theme.text),
         )),
         Line::from(""),
@@ -744,16 +951,264 @@ fn draw_remove_confirmation(f: &mut Frame, platform: &str, user_id: &str, theme:
     f.render_widget(p, area);
 }
 
+fn draw_entry_view(
+    f: &mut Frame,
+    platform: &str,
+    user_id: &str,
+    details: &EntryDetails,
+    totp: Option<&TotpSecret>,
+    typed: Option<&TypedSecret>,
+    theme: &Theme,
+) {
+    let area = centered_rect_fixed(60, 20, f.area());
+    draw_shadow(f, area);
+
+    let block = Block::default()
+        .title(format!(" 🔎 {platform} - {user_id} "))
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(theme.accent))
+        .style(Style::default().bg(theme.surface).fg(theme.text));
+
+    let label = Style::default().fg(theme.muted);
+    let mut text = Vec::new();
+    if let Some(secret) = typed {
+        let mut kind = vec![Span::styled(format!("Kind:   {}", secret.kind()), label)];
+        if secret.is_expired(chrono::Local::now().date_naive()) {
+            kind.push(Span::styled("  expired", Style::default().fg(theme.error)));
+        }
+        text.push(Line::from(kind));
+        for (name, value, masked) in secret.fields() {
+            let value = if masked {
+                PASSWORD_MASK_SYMBOL.repeat(8)
+            } else {
+                value
+            };
+            text.push(Line::from(vec![
+                Span::styled(format!("{name}: "), label),
+                Span::raw(value),
+            ]));
+        }
+        text.push(Line::from(""));
+    }
+    match totp {
+        Some(totp) => {
+            let code = totp.code_now();
+            let remaining_style = if code.remaining <= 5 {
+                Style::default().fg(theme.error)
+            } else {
+                Style::default().fg(theme.muted)
+            };
+            text.push(Line::from(vec![
+                Span::styled("TOTP:   ", label),
+                Span::styled(
+                    code.code,
+                    Style::default()
+                        .fg(theme.accent)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    format!("  {}s / {}s", code.remaining, totp.period()),
+                    remaining_style,
+                ),
+            ]));
+        }
+        None => text.push(Line::from(Span::styled("TOTP:   none", label))),
+    }
+    text.push(Line::from(""));
+    if details.notes.is_empty() {
+        text.push(Line::from(Span::styled("Notes:  none", label)));
+    } else {
+        text.push(Line::from(Span::styled("Notes:", label)));
+        text.extend(
+            details
+                .notes
+                .lines()
+                .map(|line| Line::from(line.to_string())),
+        );
+    }
+    text.push(Line::from(""));
+    for field in &details.fields {
+        let value = if field.secret {
+            PASSWORD_MASK_SYMBOL.repeat(8)
+        } else {
+            field.value.clone()
+        };
+        text.push(Line::from(vec![
+            Span::styled(format!("{}: ", field.name), label),
+            Span::raw(value),
+        ]));
+    }
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(block.inner(area));
+
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+    f.render_widget(
+        Paragraph::new(text).wrap(ratatui::widgets::Wrap { trim: false }),
+        chunks[0],
+    );
+    f.render_widget(
+        Paragraph::new("<c> Copy TOTP code  <Esc> Back")
+            .style(Style::default().fg(theme.muted))
+            .alignment(ratatui::layout::Alignment::Center),
+        chunks[1],
+    );
+}
+
+fn draw_history(
+    f: &mut Frame,
+    platform: &str,
+    user_id: &str,
+    versions: &[HistoryEntry],
+    selected: usize,
+    theme: &Theme,
+) {
+    let area = centered_rect_fixed(60, 16, f.area());
+    draw_shadow(f, area);
+
+    let block = Block::default()
+        .title(format!(" 🕘 History: {platform} - {user_id} "))
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(theme.accent))
+        .style(Style::default().bg(theme.surface).fg(theme.text));
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(block.inner(area));
+
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+
+    if versions.is_empty() {
+        f.render_widget(
+            Paragraph::new("No previous passwords").style(Style::default().fg(theme.muted)),
+            chunks[0],
+        );
+    } else {
+        let items: Vec<ListItem> = versions
+            .iter()
+            .enumerate()
+            .map(|(index, version)| {
+                let replaced = DateTime::from_timestamp(version.replaced_at, 0)
+                    .unwrap_or_default()
+                    .with_timezone(&Local);
+                let line = format!(
+                    "{}. {}  replaced {}",
+                    index + 1,
+                    PASSWORD_MASK_SYMBOL.repeat(8),
+                    replaced.format("%B %d %Y %H:%M")
+                );
+                let style = if index == selected {
+                    Style::default()
+                        .bg(theme.accent)
+                        .fg(theme.bg)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(theme.text)
+                };
+                ListItem::new(Line::from(line)).style(style)
+            })
+            .collect();
+        f.render_widget(List::new(items), chunks[0]);
+    }
+
+    f.render_widget(
+        Paragraph::new("<Enter> Copy  <r> Restore  <Esc> Back")
+            .style(Style::default().fg(theme.muted))
+            .alignment(ratatui::layout::Alignment::Center),
+        chunks[1],
+    );
+}
+
+fn draw_trash(
+    f: &mut Frame,
+    entries: &[TrashedEntry],
+    selected: usize,
+    confirm_purge: bool,
+    theme: &Theme,
+) {
+    let area = centered_rect_fixed(70, 18, f.area());
+    draw_shadow(f, area);
+
+    let block = Block::default()
+        .title(" 🗑️  Trash ")
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(theme.accent))
+        .style(Style::default().bg(theme.surface).fg(theme.text));
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(1)])
+        .split(block.inner(area));
+
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+
+    if entries.is_empty() {
+        f.render_widget(
+            Paragraph::new("The trash is empty").style(Style::default().fg(theme.muted)),
+            chunks[0],
+        );
+    } else {
+        let items: Vec<ListItem> = entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let removed = DateTime::from_timestamp(entry.deleted_at, 0)
+                    .unwrap_or_default()
+                    .with_timezone(&Local);
+                let line = format!(
+                    "{} - {}  removed {}",
+                    entry.metadata.platform,
+                    entry.metadata.user_id,
+                    removed.format("%B %d %Y %H:%M")
+                );
+                let style = if index == selected {
+                    Style::default()
+                        .bg(theme.accent)
+                        .fg(theme.bg)
+                        .add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default().fg(theme.text)
+                };
+                ListItem::new(Line::from(line)).style(style)
+            })
+            .collect();
+        f.render_widget(List::new(items), chunks[0]);
+    }
+
+    let help = if confirm_purge {
+        Paragraph::new("Delete permanently? [y] Yes / [n] No").style(
+            Style::default()
+                .fg(theme.error)
+                .add_modifier(Modifier::BOLD),
+        )
+    } else {
+        Paragraph::new("<Enter> Restore  <x> Delete permanently  <Esc> Back")
+            .style(Style::default().fg(theme.muted))
+    };
+    f.render_widget(
+        help.alignment(ratatui::layout::Alignment::Center),
+        chunks[1],
+    );
+}
+
 fn draw_edit_entry(
     f: &mut Frame,
     platform: &str,
-    _original_user_id: &str,
-    user_id: &InputState,
-    password: &InputState,
+    kind: EntryKind,
+    [user_id, password, notes, fields]: [&InputState; 4],
     stage: &EditEntryStage,
     theme: &Theme,
 ) {
-    let area = centered_rect_fixed(50, 15, f.area());
+    let area = centered_rect_fixed(50, 23, f.area());
     draw_shadow(f, area);
 
     // Modal block
@@ -777,6 +1232,10 @@ fn draw_edit_entry(
             Constraint::Length(3), // UserID (Editable)
             Constraint::Length(1), // Spacer
             Constraint::Length(3), // Password (Editable)
+            Constraint::Length(1), // Spacer
+            Constraint::Length(3), // Notes
+            Constraint::Length(1), // Spacer
+            Constraint::Length(3), // Custom fields
         ])
         .split(area);
 
@@ -802,31 +1261,91 @@ fn draw_edit_entry(
         false,
         theme,
     );
+    let active = matches!(stage, EditEntryStage::Password);
+    let (title, placeholder) = match kind {
+        EntryKind::Login => ("New Password", "Leave empty to keep current"),
+        kind => secret_input_labels(kind),
+    };
     draw_input_box(
         f,
         chunks[4],
-        "New Password",
+        title,
         password,
-        "Leave empty to keep current",
-        matches!(stage, EditEntryStage::Password),
-        true,
+        placeholder,
+        active,
+        kind == EntryKind::Login || !active,
+        theme,
+    );
+    draw_details_inputs(
+        f,
+        [chunks[6], chunks[8]],
+        [notes, fields],
+        [
+            matches!(stage, EditEntryStage::Notes),
+            matches!(stage, EditEntryStage::Fields),
+        ],
+        theme,
+    );
+}
+
+/// Title and placeholder of the secret box of the add and edit dialogs for an entry `kind`.
+fn secret_input_labels(kind: EntryKind) -> (&'static str, &'static str) {
+    match kind {
+        EntryKind::Login => ("Password", "••••••••"),
+        EntryKind::Note => ("Note", "Text of the secure note"),
+        EntryKind::Card => ("Card", "number=4111…; expiry=MM/YY; cvv=123; holder=…"),
+        EntryKind::Identity => ("Identity", "name=…; email=…; phone=…; address=…; city=…"),
+        EntryKind::ApiKey => ("API key", "token=…; expires=YYYY-MM-DD"),
+    }
+}
+
+/// Notes and custom fields boxes shared by the add and edit dialogs. The fields box is masked
+/// while unfocused if it holds a secret field.
+fn draw_details_inputs(
+    f: &mut Frame,
+    [notes_area, fields_area]: [Rect; 2],
+    [notes, fields]: [&InputState; 2],
+    [notes_active, fields_active]: [bool; 2],
+    theme: &Theme,
+) {
+    draw_input_box(
+        f,
+        notes_area,
+        "Notes",
+        notes,
+        "Optional",
+        notes_active,
+        false,
+        theme,
+    );
+    let has_secret = fields
+        .value
+        .split(';')
+        .any(|field| field.trim_start().starts_with('*'));
+    draw_input_box(
+        f,
+        fields_area,
+        "Fields",
+        fields,
+        "account=123; *pin=0000",
+        fields_active,
+        has_secret && !fields_active,
         theme,
     );
 }
 
 fn draw_add_entry(
     f: &mut Frame,
-    platform: &InputState,
-    user_id: &InputState,
-    password: &InputState,
+    kind: EntryKind,
+    [platform, user_id, password, notes, fields]: [&InputState; 5],
     stage: &AddEntryStage,
     theme: &Theme,
 ) {
-    let area = centered_rect_fixed(50, 15, f.area());
+    let area = centered_rect_fixed(50, 23, f.area());
     draw_shadow(f, area);
 
     let block = Block::default()
-        .title(" ➕ Add New Entry ")
+        .title(format!(" ➕ Add New Entry: {kind} (Tab to change) "))
         .borders(Borders::ALL)
         .border_type(BorderType::Rounded)
         .border_style(Style::default().fg(theme.accent))
@@ -844,6 +1363,10 @@ fn draw_add_entry(
             Constraint::Length(3), // UserID
             Constraint::Length(1), // Spacer
             Constraint::Length(3), // Password
+            Constraint::Length(1), // Spacer
+            Constraint::Length(3), // Notes
+            Constraint::Length(1), // Spacer
+            Constraint::Length(3), // Custom fields
         ])
         .split(area);
 
@@ -867,16 +1390,28 @@ fn draw_add_entry(
         false,
         theme,
     );
+    let active = matches!(stage, AddEntryStage::Password);
+    let (title, placeholder) = secret_input_labels(kind);
     draw_input_box(
         f,
         chunks[4],
-        "Password",
+        title,
         password,
-        "••••••••",
-        matches!(stage, AddEntryStage::Password),
-        true,
+        placeholder,
+        active,
+        kind == EntryKind::Login || !active, // Typed secrets are masked while unfocused
+        theme,
+    );
+    draw_details_inputs(
+        f,
+        [chunks[6], chunks[8]],
+        [notes, fields],
+        [
+            matches!(stage, AddEntryStage::Notes),
+            matches!(stage, AddEntryStage::Fields),
+        ],
         theme,
-    ); // Masked
+    );
 }
 
 fn draw_backup_create(
@@ -968,6 +1503,75 @@ fn draw_backup_restore(
     );
 }
 
+fn draw_change_password(
+    f: &mut Frame,
+    current: &InputState,
+    new_password: &InputState,
+    confirm: &InputState,
+    stage: &ChangePasswordStage,
+    error: &Option<String>,
+    theme: &Theme,
+) {
+    let area = centered_rect_fixed(58, 16, f.area());
+    draw_shadow(f, area);
+    let block = Block::default()
+        .title(" 🔑 Change Master Password ")
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(Style::default().fg(theme.accent))
+        .style(Style::default().bg(theme.surface).fg(theme.text));
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Min(0),
+        ])
+        .split(area);
+    draw_input_box(
+        f,
+        chunks[0],
+        "Current Password",
+        current,
+        "••••••••",
+        matches!(stage, ChangePasswordStage::Current),
+        true,
+        theme,
+    );
+    draw_input_box(
+        f,
+        chunks[2],
+        "New Password",
+        new_password,
+        "••••••••",
+        matches!(stage, ChangePasswordStage::New),
+        true,
+        theme,
+    );
+    draw_input_box(
+        f,
+        chunks[4],
+        "Confirm New Password",
+        confirm,
+        "••••••••",
+        matches!(stage, ChangePasswordStage::Confirm),
+        true,
+        theme,
+    );
+
+    if let Some(err) = error {
+        let error_text = Paragraph::new(Span::styled(err, Style::default().fg(theme.error)))
+            .alignment(ratatui::layout::Alignment::Center);
+        f.render_widget(error_text, chunks[5]);
+    }
+}
+
 fn draw_export_entry(
     f: &mut Frame,
     platform: &str,
@@ -1287,7 +1891,17 @@ mod tests {
         ];
 
         terminal
-            .draw(|f| draw_main(f, &items, &mut list_state, &None, &theme))
+            .draw(|f| {
+                draw_main(
+                    f,
+                    &items,
+                    &mut list_state,
+                    &None,
+                    DEFAULT_VAULT,
+                    None,
+                    &theme,
+                )
+            })
             .expect("draw main");
 
         let lines = buffer_lines(&terminal);
@@ -1338,6 +1952,11 @@ mod tests {
             id: None,
             created_at: 0,
             updated_at: 0,
+            folder: None,
+            tags: Vec::new(),
+            urls: Vec::new(),
+            kind: rvault_core::kind::EntryKind::Login,
+            revision: 1,
         }
     }
 
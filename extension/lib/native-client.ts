@@ -1,19 +1,43 @@
 export const HOST_NAME = "io.github.ata_sesli.rvault"
 
+export type MatchMode = "base-domain" | "host" | "starts-with" | "regex" | "never"
+
+export type EntryUrl = { url: string; match?: MatchMode }
+
+export type VaultSummary = {
+  name: string
+  entries: number
+  encryptedMetadata: boolean
+  shared: boolean
+  createdAt: number
+  modifiedAt: number
+}
+
 export type HostRequest =
   | { type: "status" }
   | { type: "unlock"; masterPassword: string }
   | { type: "lock" }
   | { type: "quit" }
-  | { type: "list"; query?: string; vault?: string }
+  | { type: "list"; query?: string; vault?: string; includeVaults?: boolean }
+  | { type: "matchOrigin"; origin: string; vault?: string }
   | { type: "get"; platform: string; userId: string; vault?: string }
-  | { type: "create"; platform: string; userId: string; password: string; vault?: string }
+  | { type: "totp"; platform: string; userId: string; vault?: string }
+  | {
+      type: "create"
+      platform: string
+      userId: string
+      password: string
+      urls?: EntryUrl[]
+      vault?: string
+    }
   | {
       type: "update"
       platform: string
       oldUserId: string
       newUserId: string
       password: string
+      urls?: EntryUrl[]
+      expectedRevision?: number
       vault?: string
     }
   | { type: "delete"; platform: string; userId: string; vault?: string }
@@ -48,6 +72,7 @@ export type HostErrorCode =
   | "not_found"
   | "invalid_request"
   | "storage_error"
+  | "revision_mismatch"
 
 export type HostResponse<T> =
   | { ok: true; data: T }
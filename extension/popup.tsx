@@ -26,6 +26,7 @@ import {
 } from "lucide-react"
 
 import {
+  type EntryUrl,
   HostApiError,
   createHostRequest,
   sendHostRequest
@@ -51,6 +52,10 @@ type VaultEntry = {
   pinned: boolean
   createdAt: number
   updatedAt: number
+  revision: number
+  folder: string | null
+  tags: string[]
+  urls: EntryUrl[]
 }
 
 type DetectedForm = {
@@ -195,7 +200,8 @@ function Popup() {
           platform: selected.platform,
           oldUserId: selected.userId,
           newUserId: userId.trim(),
-          password
+          password,
+          expectedRevision: selected.revision
         })
       )
     } else {